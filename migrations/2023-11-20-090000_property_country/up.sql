@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE properties ADD COLUMN country VARCHAR(2) NOT NULL DEFAULT 'CH';
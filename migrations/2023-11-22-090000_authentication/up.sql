@@ -0,0 +1,19 @@
+-- Your SQL goes here
+ALTER TABLE users ADD COLUMN email VARCHAR(255) UNIQUE;
+ALTER TABLE users ADD COLUMN password_hash VARCHAR;
+
+CREATE TABLE sessions (
+    id UUID DEFAULT Uuid_generate_v4 (),
+    owner UUID NOT NULL,
+    token_hash VARCHAR NOT NULL UNIQUE,
+    refresh_hash VARCHAR NOT NULL UNIQUE,
+    expires_at TIMESTAMP NOT NULL,
+    refresh_expires_at TIMESTAMP NOT NULL,
+    revoked_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (id),
+    FOREIGN KEY (owner) REFERENCES users (id) ON DELETE CASCADE
+);
+
+CREATE INDEX sessions_owner_idx ON sessions (owner);
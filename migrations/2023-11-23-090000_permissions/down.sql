@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE role_permissions;
+DROP TABLE permissions;
+
+-- Roles still assigned to users have to stay, up.sql grants them their permissions again
+DELETE FROM roles
+WHERE name IN ('admin', 'caretaker', 'tenant')
+AND NOT EXISTS (SELECT 1 FROM users WHERE users.role = roles.id);
@@ -0,0 +1,62 @@
+-- Your SQL goes here
+CREATE TABLE permissions (
+    name VARCHAR NOT NULL,
+    description VARCHAR NOT NULL,
+    PRIMARY KEY (name)
+);
+
+CREATE TABLE role_permissions (
+    role UUID NOT NULL,
+    permission VARCHAR NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (role, permission),
+    FOREIGN KEY (role) REFERENCES roles (id) ON DELETE CASCADE,
+    FOREIGN KEY (permission) REFERENCES permissions (name) ON DELETE CASCADE
+);
+
+INSERT INTO permissions (name, description) VALUES
+    ('users:read', 'List and view users'),
+    ('users:write:own', 'Update the own user, except its role'),
+    ('users:admin', 'Create, update and delete any user and assign roles'),
+    ('roles:read', 'List and view roles and their permissions'),
+    ('roles:write', 'Create, update and delete roles and grant permissions'),
+    ('properties:read', 'List and view properties'),
+    ('properties:write:own', 'Create, update and delete properties owned by oneself'),
+    ('properties:write:any', 'Create, update and delete any property'),
+    ('machines:read', 'List and view machines'),
+    ('machines:write', 'Create, update and delete machines'),
+    ('reservations:read', 'List and view reservations'),
+    ('reservations:write:own', 'Create, update and delete own reservations'),
+    ('reservations:write:any', 'Create, update and delete any reservation'),
+    ('items:read', 'List and view items'),
+    ('items:write:own', 'Create, update and delete own items'),
+    ('items:write:any', 'Create, update and delete any item');
+
+INSERT INTO roles (name)
+SELECT name FROM (VALUES ('admin'), ('caretaker'), ('tenant')) AS seed (name)
+WHERE NOT EXISTS (SELECT 1 FROM roles WHERE roles.name = seed.name);
+
+INSERT INTO role_permissions (role, permission)
+SELECT roles.id, permissions.name FROM roles, permissions
+WHERE roles.name = 'admin';
+
+INSERT INTO role_permissions (role, permission)
+SELECT roles.id, permissions.name FROM roles, permissions
+WHERE roles.name = 'caretaker'
+AND permissions.name IN (
+    'users:read', 'users:write:own', 'roles:read',
+    'properties:read', 'properties:write:own',
+    'machines:read', 'machines:write',
+    'reservations:read', 'reservations:write:any',
+    'items:read', 'items:write:own'
+);
+
+INSERT INTO role_permissions (role, permission)
+SELECT roles.id, permissions.name FROM roles, permissions
+WHERE roles.name = 'tenant'
+AND permissions.name IN (
+    'users:read', 'users:write:own', 'roles:read',
+    'properties:read', 'machines:read',
+    'reservations:read', 'reservations:write:own',
+    'items:read', 'items:write:own'
+);
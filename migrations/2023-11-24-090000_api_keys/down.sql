@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE api_keys;
+
+DELETE FROM permissions WHERE name IN ('api_keys:write:own', 'api_keys:write:any');
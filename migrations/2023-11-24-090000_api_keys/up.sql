@@ -0,0 +1,30 @@
+-- Your SQL goes here
+CREATE TABLE api_keys (
+    id UUID DEFAULT Uuid_generate_v4 (),
+    name VARCHAR NOT NULL,
+    property UUID NOT NULL,
+    owner UUID NOT NULL,
+    prefix VARCHAR NOT NULL,
+    key_hash VARCHAR NOT NULL UNIQUE,
+    permissions TEXT[] NOT NULL,
+    expires_at TIMESTAMP,
+    revoked_at TIMESTAMP,
+    last_used_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (id),
+    FOREIGN KEY (property) REFERENCES properties (id) ON DELETE CASCADE,
+    FOREIGN KEY (owner) REFERENCES users (id) ON DELETE CASCADE
+);
+
+CREATE INDEX api_keys_property_idx ON api_keys (property);
+
+INSERT INTO permissions (name, description) VALUES
+    ('api_keys:write:own', 'Create, list and revoke API keys of properties owned by oneself'),
+    ('api_keys:write:any', 'Create, list and revoke API keys of any property');
+
+INSERT INTO role_permissions (role, permission)
+SELECT roles.id, 'api_keys:write:any' FROM roles WHERE roles.name = 'admin';
+
+INSERT INTO role_permissions (role, permission)
+SELECT roles.id, 'api_keys:write:own' FROM roles WHERE roles.name IN ('admin', 'caretaker');
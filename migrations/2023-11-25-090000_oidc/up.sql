@@ -0,0 +1,10 @@
+-- Your SQL goes here
+ALTER TABLE users ADD COLUMN oidc_subject VARCHAR UNIQUE;
+
+CREATE TABLE oidc_logins (
+    state VARCHAR NOT NULL,
+    code_verifier VARCHAR NOT NULL,
+    nonce VARCHAR NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (state)
+);
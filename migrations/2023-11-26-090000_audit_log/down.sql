@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE audit_log;
+
+DROP FUNCTION audit_log_append_only;
+
+DELETE FROM permissions WHERE name = 'audit:read';
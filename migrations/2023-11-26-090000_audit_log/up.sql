@@ -0,0 +1,33 @@
+-- Your SQL goes here
+CREATE TABLE audit_log (
+    id UUID DEFAULT Uuid_generate_v4 (),
+    actor VARCHAR NOT NULL,
+    action VARCHAR NOT NULL,
+    resource VARCHAR NOT NULL,
+    resource_id UUID NOT NULL,
+    before JSONB,
+    after JSONB,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (id)
+);
+
+CREATE INDEX audit_log_resource_idx ON audit_log (resource, resource_id);
+CREATE INDEX audit_log_actor_idx ON audit_log (actor);
+CREATE INDEX audit_log_created_at_idx ON audit_log (created_at);
+
+-- Entries are never changed or removed, not even by the API itself
+CREATE FUNCTION audit_log_append_only() RETURNS TRIGGER AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_log is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_log_append_only
+BEFORE UPDATE OR DELETE ON audit_log
+FOR EACH ROW EXECUTE FUNCTION audit_log_append_only();
+
+INSERT INTO permissions (name, description) VALUES
+    ('audit:read', 'Read the audit log of all changes');
+
+INSERT INTO role_permissions (role, permission)
+SELECT roles.id, 'audit:read' FROM roles WHERE roles.name = 'admin';
@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+CREATE OR REPLACE FUNCTION audit_log_append_only() RETURNS TRIGGER AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_log is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+ALTER TABLE users DROP COLUMN erased_at;
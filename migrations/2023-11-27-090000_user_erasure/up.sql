@@ -0,0 +1,13 @@
+-- Your SQL goes here
+ALTER TABLE users ADD COLUMN erased_at TIMESTAMP;
+
+-- Erasing a user redacts their personal data from earlier entries too, which is
+-- the only change allowed and only while `audit_log.redacting` is set
+CREATE OR REPLACE FUNCTION audit_log_append_only() RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP = 'UPDATE' AND current_setting('audit_log.redacting', true) = 'on' THEN
+        RETURN NEW;
+    END IF;
+    RAISE EXCEPTION 'audit_log is append-only';
+END;
+$$ LANGUAGE plpgsql;
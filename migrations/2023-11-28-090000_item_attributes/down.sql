@@ -0,0 +1,10 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE items DROP COLUMN weight;
+ALTER TABLE items DROP COLUMN delicate;
+ALTER TABLE items DROP COLUMN tumble_dry;
+ALTER TABLE items DROP COLUMN max_wash_temperature;
+ALTER TABLE items DROP COLUMN fabric;
+
+DROP INDEX items_colors_idx;
+
+ALTER TABLE items ALTER COLUMN colors TYPE VARCHAR USING array_to_string(colors, ', ');
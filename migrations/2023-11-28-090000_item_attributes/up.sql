@@ -0,0 +1,30 @@
+-- Your SQL goes here
+
+-- Splits free-form colors like "Red/white" into palette names, unknown ones become
+-- "other". Only needed while converting the column.
+CREATE FUNCTION item_palette_colors(colors VARCHAR) RETURNS TEXT[] AS $$
+    SELECT COALESCE(
+        array_agg(DISTINCT CASE
+            WHEN color = 'grey' THEN 'gray'
+            WHEN color IN ('white', 'black', 'gray', 'beige', 'brown', 'red', 'orange',
+                'yellow', 'green', 'blue', 'purple', 'pink', 'multicolor') THEN color
+            ELSE 'other'
+        END),
+        ARRAY['other']
+    )
+    FROM regexp_split_to_table(lower(colors), '[^a-z]+') AS color
+    WHERE color NOT IN ('', 'and');
+$$ LANGUAGE SQL IMMUTABLE;
+
+ALTER TABLE items ALTER COLUMN colors TYPE TEXT[] USING item_palette_colors(colors);
+
+DROP FUNCTION item_palette_colors;
+
+CREATE INDEX items_colors_idx ON items USING GIN (colors);
+
+-- Care label, unknown unless given
+ALTER TABLE items ADD COLUMN fabric VARCHAR;
+ALTER TABLE items ADD COLUMN max_wash_temperature SMALLINT;
+ALTER TABLE items ADD COLUMN tumble_dry BOOLEAN;
+ALTER TABLE items ADD COLUMN delicate BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE items ADD COLUMN weight INTEGER;
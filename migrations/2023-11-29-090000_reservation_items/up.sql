@@ -0,0 +1,11 @@
+-- Your SQL goes here
+CREATE TABLE reservation_items (
+    reservation UUID NOT NULL,
+    item UUID NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (reservation, item),
+    FOREIGN KEY (reservation) REFERENCES reservations (id) ON DELETE CASCADE,
+    FOREIGN KEY (item) REFERENCES items (id) ON DELETE CASCADE
+);
+
+CREATE INDEX reservation_items_item_idx ON reservation_items (item);
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE notifications;
+
+DROP TABLE found_items;
+
+DELETE FROM permissions WHERE name LIKE 'found_items:%';
@@ -0,0 +1,50 @@
+-- Your SQL goes here
+CREATE TABLE found_items (
+    id UUID DEFAULT Uuid_generate_v4 (),
+    property UUID NOT NULL,
+    reporter UUID NOT NULL,
+    machine UUID,
+    description VARCHAR NOT NULL,
+    colors TEXT[] NOT NULL DEFAULT '{}',
+    size VARCHAR,
+    photo_url VARCHAR,
+    found_at TIMESTAMP NOT NULL,
+    item UUID,
+    claimed_by UUID,
+    claimed_at TIMESTAMP,
+    archived_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (id),
+    FOREIGN KEY (property) REFERENCES properties (id) ON DELETE CASCADE,
+    FOREIGN KEY (reporter) REFERENCES users (id),
+    FOREIGN KEY (machine) REFERENCES machines (id) ON DELETE SET NULL,
+    FOREIGN KEY (item) REFERENCES items (id) ON DELETE SET NULL,
+    FOREIGN KEY (claimed_by) REFERENCES users (id)
+);
+
+CREATE INDEX found_items_property_idx ON found_items (property);
+
+CREATE TABLE notifications (
+    id UUID DEFAULT Uuid_generate_v4 (),
+    recipient UUID NOT NULL,
+    kind VARCHAR NOT NULL,
+    subject UUID,
+    message VARCHAR NOT NULL,
+    read_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (id),
+    FOREIGN KEY (recipient) REFERENCES users (id) ON DELETE CASCADE
+);
+
+CREATE INDEX notifications_recipient_idx ON notifications (recipient, created_at);
+
+INSERT INTO permissions (name, description) VALUES
+    ('found_items:read', 'List and view lost-and-found reports'),
+    ('found_items:write:own', 'Report found items, update and claim own reports'),
+    ('found_items:write:any', 'Update, match, claim and delete any lost-and-found report');
+
+INSERT INTO role_permissions (role, permission)
+SELECT roles.id, permissions.name FROM roles, permissions
+WHERE (roles.name IN ('admin', 'caretaker') AND permissions.name LIKE 'found_items:%')
+OR (roles.name = 'tenant' AND permissions.name IN ('found_items:read', 'found_items:write:own'));
@@ -0,0 +1,21 @@
+-- Your SQL goes here
+CREATE TABLE images (
+    id UUID DEFAULT Uuid_generate_v4 (),
+    item UUID,
+    machine UUID,
+    uploader UUID NOT NULL,
+    content_type VARCHAR NOT NULL,
+    size INTEGER NOT NULL,
+    width INTEGER NOT NULL,
+    height INTEGER NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (id),
+    FOREIGN KEY (item) REFERENCES items (id) ON DELETE CASCADE,
+    FOREIGN KEY (machine) REFERENCES machines (id) ON DELETE CASCADE,
+    FOREIGN KEY (uploader) REFERENCES users (id),
+    CHECK ((item IS NULL) <> (machine IS NULL))
+);
+
+CREATE INDEX images_item_idx ON images (item);
+
+CREATE INDEX images_machine_idx ON images (machine);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE items DROP COLUMN tag;
+
+DROP FUNCTION generate_item_tag();
@@ -0,0 +1,10 @@
+-- Your SQL goes here
+-- Eight characters without look-alikes such as 0/O and 1/I, about 8.5e11 codes.
+CREATE FUNCTION generate_item_tag() RETURNS VARCHAR AS $$
+    SELECT string_agg(substr('23456789ABCDEFGHJKMNPQRSTUVWXYZ', 1 + floor(random() * 31)::INTEGER, 1), '')
+    FROM generate_series(1, 8)
+$$ LANGUAGE SQL VOLATILE;
+
+ALTER TABLE items ADD COLUMN tag VARCHAR NOT NULL DEFAULT generate_item_tag();
+
+ALTER TABLE items ADD CONSTRAINT items_tag_key UNIQUE (tag);
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE loans;
+
+ALTER TABLE items DROP COLUMN holder;
+
+DELETE FROM permissions WHERE name LIKE 'loans:%';
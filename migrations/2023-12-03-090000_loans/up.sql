@@ -0,0 +1,37 @@
+-- Your SQL goes here
+ALTER TABLE items ADD COLUMN holder UUID REFERENCES users (id) ON DELETE SET NULL;
+
+CREATE TABLE loans (
+    id UUID DEFAULT Uuid_generate_v4 (),
+    item UUID NOT NULL,
+    borrower UUID NOT NULL,
+    status VARCHAR NOT NULL DEFAULT 'requested',
+    message VARCHAR,
+    due_at TIMESTAMP NOT NULL,
+    approved_at TIMESTAMP,
+    returned_at TIMESTAMP,
+    reminded_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (id),
+    FOREIGN KEY (item) REFERENCES items (id) ON DELETE CASCADE,
+    FOREIGN KEY (borrower) REFERENCES users (id) ON DELETE CASCADE,
+    CHECK (status IN ('requested', 'approved', 'rejected', 'cancelled', 'returned'))
+);
+
+CREATE INDEX loans_item_idx ON loans (item);
+
+CREATE INDEX loans_borrower_idx ON loans (borrower);
+
+-- An item is lent to one borrower at a time.
+CREATE UNIQUE INDEX loans_lent_idx ON loans (item) WHERE status = 'approved';
+
+INSERT INTO permissions (name, description) VALUES
+    ('loans:read', 'List and view loans of items'),
+    ('loans:write:own', 'Borrow items and handle requests for own items'),
+    ('loans:write:any', 'Handle any loan');
+
+INSERT INTO role_permissions (role, permission)
+SELECT roles.id, permissions.name FROM roles, permissions
+WHERE (roles.name IN ('admin', 'caretaker') AND permissions.name LIKE 'loans:%')
+OR (roles.name = 'tenant' AND permissions.name IN ('loans:read', 'loans:write:own'));
@@ -0,0 +1,22 @@
+-- This file should undo anything in `up.sql`
+DELETE FROM permissions WHERE name LIKE 'resource%';
+
+DROP INDEX reservations_resource_idx;
+
+-- Only machines fit back into the table.
+DELETE FROM reservations WHERE resource IN (SELECT id FROM resources WHERE kind <> 'machine');
+DELETE FROM resources WHERE kind <> 'machine';
+
+ALTER TABLE reservations RENAME CONSTRAINT reservations_resource_fkey TO reservations_machine_fkey;
+ALTER TABLE reservations RENAME COLUMN resource TO machine;
+
+DROP INDEX resources_property_idx;
+
+ALTER TABLE resources DROP COLUMN capacity;
+ALTER TABLE resources DROP COLUMN kind;
+
+ALTER TABLE resources RENAME CONSTRAINT resources_property_fkey TO machines_property_fkey;
+ALTER TABLE resources RENAME CONSTRAINT resources_pkey TO machines_pkey;
+ALTER TABLE resources RENAME TO machines;
+
+DROP TABLE resource_kinds;
@@ -0,0 +1,56 @@
+-- Your SQL goes here
+CREATE TABLE resource_kinds (
+    id UUID DEFAULT Uuid_generate_v4 (),
+    name VARCHAR NOT NULL,
+    -- Unit of the capacity of resources of this kind.
+    unit VARCHAR NOT NULL DEFAULT 'slots',
+    -- Booking rules, no limit when NULL.
+    max_duration_minutes INTEGER,
+    max_advance_days INTEGER,
+    max_upcoming INTEGER,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (id),
+    UNIQUE (name),
+    CHECK (unit IN ('slots', 'kg'))
+);
+
+INSERT INTO resource_kinds (name, unit, max_duration_minutes, max_advance_days, max_upcoming) VALUES
+    ('machine', 'kg', NULL, NULL, NULL),
+    ('drying_room', 'slots', 1440, 14, 2),
+    ('ironing_station', 'slots', 120, 14, 2),
+    ('cargo_bike', 'slots', 480, 30, 1);
+
+-- Machines become resources of the kind machine, keeping their ids.
+ALTER TABLE machines RENAME TO resources;
+ALTER TABLE resources RENAME CONSTRAINT machines_pkey TO resources_pkey;
+ALTER TABLE resources RENAME CONSTRAINT machines_property_fkey TO resources_property_fkey;
+
+ALTER TABLE resources ADD COLUMN kind VARCHAR NOT NULL DEFAULT 'machine'
+    REFERENCES resource_kinds (name) ON UPDATE CASCADE;
+ALTER TABLE resources ALTER COLUMN kind DROP DEFAULT;
+
+ALTER TABLE resources ADD COLUMN capacity INTEGER NOT NULL DEFAULT 1 CHECK (capacity > 0);
+-- Kilograms of dry laundry.
+UPDATE resources SET capacity = 7 WHERE kind = 'machine';
+
+CREATE INDEX resources_property_idx ON resources (property);
+
+ALTER TABLE reservations RENAME COLUMN machine TO resource;
+ALTER TABLE reservations RENAME CONSTRAINT reservations_machine_fkey TO reservations_resource_fkey;
+
+CREATE INDEX reservations_resource_idx ON reservations (resource, start_time);
+
+INSERT INTO permissions (name, description) VALUES
+    ('resources:read', 'List and view bookable resources and their kinds'),
+    ('resources:write', 'Create, update and delete bookable resources'),
+    ('resource_kinds:write', 'Create, update and delete kinds of resources and their booking rules');
+
+INSERT INTO role_permissions (role, permission)
+SELECT role, 'resources:read' FROM role_permissions WHERE permission = 'machines:read';
+
+INSERT INTO role_permissions (role, permission)
+SELECT role, 'resources:write' FROM role_permissions WHERE permission = 'machines:write';
+
+INSERT INTO role_permissions (role, permission)
+SELECT roles.id, 'resource_kinds:write' FROM roles WHERE roles.name = 'admin';
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE participants;
+
+ALTER TABLE reservations DROP COLUMN amount;
@@ -0,0 +1,21 @@
+-- Your SQL goes here
+-- Share of the resource's capacity the owner of a reservation uses, in the unit of its kind.
+ALTER TABLE reservations ADD COLUMN amount INTEGER NOT NULL DEFAULT 1 CHECK (amount > 0);
+
+CREATE TABLE participants (
+    id UUID DEFAULT Uuid_generate_v4 (),
+    reservation UUID NOT NULL,
+    participant UUID NOT NULL,
+    amount INTEGER NOT NULL DEFAULT 1,
+    status VARCHAR NOT NULL DEFAULT 'requested',
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (id),
+    UNIQUE (reservation, participant),
+    FOREIGN KEY (reservation) REFERENCES reservations (id) ON DELETE CASCADE,
+    FOREIGN KEY (participant) REFERENCES users (id) ON DELETE CASCADE,
+    CHECK (amount > 0),
+    CHECK (status IN ('requested', 'approved'))
+);
+
+CREATE INDEX participants_participant_idx ON participants (participant);
@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+DELETE FROM images WHERE found_item IS NOT NULL;
+
+ALTER TABLE images
+    DROP CONSTRAINT images_check,
+    DROP COLUMN found_item,
+    ADD CONSTRAINT images_check CHECK ((item IS NULL) <> (machine IS NULL));
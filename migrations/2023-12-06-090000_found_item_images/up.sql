@@ -0,0 +1,7 @@
+-- Your SQL goes here
+ALTER TABLE images
+    ADD COLUMN found_item UUID REFERENCES found_items (id) ON DELETE CASCADE,
+    DROP CONSTRAINT images_check,
+    ADD CONSTRAINT images_check CHECK (num_nonnulls(item, machine, found_item) = 1);
+
+CREATE INDEX images_found_item_idx ON images (found_item);
@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE found_items ADD COLUMN photo_url VARCHAR;
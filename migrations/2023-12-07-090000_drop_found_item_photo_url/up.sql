@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE found_items DROP COLUMN photo_url;
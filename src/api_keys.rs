@@ -0,0 +1,292 @@
+use actix_web::{delete, get, post, web, HttpResponse};
+use diesel::prelude::*;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use super::DbPool;
+use crate::auth::{self, Credential, Identity};
+use crate::helpers::{ApiError, FieldError, Json, PaginatedResponse, SuccessResponse};
+use crate::models::api_key::{ApiKey, ApiKeyPayload, CreatedApiKey, NewApiKey};
+use crate::models::user::User;
+use crate::pagination::{paginate, Page, PageParams, Paginated};
+use crate::permissions;
+use crate::validation::Validate;
+
+/// Bearer tokens starting with this are API keys rather than session tokens.
+pub const KEY_PREFIX: &str = "bk_";
+/// Characters of a key stored in clear, enough to tell keys apart in listings.
+const VISIBLE_LENGTH: usize = 11;
+/// Minutes between updates of `last_used_at`, so not every request writes to the database.
+const LAST_USED_RESOLUTION_MINUTES: i64 = 1;
+
+#[derive(Debug, Deserialize)]
+struct Filters {
+    property: Option<Uuid>,
+    revoked: Option<bool>,
+}
+
+const SORTABLE: [&str; 3] = ["created_at", "updated_at", "name"];
+
+#[get("/api-keys")]
+async fn index(
+    page: web::Query<PageParams>,
+    filters: web::Query<Filters>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require_session()?;
+    let owner = if identity.can(permissions::API_KEYS_WRITE.any) {
+        None
+    } else {
+        identity.require(permissions::API_KEYS_WRITE.own)?;
+        Some(identity.user.id)
+    };
+
+    let page = page.resolve(&SORTABLE)?;
+    let api_keys = web::block(move || {
+        let mut conn = pool.get()?;
+        find_all(&filters, owner, &page, &mut conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(PaginatedResponse {
+        status: 200,
+        message: "OK".to_string(),
+        data: api_keys.data,
+        meta: api_keys.meta,
+    }))
+}
+
+/// Creates a key for a property. The key is part of this response only.
+#[post("/api-keys")]
+async fn create(
+    pool: web::Data<DbPool>,
+    payload: Json<ApiKeyPayload>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require_session()?;
+
+    payload.validate()?;
+
+    let created = web::block(move || {
+        let mut conn = pool.get()?;
+        let owner = property_owner(payload.property, &mut conn)?;
+        identity.require_owner(&permissions::API_KEYS_WRITE, owner)?;
+        check_permissions(&identity, &payload.permissions)?;
+        add(&payload, identity.user.id, &mut conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Created().json(SuccessResponse {
+        status: 201,
+        message: "Created".to_string(),
+        data: created,
+    }))
+}
+
+/// Revokes a key. Revoked keys are kept to show when they were last used.
+#[delete("/api-keys/{id}")]
+async fn destroy(
+    id: web::Path<Uuid>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require_session()?;
+
+    let api_key = web::block(move || {
+        let mut conn = pool.get()?;
+        revoke(id.into_inner(), &identity, &mut conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(SuccessResponse {
+        status: 200,
+        message: "Revoked".to_string(),
+        data: api_key,
+    }))
+}
+
+/// Resolves an API key to the identity of its creator, limited to the key's property
+/// and to the permissions both the key and the creator's role grant.
+pub fn authenticate(key: &str, conn: &mut PgConnection) -> Result<Identity, ApiError> {
+    use crate::schema::{api_keys, users};
+
+    let now = chrono::Local::now().naive_local();
+    let (api_key, user) = api_keys::table
+        .inner_join(users::table)
+        .filter(api_keys::key_hash.eq(auth::token_hash(key)))
+        .filter(api_keys::revoked_at.is_null())
+        .filter(
+            api_keys::expires_at
+                .is_null()
+                .or(api_keys::expires_at.gt(now)),
+        )
+        .select((ApiKey::as_select(), users::all_columns))
+        .first::<(ApiKey, User)>(conn)
+        .optional()?
+        .ok_or_else(|| ApiError::Unauthorized("Invalid, expired or revoked API key".to_string()))?;
+
+    let granted = permissions::granted(user.role, conn)?;
+    let permissions = api_key
+        .permissions
+        .into_iter()
+        .filter(|permission| granted.contains(permission))
+        .collect();
+
+    let stale = now - chrono::Duration::minutes(LAST_USED_RESOLUTION_MINUTES);
+    if api_key.last_used_at.is_none_or(|used| used < stale) {
+        diesel::update(api_keys::table.find(api_key.id))
+            .set(api_keys::last_used_at.eq(now))
+            .execute(conn)?;
+    }
+
+    Ok(Identity {
+        user,
+        credential: Credential::ApiKey {
+            id: api_key.id,
+            property: api_key.property,
+        },
+        permissions,
+    })
+}
+
+/// Keys may only carry [`permissions::DELEGABLE`] permissions the caller holds itself.
+fn check_permissions(identity: &Identity, names: &[String]) -> Result<(), ApiError> {
+    let errors: Vec<FieldError> = names
+        .iter()
+        .enumerate()
+        .filter_map(|(position, name)| {
+            let message = if !permissions::DELEGABLE.contains(&name.as_str()) {
+                format!("{} cannot be granted to API keys", name)
+            } else if !identity.can(name) {
+                format!("{} is not granted to you", name)
+            } else {
+                return None;
+            };
+            Some(FieldError {
+                field: format!("permissions[{}]", position),
+                code: "invalid".to_string(),
+                message,
+            })
+        })
+        .collect();
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(ApiError::InvalidPayload(errors))
+    }
+}
+
+fn add(
+    payload: &ApiKeyPayload,
+    owner: Uuid,
+    conn: &mut PgConnection,
+) -> Result<CreatedApiKey, ApiError> {
+    use crate::schema::api_keys;
+
+    let key = format!("{}{}", KEY_PREFIX, auth::random_token());
+    let mut names = payload.permissions.clone();
+    names.sort_unstable();
+    names.dedup();
+
+    let now = chrono::Local::now().naive_local();
+    let api_key = diesel::insert_into(api_keys::table)
+        .values(&NewApiKey {
+            name: &payload.name,
+            property: payload.property,
+            owner,
+            prefix: &key[..VISIBLE_LENGTH],
+            key_hash: &auth::token_hash(&key),
+            permissions: &names,
+            expires_at: payload.expires_at,
+            created_at: now,
+            updated_at: now,
+        })
+        .returning(ApiKey::as_returning())
+        .get_result(conn)?;
+
+    Ok(CreatedApiKey { api_key, key })
+}
+
+/// Lists keys, only those of properties owned by `owned_by` if given.
+fn find_all(
+    filters: &Filters,
+    owned_by: Option<Uuid>,
+    page: &Page,
+    conn: &mut PgConnection,
+) -> Result<Paginated<ApiKey>, ApiError> {
+    use crate::schema::api_keys::dsl::*;
+    use crate::schema::properties;
+
+    let filtered = || {
+        let mut query = api_keys.select(ApiKey::as_select()).into_boxed();
+        if let Some(owner_id) = owned_by {
+            query = query.filter(
+                property.eq_any(
+                    properties::table
+                        .filter(properties::owner.eq(owner_id))
+                        .select(properties::id),
+                ),
+            );
+        }
+        if let Some(property_id) = filters.property {
+            query = query.filter(property.eq(property_id));
+        }
+        match filters.revoked {
+            Some(true) => query = query.filter(revoked_at.is_not_null()),
+            Some(false) => query = query.filter(revoked_at.is_null()),
+            None => {}
+        }
+        query
+    };
+
+    let total = filtered().count().get_result(conn)?;
+    let rows = match page.column.as_str() {
+        "updated_at" => {
+            paginate!(filtered(), page, updated_at, id, chrono::NaiveDateTime).load(conn)?
+        }
+        "name" => paginate!(filtered(), page, name, id, String).load(conn)?,
+        _ => paginate!(filtered(), page, created_at, id, chrono::NaiveDateTime).load(conn)?,
+    };
+
+    Ok(page.finish(rows, total))
+}
+
+fn revoke(key_id: Uuid, identity: &Identity, conn: &mut PgConnection) -> Result<ApiKey, ApiError> {
+    use crate::schema::api_keys::dsl::*;
+
+    conn.transaction(|conn| {
+        let current = api_keys
+            .find(key_id)
+            .select(ApiKey::as_select())
+            .for_update()
+            .first::<ApiKey>(conn)
+            .optional()?
+            .ok_or_else(|| ApiError::NotFound("API key not found".to_string()))?;
+        let owner_id = property_owner(current.property, conn)?;
+        identity.require_owner(&crate::permissions::API_KEYS_WRITE, owner_id)?;
+
+        if current.revoked_at.is_some() {
+            return Ok(current);
+        }
+
+        let now = chrono::Local::now().naive_local();
+        let api_key = diesel::update(api_keys.find(key_id))
+            .set((revoked_at.eq(now), updated_at.eq(now)))
+            .returning(ApiKey::as_returning())
+            .get_result(conn)?;
+        Ok(api_key)
+    })
+}
+
+fn property_owner(property_id: Uuid, conn: &mut PgConnection) -> Result<Uuid, ApiError> {
+    use crate::schema::properties::dsl::*;
+
+    properties
+        .find(property_id)
+        .select(owner)
+        .first::<Uuid>(conn)
+        .optional()?
+        .ok_or_else(|| ApiError::NotFound("Property not found".to_string()))
+}
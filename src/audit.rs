@@ -0,0 +1,268 @@
+use actix_web::{get, web, HttpResponse};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use uuid::Uuid;
+
+use super::DbPool;
+use crate::auth::Identity;
+use crate::helpers::{self, ApiError, FieldError, PaginatedResponse};
+use crate::includes::Resource;
+use crate::models::audit_entry::{AuditEntry, NewAuditEntry};
+use crate::pagination::{paginate, Page, PageParams, Paginated};
+use crate::permissions;
+
+/// Actor of changes the server makes on its own, e.g. creating the administrator on startup.
+pub const SYSTEM: &str = "system";
+
+#[derive(Debug, Deserialize)]
+struct Filters {
+    resource: Option<String>,
+    resource_id: Option<Uuid>,
+    /// As recorded, e.g. `user:<id>` or `api_key:<id>`.
+    actor: Option<String>,
+    from: Option<chrono::NaiveDateTime>,
+    to: Option<chrono::NaiveDateTime>,
+}
+
+const SORTABLE: [&str; 1] = ["created_at"];
+
+#[get("/audit")]
+async fn index(
+    page: web::Query<PageParams>,
+    filters: web::Query<Filters>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::AUDIT_READ)?;
+
+    let page = page.resolve(&SORTABLE)?;
+    let mut errors = Vec::new();
+    if let Some(resource) = &filters.resource {
+        if !Resource::ALL.iter().any(|known| known.name() == resource) {
+            let names: Vec<&str> = Resource::ALL.iter().map(|known| known.name()).collect();
+            errors.push(FieldError {
+                field: "resource".to_string(),
+                code: "invalid".to_string(),
+                message: format!("resource must be one of {}", names.join(", ")),
+            });
+        }
+    }
+    if let (Some(from), Some(to)) = (filters.from, filters.to) {
+        if from > to {
+            errors.push(FieldError {
+                field: "to".to_string(),
+                code: "invalid".to_string(),
+                message: "to must not be before from".to_string(),
+            });
+        }
+    }
+    if !errors.is_empty() {
+        return Err(ApiError::InvalidParameters(errors));
+    }
+
+    let entries = web::block(move || {
+        let mut conn = pool.get()?;
+        find_all(&filters, &page, &mut conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(PaginatedResponse {
+        status: 200,
+        message: "OK".to_string(),
+        data: entries.data,
+        meta: entries.meta,
+    }))
+}
+
+/// Records that `actor` created `row`. Call it in the transaction creating the row.
+pub fn created<T: Serialize>(
+    actor: &str,
+    resource: Resource,
+    id: Uuid,
+    row: &T,
+    conn: &mut PgConnection,
+) -> Result<(), ApiError> {
+    record(
+        actor,
+        "create",
+        resource,
+        id,
+        None,
+        Some(to_value(row)?),
+        conn,
+    )
+}
+
+/// Records the fields of a row `actor` changed from `before` to `after`.
+pub fn updated<T: Serialize>(
+    actor: &str,
+    resource: Resource,
+    id: Uuid,
+    before: &T,
+    after: &T,
+    conn: &mut PgConnection,
+) -> Result<(), ApiError> {
+    let (before, after) = diff(to_value(before)?, to_value(after)?);
+    record(
+        actor,
+        "update",
+        resource,
+        id,
+        Some(before),
+        Some(after),
+        conn,
+    )
+}
+
+/// Records that `actor` deleted `row`, keeping its last state.
+pub fn deleted<T: Serialize>(
+    actor: &str,
+    resource: Resource,
+    id: Uuid,
+    row: &T,
+    conn: &mut PgConnection,
+) -> Result<(), ApiError> {
+    record(
+        actor,
+        "delete",
+        resource,
+        id,
+        Some(to_value(row)?),
+        None,
+        conn,
+    )
+}
+
+/// Records that `actor` erased the personal data of a row, see [`redact`].
+pub fn erased(
+    actor: &str,
+    resource: Resource,
+    id: Uuid,
+    conn: &mut PgConnection,
+) -> Result<(), ApiError> {
+    record(actor, "erase", resource, id, None, None, conn)
+}
+
+/// Overwrites `fields` in all earlier entries of a row. Entries are otherwise never
+/// changed, the database only allows it within the transaction calling this.
+pub fn redact(
+    resource: Resource,
+    id: Uuid,
+    fields: &Map<String, Value>,
+    conn: &mut PgConnection,
+) -> Result<(), ApiError> {
+    use crate::schema::audit_log;
+
+    diesel::sql_query("SET LOCAL audit_log.redacting = 'on'").execute(conn)?;
+
+    let entries = audit_log::table
+        .filter(audit_log::resource.eq(resource.name()))
+        .filter(audit_log::resource_id.eq(id))
+        .load::<AuditEntry>(conn)?;
+    for entry in entries {
+        let redacted = |state: Option<Value>| {
+            state.map(|mut state| {
+                if let Value::Object(state) = &mut state {
+                    for (field, value) in fields {
+                        if let Some(old) = state.get_mut(field) {
+                            *old = value.clone();
+                        }
+                    }
+                }
+                state
+            })
+        };
+        diesel::update(audit_log::table.find(entry.id))
+            .set((
+                audit_log::before.eq(redacted(entry.before)),
+                audit_log::after.eq(redacted(entry.after)),
+            ))
+            .execute(conn)?;
+    }
+
+    diesel::sql_query("SET LOCAL audit_log.redacting = 'off'").execute(conn)?;
+    Ok(())
+}
+
+fn record(
+    actor: &str,
+    action: &str,
+    resource: Resource,
+    resource_id: Uuid,
+    before: Option<Value>,
+    after: Option<Value>,
+    conn: &mut PgConnection,
+) -> Result<(), ApiError> {
+    use crate::schema::audit_log;
+
+    diesel::insert_into(audit_log::table)
+        .values(&NewAuditEntry {
+            actor,
+            action,
+            resource: resource.name(),
+            resource_id,
+            before,
+            after,
+            created_at: chrono::Local::now().naive_local(),
+        })
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// Reduces two versions of a row to the fields which differ. `updated_at` is left out
+/// as it changes with every update.
+fn diff(before: Value, after: Value) -> (Value, Value) {
+    let (Value::Object(before), Value::Object(mut after)) = (before, after) else {
+        return (Value::Null, Value::Null);
+    };
+
+    let mut old = Map::new();
+    let mut new = Map::new();
+    for (field, value) in before {
+        let changed = after.remove(&field).unwrap_or_default();
+        if field != "updated_at" && value != changed {
+            old.insert(field.clone(), value);
+            new.insert(field, changed);
+        }
+    }
+    (Value::Object(old), Value::Object(new))
+}
+
+fn to_value<T: Serialize>(row: &T) -> Result<Value, ApiError> {
+    serde_json::to_value(row).map_err(helpers::internal)
+}
+
+fn find_all(
+    filters: &Filters,
+    page: &Page,
+    conn: &mut PgConnection,
+) -> Result<Paginated<AuditEntry>, ApiError> {
+    use crate::schema::audit_log::dsl::*;
+
+    let filtered = || {
+        let mut query = audit_log.into_boxed();
+        if let Some(name) = &filters.resource {
+            query = query.filter(resource.eq(name));
+        }
+        if let Some(id_filter) = filters.resource_id {
+            query = query.filter(resource_id.eq(id_filter));
+        }
+        if let Some(name) = &filters.actor {
+            query = query.filter(actor.eq(name));
+        }
+        if let Some(from) = filters.from {
+            query = query.filter(created_at.ge(from));
+        }
+        if let Some(to) = filters.to {
+            query = query.filter(created_at.le(to));
+        }
+        query
+    };
+
+    let total = filtered().count().get_result(conn)?;
+    let rows = paginate!(filtered(), page, created_at, id, chrono::NaiveDateTime).load(conn)?;
+
+    Ok(page.finish(rows, total))
+}
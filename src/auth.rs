@@ -0,0 +1,543 @@
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::{env, io};
+
+use actix_web::body::EitherBody;
+use actix_web::dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::{header, Method};
+use actix_web::{delete, get, post, web, FromRequest, HttpMessage, HttpRequest, HttpResponse};
+use argon2::password_hash::{rand_core::OsRng, PasswordHash, SaltString};
+use argon2::{Argon2, PasswordHasher, PasswordVerifier};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use diesel::prelude::*;
+use rand::RngCore;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use super::DbPool;
+use crate::api_keys;
+use crate::audit;
+use crate::db;
+use crate::helpers::{self, ApiError, Json, SuccessResponse};
+use crate::includes::Resource;
+use crate::models::role::Role;
+use crate::models::session::{LoginPayload, NewSession, RefreshPayload, Session, Tokens};
+use crate::models::user::{NewUser, User};
+use crate::permissions;
+
+/// Routes reachable without a session.
+const PUBLIC: [&str; 7] = [
+    "/",
+    "/favicon.ico",
+    "/tea",
+    "/auth/login",
+    "/auth/refresh",
+    "/auth/oidc/authorize",
+    "/auth/oidc/callback",
+];
+
+/// Minutes an access token is valid, overridable with `AUTH_ACCESS_TTL_MINUTES`.
+const DEFAULT_ACCESS_TTL_MINUTES: i64 = 15;
+/// Days a refresh token is valid, overridable with `AUTH_REFRESH_TTL_DAYS`.
+const DEFAULT_REFRESH_TTL_DAYS: i64 = 30;
+
+/// The authenticated caller of a request, resolved by [`Authentication`].
+#[derive(Debug, Clone)]
+pub struct Identity {
+    /// The user acting, for API keys the user who created the key.
+    pub user: User,
+    pub credential: Credential,
+    /// Permissions granted by the user's role, narrowed down to those of an API key.
+    pub permissions: Vec<String>,
+}
+
+/// How the caller authenticated.
+#[derive(Debug, Clone, Copy)]
+pub enum Credential {
+    Session(Uuid),
+    ApiKey { id: Uuid, property: Uuid },
+}
+
+impl Identity {
+    /// Names the caller in logs and audit records, e.g. `user:<id>` or `api_key:<id>`.
+    pub fn actor(&self) -> String {
+        match self.credential {
+            Credential::Session(_) => format!("user:{}", self.user.id),
+            Credential::ApiKey { id, .. } => format!("api_key:{}", id),
+        }
+    }
+
+    /// Returns the session of the caller, failing with `403` for API keys.
+    pub fn require_session(&self) -> Result<Uuid, ApiError> {
+        match self.credential {
+            Credential::Session(session) => Ok(session),
+            Credential::ApiKey { .. } => Err(ApiError::Forbidden(
+                "This requires logging in as a user, API keys are not accepted".to_string(),
+            )),
+        }
+    }
+}
+
+/// The actor of a request for the access log, `-` if it was not authenticated.
+pub fn log_actor(res: &ServiceResponse) -> String {
+    res.request()
+        .extensions()
+        .get::<Identity>()
+        .map_or_else(|| "-".to_string(), Identity::actor)
+}
+
+impl FromRequest for Identity {
+    type Error = ApiError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let identity = req.extensions().get::<Identity>().cloned();
+        ready(identity.ok_or_else(|| ApiError::Unauthorized("Authentication required".to_string())))
+    }
+}
+
+/// Middleware resolving the bearer token of every request outside of [`PUBLIC`] to an
+/// [`Identity`], answering `401` if there is none.
+///
+/// Paths no route is registered for pass through unauthenticated, so they get the `404`
+/// of [`helpers::default_service`] rather than a `401`.
+pub struct Authentication;
+
+impl<S, B> Transform<S, ServiceRequest> for Authentication
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Transform = AuthenticationMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(AuthenticationMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct AuthenticationMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for AuthenticationMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+
+        Box::pin(async move {
+            let routed = req.resource_map().has_resource(req.path());
+            if routed && req.method() != Method::OPTIONS && !PUBLIC.contains(&req.path()) {
+                match identify(&req).await {
+                    Ok(identity) => {
+                        req.extensions_mut().insert(identity);
+                    }
+                    Err(err) => return Ok(req.error_response(err).map_into_right_body()),
+                }
+            }
+
+            service
+                .call(req)
+                .await
+                .map(ServiceResponse::map_into_left_body)
+        })
+    }
+}
+
+async fn identify(req: &ServiceRequest) -> Result<Identity, ApiError> {
+    let token = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| token.trim().to_string())
+        .ok_or_else(|| ApiError::Unauthorized("Missing bearer token".to_string()))?;
+    let pool = req
+        .app_data::<web::Data<DbPool>>()
+        .cloned()
+        .ok_or_else(|| ApiError::Internal("Database pool is not configured".to_string()))?;
+
+    web::block(move || {
+        let mut conn = pool.get()?;
+        if token.starts_with(api_keys::KEY_PREFIX) {
+            api_keys::authenticate(&token, &mut conn)
+        } else {
+            find_by_access_token(&token, &mut conn)
+        }
+    })
+    .await?
+}
+
+#[post("/auth/login")]
+async fn login(
+    pool: web::Data<DbPool>,
+    payload: Json<LoginPayload>,
+) -> Result<HttpResponse, ApiError> {
+    let tokens = web::block(move || {
+        let mut conn = pool.get()?;
+        let user = find_by_credentials(&payload.email, &payload.password, &mut conn)?;
+        start_session(user.id, &mut conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(SuccessResponse {
+        status: 200,
+        message: "OK".to_string(),
+        data: tokens,
+    }))
+}
+
+#[post("/auth/refresh")]
+async fn refresh(
+    pool: web::Data<DbPool>,
+    payload: Json<RefreshPayload>,
+) -> Result<HttpResponse, ApiError> {
+    let tokens = web::block(move || {
+        let mut conn = pool.get()?;
+        rotate(&payload.refresh_token, &mut conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(SuccessResponse {
+        status: 200,
+        message: "OK".to_string(),
+        data: tokens,
+    }))
+}
+
+#[post("/auth/logout")]
+async fn logout(identity: Identity, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    let session = identity.require_session()?;
+
+    web::block(move || {
+        let mut conn = pool.get()?;
+        revoke(identity.user.id, session, &mut conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// The current user together with the permissions of its role.
+#[derive(Serialize)]
+struct Me {
+    #[serde(flatten)]
+    user: User,
+    permissions: Vec<String>,
+}
+
+#[get("/auth/me")]
+async fn me(identity: Identity) -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok().json(SuccessResponse {
+        status: 200,
+        message: "OK".to_string(),
+        data: Me {
+            user: identity.user,
+            permissions: identity.permissions,
+        },
+    }))
+}
+
+/// Lists the active sessions of the current user.
+#[get("/auth/sessions")]
+async fn sessions(identity: Identity, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    identity.require_session()?;
+
+    let sessions = web::block(move || {
+        let mut conn = pool.get()?;
+        find_active(identity.user.id, &mut conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(SuccessResponse {
+        status: 200,
+        message: "OK".to_string(),
+        data: sessions,
+    }))
+}
+
+/// Revokes one of the current user's sessions, e.g. on a lost device.
+#[delete("/auth/sessions/{id}")]
+async fn revoke_session(
+    id: web::Path<Uuid>,
+    identity: Identity,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, ApiError> {
+    identity.require_session()?;
+
+    web::block(move || {
+        let mut conn = pool.get()?;
+        revoke(identity.user.id, id.into_inner(), &mut conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Creates the user named by `ADMIN_EMAIL` and `ADMIN_PASSWORD` if it doesn't exist yet,
+/// so a fresh installation has someone who can log in.
+pub fn bootstrap(pool: &DbPool) -> io::Result<()> {
+    use crate::schema::{roles, users};
+
+    let (Ok(email), Ok(password)) = (env::var("ADMIN_EMAIL"), env::var("ADMIN_PASSWORD")) else {
+        return Ok(());
+    };
+    let email = normalize_email(&email);
+
+    let mut conn = pool.get().map_err(io::Error::other)?;
+    conn.transaction(|conn| {
+        let exists = diesel::select(diesel::dsl::exists(
+            users::table.filter(users::email.eq(&email)),
+        ))
+        .get_result::<bool>(conn)?;
+        if exists {
+            return Ok(());
+        }
+
+        let now = chrono::Local::now().naive_local();
+        let role = match roles::table
+            .filter(roles::name.eq("admin"))
+            .select(roles::id)
+            .first::<Uuid>(conn)
+            .optional()?
+        {
+            Some(role) => role,
+            None => {
+                let role = diesel::insert_into(roles::table)
+                    .values((
+                        roles::name.eq("admin"),
+                        roles::created_at.eq(now),
+                        roles::updated_at.eq(now),
+                    ))
+                    .get_result::<Role>(conn)?;
+                audit::created(audit::SYSTEM, Resource::Role, role.id, &role, conn)?;
+                role.id
+            }
+        };
+
+        let user = diesel::insert_into(users::table)
+            .values(&NewUser {
+                name: "Administrator",
+                role,
+                property: None,
+                created_at: now,
+                updated_at: now,
+                email: Some(email.clone()),
+                password_hash: Some(hash_password(&password)?),
+                oidc_subject: None,
+            })
+            .get_result::<User>(conn)?;
+        audit::created(audit::SYSTEM, Resource::User, user.id, &user, conn)?;
+        log::info!("Created administrator {}", email);
+        Ok(())
+    })
+    .map_err(|err: ApiError| io::Error::other(err.to_string()))
+}
+
+/// Hashes a password with argon2 and a random salt, in PHC string format.
+pub fn hash_password(password: &str) -> Result<String, ApiError> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(helpers::internal)
+}
+
+fn verify_password(hash: &str, password: &str) -> bool {
+    PasswordHash::new(hash)
+        .and_then(|hash| Argon2::default().verify_password(password.as_bytes(), &hash))
+        .is_ok()
+}
+
+/// Emails are compared case-insensitively and stored in lowercase.
+pub fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+fn find_by_credentials(
+    email: &str,
+    password: &str,
+    conn: &mut PgConnection,
+) -> Result<User, ApiError> {
+    use crate::schema::users;
+
+    let user = users::table
+        .filter(users::email.eq(normalize_email(email)))
+        .first::<User>(conn)
+        .optional()?;
+
+    match user {
+        Some(user)
+            if user
+                .password_hash
+                .as_deref()
+                .is_some_and(|hash| verify_password(hash, password)) =>
+        {
+            Ok(user)
+        }
+        Some(_) => Err(invalid_credentials()),
+        None => {
+            // Spend the same time as for a known email so users can't be enumerated
+            let _ = hash_password(password);
+            Err(invalid_credentials())
+        }
+    }
+}
+
+fn invalid_credentials() -> ApiError {
+    ApiError::Unauthorized("Invalid email or password".to_string())
+}
+
+fn find_by_access_token(token: &str, conn: &mut PgConnection) -> Result<Identity, ApiError> {
+    use crate::schema::{sessions, users};
+
+    let now = chrono::Local::now().naive_local();
+    let (session, user) = sessions::table
+        .inner_join(users::table)
+        .filter(sessions::token_hash.eq(token_hash(token)))
+        .filter(sessions::revoked_at.is_null())
+        .filter(sessions::expires_at.gt(now))
+        .select((sessions::id, users::all_columns))
+        .first::<(Uuid, User)>(conn)
+        .optional()?
+        .ok_or_else(|| ApiError::Unauthorized("Invalid or expired token".to_string()))?;
+
+    let permissions = permissions::granted(user.role, conn)?;
+
+    Ok(Identity {
+        user,
+        credential: Credential::Session(session),
+        permissions,
+    })
+}
+
+pub(crate) fn start_session(owner: Uuid, conn: &mut PgConnection) -> Result<Tokens, ApiError> {
+    use crate::schema::sessions;
+
+    let now = chrono::Local::now().naive_local();
+    let tokens = issue(Uuid::new_v4(), now);
+    diesel::insert_into(sessions::table)
+        .values(&NewSession {
+            id: tokens.session,
+            owner,
+            token_hash: &token_hash(&tokens.access_token),
+            refresh_hash: &token_hash(&tokens.refresh_token),
+            expires_at: tokens.expires_at,
+            refresh_expires_at: tokens.refresh_expires_at,
+            created_at: now,
+            updated_at: now,
+        })
+        .execute(conn)?;
+
+    Ok(tokens)
+}
+
+/// Exchanges a refresh token for a new pair of tokens.
+///
+/// Refresh tokens are single use, the old pair stops working immediately.
+fn rotate(refresh_token: &str, conn: &mut PgConnection) -> Result<Tokens, ApiError> {
+    use crate::schema::sessions;
+
+    let now = chrono::Local::now().naive_local();
+    conn.transaction(|conn| {
+        let session = sessions::table
+            .filter(sessions::refresh_hash.eq(token_hash(refresh_token)))
+            .filter(sessions::revoked_at.is_null())
+            .filter(sessions::refresh_expires_at.gt(now))
+            .select(sessions::id)
+            .for_update()
+            .first::<Uuid>(conn)
+            .optional()?
+            .ok_or_else(|| {
+                ApiError::Unauthorized("Invalid or expired refresh token".to_string())
+            })?;
+
+        let tokens = issue(session, now);
+        diesel::update(sessions::table.find(session))
+            .set((
+                sessions::token_hash.eq(token_hash(&tokens.access_token)),
+                sessions::refresh_hash.eq(token_hash(&tokens.refresh_token)),
+                sessions::expires_at.eq(tokens.expires_at),
+                sessions::refresh_expires_at.eq(tokens.refresh_expires_at),
+                sessions::updated_at.eq(now),
+            ))
+            .execute(conn)?;
+        Ok(tokens)
+    })
+}
+
+fn revoke(user_id: Uuid, session_id: Uuid, conn: &mut PgConnection) -> Result<(), ApiError> {
+    use crate::schema::sessions::dsl::*;
+
+    let now = chrono::Local::now().naive_local();
+    let count = diesel::update(
+        sessions
+            .find(session_id)
+            .filter(owner.eq(user_id))
+            .filter(revoked_at.is_null()),
+    )
+    .set((revoked_at.eq(now), updated_at.eq(now)))
+    .execute(conn)?;
+
+    if count == 0 {
+        return Err(ApiError::NotFound("Session not found".to_string()));
+    }
+    Ok(())
+}
+
+fn find_active(user_id: Uuid, conn: &mut PgConnection) -> Result<Vec<Session>, ApiError> {
+    use crate::schema::sessions::dsl::*;
+
+    let now = chrono::Local::now().naive_local();
+    let active = sessions
+        .filter(owner.eq(user_id))
+        .filter(revoked_at.is_null())
+        .filter(refresh_expires_at.gt(now))
+        .order(created_at.desc())
+        .select(Session::as_select())
+        .load::<Session>(conn)?;
+
+    Ok(active)
+}
+
+fn issue(session: Uuid, now: chrono::NaiveDateTime) -> Tokens {
+    let access_ttl = db::env_or("AUTH_ACCESS_TTL_MINUTES", DEFAULT_ACCESS_TTL_MINUTES);
+    let refresh_ttl = db::env_or("AUTH_REFRESH_TTL_DAYS", DEFAULT_REFRESH_TTL_DAYS);
+
+    Tokens {
+        session,
+        token_type: "Bearer",
+        access_token: random_token(),
+        expires_at: now + chrono::Duration::minutes(access_ttl),
+        refresh_token: random_token(),
+        refresh_expires_at: now + chrono::Duration::days(refresh_ttl),
+    }
+}
+
+/// 256 random bits, URL-safe base64 encoded.
+pub(crate) fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::rngs::OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Tokens are random enough that an unsalted hash is safe to store.
+pub(crate) fn token_hash(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
@@ -0,0 +1,95 @@
+use std::time::Duration;
+use std::{env, io, thread};
+
+use diesel::prelude::*;
+use diesel::r2d2::{self, ConnectionManager};
+use diesel_migrations::MigrationHarness;
+
+use super::{DbPool, MIGRATIONS};
+
+/// Settings for connecting to the database on startup, read from the environment.
+struct ConnectConfig {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    pool_timeout: Duration,
+}
+
+impl ConnectConfig {
+    fn from_env() -> Self {
+        ConnectConfig {
+            max_attempts: env_or("DB_CONNECT_RETRIES", 10),
+            initial_backoff: Duration::from_millis(env_or("DB_CONNECT_BACKOFF_MS", 500)),
+            max_backoff: Duration::from_millis(env_or("DB_CONNECT_MAX_BACKOFF_MS", 30_000)),
+            pool_timeout: Duration::from_secs(env_or("DB_POOL_TIMEOUT_SECS", 5)),
+        }
+    }
+}
+
+pub(crate) fn env_or<T: std::str::FromStr>(key: &str, default: T) -> T {
+    env::var(key)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+/// Creates the connection pool and runs pending migrations.
+///
+/// The database is often not ready yet when the API starts (e.g. under docker-compose),
+/// so connecting and migrating are retried together with exponential backoff.
+pub fn init() -> io::Result<DbPool> {
+    let database_url = env::var("DATABASE_URL").map_err(|_| {
+        log::error!("DATABASE_URL is not set");
+        io::Error::new(io::ErrorKind::NotFound, "DATABASE_URL must be set")
+    })?;
+    let config = ConnectConfig::from_env();
+
+    // `build_unchecked` does not try to connect, so a missing database does not fail here.
+    // Connection errors are logged by us instead of on every attempt of the pool.
+    let manager = ConnectionManager::<PgConnection>::new(database_url);
+    let pool: DbPool = r2d2::Pool::builder()
+        .connection_timeout(config.pool_timeout)
+        .error_handler(Box::new(r2d2::NopErrorHandler))
+        .build_unchecked(manager);
+
+    let mut backoff = config.initial_backoff;
+    let mut attempt = 1;
+    loop {
+        match connect_and_migrate(&pool) {
+            Ok(()) => break,
+            Err(err) if attempt < config.max_attempts => {
+                log::warn!(
+                    "Database not ready (attempt {}/{}): {}. Retrying in {:?}...",
+                    attempt,
+                    config.max_attempts,
+                    err,
+                    backoff
+                );
+                thread::sleep(backoff);
+                backoff = (backoff * 2).min(config.max_backoff);
+                attempt += 1;
+            }
+            Err(err) => {
+                log::error!(
+                    "Giving up preparing the database after {} attempts: {}",
+                    attempt,
+                    err
+                );
+                return Err(io::Error::new(io::ErrorKind::ConnectionRefused, err));
+            }
+        }
+    }
+    log::info!("Migrations are up to date!");
+
+    Ok(pool)
+}
+
+/// Acquires a connection and runs the pending migrations on it. Each migration runs in
+/// its own transaction, so a failed attempt can simply be repeated.
+fn connect_and_migrate(pool: &DbPool) -> Result<(), String> {
+    let mut conn = pool.get().map_err(|err| err.to_string())?;
+    log::info!("Connected to the database, running migrations...");
+    conn.run_pending_migrations(MIGRATIONS)
+        .map_err(|err| format!("failed to run migrations: {}", err))?;
+    Ok(())
+}
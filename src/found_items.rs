@@ -0,0 +1,764 @@
+use super::DbPool;
+use actix_web::http::header::{ETag, IfMatch, IfNoneMatch};
+use actix_web::{delete, get, patch, post, web, HttpResponse};
+use diesel::prelude::*;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::audit;
+use crate::auth::Identity;
+use crate::db;
+use crate::helpers::{self, ApiError, FieldError, Json, PaginatedResponse, SuccessResponse};
+use crate::idempotency;
+use crate::images::{self, Subject};
+use crate::includes::{IncludeParams, Includes, Resource};
+use crate::models::found_item::{
+    FoundItem, FoundItemCandidate, FoundItemClaimPayload, FoundItemMatchPayload, FoundItemPatch,
+    FoundItemPayload, NewFoundItem,
+};
+use crate::models::item::Item;
+use crate::nested::{self, Parent};
+use crate::notifications;
+use crate::pagination::{paginate, Page, PageParams, Paginated};
+use crate::permissions;
+use crate::storage::Storage;
+use crate::validation::Validate;
+
+/// Days after which unclaimed reports are archived, unless `FOUND_ITEMS_ARCHIVE_DAYS` is set.
+const DEFAULT_ARCHIVE_DAYS: i64 = 30;
+/// Hours before an item was found in which items washed in the same machine are
+/// likely candidates.
+const WASHED_WITHIN_HOURS: i64 = 48;
+/// Most candidates returned for a report.
+const MAX_CANDIDATES: usize = 20;
+
+#[derive(Debug, Deserialize)]
+struct Filters {
+    property: Option<Uuid>,
+    machine: Option<Uuid>,
+    claimed: Option<bool>,
+    archived: Option<bool>,
+}
+
+const SORTABLE: [&str; 3] = ["created_at", "updated_at", "found_at"];
+
+#[get("/found-items")]
+async fn index(
+    page: web::Query<PageParams>,
+    filters: web::Query<Filters>,
+    include: web::Query<IncludeParams>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::FOUND_ITEMS_READ)?;
+
+    let mut filters = filters.into_inner();
+    filters.property = identity.scope_filter(filters.property)?;
+    list(None, &page, filters, &include, &identity, pool).await
+}
+
+#[get("/properties/{id}/found-items")]
+async fn property_index(
+    id: web::Path<Uuid>,
+    page: web::Query<PageParams>,
+    filters: web::Query<Filters>,
+    include: web::Query<IncludeParams>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::FOUND_ITEMS_READ)?;
+
+    let parent = Parent::Property(id.into_inner());
+    identity.require_property(Some(parent.id()))?;
+    let mut filters = filters.into_inner();
+    filters.property = Some(parent.id());
+    list(Some(parent), &page, filters, &include, &identity, pool).await
+}
+
+async fn list(
+    parent: Option<Parent>,
+    page: &PageParams,
+    filters: Filters,
+    include: &IncludeParams,
+    identity: &Identity,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, ApiError> {
+    let page = page.resolve(&SORTABLE)?;
+    let includes = Includes::parse(include.include.as_deref(), Resource::FoundItem, identity)?;
+    let found_items = web::block(move || {
+        let mut conn = pool.get()?;
+        if let Some(parent) = parent {
+            parent.ensure_exists(&mut conn)?;
+        }
+        let found_items = find_all(&filters, &page, &mut conn)?;
+        includes.embed_page(Resource::FoundItem, found_items, &mut conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(PaginatedResponse {
+        status: 200,
+        message: "OK".to_string(),
+        data: found_items.data,
+        meta: found_items.meta,
+    }))
+}
+
+/// Reports an item found in the laundry room, the caller being the reporter.
+#[post("/found-items")]
+async fn create(
+    pool: web::Data<DbPool>,
+    payload: Json<FoundItemPayload>,
+    key: idempotency::Key,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    insert(
+        None,
+        identity,
+        "POST /found-items".to_string(),
+        payload.0,
+        key,
+        pool,
+    )
+    .await
+}
+
+#[post("/properties/{id}/found-items")]
+async fn property_create(
+    id: web::Path<Uuid>,
+    pool: web::Data<DbPool>,
+    body: Json<serde_json::Value>,
+    key: idempotency::Key,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    let parent = Parent::Property(id.into_inner());
+    let payload = nested::payload(body.0, "property", &parent)?;
+    let scope = format!("POST /properties/{}/found-items", parent.id());
+    insert(Some(parent), identity, scope, payload, key, pool).await
+}
+
+async fn insert(
+    parent: Option<Parent>,
+    identity: Identity,
+    scope: String,
+    payload: FoundItemPayload,
+    key: idempotency::Key,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, ApiError> {
+    identity.require_owner(&permissions::FOUND_ITEMS_WRITE, identity.user.id)?;
+    identity.require_property(Some(payload.property))?;
+
+    payload.validate()?;
+
+    let created = web::block(move || {
+        let mut conn = pool.get()?;
+        if let Some(parent) = parent {
+            parent.ensure_exists(&mut conn)?;
+        }
+        check_machine(&payload, &mut conn)?;
+        idempotency::create_once(&key, &scope, &payload, &mut conn, |conn| {
+            add(&payload, &identity, conn)
+        })
+    })
+    .await??;
+
+    Ok(created.respond())
+}
+
+#[get("/found-items/{id}")]
+async fn show(
+    id: web::Path<Uuid>,
+    include: web::Query<IncludeParams>,
+    if_none_match: Option<web::Header<IfNoneMatch>>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::FOUND_ITEMS_READ)?;
+
+    let includes = Includes::parse(include.include.as_deref(), Resource::FoundItem, &identity)?;
+    let embedding = !includes.is_empty();
+    let found_item = web::block(move || {
+        let mut conn = pool.get()?;
+        let found_item = find_by_id(id.into_inner(), &mut conn)?;
+        if let Some(found_item) = &found_item {
+            identity.require_property(Some(found_item.property))?;
+        }
+        found_item
+            .map(|found_item| {
+                let data = includes.embed_one(Resource::FoundItem, &found_item, &mut conn)?;
+                Ok::<_, ApiError>((found_item.updated_at, data))
+            })
+            .transpose()
+    })
+    .await??;
+
+    let Some((updated_at, found_item)) = found_item else {
+        return Err(ApiError::NotFound("Found item not found".to_string()));
+    };
+
+    // Embedded resources change independently of this one, so only the plain
+    // representation is cacheable by its version.
+    if embedding {
+        return Ok(HttpResponse::Ok().json(SuccessResponse {
+            status: 200,
+            message: "OK".to_string(),
+            data: found_item,
+        }));
+    }
+
+    let etag = helpers::etag(&updated_at);
+    if helpers::not_modified(if_none_match.as_deref(), &etag) {
+        return Ok(HttpResponse::NotModified()
+            .insert_header(ETag(etag))
+            .finish());
+    }
+
+    Ok(HttpResponse::Ok()
+        .insert_header(ETag(etag))
+        .json(SuccessResponse {
+            status: 200,
+            message: "OK".to_string(),
+            data: found_item,
+        }))
+}
+
+#[patch("/found-items/{id}")]
+async fn partial_update(
+    id: web::Path<Uuid>,
+    changes: Json<FoundItemPatch>,
+    if_match: Option<web::Header<IfMatch>>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    let found_item = web::block(move || {
+        let mut conn = pool.get()?;
+        patch_by_id(
+            id.into_inner(),
+            changes.0,
+            if_match.as_deref(),
+            &identity,
+            &mut conn,
+        )
+    })
+    .await??;
+
+    let etag = helpers::etag(&found_item.updated_at);
+    Ok(HttpResponse::Ok()
+        .insert_header(ETag(etag))
+        .json(SuccessResponse {
+            status: 200,
+            message: "OK".to_string(),
+            data: found_item,
+        }))
+}
+
+#[delete("/found-items/{id}")]
+async fn destroy(
+    id: web::Path<Uuid>,
+    if_match: Option<web::Header<IfMatch>>,
+    pool: web::Data<DbPool>,
+    storage: web::Data<dyn Storage>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    let result = web::block(move || {
+        let mut conn = pool.get()?;
+        delete(
+            id.into_inner(),
+            if_match.as_deref(),
+            &identity,
+            storage.as_ref(),
+            &mut conn,
+        )
+    })
+    .await?
+    .map(|found_item| {
+        HttpResponse::Ok().json(SuccessResponse {
+            status: 200,
+            message: "Deleted".to_string(),
+            data: found_item,
+        })
+    })?;
+
+    Ok(result)
+}
+
+/// Registered items of the property's tenants which could be the found one.
+#[get("/found-items/{id}/candidates")]
+async fn candidates(
+    id: web::Path<Uuid>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::FOUND_ITEMS_READ)?;
+    identity.require(permissions::ITEMS_READ)?;
+
+    let candidates = web::block(move || {
+        let mut conn = pool.get()?;
+        let found_item = find_by_id(id.into_inner(), &mut conn)?
+            .ok_or_else(|| ApiError::NotFound("Found item not found".to_string()))?;
+        identity.require_property(Some(found_item.property))?;
+        find_candidates(&found_item, &mut conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(SuccessResponse {
+        status: 200,
+        message: "OK".to_string(),
+        data: candidates,
+    }))
+}
+
+/// Records which registered item was found and notifies its owner.
+#[post("/found-items/{id}/match")]
+async fn match_item(
+    id: web::Path<Uuid>,
+    payload: Json<FoundItemMatchPayload>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::FOUND_ITEMS_WRITE.any)?;
+
+    let found_item = web::block(move || {
+        let mut conn = pool.get()?;
+        assign(id.into_inner(), payload.item, &identity, &mut conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(SuccessResponse {
+        status: 200,
+        message: "OK".to_string(),
+        data: found_item,
+    }))
+}
+
+/// Records that the item was taken back by its owner.
+#[post("/found-items/{id}/claim")]
+async fn claim(
+    id: web::Path<Uuid>,
+    payload: Option<Json<FoundItemClaimPayload>>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    let claimant = payload
+        .and_then(|payload| payload.0.claimed_by)
+        .unwrap_or(identity.user.id);
+    identity.require_owner(&permissions::FOUND_ITEMS_WRITE, claimant)?;
+
+    let found_item = web::block(move || {
+        let mut conn = pool.get()?;
+        record_claim(id.into_inner(), claimant, &identity, &mut conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(SuccessResponse {
+        status: 200,
+        message: "Claimed".to_string(),
+        data: found_item,
+    }))
+}
+
+fn add(
+    payload: &FoundItemPayload,
+    identity: &Identity,
+    conn: &mut PgConnection,
+) -> Result<FoundItem, ApiError> {
+    use crate::schema::found_items::dsl::*;
+
+    let now = chrono::Local::now().naive_local();
+    let new_found_item = NewFoundItem {
+        property: payload.property,
+        reporter: identity.user.id,
+        machine: payload.machine,
+        description: payload.description.as_str(),
+        colors: &payload.colors,
+        size: payload.size.as_deref(),
+        found_at: payload.found_at.unwrap_or(now),
+        created_at: now,
+        updated_at: now,
+    };
+
+    conn.transaction(|conn| {
+        let res = diesel::insert_into(found_items)
+            .values(&new_found_item)
+            .returning(found_items::all_columns())
+            .get_result::<FoundItem>(conn)?;
+        audit::created(&identity.actor(), Resource::FoundItem, res.id, &res, conn)?;
+        Ok(res)
+    })
+}
+
+/// The machine an item was found in must be one of the report's property.
+fn check_machine(payload: &FoundItemPayload, conn: &mut PgConnection) -> Result<(), ApiError> {
+    let Some(machine) = payload.machine else {
+        return Ok(());
+    };
+
+    if permissions::resource_property(machine, conn)? == Some(payload.property) {
+        Ok(())
+    } else {
+        Err(ApiError::InvalidPayload(vec![FieldError {
+            field: "machine".to_string(),
+            code: "invalid".to_string(),
+            message: "machine must be one of the property".to_string(),
+        }]))
+    }
+}
+
+/// How long reports stay claimable, `FOUND_ITEMS_ARCHIVE_DAYS` or the default.
+fn archive_after() -> chrono::Duration {
+    chrono::Duration::days(db::env_or("FOUND_ITEMS_ARCHIVE_DAYS", DEFAULT_ARCHIVE_DAYS))
+}
+
+/// Archives reports left unclaimed for longer than [`archive_after`], run periodically
+/// by [`jobs`](crate::jobs).
+pub fn archive_stale(conn: &mut PgConnection) -> Result<(), ApiError> {
+    use crate::schema::found_items::dsl::*;
+
+    let now = chrono::Local::now().naive_local();
+
+    conn.transaction(|conn| {
+        let stale = found_items
+            .filter(claimed_at.is_null())
+            .filter(archived_at.is_null())
+            .filter(found_at.lt(now - archive_after()))
+            .for_update()
+            .skip_locked()
+            .load::<FoundItem>(conn)?;
+        for current in stale {
+            let archived = diesel::update(found_items.find(current.id))
+                .set(archived_at.eq(now))
+                .get_result::<FoundItem>(conn)?;
+            audit::updated(
+                audit::SYSTEM,
+                Resource::FoundItem,
+                current.id,
+                &current,
+                &archived,
+                conn,
+            )?;
+        }
+        Ok(())
+    })
+}
+
+fn find_all(
+    filters: &Filters,
+    page: &Page,
+    conn: &mut PgConnection,
+) -> Result<Paginated<FoundItem>, ApiError> {
+    use crate::schema::found_items::dsl::*;
+
+    let filtered = || {
+        let mut query = found_items.into_boxed();
+        if let Some(property_id) = filters.property {
+            query = query.filter(property.eq(property_id));
+        }
+        if let Some(machine_id) = filters.machine {
+            query = query.filter(machine.eq(machine_id));
+        }
+        match filters.claimed {
+            Some(true) => query = query.filter(claimed_at.is_not_null()),
+            Some(false) => query = query.filter(claimed_at.is_null()),
+            None => {}
+        }
+        match filters.archived {
+            Some(true) => query = query.filter(archived_at.is_not_null()),
+            Some(false) => query = query.filter(archived_at.is_null()),
+            None => {}
+        }
+        query
+    };
+
+    let total = filtered().count().get_result(conn)?;
+    let rows = match page.column.as_str() {
+        "updated_at" => {
+            paginate!(filtered(), page, updated_at, id, chrono::NaiveDateTime).load(conn)?
+        }
+        "found_at" => {
+            paginate!(filtered(), page, found_at, id, chrono::NaiveDateTime).load(conn)?
+        }
+        _ => paginate!(filtered(), page, created_at, id, chrono::NaiveDateTime).load(conn)?,
+    };
+
+    Ok(page.finish(rows, total))
+}
+
+fn find_by_id(found_item_id: Uuid, conn: &mut PgConnection) -> Result<Option<FoundItem>, ApiError> {
+    use crate::schema::found_items::dsl::*;
+
+    let found_item = found_items
+        .filter(id.eq(found_item_id))
+        .first::<FoundItem>(conn)
+        .optional()?;
+
+    Ok(found_item)
+}
+
+/// Scores items of the property's tenants by shared colors, the same size and whether
+/// they were washed in the machine shortly before the item was found.
+fn find_candidates(
+    found_item: &FoundItem,
+    conn: &mut PgConnection,
+) -> Result<Vec<FoundItemCandidate>, ApiError> {
+    use crate::schema::{items, reservation_items, reservations, users};
+
+    let washed: Vec<Uuid> = match found_item.machine {
+        Some(machine) => reservation_items::table
+            .inner_join(reservations::table)
+            .filter(reservations::resource.eq(machine))
+            .filter(reservations::end_time.le(found_item.found_at))
+            .filter(
+                reservations::end_time
+                    .gt(found_item.found_at - chrono::Duration::hours(WASHED_WITHIN_HOURS)),
+            )
+            .select(reservation_items::item)
+            .load(conn)?,
+        None => Vec::new(),
+    };
+
+    let tenants = users::table
+        .filter(users::property.eq(found_item.property))
+        .select(users::id);
+    let mut query = items::table
+        .filter(items::owner.eq_any(tenants))
+        .filter(
+            items::colors
+                .overlaps_with(&found_item.colors)
+                .or(items::id.eq_any(&washed)),
+        )
+        .into_boxed();
+    if let Some(size) = &found_item.size {
+        query = query.or_filter(
+            items::owner
+                .eq_any(
+                    users::table
+                        .filter(users::property.eq(found_item.property))
+                        .select(users::id),
+                )
+                .and(items::size.ilike(size)),
+        );
+    }
+    let items = query.load::<Item>(conn)?;
+
+    let mut scored: Vec<FoundItemCandidate> = items
+        .into_iter()
+        .map(|item| {
+            let mut score = 0;
+            let mut reasons = Vec::new();
+            if washed.contains(&item.id) {
+                score += 3;
+                reasons.push("washed_in_machine");
+            }
+            let shared = item
+                .colors
+                .iter()
+                .filter(|color| found_item.colors.contains(color))
+                .count() as u32;
+            if shared > 0 {
+                score += shared;
+                reasons.push("colors");
+            }
+            let same_size = found_item
+                .size
+                .as_deref()
+                .is_some_and(|size| size.trim().eq_ignore_ascii_case(item.size.trim()));
+            if same_size {
+                score += 2;
+                reasons.push("size");
+            }
+            FoundItemCandidate {
+                item,
+                score,
+                reasons,
+            }
+        })
+        .collect();
+    scored.sort_by_key(|candidate| std::cmp::Reverse(candidate.score));
+    scored.truncate(MAX_CANDIDATES);
+    Ok(scored)
+}
+
+fn patch_by_id(
+    found_item_id: Uuid,
+    mut changes: FoundItemPatch,
+    if_match: Option<&IfMatch>,
+    identity: &Identity,
+    conn: &mut PgConnection,
+) -> Result<FoundItem, ApiError> {
+    use crate::schema::found_items::dsl::*;
+
+    conn.transaction(|conn| {
+        let current = find_for_update(found_item_id, conn)?;
+        identity.require_owner(&permissions::FOUND_ITEMS_WRITE, current.reporter)?;
+        identity.require_property(Some(current.property))?;
+        helpers::check_if_match(if_match, &current.updated_at)?;
+        let merged = changes.merge(current.clone());
+        merged.validate()?;
+        check_machine(&merged, conn)?;
+
+        changes.updated_at = Some(chrono::Local::now().naive_local());
+        let found_item = diesel::update(found_items.find(found_item_id))
+            .set(&changes)
+            .get_result::<FoundItem>(conn)?;
+        audit::updated(
+            &identity.actor(),
+            Resource::FoundItem,
+            found_item_id,
+            &current,
+            &found_item,
+            conn,
+        )?;
+        Ok(found_item)
+    })
+}
+
+fn assign(
+    found_item_id: Uuid,
+    item_id: Uuid,
+    identity: &Identity,
+    conn: &mut PgConnection,
+) -> Result<FoundItem, ApiError> {
+    use crate::schema::found_items::dsl::*;
+    use crate::schema::items;
+
+    conn.transaction(|conn| {
+        let current = find_for_update(found_item_id, conn)?;
+        identity.require_property(Some(current.property))?;
+        if current.claimed_at.is_some() {
+            return Err(ApiError::Conflict(
+                "Found item has already been claimed".to_string(),
+            ));
+        }
+
+        let (item_name, item_owner) = items::table
+            .find(item_id)
+            .select((items::name, items::owner))
+            .first::<(String, Uuid)>(conn)
+            .optional()?
+            .ok_or_else(|| {
+                ApiError::InvalidPayload(vec![FieldError {
+                    field: "item".to_string(),
+                    code: "not_found".to_string(),
+                    message: format!("Item {} not found", item_id),
+                }])
+            })?;
+
+        let found_item = diesel::update(found_items.find(found_item_id))
+            .set((
+                item.eq(item_id),
+                updated_at.eq(chrono::Local::now().naive_local()),
+            ))
+            .get_result::<FoundItem>(conn)?;
+        audit::updated(
+            &identity.actor(),
+            Resource::FoundItem,
+            found_item_id,
+            &current,
+            &found_item,
+            conn,
+        )?;
+        notifications::notify(
+            item_owner,
+            "found_item",
+            Some(found_item_id),
+            &format!(
+                "Your {} may have been found in the laundry room: {}",
+                item_name, found_item.description
+            ),
+            conn,
+        )?;
+        Ok(found_item)
+    })
+}
+
+fn record_claim(
+    found_item_id: Uuid,
+    claimant: Uuid,
+    identity: &Identity,
+    conn: &mut PgConnection,
+) -> Result<FoundItem, ApiError> {
+    use crate::schema::found_items::dsl::*;
+
+    conn.transaction(|conn| {
+        let current = find_for_update(found_item_id, conn)?;
+        identity.require_property(Some(current.property))?;
+        if current.claimed_at.is_some() {
+            return Err(ApiError::Conflict(
+                "Found item has already been claimed".to_string(),
+            ));
+        }
+        // Stale reports may not have been archived by the periodic job yet
+        let now = chrono::Local::now().naive_local();
+        if current.archived_at.is_some() || current.found_at < now - archive_after() {
+            return Err(ApiError::Conflict(
+                "Found item has been archived".to_string(),
+            ));
+        }
+
+        let found_item = diesel::update(found_items.find(found_item_id))
+            .set((
+                claimed_by.eq(claimant),
+                claimed_at.eq(now),
+                updated_at.eq(now),
+            ))
+            .get_result::<FoundItem>(conn)?;
+        audit::updated(
+            &identity.actor(),
+            Resource::FoundItem,
+            found_item_id,
+            &current,
+            &found_item,
+            conn,
+        )?;
+        if current.reporter != claimant {
+            notifications::notify(
+                current.reporter,
+                "found_item_claimed",
+                Some(found_item_id),
+                &format!("The item you found was claimed: {}", current.description),
+                conn,
+            )?;
+        }
+        Ok(found_item)
+    })
+}
+
+/// Loads a found item and locks it until the end of the transaction.
+fn find_for_update(found_item_id: Uuid, conn: &mut PgConnection) -> Result<FoundItem, ApiError> {
+    use crate::schema::found_items::dsl::*;
+
+    found_items
+        .find(found_item_id)
+        .for_update()
+        .first::<FoundItem>(conn)
+        .optional()?
+        .ok_or_else(|| ApiError::NotFound("Found item not found".to_string()))
+}
+
+fn delete(
+    found_item_id: Uuid,
+    if_match: Option<&IfMatch>,
+    identity: &Identity,
+    storage: &dyn Storage,
+    conn: &mut PgConnection,
+) -> Result<usize, ApiError> {
+    use crate::schema::found_items::dsl::*;
+
+    conn.transaction(|conn| {
+        let current = find_for_update(found_item_id, conn)?;
+        identity.require_owner(&permissions::FOUND_ITEMS_WRITE, current.reporter)?;
+        identity.require_property(Some(current.property))?;
+        helpers::check_if_match(if_match, &current.updated_at)?;
+
+        let image_ids = images::ids_of(Subject::FoundItem(found_item_id), conn)?;
+        let count = diesel::delete(found_items.find(found_item_id)).execute(conn)?;
+        audit::deleted(
+            &identity.actor(),
+            Resource::FoundItem,
+            found_item_id,
+            &current,
+            conn,
+        )?;
+        images::remove_files(&image_ids, storage)?;
+        Ok(count)
+    })
+}
@@ -1,9 +1,43 @@
-use serde::Serialize;
+use std::fmt;
+use std::future::Future;
+use std::ops::Deref;
+use std::pin::Pin;
+
+use actix_web::{
+    dev::Payload,
+    error::{BlockingError, JsonPayloadError, PathError, QueryPayloadError},
+    http::{
+        header::{self, EntityTag, IfMatch, IfNoneMatch},
+        Method, StatusCode,
+    },
+    web, FromRequest, HttpRequest, HttpResponse, ResponseError,
+};
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize};
+
+use crate::pagination::PageMeta;
+
+/// Seconds clients are asked to wait before retrying when the database is unavailable.
+const DB_RETRY_AFTER_SECS: u32 = 5;
+
+/// Path parameters taken as they are, like tag codes and names of resource kinds. All
+/// others are UUIDs.
+const TEXT_PATH_PARAMS: [&str; 2] = ["code", "kind"];
 
 #[derive(Debug, Serialize)]
 pub struct ErrorResponse {
     pub status: i16,
     pub message: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<FieldError>,
+}
+
+/// Describes a problem with a single field of the request.
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub code: String,
+    pub message: String,
 }
 
 #[derive(Debug, Serialize)]
@@ -12,3 +46,345 @@ pub struct SuccessResponse<T> {
     pub message: String,
     pub data: T,
 }
+
+/// Response of index endpoints, carrying one page of results.
+#[derive(Debug, Serialize)]
+pub struct PaginatedResponse<T> {
+    pub status: i16,
+    pub message: String,
+    pub data: Vec<T>,
+    pub meta: PageMeta,
+}
+
+/// Error type shared by all handlers, rendered as an [`ErrorResponse`].
+#[derive(Debug)]
+pub enum ApiError {
+    BadRequest(String),
+    InvalidParameters(Vec<FieldError>),
+    Unauthorized(String),
+    Forbidden(String),
+    NotFound(String),
+    MethodNotAllowed(String),
+    Conflict(String),
+    PayloadTooLarge(String),
+    UnsupportedMediaType(String),
+    PreconditionFailed(String),
+    UnprocessableEntity(String),
+    InvalidPayload(Vec<FieldError>),
+    ServiceUnavailable(String),
+    Internal(String),
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::InvalidParameters(_) => f.write_str("Invalid request parameters"),
+            ApiError::InvalidPayload(_) => f.write_str("Invalid request body"),
+            ApiError::BadRequest(message)
+            | ApiError::Unauthorized(message)
+            | ApiError::Forbidden(message)
+            | ApiError::NotFound(message)
+            | ApiError::MethodNotAllowed(message)
+            | ApiError::Conflict(message)
+            | ApiError::PayloadTooLarge(message)
+            | ApiError::UnsupportedMediaType(message)
+            | ApiError::PreconditionFailed(message)
+            | ApiError::UnprocessableEntity(message)
+            | ApiError::ServiceUnavailable(message)
+            | ApiError::Internal(message) => f.write_str(message),
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) | ApiError::InvalidParameters(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::MethodNotAllowed(_) => StatusCode::METHOD_NOT_ALLOWED,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ApiError::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
+            ApiError::UnprocessableEntity(_) | ApiError::InvalidPayload(_) => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+            ApiError::ServiceUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        let mut response = HttpResponse::build(status);
+        match self {
+            ApiError::Unauthorized(_) => {
+                response.insert_header((header::WWW_AUTHENTICATE, "Bearer"));
+            }
+            ApiError::ServiceUnavailable(_) => {
+                response.insert_header((header::RETRY_AFTER, DB_RETRY_AFTER_SECS));
+            }
+            _ => {}
+        }
+
+        let errors = match self {
+            ApiError::InvalidParameters(errors) | ApiError::InvalidPayload(errors) => errors,
+            _ => &[][..],
+        };
+
+        response.json(ErrorResponse {
+            status: status.as_u16() as i16,
+            message: self.to_string(),
+            errors: errors.to_vec(),
+        })
+    }
+}
+
+impl From<DieselError> for ApiError {
+    fn from(err: DieselError) -> Self {
+        match err {
+            DieselError::NotFound => ApiError::NotFound("Resource not found".to_string()),
+            DieselError::DatabaseError(kind, info) => {
+                let detail = info.details().unwrap_or_else(|| info.message()).to_string();
+                match kind {
+                    // Postgres reports deleting a still referenced row as "update or delete on ..."
+                    DatabaseErrorKind::ForeignKeyViolation
+                        if info.message().starts_with("update or delete") =>
+                    {
+                        ApiError::Conflict(format!("Resource is still referenced. {}", detail))
+                    }
+                    DatabaseErrorKind::ForeignKeyViolation => ApiError::UnprocessableEntity(
+                        format!("Referenced resource does not exist. {}", detail),
+                    ),
+                    DatabaseErrorKind::UniqueViolation => {
+                        ApiError::Conflict(format!("Resource already exists. {}", detail))
+                    }
+                    DatabaseErrorKind::NotNullViolation | DatabaseErrorKind::CheckViolation => {
+                        ApiError::UnprocessableEntity(detail)
+                    }
+                    DatabaseErrorKind::ClosedConnection => {
+                        log::warn!("Database connection closed: {}", info.message());
+                        ApiError::ServiceUnavailable(
+                            "Database unavailable, please try again later".to_string(),
+                        )
+                    }
+                    _ => internal(info.message()),
+                }
+            }
+            err => internal(err),
+        }
+    }
+}
+
+impl From<diesel::r2d2::PoolError> for ApiError {
+    fn from(err: diesel::r2d2::PoolError) -> Self {
+        log::warn!("Could not get a database connection: {}", err);
+        ApiError::ServiceUnavailable("Database unavailable, please try again later".to_string())
+    }
+}
+
+impl From<BlockingError> for ApiError {
+    fn from(err: BlockingError) -> Self {
+        internal(err)
+    }
+}
+
+/// Logs the underlying error and hides its details from the client.
+pub fn internal(err: impl fmt::Display) -> ApiError {
+    log::error!("Internal error: {}", err);
+    ApiError::Internal("Internal server error".to_string())
+}
+
+/// Strong ETag identifying the version of a row, derived from its `updated_at`.
+pub fn etag(updated_at: &chrono::NaiveDateTime) -> EntityTag {
+    EntityTag::new_strong(format!("{:x}", updated_at.timestamp_micros()))
+}
+
+/// Fails with `412` unless `If-Match` is absent or matches the current version.
+pub fn check_if_match(
+    if_match: Option<&IfMatch>,
+    updated_at: &chrono::NaiveDateTime,
+) -> Result<(), ApiError> {
+    let current = etag(updated_at);
+    match if_match {
+        None | Some(IfMatch::Any) => Ok(()),
+        // The extractor yields an empty list when the header is absent
+        Some(IfMatch::Items(tags)) if tags.is_empty() => Ok(()),
+        Some(IfMatch::Items(tags)) if tags.iter().any(|tag| tag.strong_eq(&current)) => Ok(()),
+        Some(_) => Err(ApiError::PreconditionFailed(
+            "Resource has been modified in the meantime".to_string(),
+        )),
+    }
+}
+
+/// Whether the version cached by the client according to `If-None-Match` is current.
+pub fn not_modified(if_none_match: Option<&IfNoneMatch>, current: &EntityTag) -> bool {
+    match if_none_match {
+        None => false,
+        Some(IfNoneMatch::Any) => true,
+        Some(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(current)),
+    }
+}
+
+/// JSON body extractor which reports the path of the offending field when the body
+/// does not match the expected payload.
+///
+/// Reading and parsing the body is delegated to [`web::Json`], so its errors are
+/// handled by [`json_config`].
+#[derive(Debug)]
+pub struct Json<T>(pub T);
+
+impl<T> Deref for Json<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: DeserializeOwned + 'static> FromRequest for Json<T> {
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let value = web::Json::<serde_json::Value>::from_request(req, payload);
+
+        Box::pin(async move {
+            let value = value.await?.into_inner();
+            Ok(Json(from_value(value)?))
+        })
+    }
+}
+
+/// Deserializes a JSON body, reporting the path of the offending field on failure.
+pub fn from_value<T: DeserializeOwned>(value: serde_json::Value) -> Result<T, ApiError> {
+    serde_path_to_error::deserialize(value).map_err(|err| {
+        let path = match err.path().to_string().as_str() {
+            "." => None,
+            path => Some(path.to_string()),
+        };
+        let error = field_error(path, &err.into_inner().to_string(), "body");
+        ApiError::InvalidPayload(vec![error])
+    })
+}
+
+/// Deserializes a field of a patch which may be absent but must not be `null`.
+///
+/// Use together with `#[serde(default)]` so a missing field becomes `None`.
+pub fn non_null<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+/// Deserializes a nullable field of a patch, telling apart an absent field (`None`)
+/// from one explicitly set to `null` (`Some(None)`).
+///
+/// Use together with `#[serde(default)]`.
+pub fn nullable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+/// Builds a [`FieldError`] from a serde error message, appending the name of a
+/// missing or unknown field to `path`.
+fn field_error(path: Option<String>, message: &str, fallback: &str) -> FieldError {
+    let (code, named) = if message.starts_with("missing field") {
+        ("required", true)
+    } else if message.starts_with("unknown field") {
+        ("unknown_field", true)
+    } else if message.starts_with("duplicate field") {
+        ("duplicate_field", true)
+    } else if message.starts_with("invalid type") {
+        ("invalid_type", false)
+    } else {
+        ("invalid", false)
+    };
+
+    let name = message.split('`').nth(1).filter(|_| named);
+    let field = match (path, name) {
+        // Unknown fields are already part of the path
+        (Some(path), Some(name)) if path == name || path.ends_with(&format!(".{}", name)) => path,
+        (Some(path), Some(name)) => format!("{}.{}", path, name),
+        (Some(path), None) => path,
+        (None, Some(name)) => name.to_string(),
+        (None, None) => fallback.to_string(),
+    };
+
+    FieldError {
+        field,
+        code: code.to_string(),
+        message: message.to_string(),
+    }
+}
+
+pub fn json_config() -> web::JsonConfig {
+    web::JsonConfig::default().error_handler(|err, _req| {
+        let error = match err {
+            JsonPayloadError::OverflowKnownLength { .. } | JsonPayloadError::Overflow { .. } => {
+                ApiError::PayloadTooLarge(err.to_string())
+            }
+            JsonPayloadError::ContentType => ApiError::UnsupportedMediaType(
+                "Expected a body with content type application/json".to_string(),
+            ),
+            JsonPayloadError::Deserialize(err) => {
+                ApiError::BadRequest(format!("Malformed JSON: {}", err))
+            }
+            err => ApiError::BadRequest(err.to_string()),
+        };
+        error.into()
+    })
+}
+
+pub fn path_config() -> web::PathConfig {
+    web::PathConfig::default().error_handler(|err, req| {
+        let PathError::Deserialize(err) = err else {
+            return ApiError::BadRequest(err.to_string()).into();
+        };
+
+        // Only UUID parameters can fail, so the first one which does not parse is the culprit.
+        let params = req.match_info();
+        let field = params
+            .iter()
+            .filter(|(name, _)| !TEXT_PATH_PARAMS.contains(name))
+            .find(|(_, value)| uuid::Uuid::parse_str(value).is_err())
+            .or_else(|| params.iter().next())
+            .map(|(name, _)| name.to_string());
+
+        let error = field_error(field, &err.to_string(), "path");
+        ApiError::InvalidParameters(vec![error]).into()
+    })
+}
+
+pub fn query_config() -> web::QueryConfig {
+    web::QueryConfig::default().error_handler(|err, _req| {
+        let QueryPayloadError::Deserialize(err) = err else {
+            return ApiError::BadRequest(err.to_string()).into();
+        };
+
+        let error = field_error(None, &err.to_string(), "query");
+        ApiError::InvalidParameters(vec![error]).into()
+    })
+}
+
+/// Fallback for requests which did not match any route.
+///
+/// Routes are guarded by method, so a known path with the wrong method also ends up here.
+pub async fn default_service(req: HttpRequest) -> Result<HttpResponse, ApiError> {
+    if req.resource_map().has_resource(req.path()) && req.method() != Method::OPTIONS {
+        return Err(ApiError::MethodNotAllowed(format!(
+            "Method {} is not allowed for {}",
+            req.method(),
+            req.path()
+        )));
+    }
+
+    Err(ApiError::NotFound(format!("No route for {}", req.path())))
+}
@@ -0,0 +1,158 @@
+use std::future::{ready, Ready};
+
+use actix_web::{dev::Payload, FromRequest, HttpMessage, HttpRequest, HttpResponse};
+use diesel::prelude::*;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::auth::Identity;
+use crate::helpers::{self, ApiError, SuccessResponse};
+use crate::models::idempotency_key::{IdempotencyKey, NewIdempotencyKey};
+
+const HEADER: &str = "Idempotency-Key";
+const MAX_KEY_LENGTH: usize = 255;
+/// Hours a key is remembered, overridable with `IDEMPOTENCY_TTL_HOURS`.
+const DEFAULT_TTL_HOURS: i64 = 24;
+
+/// The optional `Idempotency-Key` header of a create request.
+///
+/// Keys are remembered per caller, so clients can't replay each other's responses.
+#[derive(Debug)]
+pub struct Key {
+    key: Option<String>,
+    actor: Option<String>,
+}
+
+impl FromRequest for Key {
+    type Error = ApiError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let actor = req.extensions().get::<Identity>().map(Identity::actor);
+        let key = match req.headers().get(HEADER).map(|value| value.to_str()) {
+            None => Ok(Key { key: None, actor }),
+            Some(Ok(key)) if !key.is_empty() && key.len() <= MAX_KEY_LENGTH => Ok(Key {
+                key: Some(key.to_string()),
+                actor,
+            }),
+            Some(_) => Err(ApiError::BadRequest(format!(
+                "{} must be between 1 and {} visible ASCII characters",
+                HEADER, MAX_KEY_LENGTH
+            ))),
+        };
+        ready(key)
+    }
+}
+
+/// Response body of a create request, possibly replayed from an earlier request.
+pub struct Created {
+    body: serde_json::Value,
+    replayed: bool,
+}
+
+impl Created {
+    pub fn respond(self) -> HttpResponse {
+        let mut response = HttpResponse::Created();
+        if self.replayed {
+            response.insert_header(("Idempotent-Replayed", "true"));
+        }
+        response.json(self.body)
+    }
+}
+
+/// Runs `create` at most once per idempotency key and `scope`.
+///
+/// The response is stored together with a hash of the request in the same transaction
+/// as the created resource. Retries with the same key and request get the stored
+/// response, reusing a key for a different request fails with `422`. `request` has to
+/// serialize every field of the request, including those skipped in responses.
+pub fn create_once<T: Serialize>(
+    key: &Key,
+    scope: &str,
+    request: &impl Serialize,
+    conn: &mut PgConnection,
+    create: impl FnOnce(&mut PgConnection) -> Result<T, ApiError>,
+) -> Result<Created, ApiError> {
+    let Some(idempotency_key) = key.key.as_deref() else {
+        return Ok(Created {
+            body: response_body(create(conn)?)?,
+            replayed: false,
+        });
+    };
+    let hash = request_hash(request)?;
+    let scope = match &key.actor {
+        Some(actor) => format!("{} {}", actor, scope),
+        None => scope.to_string(),
+    };
+
+    conn.transaction(|conn| {
+        use crate::schema::idempotency_keys::dsl;
+
+        let ttl_hours = std::env::var("IDEMPOTENCY_TTL_HOURS")
+            .ok()
+            .and_then(|hours| hours.parse().ok())
+            .unwrap_or(DEFAULT_TTL_HOURS);
+        let now = chrono::Local::now().naive_local();
+        diesel::delete(dsl::idempotency_keys)
+            .filter(dsl::created_at.lt(now - chrono::Duration::hours(ttl_hours)))
+            .execute(conn)?;
+
+        let stored = dsl::idempotency_keys
+            .find((scope.as_str(), idempotency_key))
+            .first::<IdempotencyKey>(conn)
+            .optional()?;
+        if let Some(stored) = stored {
+            if stored.request_hash != hash {
+                return Err(ApiError::UnprocessableEntity(format!(
+                    "{} was already used for a different request",
+                    HEADER
+                )));
+            }
+            return Ok(Created {
+                body: stored.response,
+                replayed: true,
+            });
+        }
+
+        let body = response_body(create(conn)?)?;
+        diesel::insert_into(dsl::idempotency_keys)
+            .values(&NewIdempotencyKey {
+                scope: &scope,
+                key: idempotency_key,
+                request_hash: &hash,
+                response: &body,
+                created_at: now,
+            })
+            .execute(conn)
+            .map_err(|err| match err {
+                // A concurrent request with the same key committed first
+                DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+                    ApiError::Conflict(format!(
+                        "A request with this {} is already being processed",
+                        HEADER
+                    ))
+                }
+                err => err.into(),
+            })?;
+
+        Ok(Created {
+            body,
+            replayed: false,
+        })
+    })
+}
+
+fn response_body<T: Serialize>(data: T) -> Result<serde_json::Value, ApiError> {
+    serde_json::to_value(SuccessResponse {
+        status: 201,
+        message: "Created".to_string(),
+        data,
+    })
+    .map_err(helpers::internal)
+}
+
+fn request_hash(request: &impl Serialize) -> Result<String, ApiError> {
+    let bytes = serde_json::to_vec(request).map_err(helpers::internal)?;
+    Ok(format!("{:x}", Sha256::digest(bytes)))
+}
@@ -0,0 +1,632 @@
+use super::DbPool;
+use actix_multipart::{Multipart, MultipartError};
+use actix_web::http::header::{HeaderValue, CACHE_CONTROL};
+use actix_web::{delete, get, post, web, HttpRequest, HttpResponse};
+use diesel::prelude::*;
+use futures_util::StreamExt;
+use image::{DynamicImage, ImageFormat, ImageOutputFormat};
+use std::io::{self, Cursor};
+use uuid::Uuid;
+
+use crate::audit;
+use crate::auth::Identity;
+use crate::helpers::{self, ApiError, FieldError, PaginatedResponse, SuccessResponse};
+use crate::includes::Resource;
+use crate::models::image::{Image, NewImage};
+use crate::nested::Parent;
+use crate::pagination::{paginate, Page, PageParams, Paginated};
+use crate::permissions;
+use crate::storage::{Object, Storage};
+
+/// Largest accepted upload, unless `UPLOAD_MAX_BYTES` is set.
+const DEFAULT_MAX_BYTES: usize = 10 * 1024 * 1024;
+/// Largest accepted width and height, which keeps decoding in bounded memory.
+const MAX_DIMENSION: u32 = 10_000;
+/// Longest side of thumbnails.
+const THUMBNAIL_SIZE: u32 = 320;
+const JPEG_QUALITY: u8 = 85;
+
+const SORTABLE: [&str; 1] = ["created_at"];
+
+/// What an image is a photo of.
+#[derive(Debug, Clone, Copy)]
+pub enum Subject {
+    Item(Uuid),
+    Machine(Uuid),
+    FoundItem(Uuid),
+}
+
+impl Subject {
+    fn of(image: &Image) -> Subject {
+        // The table requires exactly one of them.
+        match (image.item, image.found_item) {
+            (Some(item), _) => Subject::Item(item),
+            (None, Some(found_item)) => Subject::FoundItem(found_item),
+            (None, None) => Subject::Machine(image.machine.unwrap_or_default()),
+        }
+    }
+
+    fn parent(self) -> Parent {
+        match self {
+            Subject::Item(id) => Parent::Item(id),
+            Subject::Machine(id) => Parent::Machine(id),
+            Subject::FoundItem(id) => Parent::FoundItem(id),
+        }
+    }
+
+    /// Checks the caller may see the subject, and with it its images.
+    fn authorize_read(self, identity: &Identity, conn: &mut PgConnection) -> Result<(), ApiError> {
+        match self {
+            Subject::Item(id) => {
+                identity.require(permissions::ITEMS_READ)?;
+                let owner = item_owner(id, conn)?;
+                identity.require_property_of(|| permissions::user_property(owner, conn))
+            }
+            Subject::Machine(id) => {
+                identity.require(permissions::MACHINES_READ)?;
+                identity.require_property_of(|| permissions::resource_property(id, conn))
+            }
+            Subject::FoundItem(id) => {
+                identity.require(permissions::FOUND_ITEMS_READ)?;
+                let (property, _) = found_item_reporter(id, conn)?;
+                identity.require_property(Some(property))
+            }
+        }
+    }
+
+    /// Checks the caller may change the subject, and with it its images.
+    fn authorize_write(self, identity: &Identity, conn: &mut PgConnection) -> Result<(), ApiError> {
+        match self {
+            Subject::Item(id) => {
+                let owner = item_owner(id, conn)?;
+                identity.require_owner(&permissions::ITEMS_WRITE, owner)?;
+                identity.require_property_of(|| permissions::user_property(owner, conn))
+            }
+            Subject::Machine(id) => {
+                identity.require(permissions::MACHINES_WRITE)?;
+                identity.require_property(permissions::resource_property(id, conn)?)
+            }
+            Subject::FoundItem(id) => {
+                let (property, reporter) = found_item_reporter(id, conn)?;
+                identity.require_owner(&permissions::FOUND_ITEMS_WRITE, reporter)?;
+                identity.require_property(Some(property))
+            }
+        }
+    }
+}
+
+/// An upload ready to be stored, re-encoded without its metadata.
+struct Processed {
+    content_type: &'static str,
+    file: Vec<u8>,
+    thumbnail: Vec<u8>,
+    width: u32,
+    height: u32,
+}
+
+#[get("/items/{id}/images")]
+async fn item_index(
+    id: web::Path<Uuid>,
+    page: web::Query<PageParams>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    list(Subject::Item(id.into_inner()), &page, identity, pool).await
+}
+
+#[get("/machines/{id}/images")]
+async fn machine_index(
+    id: web::Path<Uuid>,
+    page: web::Query<PageParams>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    list(Subject::Machine(id.into_inner()), &page, identity, pool).await
+}
+
+#[get("/found-items/{id}/images")]
+async fn found_item_index(
+    id: web::Path<Uuid>,
+    page: web::Query<PageParams>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    list(Subject::FoundItem(id.into_inner()), &page, identity, pool).await
+}
+
+async fn list(
+    subject: Subject,
+    page: &PageParams,
+    identity: Identity,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, ApiError> {
+    let page = page.resolve(&SORTABLE)?;
+    let images = web::block(move || {
+        let mut conn = pool.get()?;
+        subject.parent().ensure_exists(&mut conn)?;
+        subject.authorize_read(&identity, &mut conn)?;
+        find_all(subject, &page, &mut conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(PaginatedResponse {
+        status: 200,
+        message: "OK".to_string(),
+        data: images.data,
+        meta: images.meta,
+    }))
+}
+
+/// Uploads a photo of an item as the `file` field of a `multipart/form-data` body.
+#[post("/items/{id}/images")]
+async fn item_create(
+    id: web::Path<Uuid>,
+    payload: Multipart,
+    pool: web::Data<DbPool>,
+    storage: web::Data<dyn Storage>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    upload(
+        Subject::Item(id.into_inner()),
+        payload,
+        identity,
+        storage,
+        pool,
+    )
+    .await
+}
+
+/// Uploads a photo of a machine as the `file` field of a `multipart/form-data` body.
+#[post("/machines/{id}/images")]
+async fn machine_create(
+    id: web::Path<Uuid>,
+    payload: Multipart,
+    pool: web::Data<DbPool>,
+    storage: web::Data<dyn Storage>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    upload(
+        Subject::Machine(id.into_inner()),
+        payload,
+        identity,
+        storage,
+        pool,
+    )
+    .await
+}
+
+/// Uploads a photo of a found item as the `file` field of a `multipart/form-data` body.
+#[post("/found-items/{id}/images")]
+async fn found_item_create(
+    id: web::Path<Uuid>,
+    payload: Multipart,
+    pool: web::Data<DbPool>,
+    storage: web::Data<dyn Storage>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    upload(
+        Subject::FoundItem(id.into_inner()),
+        payload,
+        identity,
+        storage,
+        pool,
+    )
+    .await
+}
+
+async fn upload(
+    subject: Subject,
+    payload: Multipart,
+    identity: Identity,
+    storage: web::Data<dyn Storage>,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, ApiError> {
+    // Checked before the upload is read, so unauthorized uploads are not processed.
+    let checked = pool.clone();
+    let identity = web::block(move || {
+        let mut conn = checked.get()?;
+        subject.parent().ensure_exists(&mut conn)?;
+        subject.authorize_write(&identity, &mut conn)?;
+        Ok::<_, ApiError>(identity)
+    })
+    .await??;
+
+    let bytes = read_file(payload).await?;
+    let image = web::block(move || {
+        let processed = process(&bytes)?;
+        let mut conn = pool.get()?;
+        add(subject, &processed, &identity, storage.as_ref(), &mut conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Created().json(SuccessResponse {
+        status: 201,
+        message: "Created".to_string(),
+        data: image,
+    }))
+}
+
+#[get("/images/{id}/file")]
+async fn show_file(
+    id: web::Path<Uuid>,
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    storage: web::Data<dyn Storage>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    serve(id.into_inner(), false, req, identity, storage, pool).await
+}
+
+#[get("/images/{id}/thumbnail")]
+async fn show_thumbnail(
+    id: web::Path<Uuid>,
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    storage: web::Data<dyn Storage>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    serve(id.into_inner(), true, req, identity, storage, pool).await
+}
+
+async fn serve(
+    image_id: Uuid,
+    is_thumbnail: bool,
+    req: HttpRequest,
+    identity: Identity,
+    storage: web::Data<dyn Storage>,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, ApiError> {
+    let (image, object) = web::block(move || {
+        let mut conn = pool.get()?;
+        let image = find_by_id(image_id, &mut conn)?
+            .ok_or_else(|| ApiError::NotFound("Image not found".to_string()))?;
+        Subject::of(&image).authorize_read(&identity, &mut conn)?;
+        let object = storage
+            .get(&key(image.id, is_thumbnail))
+            .map_err(|err| match err.kind() {
+                io::ErrorKind::NotFound => ApiError::NotFound("Image file not found".to_string()),
+                _ => helpers::internal(err),
+            })?;
+        Ok::<_, ApiError>((image, object))
+    })
+    .await??;
+
+    let content_type: mime::Mime = image.content_type.parse().map_err(helpers::internal)?;
+    let mut response = match object {
+        Object::File(path) => actix_files::NamedFile::open(path)
+            .map_err(helpers::internal)?
+            .set_content_type(content_type)
+            .use_last_modified(false)
+            .into_response(&req),
+        Object::Bytes(bytes) => HttpResponse::Ok().content_type(content_type).body(bytes),
+    };
+    // Files never change once uploaded, a new upload gets a new id.
+    response.headers_mut().insert(
+        CACHE_CONTROL,
+        HeaderValue::from_static("private, max-age=31536000"),
+    );
+    Ok(response)
+}
+
+#[delete("/images/{id}")]
+async fn destroy(
+    id: web::Path<Uuid>,
+    pool: web::Data<DbPool>,
+    storage: web::Data<dyn Storage>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    let count = web::block(move || {
+        let mut conn = pool.get()?;
+        delete(id.into_inner(), &identity, storage.as_ref(), &mut conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(SuccessResponse {
+        status: 200,
+        message: "Deleted".to_string(),
+        data: count,
+    }))
+}
+
+/// Collects the `file` field of a multipart body, failing once it exceeds
+/// `UPLOAD_MAX_BYTES`.
+async fn read_file(mut payload: Multipart) -> Result<Vec<u8>, ApiError> {
+    let max_bytes = std::env::var("UPLOAD_MAX_BYTES")
+        .ok()
+        .and_then(|bytes| bytes.parse().ok())
+        .unwrap_or(DEFAULT_MAX_BYTES);
+
+    while let Some(field) = payload.next().await {
+        let mut field = field.map_err(multipart_error)?;
+        if field.name() != Some("file") {
+            continue;
+        }
+
+        let mut bytes = Vec::new();
+        while let Some(chunk) = field.next().await {
+            let chunk = chunk.map_err(multipart_error)?;
+            if bytes.len() + chunk.len() > max_bytes {
+                return Err(ApiError::PayloadTooLarge(format!(
+                    "Files may be at most {} bytes",
+                    max_bytes
+                )));
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+        return Ok(bytes);
+    }
+
+    Err(ApiError::InvalidPayload(vec![FieldError {
+        field: "file".to_string(),
+        code: "required".to_string(),
+        message: "file is required".to_string(),
+    }]))
+}
+
+fn multipart_error(err: MultipartError) -> ApiError {
+    match err {
+        MultipartError::ContentTypeMissing
+        | MultipartError::ContentTypeParse
+        | MultipartError::ContentTypeIncompatible => {
+            ApiError::UnsupportedMediaType("Content type must be multipart/form-data".to_string())
+        }
+        err => ApiError::BadRequest(err.to_string()),
+    }
+}
+
+/// Sniffs the format from the content rather than trusting the client, turns the image
+/// upright and re-encodes it, which drops EXIF and other metadata such as GPS positions.
+fn process(bytes: &[u8]) -> Result<Processed, ApiError> {
+    let unsupported = || {
+        ApiError::UnsupportedMediaType(
+            "Only JPEG, PNG, GIF and WebP images are accepted".to_string(),
+        )
+    };
+    let format = image::guess_format(bytes).map_err(|_| unsupported())?;
+    if !matches!(
+        format,
+        ImageFormat::Jpeg | ImageFormat::Png | ImageFormat::Gif | ImageFormat::WebP
+    ) {
+        return Err(unsupported());
+    }
+
+    let mut limits = image::io::Limits::default();
+    limits.max_image_width = Some(MAX_DIMENSION);
+    limits.max_image_height = Some(MAX_DIMENSION);
+    let mut reader = image::io::Reader::with_format(Cursor::new(bytes), format);
+    reader.limits(limits);
+    let decoded = reader.decode().map_err(|err| {
+        ApiError::UnprocessableEntity(format!("Image could not be decoded: {}", err))
+    })?;
+    let upright = orient(decoded, orientation(bytes));
+
+    // Photos become JPEG, anything which may be transparent stays lossless.
+    let lossless = format == ImageFormat::Png || upright.color().has_alpha();
+    let (content_type, output) = if lossless {
+        ("image/png", ImageOutputFormat::Png)
+    } else {
+        ("image/jpeg", ImageOutputFormat::Jpeg(JPEG_QUALITY))
+    };
+    let encode = |image: &DynamicImage| {
+        let mut encoded = Cursor::new(Vec::new());
+        let result = if lossless {
+            image.write_to(&mut encoded, output.clone())
+        } else {
+            DynamicImage::ImageRgb8(image.to_rgb8()).write_to(&mut encoded, output.clone())
+        };
+        result
+            .map(|_| encoded.into_inner())
+            .map_err(helpers::internal)
+    };
+
+    Ok(Processed {
+        content_type,
+        file: encode(&upright)?,
+        thumbnail: encode(&shrink(&upright))?,
+        width: upright.width(),
+        height: upright.height(),
+    })
+}
+
+/// The EXIF orientation of a photo, 1 (upright) if it has none.
+fn orientation(bytes: &[u8]) -> u32 {
+    exif::Reader::new()
+        .read_from_container(&mut Cursor::new(bytes))
+        .ok()
+        .and_then(|exif| {
+            exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)
+                .and_then(|field| field.value.get_uint(0))
+        })
+        .unwrap_or(1)
+}
+
+fn orient(image: DynamicImage, orientation: u32) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
+
+/// Scales the image down to fit a thumbnail, small images are kept as they are.
+fn shrink(image: &DynamicImage) -> DynamicImage {
+    let thumbnail = if image.width() > THUMBNAIL_SIZE || image.height() > THUMBNAIL_SIZE {
+        image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE)
+    } else {
+        image.clone()
+    };
+    // Thumbnails are for screens, 8 bits per channel are plenty.
+    DynamicImage::ImageRgba8(thumbnail.to_rgba8())
+}
+
+pub fn key(image_id: Uuid, is_thumbnail: bool) -> String {
+    if is_thumbnail {
+        format!("images/{}-thumbnail", image_id)
+    } else {
+        format!("images/{}", image_id)
+    }
+}
+
+fn add(
+    subject: Subject,
+    processed: &Processed,
+    identity: &Identity,
+    storage: &dyn Storage,
+    conn: &mut PgConnection,
+) -> Result<Image, ApiError> {
+    use crate::schema::images::dsl::*;
+
+    let new_image = NewImage {
+        id: Uuid::new_v4(),
+        item: match subject {
+            Subject::Item(item_id) => Some(item_id),
+            _ => None,
+        },
+        machine: match subject {
+            Subject::Machine(machine_id) => Some(machine_id),
+            _ => None,
+        },
+        found_item: match subject {
+            Subject::FoundItem(found_item_id) => Some(found_item_id),
+            _ => None,
+        },
+        uploader: identity.user.id,
+        content_type: processed.content_type,
+        size: i32::try_from(processed.file.len()).map_err(helpers::internal)?,
+        width: processed.width as i32,
+        height: processed.height as i32,
+        created_at: chrono::Local::now().naive_local(),
+    };
+
+    // The files are stored last, so a failure to store them leaves no row behind.
+    conn.transaction(|conn| {
+        let res = diesel::insert_into(images)
+            .values(&new_image)
+            .returning(images::all_columns())
+            .get_result::<Image>(conn)?;
+        audit::created(&identity.actor(), Resource::Image, res.id, &res, conn)?;
+        storage
+            .put(
+                &key(res.id, true),
+                processed.content_type,
+                &processed.thumbnail,
+            )
+            .and_then(|_| storage.put(&key(res.id, false), processed.content_type, &processed.file))
+            .map_err(helpers::internal)?;
+        Ok(res)
+    })
+}
+
+fn find_all(
+    subject: Subject,
+    page: &Page,
+    conn: &mut PgConnection,
+) -> Result<Paginated<Image>, ApiError> {
+    use crate::schema::images::dsl::*;
+
+    let filtered = || {
+        let query = images.into_boxed();
+        match subject {
+            Subject::Item(item_id) => query.filter(item.eq(item_id)),
+            Subject::Machine(machine_id) => query.filter(machine.eq(machine_id)),
+            Subject::FoundItem(found_item_id) => query.filter(found_item.eq(found_item_id)),
+        }
+    };
+
+    let total = filtered().count().get_result(conn)?;
+    let rows = paginate!(filtered(), page, created_at, id, chrono::NaiveDateTime).load(conn)?;
+
+    Ok(page.finish(rows, total))
+}
+
+fn find_by_id(image_id: Uuid, conn: &mut PgConnection) -> Result<Option<Image>, ApiError> {
+    use crate::schema::images::dsl::*;
+
+    let image = images
+        .filter(id.eq(image_id))
+        .first::<Image>(conn)
+        .optional()?;
+
+    Ok(image)
+}
+
+/// Ids of the images of `subject`, to remove their files with [`remove_files`] once
+/// deleting the subject removed their rows.
+pub fn ids_of(subject: Subject, conn: &mut PgConnection) -> Result<Vec<Uuid>, ApiError> {
+    use crate::schema::images::dsl::*;
+
+    let query = images.select(id).into_boxed();
+    let ids = match subject {
+        Subject::Item(item_id) => query.filter(item.eq(item_id)),
+        Subject::Machine(machine_id) => query.filter(machine.eq(machine_id)),
+        Subject::FoundItem(found_item_id) => query.filter(found_item.eq(found_item_id)),
+    }
+    .load::<Uuid>(conn)?;
+
+    Ok(ids)
+}
+
+/// Removes the stored files and thumbnails of images.
+pub fn remove_files(image_ids: &[Uuid], storage: &dyn Storage) -> Result<(), ApiError> {
+    for image_id in image_ids {
+        storage
+            .delete(&key(*image_id, false))
+            .and_then(|_| storage.delete(&key(*image_id, true)))
+            .map_err(helpers::internal)?;
+    }
+    Ok(())
+}
+
+fn item_owner(item_id: Uuid, conn: &mut PgConnection) -> Result<Uuid, ApiError> {
+    use crate::schema::items::dsl::*;
+
+    items
+        .find(item_id)
+        .select(owner)
+        .first::<Uuid>(conn)
+        .optional()?
+        .ok_or_else(|| ApiError::NotFound("Item not found".to_string()))
+}
+
+fn found_item_reporter(
+    found_item_id: Uuid,
+    conn: &mut PgConnection,
+) -> Result<(Uuid, Uuid), ApiError> {
+    use crate::schema::found_items::dsl::*;
+
+    found_items
+        .find(found_item_id)
+        .select((property, reporter))
+        .first::<(Uuid, Uuid)>(conn)
+        .optional()?
+        .ok_or_else(|| ApiError::NotFound("Found item not found".to_string()))
+}
+
+fn delete(
+    image_id: Uuid,
+    identity: &Identity,
+    storage: &dyn Storage,
+    conn: &mut PgConnection,
+) -> Result<usize, ApiError> {
+    use crate::schema::images::dsl::*;
+
+    conn.transaction(|conn| {
+        let current = images
+            .find(image_id)
+            .for_update()
+            .first::<Image>(conn)
+            .optional()?
+            .ok_or_else(|| ApiError::NotFound("Image not found".to_string()))?;
+        Subject::of(&current).authorize_write(identity, conn)?;
+
+        let count = diesel::delete(images.find(image_id)).execute(conn)?;
+        audit::deleted(&identity.actor(), Resource::Image, image_id, &current, conn)?;
+        remove_files(&[image_id], storage)?;
+        Ok(count)
+    })
+}
@@ -0,0 +1,418 @@
+use std::collections::{BTreeMap, HashMap};
+
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::auth::Identity;
+use crate::helpers::{self, ApiError, FieldError};
+use crate::models::{
+    bookable::Bookable,
+    item::Item,
+    machine::{self, Machine},
+    property::Property,
+    reservation::Reservation,
+    role::Role,
+    user::User,
+};
+use crate::pagination::Paginated;
+use crate::permissions;
+
+/// The `include` query parameter, a comma separated list of relations to embed,
+/// e.g. `include=resource,owner,resource.property`.
+#[derive(Debug, Deserialize)]
+pub struct IncludeParams {
+    pub include: Option<String>,
+}
+
+/// Resources which can be embedded or embed others.
+#[derive(Debug, Clone, Copy)]
+pub enum Resource {
+    User,
+    Role,
+    Property,
+    Machine,
+    Bookable,
+    Kind,
+    Reservation,
+    Item,
+    FoundItem,
+    Image,
+    Loan,
+    Participant,
+}
+
+impl Resource {
+    pub const ALL: [Resource; 12] = [
+        Resource::User,
+        Resource::Role,
+        Resource::Property,
+        Resource::Machine,
+        Resource::Bookable,
+        Resource::Kind,
+        Resource::Reservation,
+        Resource::Item,
+        Resource::FoundItem,
+        Resource::Image,
+        Resource::Loan,
+        Resource::Participant,
+    ];
+
+    /// Name of the resource in paths and in the audit log.
+    pub fn name(self) -> &'static str {
+        match self {
+            Resource::User => "users",
+            Resource::Role => "roles",
+            Resource::Property => "properties",
+            Resource::Machine => "machines",
+            Resource::Bookable => "resources",
+            Resource::Kind => "resource_kinds",
+            Resource::Reservation => "reservations",
+            Resource::Item => "items",
+            Resource::FoundItem => "found_items",
+            Resource::Image => "images",
+            Resource::Loan => "loans",
+            Resource::Participant => "participants",
+        }
+    }
+
+    /// Permission needed to read the resource, and so to embed it.
+    fn read_permission(self) -> &'static str {
+        match self {
+            Resource::User => permissions::USERS_READ,
+            Resource::Role => permissions::ROLES_READ,
+            Resource::Property => permissions::PROPERTIES_READ,
+            Resource::Machine => permissions::MACHINES_READ,
+            Resource::Bookable | Resource::Kind => permissions::RESOURCES_READ,
+            Resource::Reservation | Resource::Participant => permissions::RESERVATIONS_READ,
+            Resource::Item | Resource::Image => permissions::ITEMS_READ,
+            Resource::FoundItem => permissions::FOUND_ITEMS_READ,
+            Resource::Loan => permissions::LOANS_READ,
+        }
+    }
+
+    /// Resource referenced by the foreign key `field`, mirroring `joinable!` in the schema.
+    fn relation(self, field: &str) -> Option<Resource> {
+        match (self, field) {
+            (Resource::User, "role") => Some(Resource::Role),
+            (Resource::User, "property") => Some(Resource::Property),
+            (Resource::Property, "owner") => Some(Resource::User),
+            (Resource::Machine, "property") => Some(Resource::Property),
+            (Resource::Bookable, "property") => Some(Resource::Property),
+            (Resource::Reservation, "resource") => Some(Resource::Bookable),
+            (Resource::Reservation, "machine") => Some(Resource::Bookable),
+            (Resource::Reservation, "owner") => Some(Resource::User),
+            (Resource::Item, "owner") => Some(Resource::User),
+            (Resource::Item, "holder") => Some(Resource::User),
+            (Resource::FoundItem, "property") => Some(Resource::Property),
+            (Resource::FoundItem, "reporter") => Some(Resource::User),
+            (Resource::FoundItem, "machine") => Some(Resource::Machine),
+            (Resource::FoundItem, "item") => Some(Resource::Item),
+            (Resource::FoundItem, "claimed_by") => Some(Resource::User),
+            (Resource::Image, "item") => Some(Resource::Item),
+            (Resource::Image, "machine") => Some(Resource::Machine),
+            (Resource::Image, "uploader") => Some(Resource::User),
+            (Resource::Loan, "item") => Some(Resource::Item),
+            (Resource::Loan, "borrower") => Some(Resource::User),
+            _ => None,
+        }
+    }
+
+    /// Loads the rows with the given ids, only those of `scope` when set. Roles are
+    /// not bound to a property.
+    fn load(
+        self,
+        ids: &[Uuid],
+        scope: Option<Uuid>,
+        conn: &mut PgConnection,
+    ) -> Result<Vec<Value>, ApiError> {
+        use crate::schema::{items, properties, reservations, resources, roles, users};
+
+        match self {
+            Resource::User => {
+                let mut query = users::table.filter(users::id.eq_any(ids)).into_boxed();
+                if let Some(property_id) = scope {
+                    query = query.filter(users::property.eq(property_id));
+                }
+                to_values(query.load::<User>(conn)?)
+            }
+            Resource::Role => to_values(
+                roles::table
+                    .filter(roles::id.eq_any(ids))
+                    .load::<Role>(conn)?,
+            ),
+            Resource::Property => {
+                let mut query = properties::table
+                    .filter(properties::id.eq_any(ids))
+                    .into_boxed();
+                if let Some(property_id) = scope {
+                    query = query.filter(properties::id.eq(property_id));
+                }
+                to_values(query.load::<Property>(conn)?)
+            }
+            Resource::Machine => {
+                let mut query = resources::table
+                    .filter(resources::id.eq_any(ids))
+                    .select(machine::COLUMNS)
+                    .into_boxed();
+                if let Some(property_id) = scope {
+                    query = query.filter(resources::property.eq(property_id));
+                }
+                to_values(query.load::<Machine>(conn)?)
+            }
+            Resource::Bookable => {
+                let mut query = resources::table
+                    .filter(resources::id.eq_any(ids))
+                    .into_boxed();
+                if let Some(property_id) = scope {
+                    query = query.filter(resources::property.eq(property_id));
+                }
+                to_values(query.load::<Bookable>(conn)?)
+            }
+            Resource::Reservation => {
+                let mut query = reservations::table
+                    .filter(reservations::id.eq_any(ids))
+                    .into_boxed();
+                if let Some(property_id) = scope {
+                    query = query.filter(
+                        reservations::resource.eq_any(
+                            resources::table
+                                .filter(resources::property.eq(property_id))
+                                .select(resources::id),
+                        ),
+                    );
+                }
+                to_values(query.load::<Reservation>(conn)?)
+            }
+            Resource::Item => {
+                let mut query = items::table.filter(items::id.eq_any(ids)).into_boxed();
+                if let Some(property_id) = scope {
+                    query = query.filter(
+                        items::owner.eq_any(
+                            users::table
+                                .filter(users::property.eq(property_id))
+                                .select(users::id),
+                        ),
+                    );
+                }
+                to_values(query.load::<Item>(conn)?)
+            }
+            // Nothing references found items, images, loans or participants, and
+            // resources reference their kind by name
+            Resource::FoundItem
+            | Resource::Image
+            | Resource::Loan
+            | Resource::Participant
+            | Resource::Kind => Ok(Vec::new()),
+        }
+    }
+}
+
+/// Relations to embed for a caller. Embedded rows outside of the property an API key
+/// is restricted to are left as ids.
+#[derive(Debug, Default)]
+pub struct Includes {
+    tree: Tree,
+    scope: Option<Uuid>,
+}
+
+impl Includes {
+    /// Parses and validates the `include` parameter for `resource`, failing with `403`
+    /// when the caller may not read an included resource.
+    pub fn parse(
+        param: Option<&str>,
+        resource: Resource,
+        identity: &Identity,
+    ) -> Result<Self, ApiError> {
+        let tree = Tree::parse(param, resource)?;
+        tree.authorize(resource, identity)?;
+
+        Ok(Includes {
+            tree,
+            scope: identity.property_scope(),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.0.is_empty()
+    }
+
+    /// Serializes `rows` and replaces included foreign keys with the referenced objects.
+    ///
+    /// Each relation is loaded with a single query for all rows.
+    pub fn embed<T: Serialize>(
+        &self,
+        resource: Resource,
+        rows: Vec<T>,
+        conn: &mut PgConnection,
+    ) -> Result<Vec<Value>, ApiError> {
+        let mut values = to_values(rows)?;
+        self.tree
+            .embed_values(resource, &mut values, self.scope, conn)?;
+        Ok(values)
+    }
+
+    pub fn embed_one<T: Serialize>(
+        &self,
+        resource: Resource,
+        row: &T,
+        conn: &mut PgConnection,
+    ) -> Result<Value, ApiError> {
+        let mut values = self.embed(resource, vec![row], conn)?;
+        Ok(values.pop().unwrap_or_default())
+    }
+
+    pub fn embed_page<T: Serialize>(
+        &self,
+        resource: Resource,
+        page: Paginated<T>,
+        conn: &mut PgConnection,
+    ) -> Result<Paginated<Value>, ApiError> {
+        Ok(Paginated {
+            data: self.embed(resource, page.data, conn)?,
+            meta: page.meta,
+        })
+    }
+}
+
+/// Relations to embed, as a tree of foreign key fields.
+#[derive(Debug, Default, PartialEq)]
+struct Tree(BTreeMap<String, Tree>);
+
+impl Tree {
+    fn parse(param: Option<&str>, resource: Resource) -> Result<Self, ApiError> {
+        let mut tree = Tree::default();
+
+        for path in param.unwrap_or_default().split(',').map(str::trim) {
+            if path.is_empty() {
+                continue;
+            }
+
+            let mut node = &mut tree;
+            let mut current = resource;
+            for field in path.split('.') {
+                current = current.relation(field).ok_or_else(|| {
+                    ApiError::InvalidParameters(vec![FieldError {
+                        field: "include".to_string(),
+                        code: "invalid".to_string(),
+                        message: format!("{} cannot be included", path),
+                    }])
+                })?;
+                node = node.0.entry(field.to_string()).or_default();
+            }
+        }
+
+        Ok(tree)
+    }
+
+    fn authorize(&self, resource: Resource, identity: &Identity) -> Result<(), ApiError> {
+        for (field, nested) in &self.0 {
+            if let Some(related) = resource.relation(field) {
+                identity.require(related.read_permission())?;
+                nested.authorize(related, identity)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn embed_values(
+        &self,
+        resource: Resource,
+        values: &mut [Value],
+        scope: Option<Uuid>,
+        conn: &mut PgConnection,
+    ) -> Result<(), ApiError> {
+        for (field, nested) in &self.0 {
+            let Some(related) = resource.relation(field) else {
+                continue;
+            };
+
+            let mut ids: Vec<Uuid> = values
+                .iter()
+                .filter_map(|value| value.get(field)?.as_str()?.parse().ok())
+                .collect();
+            ids.sort_unstable();
+            ids.dedup();
+
+            let mut objects = related.load(&ids, scope, conn)?;
+            nested.embed_values(related, &mut objects, scope, conn)?;
+            let by_id: HashMap<String, Value> = objects
+                .into_iter()
+                .filter_map(|object| Some((object.get("id")?.as_str()?.to_string(), object)))
+                .collect();
+
+            for value in values.iter_mut() {
+                let object = value
+                    .get(field)
+                    .and_then(Value::as_str)
+                    .and_then(|id| by_id.get(id));
+                if let Some(object) = object.cloned() {
+                    value[field.as_str()] = object;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn to_values<T: Serialize>(rows: Vec<T>) -> Result<Vec<Value>, ApiError> {
+    rows.into_iter()
+        .map(|row| serde_json::to_value(row).map_err(helpers::internal))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree(entries: Vec<(&str, Tree)>) -> Tree {
+        Tree(
+            entries
+                .into_iter()
+                .map(|(field, nested)| (field.to_string(), nested))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn parses_nested_paths_into_a_tree() {
+        let parsed = Tree::parse(
+            Some("owner, resource.property,resource,,owner.role"),
+            Resource::Reservation,
+        )
+        .unwrap();
+        let expected = tree(vec![
+            ("owner", tree(vec![("role", Tree::default())])),
+            ("resource", tree(vec![("property", Tree::default())])),
+        ]);
+        assert_eq!(parsed, expected);
+    }
+
+    #[test]
+    fn parses_the_former_name_of_a_reservations_resource() {
+        let parsed = Tree::parse(Some("machine.property"), Resource::Reservation).unwrap();
+        let expected = tree(vec![("machine", tree(vec![("property", Tree::default())]))]);
+        assert_eq!(parsed, expected);
+    }
+
+    #[test]
+    fn parses_a_missing_parameter_as_nothing() {
+        assert_eq!(Tree::parse(None, Resource::Item).unwrap(), Tree::default());
+        assert_eq!(
+            Tree::parse(Some(""), Resource::Item).unwrap(),
+            Tree::default()
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_relations() {
+        for param in ["color", "owner.email", "owner..role", "resource.owner"] {
+            assert!(
+                Tree::parse(Some(param), Resource::Reservation).is_err(),
+                "{param}"
+            );
+        }
+    }
+}
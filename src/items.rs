@@ -1,64 +1,261 @@
 use super::DbPool;
-use actix_web::{delete, get, post, put, web, Error, HttpResponse};
+use actix_web::http::header::{ETag, IfMatch, IfNoneMatch};
+use actix_web::{delete, get, patch, post, put, web, HttpResponse};
 use diesel::prelude::*;
+use serde::Deserialize;
 use uuid::Uuid;
 
-use crate::helpers::{ErrorResponse, SuccessResponse};
-use crate::models::item::{Item, ItemPayload, NewItem};
+use crate::audit;
+use crate::auth::Identity;
+use crate::helpers::{self, ApiError, FieldError, Json, PaginatedResponse, SuccessResponse};
+use crate::idempotency;
+use crate::images::{self, Subject};
+use crate::includes::{IncludeParams, Includes, Resource};
+use crate::models::item::{self, Item, ItemPatch, ItemPayload, NewItem};
+use crate::nested::{self, Parent};
+use crate::pagination::{paginate, Page, PageParams, Paginated};
+use crate::permissions;
+use crate::storage::Storage;
+use crate::validation::Validate;
 
-type DbError = Box<dyn std::error::Error + Send + Sync>;
+#[derive(Debug, Deserialize)]
+struct Filters {
+    owner: Option<Uuid>,
+    size: Option<String>,
+    /// Comma separated, items having all of these colors.
+    colors: Option<String>,
+    fabric: Option<String>,
+    /// Items which may be washed at this temperature.
+    washable_at: Option<i16>,
+    tumble_dry: Option<bool>,
+    delicate: Option<bool>,
+    /// Items washed in the reservation.
+    reservation: Option<Uuid>,
+    /// Property an API key is restricted to.
+    #[serde(skip)]
+    property: Option<Uuid>,
+}
+
+const SORTABLE: [&str; 4] = ["created_at", "updated_at", "name", "size"];
 
 #[get("/items")]
-async fn index(pool: web::Data<DbPool>) -> Result<HttpResponse, Error> {
+async fn index(
+    page: web::Query<PageParams>,
+    filters: web::Query<Filters>,
+    include: web::Query<IncludeParams>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::ITEMS_READ)?;
+
+    let mut filters = filters.into_inner();
+    filters.property = identity.property_scope();
+    list(None, &page, filters, &include, &identity, pool).await
+}
+
+#[get("/users/{id}/items")]
+async fn user_index(
+    id: web::Path<Uuid>,
+    page: web::Query<PageParams>,
+    filters: web::Query<Filters>,
+    include: web::Query<IncludeParams>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::ITEMS_READ)?;
+
+    let parent = Parent::User(id.into_inner());
+    let mut filters = filters.into_inner();
+    filters.owner = Some(parent.id());
+    filters.property = identity.property_scope();
+    list(Some(parent), &page, filters, &include, &identity, pool).await
+}
+
+/// The items washed in a reservation, e.g. to see what is in a machine.
+#[get("/reservations/{id}/items")]
+async fn reservation_index(
+    id: web::Path<Uuid>,
+    page: web::Query<PageParams>,
+    filters: web::Query<Filters>,
+    include: web::Query<IncludeParams>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::ITEMS_READ)?;
+    identity.require(permissions::RESERVATIONS_READ)?;
+
+    let parent = Parent::Reservation(id.into_inner());
+    let mut filters = filters.into_inner();
+    filters.reservation = Some(parent.id());
+    filters.property = identity.property_scope();
+    list(Some(parent), &page, filters, &include, &identity, pool).await
+}
+
+async fn list(
+    parent: Option<Parent>,
+    page: &PageParams,
+    filters: Filters,
+    include: &IncludeParams,
+    identity: &Identity,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, ApiError> {
+    let page = page.resolve(&SORTABLE)?;
+    let includes = Includes::parse(include.include.as_deref(), Resource::Item, identity)?;
     let items = web::block(move || {
         let mut conn = pool.get()?;
-        find_all(&mut conn)
+        if let Some(parent) = parent {
+            parent.ensure_exists(&mut conn)?;
+        }
+        let items = find_all(&filters, &page, &mut conn)?;
+        includes.embed_page(Resource::Item, items, &mut conn)
     })
-    .await?
-    .map_err(actix_web::error::ErrorInternalServerError)?;
+    .await??;
 
-    Ok(HttpResponse::Ok().json(SuccessResponse {
+    Ok(HttpResponse::Ok().json(PaginatedResponse {
         status: 200,
         message: "OK".to_string(),
-        data: items,
+        data: items.data,
+        meta: items.meta,
     }))
 }
 
 #[post("/items")]
 async fn create(
     pool: web::Data<DbPool>,
-    payload: web::Json<ItemPayload>,
-) -> Result<HttpResponse, Error> {
-    let item = web::block(move || {
+    payload: Json<ItemPayload>,
+    key: idempotency::Key,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    insert(
+        None,
+        identity,
+        "POST /items".to_string(),
+        payload.0,
+        key,
+        pool,
+    )
+    .await
+}
+
+#[post("/users/{id}/items")]
+async fn user_create(
+    id: web::Path<Uuid>,
+    pool: web::Data<DbPool>,
+    body: Json<serde_json::Value>,
+    key: idempotency::Key,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    let parent = Parent::User(id.into_inner());
+    let payload = nested::payload(body.0, "owner", &parent)?;
+    let scope = format!("POST /users/{}/items", parent.id());
+    insert(Some(parent), identity, scope, payload, key, pool).await
+}
+
+async fn insert(
+    parent: Option<Parent>,
+    identity: Identity,
+    scope: String,
+    payload: ItemPayload,
+    key: idempotency::Key,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, ApiError> {
+    identity.require_owner(&permissions::ITEMS_WRITE, payload.owner)?;
+
+    payload.validate()?;
+
+    let created = web::block(move || {
         let mut conn = pool.get()?;
-        add(&payload, &mut conn)
+        if let Some(parent) = parent {
+            parent.ensure_exists(&mut conn)?;
+        }
+        identity.require_property_of(|| permissions::user_property(payload.owner, &mut conn))?;
+        idempotency::create_once(&key, &scope, &payload, &mut conn, |conn| {
+            add(&payload, &identity, conn)
+        })
     })
-    .await?
-    .map_err(actix_web::error::ErrorInternalServerError)?;
+    .await??;
 
-    Ok(HttpResponse::Created().json(SuccessResponse {
-        status: 201,
-        message: "Created".to_string(),
-        data: item,
-    }))
+    Ok(created.respond())
 }
 
 #[get("/items/{id}")]
-async fn show(id: web::Path<Uuid>, pool: web::Data<DbPool>) -> Result<HttpResponse, Error> {
+async fn show(
+    id: web::Path<Uuid>,
+    include: web::Query<IncludeParams>,
+    if_none_match: Option<web::Header<IfNoneMatch>>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::ITEMS_READ)?;
+
+    let includes = Includes::parse(include.include.as_deref(), Resource::Item, &identity)?;
+    let embedding = !includes.is_empty();
     let item = web::block(move || {
         let mut conn = pool.get()?;
-        find_by_id(id.into_inner(), &mut conn)
+        let item = find_by_id(id.into_inner(), &mut conn)?;
+        if let Some(item) = &item {
+            identity.require_property_of(|| permissions::user_property(item.owner, &mut conn))?;
+        }
+        item.map(|item| {
+            let data = includes.embed_one(Resource::Item, &item, &mut conn)?;
+            Ok::<_, ApiError>((item.updated_at, data))
+        })
+        .transpose()
     })
-    .await?
-    .map_err(actix_web::error::ErrorInternalServerError)?;
+    .await??;
 
-    if item.is_none() {
-        return Ok(HttpResponse::NotFound().json(ErrorResponse {
-            status: 404,
-            message: "Item not found".to_string(),
+    let Some((updated_at, item)) = item else {
+        return Err(ApiError::NotFound("Item not found".to_string()));
+    };
+
+    // Embedded resources change independently of this one, so only the plain
+    // representation is cacheable by its version.
+    if embedding {
+        return Ok(HttpResponse::Ok().json(SuccessResponse {
+            status: 200,
+            message: "OK".to_string(),
+            data: item,
         }));
     }
 
+    let etag = helpers::etag(&updated_at);
+    if helpers::not_modified(if_none_match.as_deref(), &etag) {
+        return Ok(HttpResponse::NotModified()
+            .insert_header(ETag(etag))
+            .finish());
+    }
+
+    Ok(HttpResponse::Ok()
+        .insert_header(ETag(etag))
+        .json(SuccessResponse {
+            status: 200,
+            message: "OK".to_string(),
+            data: item,
+        }))
+}
+
+/// Finds the item a scanned or typed label belongs to, so it can be returned to its owner.
+#[get("/items/by-tag/{code}")]
+async fn show_by_tag(
+    code: web::Path<String>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::ITEMS_READ)?;
+
+    let not_found = || ApiError::NotFound("Item not found".to_string());
+    let code = normalize_tag(&code).ok_or_else(not_found)?;
+    let item = web::block(move || {
+        let mut conn = pool.get()?;
+        let item = find_by_tag(&code, &mut conn)?;
+        if let Some(item) = &item {
+            identity.require_property_of(|| permissions::user_property(item.owner, &mut conn))?;
+        }
+        Ok::<_, ApiError>(item)
+    })
+    .await??
+    .ok_or_else(not_found)?;
+
     Ok(HttpResponse::Ok().json(SuccessResponse {
         status: 200,
         message: "OK".to_string(),
@@ -69,28 +266,82 @@ async fn show(id: web::Path<Uuid>, pool: web::Data<DbPool>) -> Result<HttpRespon
 #[put("/items/{id}")]
 async fn update(
     id: web::Path<Uuid>,
-    payload: web::Json<ItemPayload>,
+    payload: Json<ItemPayload>,
+    if_match: Option<web::Header<IfMatch>>,
     pool: web::Data<DbPool>,
-) -> Result<HttpResponse, Error> {
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    payload.validate()?;
+
     let item = web::block(move || {
         let mut conn = pool.get()?;
-        update_by_id(id.into_inner(), &payload, &mut conn)
+        update_by_id(
+            id.into_inner(),
+            &payload,
+            if_match.as_deref(),
+            &identity,
+            &mut conn,
+        )
     })
-    .await?
-    .map_err(actix_web::error::ErrorInternalServerError)?;
+    .await??;
 
-    Ok(HttpResponse::Ok().json(SuccessResponse {
-        status: 200,
-        message: "OK".to_string(),
-        data: item,
-    }))
+    let etag = helpers::etag(&item.updated_at);
+    Ok(HttpResponse::Ok()
+        .insert_header(ETag(etag))
+        .json(SuccessResponse {
+            status: 200,
+            message: "OK".to_string(),
+            data: item,
+        }))
+}
+
+#[patch("/items/{id}")]
+async fn partial_update(
+    id: web::Path<Uuid>,
+    changes: Json<ItemPatch>,
+    if_match: Option<web::Header<IfMatch>>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    let item = web::block(move || {
+        let mut conn = pool.get()?;
+        patch_by_id(
+            id.into_inner(),
+            changes.0,
+            if_match.as_deref(),
+            &identity,
+            &mut conn,
+        )
+    })
+    .await??;
+
+    let etag = helpers::etag(&item.updated_at);
+    Ok(HttpResponse::Ok()
+        .insert_header(ETag(etag))
+        .json(SuccessResponse {
+            status: 200,
+            message: "OK".to_string(),
+            data: item,
+        }))
 }
 
 #[delete("/items/{id}")]
-async fn destroy(id: web::Path<Uuid>, pool: web::Data<DbPool>) -> Result<HttpResponse, Error> {
+async fn destroy(
+    id: web::Path<Uuid>,
+    if_match: Option<web::Header<IfMatch>>,
+    pool: web::Data<DbPool>,
+    storage: web::Data<dyn Storage>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
     let result = web::block(move || {
         let mut conn = pool.get()?;
-        delete(id.into_inner(), &mut conn)
+        delete(
+            id.into_inner(),
+            if_match.as_deref(),
+            &identity,
+            storage.as_ref(),
+            &mut conn,
+        )
     })
     .await?
     .map(|item| {
@@ -99,40 +350,132 @@ async fn destroy(id: web::Path<Uuid>, pool: web::Data<DbPool>) -> Result<HttpRes
             message: "Deleted".to_string(),
             data: item,
         })
-    })
-    .map_err(actix_web::error::ErrorInternalServerError)?;
+    })?;
 
     Ok(result)
 }
 
-fn add(payload: &ItemPayload, conn: &mut PgConnection) -> Result<Item, DbError> {
+fn add(
+    payload: &ItemPayload,
+    identity: &Identity,
+    conn: &mut PgConnection,
+) -> Result<Item, ApiError> {
     use crate::schema::items::dsl::*;
 
     let new_item = NewItem {
         name: payload.name.as_str(),
         size: payload.size.as_str(),
-        colors: payload.colors.as_str(),
+        colors: &payload.colors,
         owner: payload.owner,
         created_at: chrono::Local::now().naive_local(),
         updated_at: chrono::Local::now().naive_local(),
+        fabric: payload.fabric.as_deref(),
+        max_wash_temperature: payload.max_wash_temperature,
+        tumble_dry: payload.tumble_dry,
+        delicate: payload.delicate,
+        weight: payload.weight,
     };
 
-    let res = diesel::insert_into(items)
-        .values(&new_item)
-        .returning(items::all_columns())
-        .get_result(conn)?;
-
-    Ok(res)
+    conn.transaction(|conn| {
+        let res = diesel::insert_into(items)
+            .values(&new_item)
+            .returning(items::all_columns())
+            .get_result::<Item>(conn)?;
+        audit::created(&identity.actor(), Resource::Item, res.id, &res, conn)?;
+        Ok(res)
+    })
 }
 
-fn find_all(conn: &mut PgConnection) -> Result<Vec<Item>, DbError> {
+fn find_all(
+    filters: &Filters,
+    page: &Page,
+    conn: &mut PgConnection,
+) -> Result<Paginated<Item>, ApiError> {
     use crate::schema::items::dsl::*;
+    use crate::schema::{reservation_items, users};
 
-    let items_ = items.load::<Item>(conn)?;
-    Ok(items_)
+    let palette = color_filter(filters)?;
+    let filtered = || {
+        let mut query = items.into_boxed();
+        if let Some(owner_id) = filters.owner {
+            query = query.filter(owner.eq(owner_id));
+        }
+        if let Some(property_id) = filters.property {
+            query = query.filter(
+                owner.eq_any(
+                    users::table
+                        .filter(users::property.eq(property_id))
+                        .select(users::id),
+                ),
+            );
+        }
+        if let Some(item_size) = &filters.size {
+            query = query.filter(size.eq(item_size));
+        }
+        if let Some(palette) = &palette {
+            query = query.filter(colors.contains(palette));
+        }
+        if let Some(item_fabric) = &filters.fabric {
+            query = query.filter(fabric.eq(item_fabric));
+        }
+        if let Some(temperature) = filters.washable_at {
+            query = query.filter(max_wash_temperature.ge(temperature));
+        }
+        if let Some(allowed) = filters.tumble_dry {
+            query = query.filter(tumble_dry.eq(allowed));
+        }
+        if let Some(is_delicate) = filters.delicate {
+            query = query.filter(delicate.eq(is_delicate));
+        }
+        if let Some(reservation_id) = filters.reservation {
+            query = query.filter(
+                id.eq_any(
+                    reservation_items::table
+                        .filter(reservation_items::reservation.eq(reservation_id))
+                        .select(reservation_items::item),
+                ),
+            );
+        }
+        query
+    };
+
+    let total = filtered().count().get_result(conn)?;
+    let rows = match page.column.as_str() {
+        "updated_at" => {
+            paginate!(filtered(), page, updated_at, id, chrono::NaiveDateTime).load(conn)?
+        }
+        "name" => paginate!(filtered(), page, name, id, String).load(conn)?,
+        "size" => paginate!(filtered(), page, size, id, String).load(conn)?,
+        _ => paginate!(filtered(), page, created_at, id, chrono::NaiveDateTime).load(conn)?,
+    };
+
+    Ok(page.finish(rows, total))
 }
 
-fn find_by_id(item_id: Uuid, conn: &mut PgConnection) -> Result<Option<Item>, DbError> {
+/// Splits the `colors` filter, failing on names outside of the palette.
+fn color_filter(filters: &Filters) -> Result<Option<Vec<String>>, ApiError> {
+    let Some(names) = &filters.colors else {
+        return Ok(None);
+    };
+
+    let palette: Vec<String> = names
+        .split(',')
+        .map(|name| name.trim().to_lowercase())
+        .collect();
+    if let Some(unknown) = palette
+        .iter()
+        .find(|name| !item::COLORS.contains(&name.as_str()))
+    {
+        return Err(ApiError::InvalidParameters(vec![FieldError {
+            field: "colors".to_string(),
+            code: "invalid_choice".to_string(),
+            message: format!("{} is not one of {}", unknown, item::COLORS.join(", ")),
+        }]));
+    }
+    Ok(Some(palette))
+}
+
+fn find_by_id(item_id: Uuid, conn: &mut PgConnection) -> Result<Option<Item>, ApiError> {
     use crate::schema::items::dsl::*;
 
     let item = items
@@ -143,28 +486,135 @@ fn find_by_id(item_id: Uuid, conn: &mut PgConnection) -> Result<Option<Item>, Db
     Ok(item)
 }
 
+/// Tags as typed from a label may be lowercase or grouped with dashes and spaces.
+fn normalize_tag(code: &str) -> Option<String> {
+    let code: String = code
+        .chars()
+        .filter(|c| *c != '-' && !c.is_whitespace())
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    code.chars()
+        .all(|c| item::TAG_ALPHABET.contains(c))
+        .then_some(code)
+}
+
+fn find_by_tag(code: &str, conn: &mut PgConnection) -> Result<Option<Item>, ApiError> {
+    use crate::schema::items::dsl::*;
+
+    let item = items.filter(tag.eq(code)).first::<Item>(conn).optional()?;
+
+    Ok(item)
+}
+
 fn update_by_id(
     item_id: Uuid,
     payload: &ItemPayload,
+    if_match: Option<&IfMatch>,
+    identity: &Identity,
     conn: &mut PgConnection,
-) -> Result<Item, DbError> {
+) -> Result<Item, ApiError> {
     use crate::schema::items::dsl::*;
 
-    let item = diesel::update(items.find(item_id))
-        .set((
-            name.eq(payload.name.as_str()),
-            size.eq(payload.size.as_str()),
-            colors.eq(payload.colors.as_str()),
-            owner.eq(payload.owner),
-            updated_at.eq(chrono::Local::now().naive_local()),
-        ))
-        .get_result::<Item>(conn)?;
-    Ok(item)
+    conn.transaction(|conn| {
+        let current = find_for_update(item_id, conn)?;
+        identity.require_owner(&permissions::ITEMS_WRITE, current.owner)?;
+        identity.require_owner(&permissions::ITEMS_WRITE, payload.owner)?;
+        identity.require_property_of(|| permissions::user_property(current.owner, conn))?;
+        identity.require_property_of(|| permissions::user_property(payload.owner, conn))?;
+        helpers::check_if_match(if_match, &current.updated_at)?;
+
+        let item = diesel::update(items.find(item_id))
+            .set((
+                name.eq(payload.name.as_str()),
+                size.eq(payload.size.as_str()),
+                colors.eq(&payload.colors),
+                owner.eq(payload.owner),
+                fabric.eq(payload.fabric.as_deref()),
+                max_wash_temperature.eq(payload.max_wash_temperature),
+                tumble_dry.eq(payload.tumble_dry),
+                delicate.eq(payload.delicate),
+                weight.eq(payload.weight),
+                updated_at.eq(chrono::Local::now().naive_local()),
+            ))
+            .get_result::<Item>(conn)?;
+        audit::updated(
+            &identity.actor(),
+            Resource::Item,
+            item_id,
+            &current,
+            &item,
+            conn,
+        )?;
+        Ok(item)
+    })
 }
 
-fn delete(item_id: Uuid, conn: &mut PgConnection) -> Result<usize, DbError> {
+fn patch_by_id(
+    item_id: Uuid,
+    mut changes: ItemPatch,
+    if_match: Option<&IfMatch>,
+    identity: &Identity,
+    conn: &mut PgConnection,
+) -> Result<Item, ApiError> {
     use crate::schema::items::dsl::*;
 
-    let count = diesel::delete(items.find(item_id)).execute(conn)?;
-    Ok(count)
+    conn.transaction(|conn| {
+        let current = find_for_update(item_id, conn)?;
+        identity.require_owner(&permissions::ITEMS_WRITE, current.owner)?;
+        identity.require_property_of(|| permissions::user_property(current.owner, conn))?;
+        helpers::check_if_match(if_match, &current.updated_at)?;
+        let merged = changes.merge(current.clone());
+        identity.require_owner(&permissions::ITEMS_WRITE, merged.owner)?;
+        identity.require_property_of(|| permissions::user_property(merged.owner, conn))?;
+        merged.validate()?;
+
+        changes.updated_at = Some(chrono::Local::now().naive_local());
+        let item = diesel::update(items.find(item_id))
+            .set(&changes)
+            .get_result::<Item>(conn)?;
+        audit::updated(
+            &identity.actor(),
+            Resource::Item,
+            item_id,
+            &current,
+            &item,
+            conn,
+        )?;
+        Ok(item)
+    })
+}
+
+/// Loads an item and locks it until the end of the transaction.
+fn find_for_update(item_id: Uuid, conn: &mut PgConnection) -> Result<Item, ApiError> {
+    use crate::schema::items::dsl::*;
+
+    items
+        .find(item_id)
+        .for_update()
+        .first::<Item>(conn)
+        .optional()?
+        .ok_or_else(|| ApiError::NotFound("Item not found".to_string()))
+}
+
+fn delete(
+    item_id: Uuid,
+    if_match: Option<&IfMatch>,
+    identity: &Identity,
+    storage: &dyn Storage,
+    conn: &mut PgConnection,
+) -> Result<usize, ApiError> {
+    use crate::schema::items::dsl::*;
+
+    conn.transaction(|conn| {
+        let current = find_for_update(item_id, conn)?;
+        identity.require_owner(&permissions::ITEMS_WRITE, current.owner)?;
+        identity.require_property_of(|| permissions::user_property(current.owner, conn))?;
+        helpers::check_if_match(if_match, &current.updated_at)?;
+
+        let image_ids = images::ids_of(Subject::Item(item_id), conn)?;
+        let count = diesel::delete(items.find(item_id)).execute(conn)?;
+        audit::deleted(&identity.actor(), Resource::Item, item_id, &current, conn)?;
+        images::remove_files(&image_ids, storage)?;
+        Ok(count)
+    })
 }
@@ -0,0 +1,43 @@
+use std::time::Duration;
+
+use actix_web::{rt, web};
+
+use super::DbPool;
+use crate::db;
+use crate::found_items;
+use crate::helpers::ApiError;
+use crate::loans;
+
+/// Seconds between runs of the periodic jobs, unless `JOBS_INTERVAL_SECS` is set.
+const DEFAULT_INTERVAL_SECS: u64 = 300;
+
+/// Starts running housekeeping which must not wait for someone to call an endpoint,
+/// such as archiving stale found item reports and reminding borrowers of overdue items,
+/// on the current runtime.
+pub fn spawn(pool: DbPool) {
+    let period = Duration::from_secs(db::env_or("JOBS_INTERVAL_SECS", DEFAULT_INTERVAL_SECS));
+
+    rt::spawn(async move {
+        let mut interval = rt::time::interval(period);
+        loop {
+            interval.tick().await;
+            let pool = pool.clone();
+            let result = web::block(move || {
+                let mut conn = pool.get()?;
+                run(&mut conn)
+            })
+            .await;
+            match result {
+                Ok(Ok(())) => {}
+                Ok(Err(err)) => log::error!("Periodic jobs failed: {}", err),
+                Err(err) => log::error!("Periodic jobs could not run: {}", err),
+            }
+        }
+    });
+}
+
+fn run(conn: &mut diesel::PgConnection) -> Result<(), ApiError> {
+    found_items::archive_stale(conn)?;
+    loans::remind_overdue(conn)?;
+    Ok(())
+}
@@ -0,0 +1,558 @@
+use actix_web::{post, web, HttpResponse};
+use diesel::prelude::*;
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+use super::DbPool;
+use crate::auth::Identity;
+use crate::helpers::{ApiError, FieldError, Json, SuccessResponse};
+use crate::models::item::Item;
+use crate::models::load_plan::{
+    Load, LoadPlan, LoadPlanPayload, Program, SuggestedReservation, DEFAULT_CAPACITY,
+};
+use crate::models::machine;
+use crate::models::resource_kind::{self, ResourceKind};
+use crate::permissions;
+use crate::reservations::{self, Usage};
+use crate::validation::Validate;
+
+/// Assumed dry weight in grams of items without one.
+const DEFAULT_ITEM_WEIGHT: i32 = 300;
+/// Assumed highest wash temperature of items without a care label.
+const DEFAULT_WASH_TEMPERATURE: i16 = 30;
+/// How far ahead of the earliest start free machines are looked for.
+const SUGGESTION_HORIZON_DAYS: i64 = 7;
+
+/// Colors which bleed onto each other are washed apart, ordered from light to dark.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum ColorGroup {
+    Whites,
+    Colors,
+    Darks,
+}
+
+impl ColorGroup {
+    /// The darkest group of any of the item's colors.
+    fn of(item: &Item) -> ColorGroup {
+        item.colors
+            .iter()
+            .map(|color| match color.as_str() {
+                "white" | "beige" => ColorGroup::Whites,
+                "black" | "gray" | "brown" | "blue" | "purple" | "green" => ColorGroup::Darks,
+                _ => ColorGroup::Colors,
+            })
+            .max()
+            .unwrap_or(ColorGroup::Colors)
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            ColorGroup::Whites => "whites",
+            ColorGroup::Colors => "colors",
+            ColorGroup::Darks => "darks",
+        }
+    }
+}
+
+/// Programs from the most robust to the gentlest. Items of the first three are washed
+/// together with the gentlest program among them, wool and delicates only on their own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum ProgramKind {
+    Cotton,
+    Mixed,
+    Synthetics,
+    Wool,
+    Delicates,
+}
+
+impl ProgramKind {
+    fn of(item: &Item) -> ProgramKind {
+        if item.delicate {
+            return ProgramKind::Delicates;
+        }
+        match item.fabric.as_deref() {
+            Some("cotton" | "linen" | "denim") => ProgramKind::Cotton,
+            Some("polyester" | "nylon" | "viscose") => ProgramKind::Synthetics,
+            Some("wool" | "silk") => ProgramKind::Wool,
+            _ => ProgramKind::Mixed,
+        }
+    }
+
+    /// Kinds sharing a class can be washed in the same load.
+    fn class(self) -> ProgramKind {
+        match self {
+            ProgramKind::Cotton | ProgramKind::Mixed | ProgramKind::Synthetics => {
+                ProgramKind::Cotton
+            }
+            kind => kind,
+        }
+    }
+
+    /// Name, highest temperature, spin speed and duration in minutes.
+    fn settings(self) -> (&'static str, i16, i16, i64) {
+        match self {
+            ProgramKind::Cotton => ("cotton", 95, 1400, 150),
+            ProgramKind::Mixed => ("mixed", 40, 1000, 90),
+            ProgramKind::Synthetics => ("synthetics", 60, 1000, 90),
+            ProgramKind::Wool => ("wool", 30, 800, 60),
+            ProgramKind::Delicates => ("delicates", 30, 600, 60),
+        }
+    }
+}
+
+/// Splits a user's items into the fewest loads which can be washed together. Nothing
+/// is written, so reading the items is enough to plan for anyone in the property.
+#[post("/users/{id}/load-plan")]
+async fn create(
+    id: web::Path<Uuid>,
+    payload: Json<LoadPlanPayload>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = id.into_inner();
+    identity.require(permissions::ITEMS_READ)?;
+    if payload.suggest_reservations {
+        identity.require(permissions::RESERVATIONS_READ)?;
+    }
+
+    payload.validate()?;
+
+    let plan = web::block(move || {
+        let mut conn = pool.get()?;
+        let property = permissions::user_property(user_id, &mut conn)?;
+        identity.require_property(property)?;
+
+        let items = find_items(user_id, payload.items.as_deref(), &mut conn)?;
+        let machines = if payload.suggest_reservations {
+            let property = property.ok_or_else(|| {
+                ApiError::UnprocessableEntity(
+                    "User has no property to suggest reservations in".to_string(),
+                )
+            })?;
+            Some(Machines::find(property, &mut conn)?)
+        } else {
+            None
+        };
+        let capacity = payload
+            .capacity
+            .or_else(|| machines.as_ref()?.largest_load())
+            .unwrap_or(DEFAULT_CAPACITY);
+
+        let mut loads = plan(&items, capacity)?;
+        if let Some(machines) = machines {
+            let earliest = payload
+                .earliest_start
+                .unwrap_or_else(|| chrono::Local::now().naive_local());
+            suggest_reservations(&mut loads, user_id, &machines, earliest, &mut conn)?;
+        }
+        Ok::<_, ApiError>(LoadPlan { loads })
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(SuccessResponse {
+        status: 200,
+        message: "OK".to_string(),
+        data: plan,
+    }))
+}
+
+/// Loads the selected items, failing on those which are not the user's.
+fn find_items(
+    user_id: Uuid,
+    selected: Option<&[Uuid]>,
+    conn: &mut PgConnection,
+) -> Result<Vec<Item>, ApiError> {
+    use crate::schema::items::dsl::*;
+
+    let mut query = items.filter(owner.eq(user_id)).into_boxed();
+    if let Some(selected) = selected {
+        query = query.filter(id.eq_any(selected));
+    }
+    let found = query.order(created_at).load::<Item>(conn)?;
+
+    let errors: Vec<FieldError> = selected
+        .unwrap_or_default()
+        .iter()
+        .enumerate()
+        .filter(|(_, item_id)| !found.iter().any(|item| item.id == **item_id))
+        .map(|(position, item_id)| FieldError {
+            field: format!("items[{}]", position),
+            code: "not_found".to_string(),
+            message: format!("Item {} of this user not found", item_id),
+        })
+        .collect();
+    if !errors.is_empty() {
+        return Err(ApiError::InvalidPayload(errors));
+    }
+    Ok(found)
+}
+
+/// Groups items by color group and program class, then packs every group into as few
+/// loads as possible, heaviest items first (first-fit decreasing).
+fn plan(items: &[Item], capacity: i32) -> Result<Vec<Load>, ApiError> {
+    let weight = |item: &Item| item.weight.unwrap_or(DEFAULT_ITEM_WEIGHT);
+    if let Some(item) = items.iter().find(|item| weight(item) > capacity) {
+        return Err(ApiError::UnprocessableEntity(format!(
+            "Item {} weighs more than a load of {} g",
+            item.id, capacity
+        )));
+    }
+
+    let mut groups: BTreeMap<(ColorGroup, ProgramKind), Vec<&Item>> = BTreeMap::new();
+    for item in items {
+        let key = (ColorGroup::of(item), ProgramKind::of(item).class());
+        groups.entry(key).or_default().push(item);
+    }
+
+    let mut loads = Vec::new();
+    for ((color_group, _), mut group) in groups {
+        group.sort_by_key(|item| std::cmp::Reverse(weight(item)));
+
+        let mut bins: Vec<Vec<&Item>> = Vec::new();
+        for item in group {
+            let free = bins.iter_mut().find(|bin| {
+                bin.iter().map(|item| weight(item)).sum::<i32>() + weight(item) <= capacity
+            });
+            match free {
+                Some(bin) => bin.push(item),
+                None => bins.push(vec![item]),
+            }
+        }
+
+        loads.extend(bins.into_iter().map(|bin| Load {
+            items: bin.iter().map(|item| item.id).collect(),
+            weight: bin.iter().map(|item| weight(item)).sum(),
+            color_group: color_group.name(),
+            program: program(&bin),
+            reservation: None,
+        }));
+    }
+    Ok(loads)
+}
+
+/// The gentlest program any item needs, at the lowest temperature any item allows.
+fn program(items: &[&Item]) -> Program {
+    let kind = items
+        .iter()
+        .map(|item| ProgramKind::of(item))
+        .max()
+        .unwrap_or(ProgramKind::Mixed);
+    let (name, max_temperature, spin, duration_minutes) = kind.settings();
+    let temperature = items
+        .iter()
+        .map(|item| {
+            item.max_wash_temperature
+                .unwrap_or(DEFAULT_WASH_TEMPERATURE)
+        })
+        .chain([max_temperature])
+        .min()
+        .unwrap_or(DEFAULT_WASH_TEMPERATURE);
+
+    Program {
+        name,
+        temperature,
+        spin,
+        tumble_dry: items.iter().all(|item| item.tumble_dry == Some(true)),
+        duration_minutes,
+    }
+}
+
+/// The machines of a property with the rules of their kind.
+struct Machines {
+    kind: ResourceKind,
+    /// Ids and capacities, by name.
+    capacities: Vec<(Uuid, i32)>,
+}
+
+impl Machines {
+    fn find(property: Uuid, conn: &mut PgConnection) -> Result<Machines, ApiError> {
+        use crate::schema::{resource_kinds, resources};
+
+        let kind = resource_kinds::table
+            .filter(resource_kinds::name.eq(machine::KIND))
+            .first::<ResourceKind>(conn)?;
+        let capacities = resources::table
+            .filter(resources::property.eq(property))
+            .filter(resources::kind.eq(machine::KIND))
+            .order(resources::name)
+            .select((resources::id, resources::capacity))
+            .load::<(Uuid, i32)>(conn)?;
+        Ok(Machines { kind, capacities })
+    }
+
+    /// Share of a machine a load of `weight` grams takes, in the unit of the kind.
+    fn amount(&self, weight: i32) -> i32 {
+        if self.kind.unit == resource_kind::KG {
+            (weight + 999) / 1000
+        } else {
+            1
+        }
+    }
+
+    /// Grams of laundry the largest machine takes, when capacities are weights.
+    fn largest_load(&self) -> Option<i32> {
+        if self.kind.unit != resource_kind::KG {
+            return None;
+        }
+        self.capacities
+            .iter()
+            .map(|(_, capacity)| capacity.saturating_mul(1000))
+            .max()
+    }
+}
+
+/// Proposes the earliest slot on any machine of the property which would accept every
+/// load as a reservation of the user, within the rules of the machine kind as if the user
+/// booked them all. Loads without such a slot within the horizon get no suggestion.
+fn suggest_reservations(
+    loads: &mut [Load],
+    user_id: Uuid,
+    machines: &Machines,
+    earliest: chrono::NaiveDateTime,
+    conn: &mut PgConnection,
+) -> Result<(), ApiError> {
+    use crate::schema::{reservations, resources};
+
+    let now = chrono::Local::now().naive_local();
+    let kind = &machines.kind;
+    let horizon = earliest + chrono::Duration::days(SUGGESTION_HORIZON_DAYS);
+    let latest_start = kind
+        .max_advance_days
+        .map(|days| now + chrono::Duration::days(days.into()));
+    let machine_ids: Vec<Uuid> = machines
+        .capacities
+        .iter()
+        .map(|(machine, _)| *machine)
+        .collect();
+    let mut booked = crate::reservations::usages(&machine_ids, earliest, horizon, None, conn)?;
+    // Suggestions are for reservations of the user's own, never shared.
+    let exclusive = crate::reservations::is_exclusive(false, &kind.unit);
+
+    // Suggestions count against the user's upcoming reservations, as booking them would.
+    let mut allowed = match kind.max_upcoming {
+        Some(limit) => {
+            let upcoming = reservations::table
+                .filter(reservations::owner.eq(user_id))
+                .filter(reservations::end_time.gt(now))
+                .filter(
+                    reservations::resource.eq_any(
+                        resources::table
+                            .filter(resources::kind.eq(machine::KIND))
+                            .select(resources::id),
+                    ),
+                )
+                .count()
+                .get_result::<i64>(conn)?;
+            (i64::from(limit) - upcoming).max(0)
+        }
+        None => i64::MAX,
+    };
+
+    for load in loads {
+        if allowed == 0 {
+            break;
+        }
+        if kind
+            .max_duration_minutes
+            .is_some_and(|minutes| load.program.duration_minutes > minutes.into())
+        {
+            continue;
+        }
+
+        let amount = machines.amount(load.weight);
+        let duration = chrono::Duration::minutes(load.program.duration_minutes);
+        let slot = machines
+            .capacities
+            .iter()
+            .filter(|(_, capacity)| amount <= *capacity)
+            .map(|(machine, capacity)| {
+                let usages = booked.get(machine).map(Vec::as_slice).unwrap_or_default();
+                let left = i64::from(*capacity - amount);
+                let start = free_slot(usages, earliest, duration, exclusive, left);
+                (*machine, start)
+            })
+            .min_by_key(|(_, start)| *start)
+            .filter(|(_, start)| *start + duration <= horizon)
+            .filter(|(_, start)| latest_start.is_none_or(|latest| *start <= latest));
+
+        if let Some((machine, start_time)) = slot {
+            let end_time = start_time + duration;
+            booked.entry(machine).or_default().push(Usage {
+                start_time,
+                end_time,
+                amount: amount.into(),
+                exclusive,
+            });
+            load.reservation = Some(SuggestedReservation {
+                machine,
+                start_time,
+                end_time,
+                amount,
+            });
+            allowed -= 1;
+        }
+    }
+    Ok(())
+}
+
+/// The earliest start from `earliest` on which a reservation of `duration` may overlap
+/// `booked` with at most `left` of the capacity in use, as when booking it. Only the
+/// earliest start and the ends of reservations are tried, the last of which always fits.
+fn free_slot(
+    booked: &[Usage],
+    earliest: chrono::NaiveDateTime,
+    duration: chrono::Duration,
+    exclusive: bool,
+    left: i64,
+) -> chrono::NaiveDateTime {
+    let mut starts: Vec<chrono::NaiveDateTime> = booked
+        .iter()
+        .map(|usage| usage.end_time)
+        .filter(|end| *end > earliest)
+        .chain([earliest])
+        .collect();
+    starts.sort();
+
+    let fits = |start: chrono::NaiveDateTime| {
+        let end = start + duration;
+        reservations::shares_with(booked, start, end, exclusive)
+            && reservations::peak(booked, start, end) <= left
+    };
+    starts
+        .iter()
+        .copied()
+        .find(|start| fits(*start))
+        .unwrap_or(earliest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(hour: u32) -> chrono::NaiveDateTime {
+        chrono::NaiveDate::from_ymd_opt(2026, 1, 1)
+            .unwrap()
+            .and_hms_opt(hour, 0, 0)
+            .unwrap()
+    }
+
+    fn item(colors: &[&str], fabric: Option<&str>, weight: Option<i32>) -> Item {
+        Item {
+            id: Uuid::new_v4(),
+            name: "Shirt".to_string(),
+            size: "M".to_string(),
+            colors: colors.iter().map(|color| color.to_string()).collect(),
+            owner: Uuid::nil(),
+            created_at: at(0),
+            updated_at: at(0),
+            fabric: fabric.map(str::to_string),
+            max_wash_temperature: None,
+            tumble_dry: None,
+            delicate: false,
+            weight,
+            tag: "ABCDEFGH".to_string(),
+            holder: None,
+        }
+    }
+
+    #[test]
+    fn packs_heaviest_items_first_into_the_fewest_loads() {
+        let items = [
+            item(&["white"], Some("cotton"), Some(2_000)),
+            item(&["white"], Some("cotton"), Some(5_000)),
+            item(&["white"], Some("cotton"), Some(3_000)),
+            item(&["white"], Some("cotton"), Some(4_000)),
+        ];
+        let loads = plan(&items, 7_000).unwrap();
+
+        let weights: Vec<i32> = loads.iter().map(|load| load.weight).collect();
+        assert_eq!(weights, vec![7_000, 7_000]);
+        assert_eq!(loads[0].items, vec![items[1].id, items[0].id]);
+        assert_eq!(loads[1].items, vec![items[3].id, items[2].id]);
+    }
+
+    #[test]
+    fn separates_color_groups_and_program_classes() {
+        let items = [
+            item(&["white"], Some("cotton"), None),
+            item(&["white", "black"], Some("cotton"), None),
+            item(&["white"], Some("wool"), None),
+            item(&["white"], Some("polyester"), None),
+        ];
+        let loads = plan(&items, 7_000).unwrap();
+
+        assert_eq!(loads.len(), 3);
+        assert_eq!(loads[0].color_group, "whites");
+        assert_eq!(loads[0].items, vec![items[0].id, items[3].id]);
+        assert_eq!(loads[0].program.name, "synthetics");
+        assert_eq!(loads[0].weight, 2 * DEFAULT_ITEM_WEIGHT);
+        assert_eq!(loads[1].program.name, "wool");
+        assert_eq!(loads[2].color_group, "darks");
+    }
+
+    #[test]
+    fn washes_at_the_lowest_allowed_temperature() {
+        let mut delicate = item(&["red"], Some("cotton"), None);
+        delicate.max_wash_temperature = Some(40);
+        delicate.tumble_dry = Some(true);
+        let mut robust = item(&["red"], Some("cotton"), None);
+        robust.max_wash_temperature = Some(60);
+        robust.tumble_dry = Some(false);
+
+        let loads = plan(&[delicate, robust], 7_000).unwrap();
+        assert_eq!(loads.len(), 1);
+        assert_eq!(loads[0].program.temperature, 40);
+        assert!(!loads[0].program.tumble_dry);
+    }
+
+    #[test]
+    fn rejects_items_heavier_than_a_load() {
+        let items = [item(&["white"], None, Some(8_000))];
+        assert!(plan(&items, 7_000).is_err());
+    }
+
+    fn usage(from: u32, to: u32, amount: i64, exclusive: bool) -> Usage {
+        Usage {
+            start_time: at(from),
+            end_time: at(to),
+            amount,
+            exclusive,
+        }
+    }
+
+    fn hours(hours: i64) -> chrono::Duration {
+        chrono::Duration::hours(hours)
+    }
+
+    #[test]
+    fn free_slot_starts_at_earliest_when_nothing_is_booked() {
+        assert_eq!(free_slot(&[], at(8), hours(2), true, 0), at(8));
+    }
+
+    #[test]
+    fn free_slot_skips_gaps_which_are_too_short() {
+        let booked = [
+            usage(13, 15, 5, true),
+            usage(9, 10, 5, true),
+            usage(11, 12, 5, true),
+        ];
+        assert_eq!(free_slot(&booked, at(8), hours(1), true, 2), at(8));
+        assert_eq!(free_slot(&booked, at(8), hours(2), true, 2), at(15));
+        assert_eq!(free_slot(&booked, at(10), hours(1), true, 2), at(10));
+    }
+
+    #[test]
+    fn free_slot_starts_after_a_reservation_in_progress() {
+        let booked = [usage(7, 9, 5, true)];
+        assert_eq!(free_slot(&booked, at(8), hours(1), true, 2), at(9));
+    }
+
+    #[test]
+    fn free_slot_shares_only_the_capacity_left() {
+        let booked = [usage(8, 10, 2, false), usage(9, 11, 2, false)];
+        assert_eq!(free_slot(&booked, at(8), hours(1), false, 2), at(8));
+        assert_eq!(free_slot(&booked, at(8), hours(2), false, 2), at(10));
+        assert_eq!(free_slot(&booked, at(8), hours(1), false, 1), at(11));
+        assert_eq!(free_slot(&booked, at(8), hours(1), true, 4), at(11));
+    }
+}
@@ -0,0 +1,598 @@
+use super::DbPool;
+use actix_web::http::header::{ETag, IfNoneMatch};
+use actix_web::{get, post, web, HttpResponse};
+use diesel::prelude::*;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::audit;
+use crate::auth::Identity;
+use crate::db;
+use crate::helpers::{self, ApiError, FieldError, Json, PaginatedResponse, SuccessResponse};
+use crate::idempotency;
+use crate::includes::{IncludeParams, Includes, Resource};
+use crate::models::item::Item;
+use crate::models::loan::{self, Loan, LoanPayload, NewLoan};
+use crate::nested::Parent;
+use crate::notifications;
+use crate::pagination::{paginate, Page, PageParams, Paginated};
+use crate::permissions;
+use crate::validation::Validate;
+
+/// Hours between reminders of an overdue return, unless `LOAN_REMINDER_HOURS` is set.
+const DEFAULT_REMINDER_HOURS: i64 = 24;
+
+#[derive(Debug, Deserialize)]
+struct Filters {
+    item: Option<Uuid>,
+    borrower: Option<Uuid>,
+    status: Option<String>,
+    /// Lent items which were not returned in time.
+    overdue: Option<bool>,
+    /// Property an API key is restricted to.
+    #[serde(skip)]
+    property: Option<Uuid>,
+}
+
+const SORTABLE: [&str; 3] = ["created_at", "updated_at", "due_at"];
+
+/// Who may take a step of a loan.
+#[derive(Debug, Clone, Copy)]
+enum Party {
+    Owner,
+    Borrower,
+}
+
+/// Steps of a loan after it was requested.
+#[derive(Debug, Clone, Copy)]
+enum Step {
+    Approve,
+    Reject,
+    Cancel,
+    Return,
+}
+
+impl Step {
+    /// Who takes the step, the status it starts from and the status it leads to.
+    fn rule(self) -> (Party, &'static str, &'static str) {
+        match self {
+            Step::Approve => (Party::Owner, loan::REQUESTED, loan::APPROVED),
+            Step::Reject => (Party::Owner, loan::REQUESTED, loan::REJECTED),
+            Step::Cancel => (Party::Borrower, loan::REQUESTED, loan::CANCELLED),
+            Step::Return => (Party::Owner, loan::APPROVED, loan::RETURNED),
+        }
+    }
+}
+
+#[get("/loans")]
+async fn index(
+    page: web::Query<PageParams>,
+    filters: web::Query<Filters>,
+    include: web::Query<IncludeParams>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::LOANS_READ)?;
+
+    let mut filters = filters.into_inner();
+    filters.property = identity.property_scope();
+    list(None, &page, filters, &include, &identity, pool).await
+}
+
+#[get("/items/{id}/loans")]
+async fn item_index(
+    id: web::Path<Uuid>,
+    page: web::Query<PageParams>,
+    filters: web::Query<Filters>,
+    include: web::Query<IncludeParams>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::LOANS_READ)?;
+
+    let parent = Parent::Item(id.into_inner());
+    let mut filters = filters.into_inner();
+    filters.item = Some(parent.id());
+    filters.property = identity.property_scope();
+    list(Some(parent), &page, filters, &include, &identity, pool).await
+}
+
+/// The loans of a user as the borrower.
+#[get("/users/{id}/loans")]
+async fn user_index(
+    id: web::Path<Uuid>,
+    page: web::Query<PageParams>,
+    filters: web::Query<Filters>,
+    include: web::Query<IncludeParams>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::LOANS_READ)?;
+
+    let parent = Parent::User(id.into_inner());
+    let mut filters = filters.into_inner();
+    filters.borrower = Some(parent.id());
+    filters.property = identity.property_scope();
+    list(Some(parent), &page, filters, &include, &identity, pool).await
+}
+
+async fn list(
+    parent: Option<Parent>,
+    page: &PageParams,
+    filters: Filters,
+    include: &IncludeParams,
+    identity: &Identity,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, ApiError> {
+    let page = page.resolve(&SORTABLE)?;
+    let includes = Includes::parse(include.include.as_deref(), Resource::Loan, identity)?;
+    let loans = web::block(move || {
+        let mut conn = pool.get()?;
+        if let Some(parent) = parent {
+            parent.ensure_exists(&mut conn)?;
+        }
+        let loans = find_all(&filters, &page, &mut conn)?;
+        includes.embed_page(Resource::Loan, loans, &mut conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(PaginatedResponse {
+        status: 200,
+        message: "OK".to_string(),
+        data: loans.data,
+        meta: loans.meta,
+    }))
+}
+
+/// Asks the owner of an item to lend it to the caller.
+#[post("/items/{id}/loans")]
+async fn create(
+    id: web::Path<Uuid>,
+    payload: Json<LoanPayload>,
+    key: idempotency::Key,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require_owner(&permissions::LOANS_WRITE, identity.user.id)?;
+
+    payload.validate()?;
+    let now = chrono::Local::now().naive_local();
+    if payload.due_at <= now {
+        return Err(ApiError::InvalidPayload(vec![FieldError {
+            field: "due_at".to_string(),
+            code: "out_of_range".to_string(),
+            message: "due_at must be in the future".to_string(),
+        }]));
+    }
+
+    let item_id = id.into_inner();
+    let scope = format!("POST /items/{}/loans", item_id);
+    let created = web::block(move || {
+        let mut conn = pool.get()?;
+        Parent::Item(item_id).ensure_exists(&mut conn)?;
+        idempotency::create_once(&key, &scope, &payload.0, &mut conn, |conn| {
+            add(item_id, &payload, &identity, conn)
+        })
+    })
+    .await??;
+
+    Ok(created.respond())
+}
+
+#[get("/loans/{id}")]
+async fn show(
+    id: web::Path<Uuid>,
+    include: web::Query<IncludeParams>,
+    if_none_match: Option<web::Header<IfNoneMatch>>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::LOANS_READ)?;
+
+    let includes = Includes::parse(include.include.as_deref(), Resource::Loan, &identity)?;
+    let embedding = !includes.is_empty();
+    let loan = web::block(move || {
+        let mut conn = pool.get()?;
+        let loan = find_by_id(id.into_inner(), &mut conn)?;
+        if let Some(loan) = &loan {
+            identity
+                .require_property_of(|| permissions::user_property(loan.borrower, &mut conn))?;
+        }
+        loan.map(|loan| {
+            let data = includes.embed_one(Resource::Loan, &loan, &mut conn)?;
+            Ok::<_, ApiError>((loan.updated_at, data))
+        })
+        .transpose()
+    })
+    .await??;
+
+    let Some((updated_at, loan)) = loan else {
+        return Err(ApiError::NotFound("Loan not found".to_string()));
+    };
+
+    // Embedded resources change independently of this one, so only the plain
+    // representation is cacheable by its version.
+    if embedding {
+        return Ok(HttpResponse::Ok().json(SuccessResponse {
+            status: 200,
+            message: "OK".to_string(),
+            data: loan,
+        }));
+    }
+
+    let etag = helpers::etag(&updated_at);
+    if helpers::not_modified(if_none_match.as_deref(), &etag) {
+        return Ok(HttpResponse::NotModified()
+            .insert_header(ETag(etag))
+            .finish());
+    }
+
+    Ok(HttpResponse::Ok()
+        .insert_header(ETag(etag))
+        .json(SuccessResponse {
+            status: 200,
+            message: "OK".to_string(),
+            data: loan,
+        }))
+}
+
+/// The owner hands the item over, the borrower holds it from now on.
+#[post("/loans/{id}/approve")]
+async fn approve(
+    id: web::Path<Uuid>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    take(id.into_inner(), Step::Approve, identity, pool).await
+}
+
+#[post("/loans/{id}/reject")]
+async fn reject(
+    id: web::Path<Uuid>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    take(id.into_inner(), Step::Reject, identity, pool).await
+}
+
+/// The borrower withdraws a request which was not answered yet.
+#[post("/loans/{id}/cancel")]
+async fn cancel(
+    id: web::Path<Uuid>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    take(id.into_inner(), Step::Cancel, identity, pool).await
+}
+
+/// The owner confirms having the item back.
+#[post("/loans/{id}/return")]
+async fn confirm_return(
+    id: web::Path<Uuid>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    take(id.into_inner(), Step::Return, identity, pool).await
+}
+
+async fn take(
+    loan_id: Uuid,
+    step: Step,
+    identity: Identity,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, ApiError> {
+    let loan = web::block(move || {
+        let mut conn = pool.get()?;
+        advance(loan_id, step, &identity, &mut conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(SuccessResponse {
+        status: 200,
+        message: "OK".to_string(),
+        data: loan,
+    }))
+}
+
+/// Reminds borrowers of items which are overdue, at most every `LOAN_REMINDER_HOURS`.
+/// Run periodically by [`jobs`](crate::jobs).
+pub fn remind_overdue(conn: &mut PgConnection) -> Result<(), ApiError> {
+    use crate::schema::loans::dsl::*;
+    use crate::schema::{items, users};
+
+    let hours = db::env_or("LOAN_REMINDER_HOURS", DEFAULT_REMINDER_HOURS);
+    let now = chrono::Local::now().naive_local();
+    let last_reminder = now - chrono::Duration::hours(hours);
+
+    conn.transaction(|conn| {
+        let overdue = loans
+            .filter(status.eq(loan::APPROVED))
+            .filter(due_at.lt(now))
+            .filter(reminded_at.is_null().or(reminded_at.lt(last_reminder)))
+            .for_update()
+            .skip_locked()
+            .load::<Loan>(conn)?;
+        for current in overdue {
+            let (item_name, owner_name) = items::table
+                .inner_join(users::table)
+                .filter(items::id.eq(current.item))
+                .select((items::name, users::name))
+                .first::<(String, String)>(conn)?;
+            let reminded = diesel::update(loans.find(current.id))
+                .set(reminded_at.eq(now))
+                .get_result::<Loan>(conn)?;
+            audit::updated(
+                audit::SYSTEM,
+                Resource::Loan,
+                current.id,
+                &current,
+                &reminded,
+                conn,
+            )?;
+            notifications::notify(
+                current.borrower,
+                "loan_overdue",
+                Some(current.id),
+                &format!(
+                    "Please return the {} to {}, it was due on {}",
+                    item_name,
+                    owner_name,
+                    current.due_at.format("%Y-%m-%d %H:%M")
+                ),
+                conn,
+            )?;
+        }
+        Ok(())
+    })
+}
+
+fn add(
+    item_id: Uuid,
+    payload: &LoanPayload,
+    identity: &Identity,
+    conn: &mut PgConnection,
+) -> Result<Loan, ApiError> {
+    use crate::schema::loans::dsl::*;
+
+    conn.transaction(|conn| {
+        let lent = find_item(item_id, conn)?;
+        if lent.owner == identity.user.id {
+            return Err(ApiError::UnprocessableEntity(
+                "Own items cannot be borrowed".to_string(),
+            ));
+        }
+        let owner_property = permissions::user_property(lent.owner, conn)?;
+        let borrower_property = permissions::user_property(identity.user.id, conn)?;
+        if owner_property.is_none() || owner_property != borrower_property {
+            return Err(ApiError::UnprocessableEntity(
+                "Only items of neighbours in the same property can be borrowed".to_string(),
+            ));
+        }
+        identity.require_property(owner_property)?;
+
+        let open = loans
+            .filter(item.eq(item_id))
+            .filter(borrower.eq(identity.user.id))
+            .filter(status.eq_any([loan::REQUESTED, loan::APPROVED]))
+            .count()
+            .get_result::<i64>(conn)?;
+        if open > 0 {
+            return Err(ApiError::Conflict(
+                "The item is already requested or lent to you".to_string(),
+            ));
+        }
+
+        let now = chrono::Local::now().naive_local();
+        let res = diesel::insert_into(loans)
+            .values(&NewLoan {
+                item: item_id,
+                borrower: identity.user.id,
+                message: payload.message.as_deref(),
+                due_at: payload.due_at,
+                created_at: now,
+                updated_at: now,
+            })
+            .returning(loans::all_columns())
+            .get_result::<Loan>(conn)?;
+        audit::created(&identity.actor(), Resource::Loan, res.id, &res, conn)?;
+        notifications::notify(
+            lent.owner,
+            "loan_requested",
+            Some(res.id),
+            &format!(
+                "{} would like to borrow your {} until {}",
+                identity.user.name,
+                lent.name,
+                res.due_at.format("%Y-%m-%d %H:%M")
+            ),
+            conn,
+        )?;
+        Ok(res)
+    })
+}
+
+/// Takes a step of a loan, updating who holds the item and telling the other party.
+fn advance(
+    loan_id: Uuid,
+    step: Step,
+    identity: &Identity,
+    conn: &mut PgConnection,
+) -> Result<Loan, ApiError> {
+    use crate::schema::loans::dsl::*;
+
+    let (party, from, to) = step.rule();
+    conn.transaction(|conn| {
+        let current = loans
+            .find(loan_id)
+            .for_update()
+            .first::<Loan>(conn)
+            .optional()?
+            .ok_or_else(|| ApiError::NotFound("Loan not found".to_string()))?;
+        let lent = find_item(current.item, conn)?;
+        let (actor, recipient) = match party {
+            Party::Owner => (lent.owner, current.borrower),
+            Party::Borrower => (current.borrower, lent.owner),
+        };
+        identity.require_owner(&permissions::LOANS_WRITE, actor)?;
+        identity.require_property_of(|| permissions::user_property(lent.owner, conn))?;
+        if current.status != from {
+            return Err(ApiError::Conflict(format!("Loan is {}", current.status)));
+        }
+
+        let now = chrono::Local::now().naive_local();
+        let holder = match step {
+            Step::Approve if lent.holder.is_some() => {
+                return Err(ApiError::Conflict(
+                    "Item is lent to someone else".to_string(),
+                ));
+            }
+            Step::Approve => Some(Some(current.borrower)),
+            Step::Return => Some(None),
+            Step::Reject | Step::Cancel => None,
+        };
+        let loan = diesel::update(loans.find(loan_id))
+            .set((
+                status.eq(to),
+                approved_at.eq(matches!(step, Step::Approve)
+                    .then_some(now)
+                    .or(current.approved_at)),
+                returned_at.eq(matches!(step, Step::Return)
+                    .then_some(now)
+                    .or(current.returned_at)),
+                updated_at.eq(now),
+            ))
+            .get_result::<Loan>(conn)?;
+        audit::updated(
+            &identity.actor(),
+            Resource::Loan,
+            loan_id,
+            &current,
+            &loan,
+            conn,
+        )?;
+        if let Some(holder) = holder {
+            hand_over(&lent, holder, identity, conn)?;
+        }
+
+        let text = match step {
+            Step::Approve => format!(
+                "You may borrow the {} until {}",
+                lent.name,
+                loan.due_at.format("%Y-%m-%d %H:%M")
+            ),
+            Step::Reject => format!("Your request to borrow the {} was declined", lent.name),
+            Step::Cancel => format!("The request to borrow your {} was withdrawn", lent.name),
+            Step::Return => format!("The return of the {} was confirmed", lent.name),
+        };
+        notifications::notify(
+            recipient,
+            &format!("loan_{}", to),
+            Some(loan_id),
+            &text,
+            conn,
+        )?;
+        Ok(loan)
+    })
+}
+
+/// Records who holds the item now.
+fn hand_over(
+    lent: &Item,
+    new_holder: Option<Uuid>,
+    identity: &Identity,
+    conn: &mut PgConnection,
+) -> Result<(), ApiError> {
+    use crate::schema::items::dsl::*;
+
+    let handed = diesel::update(items.find(lent.id))
+        .set((
+            holder.eq(new_holder),
+            updated_at.eq(chrono::Local::now().naive_local()),
+        ))
+        .get_result::<Item>(conn)?;
+    audit::updated(
+        &identity.actor(),
+        Resource::Item,
+        lent.id,
+        lent,
+        &handed,
+        conn,
+    )?;
+    Ok(())
+}
+
+fn find_item(item_id: Uuid, conn: &mut PgConnection) -> Result<Item, ApiError> {
+    use crate::schema::items::dsl::*;
+
+    items
+        .find(item_id)
+        .for_update()
+        .first::<Item>(conn)
+        .optional()?
+        .ok_or_else(|| ApiError::NotFound("Item not found".to_string()))
+}
+
+fn find_all(
+    filters: &Filters,
+    page: &Page,
+    conn: &mut PgConnection,
+) -> Result<Paginated<Loan>, ApiError> {
+    use crate::schema::loans::dsl::*;
+    use crate::schema::{items, users};
+
+    let now = chrono::Local::now().naive_local();
+    let filtered = || {
+        let mut query = loans.into_boxed();
+        if let Some(item_id) = filters.item {
+            query = query.filter(item.eq(item_id));
+        }
+        if let Some(borrower_id) = filters.borrower {
+            query = query.filter(borrower.eq(borrower_id));
+        }
+        if let Some(loan_status) = &filters.status {
+            query = query.filter(status.eq(loan_status));
+        }
+        match filters.overdue {
+            Some(true) => {
+                query = query
+                    .filter(status.eq(loan::APPROVED))
+                    .filter(due_at.lt(now))
+            }
+            Some(false) => query = query.filter(status.ne(loan::APPROVED).or(due_at.ge(now))),
+            None => {}
+        }
+        if let Some(property_id) = filters.property {
+            query = query.filter(
+                item.eq_any(
+                    items::table
+                        .inner_join(users::table)
+                        .filter(users::property.eq(property_id))
+                        .select(items::id),
+                ),
+            );
+        }
+        query
+    };
+
+    let total = filtered().count().get_result(conn)?;
+    let rows = match page.column.as_str() {
+        "updated_at" => {
+            paginate!(filtered(), page, updated_at, id, chrono::NaiveDateTime).load(conn)?
+        }
+        "due_at" => paginate!(filtered(), page, due_at, id, chrono::NaiveDateTime).load(conn)?,
+        _ => paginate!(filtered(), page, created_at, id, chrono::NaiveDateTime).load(conn)?,
+    };
+
+    Ok(page.finish(rows, total))
+}
+
+fn find_by_id(loan_id: Uuid, conn: &mut PgConnection) -> Result<Option<Loan>, ApiError> {
+    use crate::schema::loans::dsl::*;
+
+    let loan = loans
+        .filter(id.eq(loan_id))
+        .first::<Loan>(conn)
+        .optional()?;
+
+    Ok(loan)
+}
@@ -1,96 +1,296 @@
 use super::DbPool;
-use actix_web::{delete, get, post, put, web, Error, HttpResponse};
+use actix_web::http::header::{ETag, IfMatch, IfNoneMatch};
+use actix_web::{delete, get, patch, post, put, web, HttpResponse};
 use diesel::prelude::*;
+use serde::Deserialize;
 use uuid::Uuid;
 
-use crate::helpers::{ErrorResponse, SuccessResponse};
-use crate::models::machine::{Machine, MachinePayload, NewMachine};
+use crate::audit;
+use crate::auth::Identity;
+use crate::helpers::{self, ApiError, Json, PaginatedResponse, SuccessResponse};
+use crate::idempotency;
+use crate::images::{self, Subject};
+use crate::includes::{IncludeParams, Includes, Resource};
+use crate::models::machine::{self, Machine, MachinePatch, MachinePayload, NewMachine};
+use crate::nested::{self, Parent};
+use crate::pagination::{paginate, Page, PageParams, Paginated};
+use crate::permissions;
+use crate::storage::Storage;
+use crate::validation::Validate;
 
-type DbError = Box<dyn std::error::Error + Send + Sync>;
+#[derive(Debug, Deserialize)]
+struct Filters {
+    property: Option<Uuid>,
+    status: Option<String>,
+}
+
+const SORTABLE: [&str; 5] = ["created_at", "updated_at", "name", "status", "eta"];
 
 #[get("/machines")]
-async fn index(pool: web::Data<DbPool>) -> Result<HttpResponse, Error> {
+async fn index(
+    page: web::Query<PageParams>,
+    filters: web::Query<Filters>,
+    include: web::Query<IncludeParams>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::MACHINES_READ)?;
+
+    let mut filters = filters.into_inner();
+    filters.property = identity.scope_filter(filters.property)?;
+    list(None, &page, filters, &include, &identity, pool).await
+}
+
+#[get("/properties/{id}/machines")]
+async fn property_index(
+    id: web::Path<Uuid>,
+    page: web::Query<PageParams>,
+    filters: web::Query<Filters>,
+    include: web::Query<IncludeParams>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::MACHINES_READ)?;
+
+    let parent = Parent::Property(id.into_inner());
+    identity.require_property(Some(parent.id()))?;
+    let mut filters = filters.into_inner();
+    filters.property = Some(parent.id());
+    list(Some(parent), &page, filters, &include, &identity, pool).await
+}
+
+async fn list(
+    parent: Option<Parent>,
+    page: &PageParams,
+    filters: Filters,
+    include: &IncludeParams,
+    identity: &Identity,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, ApiError> {
+    let page = page.resolve(&SORTABLE)?;
+    let includes = Includes::parse(include.include.as_deref(), Resource::Machine, identity)?;
     let machines = web::block(move || {
         let mut conn = pool.get()?;
-        find_all(&mut conn)
+        if let Some(parent) = parent {
+            parent.ensure_exists(&mut conn)?;
+        }
+        let machines = find_all(&filters, &page, &mut conn)?;
+        includes.embed_page(Resource::Machine, machines, &mut conn)
     })
-    .await?
-    .map_err(actix_web::error::ErrorInternalServerError)?;
+    .await??;
 
-    Ok(HttpResponse::Ok().json(SuccessResponse {
+    Ok(HttpResponse::Ok().json(PaginatedResponse {
         status: 200,
         message: "OK".to_string(),
-        data: machines,
+        data: machines.data,
+        meta: machines.meta,
     }))
 }
 
 #[post("/machines")]
 async fn create(
     pool: web::Data<DbPool>,
-    payload: web::Json<MachinePayload>,
-) -> Result<HttpResponse, Error> {
-    let machine = web::block(move || {
+    payload: Json<MachinePayload>,
+    key: idempotency::Key,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::MACHINES_WRITE)?;
+    identity.require_property(Some(payload.property))?;
+
+    insert(
+        None,
+        identity,
+        "POST /machines".to_string(),
+        payload.0,
+        key,
+        pool,
+    )
+    .await
+}
+
+#[post("/properties/{id}/machines")]
+async fn property_create(
+    id: web::Path<Uuid>,
+    pool: web::Data<DbPool>,
+    body: Json<serde_json::Value>,
+    key: idempotency::Key,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::MACHINES_WRITE)?;
+
+    let parent = Parent::Property(id.into_inner());
+    identity.require_property(Some(parent.id()))?;
+    let payload = nested::payload(body.0, "property", &parent)?;
+    let scope = format!("POST /properties/{}/machines", parent.id());
+    insert(Some(parent), identity, scope, payload, key, pool).await
+}
+
+async fn insert(
+    parent: Option<Parent>,
+    identity: Identity,
+    scope: String,
+    payload: MachinePayload,
+    key: idempotency::Key,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, ApiError> {
+    payload.validate()?;
+
+    let created = web::block(move || {
         let mut conn = pool.get()?;
-        add(&payload, &mut conn)
+        if let Some(parent) = parent {
+            parent.ensure_exists(&mut conn)?;
+        }
+        idempotency::create_once(&key, &scope, &payload, &mut conn, |conn| {
+            add(&payload, &identity, conn)
+        })
     })
-    .await?
-    .map_err(actix_web::error::ErrorInternalServerError)?;
+    .await??;
 
-    Ok(HttpResponse::Created().json(SuccessResponse {
-        status: 201,
-        message: "Created".to_string(),
-        data: machine,
-    }))
+    Ok(created.respond())
 }
 
 #[get("/machines/{id}")]
-async fn show(id: web::Path<Uuid>, pool: web::Data<DbPool>) -> Result<HttpResponse, Error> {
+async fn show(
+    id: web::Path<Uuid>,
+    include: web::Query<IncludeParams>,
+    if_none_match: Option<web::Header<IfNoneMatch>>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::MACHINES_READ)?;
+
+    let includes = Includes::parse(include.include.as_deref(), Resource::Machine, &identity)?;
+    let embedding = !includes.is_empty();
     let machine = web::block(move || {
         let mut conn = pool.get()?;
-        find_by_id(id.into_inner(), &mut conn)
+        let machine = find_by_id(id.into_inner(), &mut conn)?;
+        if let Some(machine) = &machine {
+            identity.require_property(Some(machine.property))?;
+        }
+        machine
+            .map(|machine| {
+                let data = includes.embed_one(Resource::Machine, &machine, &mut conn)?;
+                Ok::<_, ApiError>((machine.updated_at, data))
+            })
+            .transpose()
     })
-    .await?
-    .map_err(actix_web::error::ErrorInternalServerError)?;
+    .await??;
+
+    let Some((updated_at, machine)) = machine else {
+        return Err(ApiError::NotFound("Machine not found".to_string()));
+    };
 
-    if machine.is_none() {
-        return Ok(HttpResponse::NotFound().json(ErrorResponse {
-            status: 404,
-            message: "Machine not found".to_string(),
+    // Embedded resources change independently of this one, so only the plain
+    // representation is cacheable by its version.
+    if embedding {
+        return Ok(HttpResponse::Ok().json(SuccessResponse {
+            status: 200,
+            message: "OK".to_string(),
+            data: machine,
         }));
     }
 
-    Ok(HttpResponse::Ok().json(SuccessResponse {
-        status: 200,
-        message: "OK".to_string(),
-        data: machine,
-    }))
+    let etag = helpers::etag(&updated_at);
+    if helpers::not_modified(if_none_match.as_deref(), &etag) {
+        return Ok(HttpResponse::NotModified()
+            .insert_header(ETag(etag))
+            .finish());
+    }
+
+    Ok(HttpResponse::Ok()
+        .insert_header(ETag(etag))
+        .json(SuccessResponse {
+            status: 200,
+            message: "OK".to_string(),
+            data: machine,
+        }))
 }
 
 #[put("/machines/{id}")]
 async fn update(
     id: web::Path<Uuid>,
-    payload: web::Json<MachinePayload>,
+    payload: Json<MachinePayload>,
+    if_match: Option<web::Header<IfMatch>>,
     pool: web::Data<DbPool>,
-) -> Result<HttpResponse, Error> {
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::MACHINES_WRITE)?;
+
+    payload.validate()?;
+
     let machine = web::block(move || {
         let mut conn = pool.get()?;
-        update_by_id(id.into_inner(), &payload, &mut conn)
+        update_by_id(
+            id.into_inner(),
+            &payload,
+            if_match.as_deref(),
+            &identity,
+            &mut conn,
+        )
     })
-    .await?
-    .map_err(actix_web::error::ErrorInternalServerError)?;
+    .await??;
 
-    Ok(HttpResponse::Ok().json(SuccessResponse {
-        status: 200,
-        message: "OK".to_string(),
-        data: machine,
-    }))
+    let etag = helpers::etag(&machine.updated_at);
+    Ok(HttpResponse::Ok()
+        .insert_header(ETag(etag))
+        .json(SuccessResponse {
+            status: 200,
+            message: "OK".to_string(),
+            data: machine,
+        }))
+}
+
+#[patch("/machines/{id}")]
+async fn partial_update(
+    id: web::Path<Uuid>,
+    changes: Json<MachinePatch>,
+    if_match: Option<web::Header<IfMatch>>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::MACHINES_WRITE)?;
+
+    let machine = web::block(move || {
+        let mut conn = pool.get()?;
+        patch_by_id(
+            id.into_inner(),
+            changes.0,
+            if_match.as_deref(),
+            &identity,
+            &mut conn,
+        )
+    })
+    .await??;
+
+    let etag = helpers::etag(&machine.updated_at);
+    Ok(HttpResponse::Ok()
+        .insert_header(ETag(etag))
+        .json(SuccessResponse {
+            status: 200,
+            message: "OK".to_string(),
+            data: machine,
+        }))
 }
 
 #[delete("/machines/{id}")]
-async fn destroy(id: web::Path<Uuid>, pool: web::Data<DbPool>) -> Result<HttpResponse, Error> {
+async fn destroy(
+    id: web::Path<Uuid>,
+    if_match: Option<web::Header<IfMatch>>,
+    pool: web::Data<DbPool>,
+    storage: web::Data<dyn Storage>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::MACHINES_WRITE)?;
+
     let result = web::block(move || {
         let mut conn = pool.get()?;
-        delete(id.into_inner(), &mut conn)
+        delete(
+            id.into_inner(),
+            if_match.as_deref(),
+            &identity,
+            storage.as_ref(),
+            &mut conn,
+        )
     })
     .await?
     .map(|machine| {
@@ -99,72 +299,198 @@ async fn destroy(id: web::Path<Uuid>, pool: web::Data<DbPool>) -> Result<HttpRes
             message: "Deleted".to_string(),
             data: machine,
         })
-    })
-    .map_err(actix_web::error::ErrorInternalServerError)?;
+    })?;
 
     Ok(result)
 }
 
-fn add(payload: &MachinePayload, conn: &mut PgConnection) -> Result<Machine, DbError> {
-    use crate::schema::machines::dsl::*;
+fn add(
+    payload: &MachinePayload,
+    identity: &Identity,
+    conn: &mut PgConnection,
+) -> Result<Machine, ApiError> {
+    use crate::schema::resources::dsl::*;
 
     let new_machine = NewMachine {
         name: payload.name.as_str(),
         property: payload.property,
         status: payload.status.as_str(),
         eta: payload.eta,
+        kind: machine::KIND,
+        capacity: payload.capacity,
         created_at: chrono::Local::now().naive_local(),
         updated_at: chrono::Local::now().naive_local(),
     };
 
-    let res = diesel::insert_into(machines)
-        .values(&new_machine)
-        .returning(machines::all_columns())
-        .get_result(conn)?;
-
-    Ok(res)
+    conn.transaction(|conn| {
+        let res = diesel::insert_into(resources)
+            .values(&new_machine)
+            .returning(machine::COLUMNS)
+            .get_result::<Machine>(conn)?;
+        // Logged as a resource, as through /resources, so every row has one history.
+        audit::created(&identity.actor(), Resource::Bookable, res.id, &res, conn)?;
+        Ok(res)
+    })
 }
 
-fn find_all(conn: &mut PgConnection) -> Result<Vec<Machine>, DbError> {
-    use crate::schema::machines::dsl::*;
+fn find_all(
+    filters: &Filters,
+    page: &Page,
+    conn: &mut PgConnection,
+) -> Result<Paginated<Machine>, ApiError> {
+    use crate::schema::resources::dsl::*;
+
+    let filtered = || {
+        let mut query = resources
+            .filter(kind.eq(machine::KIND))
+            .select(machine::COLUMNS)
+            .into_boxed();
+        if let Some(property_id) = filters.property {
+            query = query.filter(property.eq(property_id));
+        }
+        if let Some(machine_status) = &filters.status {
+            query = query.filter(status.eq(machine_status));
+        }
+        query
+    };
 
-    let items = machines.load::<Machine>(conn)?;
-    Ok(items)
+    let total = filtered().count().get_result(conn)?;
+    let rows = match page.column.as_str() {
+        "updated_at" => {
+            paginate!(filtered(), page, updated_at, id, chrono::NaiveDateTime).load(conn)?
+        }
+        "name" => paginate!(filtered(), page, name, id, String).load(conn)?,
+        "status" => paginate!(filtered(), page, status, id, String).load(conn)?,
+        "eta" => paginate!(filtered(), page, eta, id, chrono::NaiveDateTime).load(conn)?,
+        _ => paginate!(filtered(), page, created_at, id, chrono::NaiveDateTime).load(conn)?,
+    };
+
+    Ok(page.finish(rows, total))
 }
 
-fn find_by_id(machine_id: Uuid, conn: &mut PgConnection) -> Result<Option<Machine>, DbError> {
-    use crate::schema::machines::dsl::*;
+fn find_by_id(machine_id: Uuid, conn: &mut PgConnection) -> Result<Option<Machine>, ApiError> {
+    use crate::schema::resources::dsl::*;
 
-    let machine = machines
+    let found = resources
         .filter(id.eq(machine_id))
+        .filter(kind.eq(machine::KIND))
+        .select(machine::COLUMNS)
         .first::<Machine>(conn)
         .optional()?;
 
-    Ok(machine)
+    Ok(found)
 }
 
 fn update_by_id(
     machine_id: Uuid,
     payload: &MachinePayload,
+    if_match: Option<&IfMatch>,
+    identity: &Identity,
+    conn: &mut PgConnection,
+) -> Result<Machine, ApiError> {
+    use crate::schema::resources::dsl::*;
+
+    conn.transaction(|conn| {
+        let current = find_for_update(machine_id, conn)?;
+        identity.require_property(Some(current.property))?;
+        identity.require_property(Some(payload.property))?;
+        helpers::check_if_match(if_match, &current.updated_at)?;
+
+        let machine = diesel::update(resources.find(machine_id))
+            .set((
+                name.eq(payload.name.to_string()),
+                property.eq(payload.property),
+                status.eq(payload.status.to_string()),
+                eta.eq(payload.eta),
+                capacity.eq(payload.capacity),
+                updated_at.eq(chrono::Local::now().naive_local()),
+            ))
+            .returning(machine::COLUMNS)
+            .get_result::<Machine>(conn)?;
+        audit::updated(
+            &identity.actor(),
+            Resource::Bookable,
+            machine_id,
+            &current,
+            &machine,
+            conn,
+        )?;
+        Ok(machine)
+    })
+}
+
+fn patch_by_id(
+    machine_id: Uuid,
+    mut changes: MachinePatch,
+    if_match: Option<&IfMatch>,
+    identity: &Identity,
     conn: &mut PgConnection,
-) -> Result<Machine, DbError> {
-    use crate::schema::machines::dsl::*;
-
-    let machine = diesel::update(machines.find(machine_id))
-        .set((
-            name.eq(payload.name.to_string()),
-            property.eq(payload.property),
-            status.eq(payload.status.to_string()),
-            eta.eq(payload.eta),
-            updated_at.eq(chrono::Local::now().naive_local()),
-        ))
-        .get_result::<Machine>(conn)?;
-    Ok(machine)
-}
-
-fn delete(machine_id: Uuid, conn: &mut PgConnection) -> Result<usize, DbError> {
-    use crate::schema::machines::dsl::*;
-
-    let count = diesel::delete(machines.find(machine_id)).execute(conn)?;
-    Ok(count)
+) -> Result<Machine, ApiError> {
+    use crate::schema::resources::dsl::*;
+
+    conn.transaction(|conn| {
+        let current = find_for_update(machine_id, conn)?;
+        identity.require_property(Some(current.property))?;
+        helpers::check_if_match(if_match, &current.updated_at)?;
+        let merged = changes.merge(current.clone());
+        identity.require_property(Some(merged.property))?;
+        merged.validate()?;
+
+        changes.updated_at = Some(chrono::Local::now().naive_local());
+        let machine = diesel::update(resources.find(machine_id))
+            .set(&changes)
+            .returning(machine::COLUMNS)
+            .get_result::<Machine>(conn)?;
+        audit::updated(
+            &identity.actor(),
+            Resource::Bookable,
+            machine_id,
+            &current,
+            &machine,
+            conn,
+        )?;
+        Ok(machine)
+    })
+}
+
+/// Loads a machine and locks it until the end of the transaction.
+fn find_for_update(machine_id: Uuid, conn: &mut PgConnection) -> Result<Machine, ApiError> {
+    use crate::schema::resources::dsl::*;
+
+    resources
+        .find(machine_id)
+        .filter(kind.eq(machine::KIND))
+        .select(machine::COLUMNS)
+        .for_update()
+        .first::<Machine>(conn)
+        .optional()?
+        .ok_or_else(|| ApiError::NotFound("Machine not found".to_string()))
+}
+
+fn delete(
+    machine_id: Uuid,
+    if_match: Option<&IfMatch>,
+    identity: &Identity,
+    storage: &dyn Storage,
+    conn: &mut PgConnection,
+) -> Result<usize, ApiError> {
+    use crate::schema::resources::dsl::*;
+
+    conn.transaction(|conn| {
+        let current = find_for_update(machine_id, conn)?;
+        identity.require_property(Some(current.property))?;
+        helpers::check_if_match(if_match, &current.updated_at)?;
+
+        let image_ids = images::ids_of(Subject::Machine(machine_id), conn)?;
+        let count = diesel::delete(resources.find(machine_id)).execute(conn)?;
+        audit::deleted(
+            &identity.actor(),
+            Resource::Bookable,
+            machine_id,
+            &current,
+            conn,
+        )?;
+        images::remove_files(&image_ids, storage)?;
+        Ok(count)
+    })
 }
@@ -5,22 +5,48 @@ use actix_cors::Cors;
 use actix_web::{http, middleware, web, App, HttpServer};
 use diesel::prelude::*;
 use diesel::r2d2::{self, ConnectionManager};
-use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use diesel_migrations::{embed_migrations, EmbeddedMigrations};
 
 pub type DbPool = r2d2::Pool<ConnectionManager<PgConnection>>;
 
+mod api_keys;
+mod audit;
+mod auth;
+mod db;
 mod favicon;
+mod found_items;
 mod helpers;
+mod idempotency;
+mod images;
+mod includes;
 mod items;
+mod jobs;
+mod load_plan;
+mod loans;
 mod machines;
 mod metrics;
 mod models;
+mod nested;
+mod notifications;
+mod oidc;
+mod pagination;
+mod participants;
+mod permissions;
+mod privacy;
 mod properties;
 mod reservations;
+mod resource_kinds;
+mod resources;
 mod roles;
 mod schema;
+mod storage;
+mod tag_sheet;
 mod tea;
 mod users;
+mod validation;
+
+/// The default access log format, followed by who made the request.
+const LOG_FORMAT: &str = r#"%a "%r" %s %b "%{Referer}i" "%{User-Agent}i" %T %{actor}xo"#;
 
 pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations/");
 
@@ -31,72 +57,168 @@ async fn main() -> std::io::Result<()> {
 
     env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
 
-    // set up database connection pool
-    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL");
-    // set up connection pool
-    let manager = ConnectionManager::<PgConnection>::new(database_url);
-    let pool: DbPool = r2d2::Pool::builder()
-        .build(manager)
-        .expect("Failed to create pool.");
-
-    // run the migrations on server startup
-    {
-        println!("Running database migrations...");
-        let conn = pool.get();
-        conn.unwrap()
-            .run_pending_migrations(MIGRATIONS)
-            .expect("Failed to run database migrations.");
-
-        println!("Migrations are up to date!")
-    }
+    // set up database connection pool and run the migrations
+    let pool = db::init()?;
+    auth::bootstrap(&pool)?;
+    let oidc = oidc::Oidc::from_env()?.map(web::Data::new);
+    let storage = web::Data::from(storage::from_env()?);
+    jobs::spawn(pool.clone());
 
     HttpServer::new(move || {
         let cors = Cors::default()
             .allowed_origin("https://app.iperka.com")
             .allowed_origin("http://localhost:5173")
-            .allowed_methods(vec!["GET", "POST", "PUT", "DELETE", "OPTIONS"])
+            .allowed_methods(vec!["GET", "POST", "PUT", "PATCH", "DELETE", "OPTIONS"])
             .allowed_headers(vec![http::header::AUTHORIZATION, http::header::ACCEPT])
             .allowed_header(http::header::CONTENT_TYPE)
+            .allowed_headers(vec![http::header::IF_MATCH, http::header::IF_NONE_MATCH])
+            .allowed_header("Idempotency-Key")
+            .expose_headers(vec![http::header::ETAG])
             .max_age(3600);
 
         App::new()
             .app_data(web::Data::new(pool.clone()))
-            .wrap(middleware::Logger::default())
+            .app_data(storage.clone())
+            .app_data(helpers::json_config())
+            .app_data(helpers::path_config())
+            .app_data(helpers::query_config())
+            .configure(|cfg| {
+                if let Some(oidc) = &oidc {
+                    cfg.app_data(oidc.clone());
+                }
+            })
+            .wrap(auth::Authentication)
+            .wrap(
+                middleware::Logger::new(LOG_FORMAT)
+                    .custom_response_replace("actor", auth::log_actor),
+            )
             .wrap(cors)
             .route("/", web::get().to(|| async { "Beutler REST API" }))
             .service(favicon::favicon)
             .service(tea::index)
             .service(metrics::index)
+            .service(auth::login)
+            .service(auth::refresh)
+            .service(auth::logout)
+            .service(auth::me)
+            .service(auth::sessions)
+            .service(auth::revoke_session)
+            .service(oidc::authorize)
+            .service(oidc::callback)
             .service(users::index)
             .service(users::create)
+            .service(users::property_index)
+            .service(users::property_create)
             .service(users::show)
             .service(users::update)
+            .service(users::partial_update)
             .service(users::destroy)
+            .service(privacy::export)
+            .service(privacy::erase)
             .service(roles::index)
             .service(roles::create)
             .service(roles::show)
             .service(roles::update)
+            .service(roles::partial_update)
             .service(roles::destroy)
+            .service(roles::show_permissions)
+            .service(roles::update_permissions)
+            .service(permissions::index)
+            .service(api_keys::index)
+            .service(api_keys::create)
+            .service(api_keys::destroy)
+            .service(audit::index)
             .service(properties::index)
             .service(properties::create)
             .service(properties::show)
             .service(properties::update)
+            .service(properties::partial_update)
             .service(properties::destroy)
             .service(machines::index)
             .service(machines::create)
+            .service(machines::property_index)
+            .service(machines::property_create)
             .service(machines::show)
             .service(machines::update)
+            .service(machines::partial_update)
             .service(machines::destroy)
+            .service(resources::machine_availability)
+            .service(resource_kinds::index)
+            .service(resource_kinds::create)
+            .service(resource_kinds::show)
+            .service(resource_kinds::update)
+            .service(resource_kinds::partial_update)
+            .service(resource_kinds::destroy)
+            .service(resources::index)
+            .service(resources::create)
+            .service(resources::property_index)
+            .service(resources::property_create)
+            .service(resources::show)
+            .service(resources::availability)
+            .service(resources::update)
+            .service(resources::partial_update)
+            .service(resources::destroy)
             .service(reservations::index)
             .service(reservations::create)
+            .service(reservations::machine_index)
+            .service(reservations::machine_create)
+            .service(reservations::resource_index)
+            .service(reservations::resource_create)
+            .service(reservations::user_index)
+            .service(reservations::user_create)
+            .service(reservations::item_index)
             .service(reservations::show)
             .service(reservations::update)
+            .service(reservations::partial_update)
             .service(reservations::destroy)
+            .service(reservations::update_items)
+            .service(participants::index)
+            .service(participants::create)
+            .service(participants::approve)
+            .service(participants::destroy)
             .service(items::index)
             .service(items::create)
+            .service(items::user_index)
+            .service(items::user_create)
+            .service(items::reservation_index)
+            .service(items::show_by_tag)
             .service(items::show)
             .service(items::update)
+            .service(items::partial_update)
             .service(items::destroy)
+            .service(load_plan::create)
+            .service(tag_sheet::show)
+            .service(found_items::index)
+            .service(found_items::create)
+            .service(found_items::property_index)
+            .service(found_items::property_create)
+            .service(found_items::show)
+            .service(found_items::partial_update)
+            .service(found_items::destroy)
+            .service(found_items::candidates)
+            .service(found_items::match_item)
+            .service(found_items::claim)
+            .service(images::item_index)
+            .service(images::item_create)
+            .service(images::machine_index)
+            .service(images::machine_create)
+            .service(images::found_item_index)
+            .service(images::found_item_create)
+            .service(images::show_file)
+            .service(images::show_thumbnail)
+            .service(images::destroy)
+            .service(loans::index)
+            .service(loans::item_index)
+            .service(loans::user_index)
+            .service(loans::create)
+            .service(loans::show)
+            .service(loans::approve)
+            .service(loans::reject)
+            .service(loans::cancel)
+            .service(loans::confirm_return)
+            .service(notifications::index)
+            .service(notifications::read)
+            .default_service(web::to(helpers::default_service))
     })
     .bind(("0.0.0.0", 8080))?
     .run()
@@ -0,0 +1,66 @@
+use crate::schema::api_keys;
+use crate::validation::{Validate, Validator};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// An API key. The hash of the key itself is never loaded.
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Selectable)]
+#[diesel(table_name = api_keys)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub name: String,
+    pub property: Uuid,
+    pub owner: Uuid,
+    /// First characters of the key, to recognize it in listings.
+    pub prefix: String,
+    pub permissions: Vec<String>,
+    pub expires_at: Option<chrono::NaiveDateTime>,
+    pub revoked_at: Option<chrono::NaiveDateTime>,
+    pub last_used_at: Option<chrono::NaiveDateTime>,
+    pub created_at: chrono::NaiveDateTime,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = api_keys)]
+pub struct NewApiKey<'a> {
+    pub name: &'a str,
+    pub property: Uuid,
+    pub owner: Uuid,
+    pub prefix: &'a str,
+    pub key_hash: &'a str,
+    pub permissions: &'a [String],
+    pub expires_at: Option<chrono::NaiveDateTime>,
+    pub created_at: chrono::NaiveDateTime,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiKeyPayload {
+    pub name: String,
+    pub property: Uuid,
+    pub permissions: Vec<String>,
+    #[serde(default)]
+    pub expires_at: Option<chrono::NaiveDateTime>,
+}
+
+impl Validate for ApiKeyPayload {
+    fn rules(&self, v: &mut Validator) {
+        v.field("name", self.name.as_str())
+            .required()
+            .max_length(100);
+        if let Some(expires_at) = &self.expires_at {
+            let now = chrono::Local::now().naive_local();
+            v.field("expires_at", expires_at).after("now", &now);
+        }
+    }
+}
+
+/// A newly created key, the only response which contains the key itself.
+#[derive(Debug, Serialize)]
+pub struct CreatedApiKey {
+    #[serde(flatten)]
+    pub api_key: ApiKey,
+    pub key: String,
+}
@@ -0,0 +1,30 @@
+use crate::schema::audit_log;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+/// A recorded change, with the changed fields before and after it.
+#[derive(Debug, Serialize, Deserialize, Queryable)]
+pub struct AuditEntry {
+    pub id: Uuid,
+    /// Who made the change, e.g. `user:<id>`, `api_key:<id>` or `system`.
+    pub actor: String,
+    pub action: String,
+    pub resource: String,
+    pub resource_id: Uuid,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = audit_log)]
+pub struct NewAuditEntry<'a> {
+    pub actor: &'a str,
+    pub action: &'a str,
+    pub resource: &'a str,
+    pub resource_id: Uuid,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+    pub created_at: chrono::NaiveDateTime,
+}
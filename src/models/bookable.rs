@@ -0,0 +1,138 @@
+use crate::helpers::non_null;
+use crate::schema::resources;
+use crate::validation::{Validate, Validator};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// A resource of a property which can be reserved, such as a machine, a drying room
+/// or a cargo bike.
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable)]
+pub struct Bookable {
+    pub id: Uuid,
+    pub name: String,
+    pub property: Uuid,
+    pub status: String,
+    /// When the resource is expected to be free again.
+    pub eta: chrono::NaiveDateTime,
+    pub created_at: chrono::NaiveDateTime,
+    pub updated_at: chrono::NaiveDateTime,
+    /// Name of its [`ResourceKind`](super::resource_kind::ResourceKind).
+    pub kind: String,
+    /// How much it holds at once, in the unit of its kind.
+    pub capacity: i32,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = resources)]
+pub struct NewBookable<'a> {
+    pub name: &'a str,
+    pub property: Uuid,
+    pub status: &'a str,
+    pub eta: chrono::NaiveDateTime,
+    pub kind: &'a str,
+    pub capacity: i32,
+    pub created_at: chrono::NaiveDateTime,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BookablePayload {
+    pub name: String,
+    pub property: Uuid,
+    pub kind: String,
+    /// How much it holds at once, in the unit of its kind.
+    pub capacity: i32,
+    pub status: String,
+    pub eta: chrono::NaiveDateTime,
+}
+
+impl Validate for BookablePayload {
+    fn rules(&self, v: &mut Validator) {
+        v.field("name", self.name.as_str())
+            .required()
+            .max_length(100);
+        v.field("kind", self.kind.as_str()).required();
+        v.field("capacity", &self.capacity).between(1, 10_000);
+        v.field("status", self.status.as_str())
+            .required()
+            .max_length(50);
+    }
+}
+
+/// Partial update in JSON Merge Patch format, absent fields are left unchanged.
+#[derive(Debug, Deserialize, AsChangeset)]
+#[diesel(table_name = resources)]
+#[serde(deny_unknown_fields)]
+pub struct BookablePatch {
+    #[serde(default, deserialize_with = "non_null")]
+    pub name: Option<String>,
+    #[serde(default, deserialize_with = "non_null")]
+    pub property: Option<Uuid>,
+    #[serde(default, deserialize_with = "non_null")]
+    pub kind: Option<String>,
+    #[serde(default, deserialize_with = "non_null")]
+    pub capacity: Option<i32>,
+    #[serde(default, deserialize_with = "non_null")]
+    pub status: Option<String>,
+    #[serde(default, deserialize_with = "non_null")]
+    pub eta: Option<chrono::NaiveDateTime>,
+    #[serde(skip)]
+    pub updated_at: Option<chrono::NaiveDateTime>,
+}
+
+impl BookablePatch {
+    /// Returns the payload resulting from applying this patch to `resource`.
+    pub fn merge(&self, resource: Bookable) -> BookablePayload {
+        BookablePayload {
+            name: self.name.clone().unwrap_or(resource.name),
+            property: self.property.unwrap_or(resource.property),
+            kind: self.kind.clone().unwrap_or(resource.kind),
+            capacity: self.capacity.unwrap_or(resource.capacity),
+            status: self.status.clone().unwrap_or(resource.status),
+            eta: self.eta.unwrap_or(resource.eta),
+        }
+    }
+}
+
+/// A stretch of time within an availability window.
+#[derive(Debug, Serialize)]
+pub struct Slot {
+    pub start_time: chrono::NaiveDateTime,
+    pub end_time: chrono::NaiveDateTime,
+}
+
+/// Time in which the resource has capacity left for another reservation.
+#[derive(Debug, Serialize)]
+pub struct FreeSlot {
+    pub start_time: chrono::NaiveDateTime,
+    pub end_time: chrono::NaiveDateTime,
+    /// Capacity left, all of it where nothing is reserved. Where less is left only a
+    /// shared reservation fits, next to the shared ones already there.
+    pub remaining: i64,
+}
+
+/// A shared reservation others can still join.
+#[derive(Debug, Serialize)]
+pub struct OpenSlot {
+    pub reservation: Uuid,
+    pub start_time: chrono::NaiveDateTime,
+    pub end_time: chrono::NaiveDateTime,
+    /// Capacity left next to the owner and the approved participants.
+    pub remaining: i64,
+}
+
+/// The reserved and free time of a resource between `from` and `to`.
+#[derive(Debug, Serialize)]
+pub struct Availability {
+    pub resource: Uuid,
+    pub from: chrono::NaiveDateTime,
+    pub to: chrono::NaiveDateTime,
+    /// Unit of `remaining` in `free` and `open`.
+    pub unit: String,
+    /// Reservations overlapping the window, unclipped.
+    pub reserved: Vec<Slot>,
+    /// Times with capacity left, clipped to the window.
+    pub free: Vec<FreeSlot>,
+    /// Shared reservations in `reserved` which have not ended and have capacity left.
+    pub open: Vec<OpenSlot>,
+}
@@ -0,0 +1,127 @@
+use crate::helpers::{non_null, nullable};
+use crate::models::item::{Item, COLORS};
+use crate::schema::found_items;
+use crate::validation::{Validate, Validator};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// A report of something left behind in a property's laundry room.
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable)]
+pub struct FoundItem {
+    pub id: Uuid,
+    pub property: Uuid,
+    pub reporter: Uuid,
+    pub machine: Option<Uuid>,
+    pub description: String,
+    /// Names from [`COLORS`].
+    pub colors: Vec<String>,
+    pub size: Option<String>,
+    pub found_at: chrono::NaiveDateTime,
+    /// The registered item this is believed to be, its owner has been notified.
+    pub item: Option<Uuid>,
+    pub claimed_by: Option<Uuid>,
+    pub claimed_at: Option<chrono::NaiveDateTime>,
+    /// Set on reports left unclaimed for too long.
+    pub archived_at: Option<chrono::NaiveDateTime>,
+    pub created_at: chrono::NaiveDateTime,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = found_items)]
+pub struct NewFoundItem<'a> {
+    pub property: Uuid,
+    pub reporter: Uuid,
+    pub machine: Option<Uuid>,
+    pub description: &'a str,
+    pub colors: &'a [String],
+    pub size: Option<&'a str>,
+    pub found_at: chrono::NaiveDateTime,
+    pub created_at: chrono::NaiveDateTime,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FoundItemPayload {
+    pub property: Uuid,
+    #[serde(default)]
+    pub machine: Option<Uuid>,
+    pub description: String,
+    #[serde(default)]
+    pub colors: Vec<String>,
+    #[serde(default)]
+    pub size: Option<String>,
+    /// When the item was found, now if absent.
+    #[serde(default)]
+    pub found_at: Option<chrono::NaiveDateTime>,
+}
+
+impl Validate for FoundItemPayload {
+    fn rules(&self, v: &mut Validator) {
+        v.field("description", self.description.as_str())
+            .required()
+            .max_length(500);
+        for (position, color) in self.colors.iter().enumerate() {
+            let field = format!("colors[{}]", position);
+            v.field(&field, color.as_str()).one_of(&COLORS);
+        }
+        if let Some(size) = &self.size {
+            v.field("size", size.as_str()).required().max_length(20);
+        }
+    }
+}
+
+/// Partial update in JSON Merge Patch format, absent fields are left unchanged.
+#[derive(Debug, Deserialize, AsChangeset)]
+#[diesel(table_name = found_items)]
+#[serde(deny_unknown_fields)]
+pub struct FoundItemPatch {
+    #[serde(default, deserialize_with = "nullable")]
+    pub machine: Option<Option<Uuid>>,
+    #[serde(default, deserialize_with = "non_null")]
+    pub description: Option<String>,
+    #[serde(default, deserialize_with = "non_null")]
+    pub colors: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub size: Option<Option<String>>,
+    #[serde(default, deserialize_with = "non_null")]
+    pub found_at: Option<chrono::NaiveDateTime>,
+    #[serde(skip)]
+    pub updated_at: Option<chrono::NaiveDateTime>,
+}
+
+impl FoundItemPatch {
+    /// Returns the payload resulting from applying this patch to `found`.
+    pub fn merge(&self, found: FoundItem) -> FoundItemPayload {
+        FoundItemPayload {
+            property: found.property,
+            machine: self.machine.unwrap_or(found.machine),
+            description: self.description.clone().unwrap_or(found.description),
+            colors: self.colors.clone().unwrap_or(found.colors),
+            size: self.size.clone().unwrap_or(found.size),
+            found_at: Some(self.found_at.unwrap_or(found.found_at)),
+        }
+    }
+}
+
+/// Names the registered item a report is believed to be.
+#[derive(Debug, Deserialize)]
+pub struct FoundItemMatchPayload {
+    pub item: Uuid,
+}
+
+/// Records who took a found item back, the caller if absent.
+#[derive(Debug, Default, Deserialize)]
+pub struct FoundItemClaimPayload {
+    #[serde(default)]
+    pub claimed_by: Option<Uuid>,
+}
+
+/// A registered item which could be the found one, best candidates first.
+#[derive(Debug, Serialize)]
+pub struct FoundItemCandidate {
+    pub item: Item,
+    pub score: u32,
+    /// Why the item is a candidate, e.g. `colors`, `size` or `washed_in_machine`.
+    pub reasons: Vec<&'static str>,
+}
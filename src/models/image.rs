@@ -0,0 +1,36 @@
+use crate::schema::images;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// A photo of an item, a machine or a found item. The file is served at `/images/{id}/file` and a
+/// thumbnail of it at `/images/{id}/thumbnail`.
+#[derive(Debug, Serialize, Deserialize, Queryable)]
+pub struct Image {
+    pub id: Uuid,
+    pub item: Option<Uuid>,
+    pub machine: Option<Uuid>,
+    pub uploader: Uuid,
+    /// `image/jpeg` or `image/png`, whatever was uploaded.
+    pub content_type: String,
+    /// Bytes of the stored file.
+    pub size: i32,
+    pub width: i32,
+    pub height: i32,
+    pub created_at: chrono::NaiveDateTime,
+    pub found_item: Option<Uuid>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = images)]
+pub struct NewImage<'a> {
+    pub id: Uuid,
+    pub item: Option<Uuid>,
+    pub machine: Option<Uuid>,
+    pub found_item: Option<Uuid>,
+    pub uploader: Uuid,
+    pub content_type: &'a str,
+    pub size: i32,
+    pub width: i32,
+    pub height: i32,
+    pub created_at: chrono::NaiveDateTime,
+}
@@ -1,16 +1,70 @@
+use crate::helpers::{non_null, nullable};
 use crate::schema::items;
+use crate::validation::{Validate, Validator};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-#[derive(Debug, Serialize, Deserialize, Queryable)]
+/// Colors an item can be described with.
+pub const COLORS: [&str; 14] = [
+    "white",
+    "black",
+    "gray",
+    "beige",
+    "brown",
+    "red",
+    "orange",
+    "yellow",
+    "green",
+    "blue",
+    "purple",
+    "pink",
+    "multicolor",
+    "other",
+];
+
+pub const FABRICS: [&str; 10] = [
+    "cotton",
+    "linen",
+    "wool",
+    "silk",
+    "polyester",
+    "nylon",
+    "viscose",
+    "denim",
+    "blend",
+    "other",
+];
+
+/// Wash temperatures of care labels in °C, 20 standing for a cold wash.
+pub const WASH_TEMPERATURES: [i16; 7] = [20, 30, 40, 50, 60, 70, 95];
+
+/// Characters of tag codes, generated by the database. Look-alikes such as 0/O and 1/I
+/// are left out so codes can be typed from a worn label.
+pub const TAG_ALPHABET: &str = "23456789ABCDEFGHJKMNPQRSTUVWXYZ";
+
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable)]
 pub struct Item {
     pub id: Uuid,
     pub name: String,
     pub size: String,
-    pub colors: String,
+    /// Names from [`COLORS`].
+    pub colors: Vec<String>,
     pub owner: Uuid,
     pub created_at: chrono::NaiveDateTime,
     pub updated_at: chrono::NaiveDateTime,
+    /// One of [`FABRICS`], the care attributes below are unknown when `None`.
+    pub fabric: Option<String>,
+    /// Highest temperature in °C the item may be washed at.
+    pub max_wash_temperature: Option<i16>,
+    pub tumble_dry: Option<bool>,
+    /// Needs a delicate (gentle) program.
+    pub delicate: bool,
+    /// Dry weight in grams.
+    pub weight: Option<i32>,
+    /// Unique code printed on the item's QR label, see [`TAG_ALPHABET`].
+    pub tag: String,
+    /// Borrower the item is lent to, it is with its owner when `None`.
+    pub holder: Option<Uuid>,
 }
 
 #[derive(Debug, Insertable, Queryable)]
@@ -18,16 +72,105 @@ pub struct Item {
 pub struct NewItem<'a> {
     pub name: &'a str,
     pub size: &'a str,
-    pub colors: &'a str,
+    pub colors: &'a [String],
     pub owner: Uuid,
     pub created_at: chrono::NaiveDateTime,
     pub updated_at: chrono::NaiveDateTime,
+    pub fabric: Option<&'a str>,
+    pub max_wash_temperature: Option<i16>,
+    pub tumble_dry: Option<bool>,
+    pub delicate: bool,
+    pub weight: Option<i32>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ItemPayload {
     pub name: String,
     pub size: String,
-    pub colors: String,
+    pub colors: Vec<String>,
     pub owner: Uuid,
+    #[serde(default)]
+    pub fabric: Option<String>,
+    #[serde(default)]
+    pub max_wash_temperature: Option<i16>,
+    #[serde(default)]
+    pub tumble_dry: Option<bool>,
+    #[serde(default)]
+    pub delicate: bool,
+    #[serde(default)]
+    pub weight: Option<i32>,
+}
+
+impl Validate for ItemPayload {
+    fn rules(&self, v: &mut Validator) {
+        v.field("name", self.name.as_str())
+            .required()
+            .max_length(100);
+        v.field("size", self.size.as_str())
+            .required()
+            .max_length(20);
+        if self.colors.is_empty() {
+            v.field("colors", "").required();
+        }
+        for (position, color) in self.colors.iter().enumerate() {
+            let field = format!("colors[{}]", position);
+            v.field(&field, color.as_str()).one_of(&COLORS);
+        }
+        if let Some(fabric) = &self.fabric {
+            v.field("fabric", fabric.as_str()).one_of(&FABRICS);
+        }
+        if let Some(temperature) = &self.max_wash_temperature {
+            v.field("max_wash_temperature", temperature)
+                .one_of(&WASH_TEMPERATURES);
+        }
+        if let Some(weight) = &self.weight {
+            v.field("weight", weight).between(1, 20_000);
+        }
+    }
+}
+
+/// Partial update in JSON Merge Patch format, absent fields are left unchanged.
+#[derive(Debug, Deserialize, AsChangeset)]
+#[diesel(table_name = items)]
+#[serde(deny_unknown_fields)]
+pub struct ItemPatch {
+    #[serde(default, deserialize_with = "non_null")]
+    pub name: Option<String>,
+    #[serde(default, deserialize_with = "non_null")]
+    pub size: Option<String>,
+    #[serde(default, deserialize_with = "non_null")]
+    pub colors: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "non_null")]
+    pub owner: Option<Uuid>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub fabric: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub max_wash_temperature: Option<Option<i16>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub tumble_dry: Option<Option<bool>>,
+    #[serde(default, deserialize_with = "non_null")]
+    pub delicate: Option<bool>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub weight: Option<Option<i32>>,
+    #[serde(skip)]
+    pub updated_at: Option<chrono::NaiveDateTime>,
+}
+
+impl ItemPatch {
+    /// Returns the payload resulting from applying this patch to `item`.
+    pub fn merge(&self, item: Item) -> ItemPayload {
+        ItemPayload {
+            name: self.name.clone().unwrap_or(item.name),
+            size: self.size.clone().unwrap_or(item.size),
+            colors: self.colors.clone().unwrap_or(item.colors),
+            owner: self.owner.unwrap_or(item.owner),
+            fabric: self.fabric.clone().unwrap_or(item.fabric),
+            max_wash_temperature: self
+                .max_wash_temperature
+                .unwrap_or(item.max_wash_temperature),
+            tumble_dry: self.tumble_dry.unwrap_or(item.tumble_dry),
+            delicate: self.delicate.unwrap_or(item.delicate),
+            weight: self.weight.unwrap_or(item.weight),
+        }
+    }
 }
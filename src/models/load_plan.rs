@@ -0,0 +1,74 @@
+use crate::validation::{Validate, Validator};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Capacity of a machine in grams of dry laundry when none is given.
+pub const DEFAULT_CAPACITY: i32 = 7_000;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LoadPlanPayload {
+    /// Items to wash, all of the user's items if absent.
+    #[serde(default)]
+    pub items: Option<Vec<Uuid>>,
+    /// Grams of dry laundry a load may weigh. When suggesting reservations, the largest
+    /// machine of the property if absent, otherwise [`DEFAULT_CAPACITY`].
+    #[serde(default)]
+    pub capacity: Option<i32>,
+    /// Also propose a free machine and time for every load.
+    #[serde(default)]
+    pub suggest_reservations: bool,
+    /// Earliest start of proposed reservations, now if absent.
+    #[serde(default)]
+    pub earliest_start: Option<chrono::NaiveDateTime>,
+}
+
+impl Validate for LoadPlanPayload {
+    fn rules(&self, v: &mut Validator) {
+        if self.items.as_ref().is_some_and(Vec::is_empty) {
+            v.field("items", "").required();
+        }
+        if let Some(capacity) = &self.capacity {
+            v.field("capacity", capacity).between(1_000, 20_000);
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct LoadPlan {
+    pub loads: Vec<Load>,
+}
+
+/// Items which can be washed together, with the program to wash them with.
+#[derive(Debug, Serialize)]
+pub struct Load {
+    pub items: Vec<Uuid>,
+    /// Estimated dry weight in grams.
+    pub weight: i32,
+    /// `whites`, `colors` or `darks`.
+    pub color_group: &'static str,
+    pub program: Program,
+    pub reservation: Option<SuggestedReservation>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Program {
+    /// `cotton`, `mixed`, `synthetics`, `wool` or `delicates`.
+    pub name: &'static str,
+    /// °C, never above the care label of any item in the load.
+    pub temperature: i16,
+    /// Spin speed in rpm.
+    pub spin: i16,
+    /// Whether every item in the load may be tumble dried.
+    pub tumble_dry: bool,
+    pub duration_minutes: i64,
+}
+
+/// A free slot on a machine of the user's property. It is not reserved yet.
+#[derive(Debug, Serialize)]
+pub struct SuggestedReservation {
+    pub machine: Uuid,
+    pub start_time: chrono::NaiveDateTime,
+    pub end_time: chrono::NaiveDateTime,
+    /// Share of the machine to reserve, in the unit of its kind.
+    pub amount: i32,
+}
@@ -0,0 +1,58 @@
+use crate::schema::loans;
+use crate::validation::{Validate, Validator};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+pub const REQUESTED: &str = "requested";
+/// The borrower holds the item until its owner confirms the return.
+pub const APPROVED: &str = "approved";
+pub const REJECTED: &str = "rejected";
+pub const CANCELLED: &str = "cancelled";
+pub const RETURNED: &str = "returned";
+
+/// A tenant borrowing an item of a neighbour.
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable)]
+pub struct Loan {
+    pub id: Uuid,
+    pub item: Uuid,
+    pub borrower: Uuid,
+    /// [`REQUESTED`], then [`APPROVED`] or [`REJECTED`] by the owner or [`CANCELLED`] by
+    /// the borrower, and finally [`RETURNED`].
+    pub status: String,
+    /// Note of the borrower to the owner.
+    pub message: Option<String>,
+    pub due_at: chrono::NaiveDateTime,
+    pub approved_at: Option<chrono::NaiveDateTime>,
+    pub returned_at: Option<chrono::NaiveDateTime>,
+    /// When the borrower was last reminded of an overdue return.
+    pub reminded_at: Option<chrono::NaiveDateTime>,
+    pub created_at: chrono::NaiveDateTime,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = loans)]
+pub struct NewLoan<'a> {
+    pub item: Uuid,
+    pub borrower: Uuid,
+    pub message: Option<&'a str>,
+    pub due_at: chrono::NaiveDateTime,
+    pub created_at: chrono::NaiveDateTime,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+/// Request to borrow an item, the caller being the borrower.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LoanPayload {
+    pub due_at: chrono::NaiveDateTime,
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+impl Validate for LoanPayload {
+    fn rules(&self, v: &mut Validator) {
+        if let Some(message) = &self.message {
+            v.field("message", message.as_str()).max_length(500);
+        }
+    }
+}
@@ -1,8 +1,35 @@
-use crate::schema::machines;
+use crate::helpers::non_null;
+use crate::schema::resources;
+use crate::validation::{Validate, Validator};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-#[derive(Debug, Serialize, Deserialize, Queryable)]
+/// Kind of the resources which are machines.
+pub const KIND: &str = "machine";
+
+/// Columns of a [`Machine`] in the `resources` table.
+pub const COLUMNS: (
+    resources::id,
+    resources::name,
+    resources::property,
+    resources::status,
+    resources::eta,
+    resources::created_at,
+    resources::updated_at,
+    resources::capacity,
+) = (
+    resources::id,
+    resources::name,
+    resources::property,
+    resources::status,
+    resources::eta,
+    resources::created_at,
+    resources::updated_at,
+    resources::capacity,
+);
+
+/// A washing machine or dryer, a resource of the kind [`KIND`].
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable)]
 pub struct Machine {
     pub id: Uuid,
     pub name: String,
@@ -11,15 +38,19 @@ pub struct Machine {
     pub eta: chrono::NaiveDateTime,
     pub created_at: chrono::NaiveDateTime,
     pub updated_at: chrono::NaiveDateTime,
+    /// Kilograms of dry laundry.
+    pub capacity: i32,
 }
 
 #[derive(Debug, Insertable, Queryable)]
-#[diesel(table_name = machines)]
+#[diesel(table_name = resources)]
 pub struct NewMachine<'a> {
     pub name: &'a str,
     pub property: Uuid,
     pub status: &'a str,
     pub eta: chrono::NaiveDateTime,
+    pub kind: &'a str,
+    pub capacity: i32,
     pub created_at: chrono::NaiveDateTime,
     pub updated_at: chrono::NaiveDateTime,
 }
@@ -30,4 +61,50 @@ pub struct MachinePayload {
     pub property: Uuid,
     pub status: String,
     pub eta: chrono::NaiveDateTime,
+    /// Kilograms of dry laundry.
+    pub capacity: i32,
+}
+
+impl Validate for MachinePayload {
+    fn rules(&self, v: &mut Validator) {
+        v.field("name", self.name.as_str())
+            .required()
+            .max_length(100);
+        v.field("status", self.status.as_str())
+            .required()
+            .max_length(50);
+        v.field("capacity", &self.capacity).between(1, 100);
+    }
+}
+
+/// Partial update in JSON Merge Patch format, absent fields are left unchanged.
+#[derive(Debug, Deserialize, AsChangeset)]
+#[diesel(table_name = resources)]
+#[serde(deny_unknown_fields)]
+pub struct MachinePatch {
+    #[serde(default, deserialize_with = "non_null")]
+    pub name: Option<String>,
+    #[serde(default, deserialize_with = "non_null")]
+    pub property: Option<Uuid>,
+    #[serde(default, deserialize_with = "non_null")]
+    pub status: Option<String>,
+    #[serde(default, deserialize_with = "non_null")]
+    pub eta: Option<chrono::NaiveDateTime>,
+    #[serde(default, deserialize_with = "non_null")]
+    pub capacity: Option<i32>,
+    #[serde(skip)]
+    pub updated_at: Option<chrono::NaiveDateTime>,
+}
+
+impl MachinePatch {
+    /// Returns the payload resulting from applying this patch to `machine`.
+    pub fn merge(&self, machine: Machine) -> MachinePayload {
+        MachinePayload {
+            name: self.name.clone().unwrap_or(machine.name),
+            property: self.property.unwrap_or(machine.property),
+            status: self.status.clone().unwrap_or(machine.status),
+            eta: self.eta.unwrap_or(machine.eta),
+            capacity: self.capacity.unwrap_or(machine.capacity),
+        }
+    }
 }
@@ -1,6 +1,20 @@
+pub mod api_key;
+pub mod audit_entry;
+pub mod bookable;
+pub mod found_item;
+pub mod idempotency_key;
+pub mod image;
 pub mod item;
+pub mod load_plan;
+pub mod loan;
 pub mod machine;
+pub mod notification;
+pub mod oidc;
+pub mod participant;
+pub mod permission;
 pub mod property;
 pub mod reservation;
+pub mod resource_kind;
 pub mod role;
+pub mod session;
 pub mod user;
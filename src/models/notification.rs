@@ -0,0 +1,27 @@
+use crate::schema::notifications;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// A message for a user, e.g. that one of their items was found.
+#[derive(Debug, Serialize, Deserialize, Queryable)]
+pub struct Notification {
+    pub id: Uuid,
+    pub recipient: Uuid,
+    /// What happened, e.g. `found_item`.
+    pub kind: String,
+    /// The resource the notification is about.
+    pub subject: Option<Uuid>,
+    pub message: String,
+    pub read_at: Option<chrono::NaiveDateTime>,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = notifications)]
+pub struct NewNotification<'a> {
+    pub recipient: Uuid,
+    pub kind: &'a str,
+    pub subject: Option<Uuid>,
+    pub message: &'a str,
+    pub created_at: chrono::NaiveDateTime,
+}
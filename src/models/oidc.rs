@@ -0,0 +1,33 @@
+use crate::schema::oidc_logins;
+use serde::Deserialize;
+
+/// A login started at the identity provider, waiting for its callback.
+#[derive(Debug, Insertable, Queryable)]
+#[diesel(table_name = oidc_logins)]
+pub struct OidcLogin {
+    pub state: String,
+    pub code_verifier: String,
+    pub nonce: String,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+/// Query of the redirect back from the identity provider.
+#[derive(Debug, Deserialize)]
+pub struct CallbackParams {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+/// Claims of an ID token used to find or create the user.
+#[derive(Debug, Deserialize)]
+pub struct IdTokenClaims {
+    pub sub: String,
+    pub nonce: Option<String>,
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    pub name: Option<String>,
+    pub preferred_username: Option<String>,
+}
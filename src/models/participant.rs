@@ -0,0 +1,49 @@
+use crate::schema::participants;
+use crate::validation::{Validate, Validator};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+pub const REQUESTED: &str = "requested";
+/// Only approved participants take up capacity of the reservation.
+pub const APPROVED: &str = "approved";
+
+/// A user joining the shared reservation of another.
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable)]
+pub struct Participant {
+    pub id: Uuid,
+    pub reservation: Uuid,
+    pub participant: Uuid,
+    /// Share of the resource's capacity, in the unit of its kind.
+    pub amount: i32,
+    /// [`REQUESTED`] until the owner of the reservation approves, then [`APPROVED`].
+    pub status: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = participants)]
+pub struct NewParticipant {
+    pub reservation: Uuid,
+    pub participant: Uuid,
+    pub amount: i32,
+    pub created_at: chrono::NaiveDateTime,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+/// Request to join a shared reservation, the caller being the participant.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ParticipantPayload {
+    #[serde(default = "default_amount")]
+    pub amount: i32,
+}
+
+fn default_amount() -> i32 {
+    1
+}
+
+impl Validate for ParticipantPayload {
+    fn rules(&self, v: &mut Validator) {
+        v.field("amount", &self.amount).between(1, 10_000);
+    }
+}
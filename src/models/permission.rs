@@ -0,0 +1,14 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, Queryable)]
+pub struct Permission {
+    pub name: String,
+    pub description: String,
+}
+
+/// Replaces the permissions granted to a role.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RolePermissionsPayload {
+    pub permissions: Vec<String>,
+}
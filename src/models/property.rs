@@ -1,8 +1,10 @@
+use crate::helpers::{non_null, nullable};
 use crate::schema::properties;
+use crate::validation::{Validate, Validator};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-#[derive(Debug, Serialize, Deserialize, Queryable)]
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable)]
 pub struct Property {
     pub id: Uuid,
     pub name: String,
@@ -13,6 +15,7 @@ pub struct Property {
     pub owner: Uuid,
     pub created_at: chrono::NaiveDateTime,
     pub updated_at: chrono::NaiveDateTime,
+    pub country: String,
 }
 
 #[derive(Debug, Insertable, Queryable)]
@@ -23,6 +26,7 @@ pub struct NewProperty<'a> {
     pub address2: Option<&'a str>,
     pub city: &'a str,
     pub zip: &'a str,
+    pub country: &'a str,
     pub owner: Uuid,
     pub created_at: chrono::NaiveDateTime,
     pub updated_at: chrono::NaiveDateTime,
@@ -35,5 +39,70 @@ pub struct PropertyPayload {
     pub address2: Option<String>,
     pub city: String,
     pub zip: String,
+    #[serde(default = "default_country")]
+    pub country: String,
     pub owner: Uuid,
 }
+
+fn default_country() -> String {
+    "CH".to_string()
+}
+
+impl Validate for PropertyPayload {
+    fn rules(&self, v: &mut Validator) {
+        v.field("name", self.name.as_str())
+            .required()
+            .max_length(255);
+        v.field("address", self.address.as_str())
+            .required()
+            .max_length(255);
+        if let Some(address2) = &self.address2 {
+            v.field("address2", address2.as_str()).max_length(255);
+        }
+        v.field("city", self.city.as_str())
+            .required()
+            .max_length(255);
+        v.field("country", self.country.as_str()).country_code();
+        v.field("zip", self.zip.as_str())
+            .required()
+            .postal_code(&self.country);
+    }
+}
+
+/// Partial update in JSON Merge Patch format, absent fields are left unchanged.
+#[derive(Debug, Deserialize, AsChangeset)]
+#[diesel(table_name = properties)]
+#[serde(deny_unknown_fields)]
+pub struct PropertyPatch {
+    #[serde(default, deserialize_with = "non_null")]
+    pub name: Option<String>,
+    #[serde(default, deserialize_with = "non_null")]
+    pub address: Option<String>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub address2: Option<Option<String>>,
+    #[serde(default, deserialize_with = "non_null")]
+    pub city: Option<String>,
+    #[serde(default, deserialize_with = "non_null")]
+    pub zip: Option<String>,
+    #[serde(default, deserialize_with = "non_null")]
+    pub country: Option<String>,
+    #[serde(default, deserialize_with = "non_null")]
+    pub owner: Option<Uuid>,
+    #[serde(skip)]
+    pub updated_at: Option<chrono::NaiveDateTime>,
+}
+
+impl PropertyPatch {
+    /// Returns the payload resulting from applying this patch to `property`.
+    pub fn merge(&self, property: Property) -> PropertyPayload {
+        PropertyPayload {
+            name: self.name.clone().unwrap_or(property.name),
+            address: self.address.clone().unwrap_or(property.address),
+            address2: self.address2.clone().unwrap_or(property.address2),
+            city: self.city.clone().unwrap_or(property.city),
+            zip: self.zip.clone().unwrap_or(property.zip),
+            country: self.country.clone().unwrap_or(property.country),
+            owner: self.owner.unwrap_or(property.owner),
+        }
+    }
+}
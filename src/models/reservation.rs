@@ -1,27 +1,53 @@
+use crate::helpers::non_null;
 use crate::schema::reservations;
-use serde::{Deserialize, Serialize};
+use crate::validation::{Validate, Validator};
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Serialize, Serializer};
 use uuid::Uuid;
 
-#[derive(Debug, Serialize, Deserialize, Queryable)]
+#[derive(Debug, Clone, Deserialize, Queryable)]
 pub struct Reservation {
     pub id: Uuid,
+    /// The machine or other resource reserved.
+    pub resource: Uuid,
     pub owner: Uuid,
-    pub machine: Uuid,
     pub start_time: chrono::NaiveDateTime,
     pub end_time: chrono::NaiveDateTime,
+    /// Whether other users may join as [`Participant`](super::participant::Participant)s.
     pub shared: bool,
     pub created_at: chrono::NaiveDateTime,
     pub updated_at: chrono::NaiveDateTime,
+    /// Share of the resource's capacity the owner uses, in the unit of its kind.
+    pub amount: i32,
+}
+
+/// Also writes `resource` as `machine`, the name older clients know it by.
+impl Serialize for Reservation {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Reservation", 10)?;
+        state.serialize_field("id", &self.id)?;
+        state.serialize_field("resource", &self.resource)?;
+        state.serialize_field("machine", &self.resource)?;
+        state.serialize_field("owner", &self.owner)?;
+        state.serialize_field("start_time", &self.start_time)?;
+        state.serialize_field("end_time", &self.end_time)?;
+        state.serialize_field("shared", &self.shared)?;
+        state.serialize_field("created_at", &self.created_at)?;
+        state.serialize_field("updated_at", &self.updated_at)?;
+        state.serialize_field("amount", &self.amount)?;
+        state.end()
+    }
 }
 
 #[derive(Debug, Insertable, Queryable)]
 #[diesel(table_name = reservations)]
 pub struct NewReservation {
     pub owner: Uuid,
-    pub machine: Uuid,
+    pub resource: Uuid,
     pub start_time: chrono::NaiveDateTime,
     pub end_time: chrono::NaiveDateTime,
     pub shared: bool,
+    pub amount: i32,
     pub created_at: chrono::NaiveDateTime,
     pub updated_at: chrono::NaiveDateTime,
 }
@@ -29,8 +55,60 @@ pub struct NewReservation {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ReservationPayload {
     pub owner: Uuid,
-    pub machine: Uuid,
+    #[serde(alias = "machine")]
+    pub resource: Uuid,
     pub start_time: chrono::NaiveDateTime,
     pub end_time: chrono::NaiveDateTime,
     pub shared: bool,
+    /// Share of the resource's capacity, in the unit of its kind.
+    pub amount: i32,
+}
+
+impl Validate for ReservationPayload {
+    fn rules(&self, v: &mut Validator) {
+        v.field("end_time", &self.end_time)
+            .after("start_time", &self.start_time);
+        v.field("amount", &self.amount).between(1, 10_000);
+    }
+}
+
+/// Partial update in JSON Merge Patch format, absent fields are left unchanged.
+#[derive(Debug, Deserialize, AsChangeset)]
+#[diesel(table_name = reservations)]
+#[serde(deny_unknown_fields)]
+pub struct ReservationPatch {
+    #[serde(default, deserialize_with = "non_null")]
+    pub owner: Option<Uuid>,
+    #[serde(default, alias = "machine", deserialize_with = "non_null")]
+    pub resource: Option<Uuid>,
+    #[serde(default, deserialize_with = "non_null")]
+    pub start_time: Option<chrono::NaiveDateTime>,
+    #[serde(default, deserialize_with = "non_null")]
+    pub end_time: Option<chrono::NaiveDateTime>,
+    #[serde(default, deserialize_with = "non_null")]
+    pub shared: Option<bool>,
+    #[serde(default, deserialize_with = "non_null")]
+    pub amount: Option<i32>,
+    #[serde(skip)]
+    pub updated_at: Option<chrono::NaiveDateTime>,
+}
+
+impl ReservationPatch {
+    /// Returns the payload resulting from applying this patch to `reservation`.
+    pub fn merge(&self, reservation: Reservation) -> ReservationPayload {
+        ReservationPayload {
+            owner: self.owner.unwrap_or(reservation.owner),
+            resource: self.resource.unwrap_or(reservation.resource),
+            start_time: self.start_time.unwrap_or(reservation.start_time),
+            end_time: self.end_time.unwrap_or(reservation.end_time),
+            shared: self.shared.unwrap_or(reservation.shared),
+            amount: self.amount.unwrap_or(reservation.amount),
+        }
+    }
+}
+
+/// The items washed in a reservation, replacing those recorded before.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReservationItemsPayload {
+    pub items: Vec<Uuid>,
 }
@@ -0,0 +1,111 @@
+use crate::helpers::{non_null, nullable};
+use crate::schema::resource_kinds;
+use crate::validation::{Validate, Validator};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Capacity counted in places, such as the racks of a drying room.
+pub const SLOTS: &str = "slots";
+/// Capacity counted in kilograms, such as the load of a washing machine.
+pub const KG: &str = "kg";
+pub const UNITS: [&str; 2] = [SLOTS, KG];
+
+/// A kind of bookable resource, such as `machine` or `cargo_bike`, with the rules
+/// reservations of its resources have to follow.
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable)]
+pub struct ResourceKind {
+    pub id: Uuid,
+    pub name: String,
+    /// Unit of the capacity of resources of this kind, one of [`UNITS`].
+    pub unit: String,
+    /// Longest a reservation may last, unlimited if `None`.
+    pub max_duration_minutes: Option<i32>,
+    /// How far ahead a reservation may start, unlimited if `None`.
+    pub max_advance_days: Option<i32>,
+    /// Reservations a user may have which have not ended yet, unlimited if `None`.
+    pub max_upcoming: Option<i32>,
+    pub created_at: chrono::NaiveDateTime,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = resource_kinds)]
+pub struct NewResourceKind<'a> {
+    pub name: &'a str,
+    pub unit: &'a str,
+    pub max_duration_minutes: Option<i32>,
+    pub max_advance_days: Option<i32>,
+    pub max_upcoming: Option<i32>,
+    pub created_at: chrono::NaiveDateTime,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ResourceKindPayload {
+    pub name: String,
+    #[serde(default = "default_unit")]
+    pub unit: String,
+    #[serde(default)]
+    pub max_duration_minutes: Option<i32>,
+    #[serde(default)]
+    pub max_advance_days: Option<i32>,
+    #[serde(default)]
+    pub max_upcoming: Option<i32>,
+}
+
+fn default_unit() -> String {
+    SLOTS.to_string()
+}
+
+impl Validate for ResourceKindPayload {
+    fn rules(&self, v: &mut Validator) {
+        v.field("name", self.name.as_str())
+            .required()
+            .max_length(50);
+        v.field("unit", self.unit.as_str()).one_of(&UNITS);
+        if let Some(minutes) = &self.max_duration_minutes {
+            v.field("max_duration_minutes", minutes).between(1, 525_600);
+        }
+        if let Some(days) = &self.max_advance_days {
+            v.field("max_advance_days", days).between(1, 365);
+        }
+        if let Some(upcoming) = &self.max_upcoming {
+            v.field("max_upcoming", upcoming).between(1, 100);
+        }
+    }
+}
+
+/// Partial update in JSON Merge Patch format, absent fields are left unchanged and
+/// rules set to `null` are lifted.
+#[derive(Debug, Deserialize, AsChangeset)]
+#[diesel(table_name = resource_kinds)]
+#[serde(deny_unknown_fields)]
+pub struct ResourceKindPatch {
+    #[serde(default, deserialize_with = "non_null")]
+    pub name: Option<String>,
+    #[serde(default, deserialize_with = "non_null")]
+    pub unit: Option<String>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub max_duration_minutes: Option<Option<i32>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub max_advance_days: Option<Option<i32>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub max_upcoming: Option<Option<i32>>,
+    #[serde(skip)]
+    pub updated_at: Option<chrono::NaiveDateTime>,
+}
+
+impl ResourceKindPatch {
+    /// Returns the payload resulting from applying this patch to `kind`.
+    pub fn merge(&self, kind: ResourceKind) -> ResourceKindPayload {
+        ResourceKindPayload {
+            name: self.name.clone().unwrap_or(kind.name),
+            unit: self.unit.clone().unwrap_or(kind.unit),
+            max_duration_minutes: self
+                .max_duration_minutes
+                .unwrap_or(kind.max_duration_minutes),
+            max_advance_days: self.max_advance_days.unwrap_or(kind.max_advance_days),
+            max_upcoming: self.max_upcoming.unwrap_or(kind.max_upcoming),
+        }
+    }
+}
@@ -1,8 +1,10 @@
+use crate::helpers::non_null;
 use crate::schema::roles;
+use crate::validation::{Validate, Validator};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-#[derive(Debug, Serialize, Deserialize, Queryable)]
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable)]
 pub struct Role {
     pub id: Uuid,
     pub name: String,
@@ -22,3 +24,31 @@ pub struct NewRole<'a> {
 pub struct RolePayload {
     pub name: String,
 }
+
+impl Validate for RolePayload {
+    fn rules(&self, v: &mut Validator) {
+        v.field("name", self.name.as_str())
+            .required()
+            .max_length(50);
+    }
+}
+
+/// Partial update in JSON Merge Patch format, absent fields are left unchanged.
+#[derive(Debug, Deserialize, AsChangeset)]
+#[diesel(table_name = roles)]
+#[serde(deny_unknown_fields)]
+pub struct RolePatch {
+    #[serde(default, deserialize_with = "non_null")]
+    pub name: Option<String>,
+    #[serde(skip)]
+    pub updated_at: Option<chrono::NaiveDateTime>,
+}
+
+impl RolePatch {
+    /// Returns the payload resulting from applying this patch to `role`.
+    pub fn merge(&self, role: Role) -> RolePayload {
+        RolePayload {
+            name: self.name.clone().unwrap_or(role.name),
+        }
+    }
+}
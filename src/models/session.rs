@@ -0,0 +1,56 @@
+use crate::schema::sessions;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// A login session. The token hashes are never loaded.
+#[derive(Debug, Serialize, Deserialize, Queryable, Selectable)]
+#[diesel(table_name = sessions)]
+pub struct Session {
+    pub id: Uuid,
+    pub owner: Uuid,
+    pub expires_at: chrono::NaiveDateTime,
+    pub refresh_expires_at: chrono::NaiveDateTime,
+    pub revoked_at: Option<chrono::NaiveDateTime>,
+    pub created_at: chrono::NaiveDateTime,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = sessions)]
+pub struct NewSession<'a> {
+    pub id: Uuid,
+    pub owner: Uuid,
+    pub token_hash: &'a str,
+    pub refresh_hash: &'a str,
+    pub expires_at: chrono::NaiveDateTime,
+    pub refresh_expires_at: chrono::NaiveDateTime,
+    pub created_at: chrono::NaiveDateTime,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+/// Credentials of `POST /auth/login`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LoginPayload {
+    pub email: String,
+    pub password: String,
+}
+
+/// Body of `POST /auth/refresh`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RefreshPayload {
+    pub refresh_token: String,
+}
+
+/// Tokens issued for a session. They are only ever returned once, the database
+/// stores their hashes.
+#[derive(Debug, Serialize)]
+pub struct Tokens {
+    pub session: Uuid,
+    pub token_type: &'static str,
+    pub access_token: String,
+    pub expires_at: chrono::NaiveDateTime,
+    pub refresh_token: String,
+    pub refresh_expires_at: chrono::NaiveDateTime,
+}
@@ -1,8 +1,22 @@
+use crate::helpers::{non_null, nullable};
+use crate::models::api_key::ApiKey;
+use crate::models::audit_entry::AuditEntry;
+use crate::models::found_item::FoundItem;
+use crate::models::image::Image;
+use crate::models::item::Item;
+use crate::models::loan::Loan;
+use crate::models::notification::Notification;
+use crate::models::participant::Participant;
+use crate::models::property::Property;
+use crate::models::reservation::Reservation;
+use crate::models::role::Role;
+use crate::models::session::Session;
 use crate::schema::users;
+use crate::validation::{Validate, Validator};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-#[derive(Debug, Serialize, Deserialize, Queryable)]
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable)]
 pub struct User {
     pub id: Uuid,
     pub name: String,
@@ -10,6 +24,14 @@ pub struct User {
     pub property: Option<Uuid>,
     pub created_at: chrono::NaiveDateTime,
     pub updated_at: chrono::NaiveDateTime,
+    pub email: Option<String>,
+    #[serde(skip)]
+    pub password_hash: Option<String>,
+    /// Subject of the linked OpenID Connect account.
+    #[serde(skip)]
+    pub oidc_subject: Option<String>,
+    /// When the user's personal data was erased, see `POST /users/{id}/erase`.
+    pub erased_at: Option<chrono::NaiveDateTime>,
 }
 
 #[derive(Debug, Insertable, Queryable, Serialize)]
@@ -20,6 +42,11 @@ pub struct NewUser<'a> {
     pub property: Option<Uuid>,
     pub created_at: chrono::NaiveDateTime,
     pub updated_at: chrono::NaiveDateTime,
+    pub email: Option<String>,
+    #[serde(skip)]
+    pub password_hash: Option<String>,
+    #[serde(skip)]
+    pub oidc_subject: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -27,4 +54,87 @@ pub struct UserPayload {
     pub name: String,
     pub role: Uuid,
     pub property: Option<Uuid>,
+    #[serde(default)]
+    pub email: Option<String>,
+    /// New password, an absent password leaves the current one unchanged.
+    #[serde(default, skip_serializing)]
+    pub password: Option<String>,
+}
+
+impl Validate for UserPayload {
+    fn rules(&self, v: &mut Validator) {
+        v.field("name", self.name.as_str())
+            .required()
+            .max_length(100);
+        if let Some(email) = &self.email {
+            v.field("email", email.as_str()).email().max_length(255);
+        }
+        if let Some(password) = &self.password {
+            v.field("password", password.as_str())
+                .min_length(8)
+                .max_length(1024);
+        }
+    }
+}
+
+/// Partial update in JSON Merge Patch format, absent fields are left unchanged.
+#[derive(Debug, Deserialize, AsChangeset)]
+#[diesel(table_name = users)]
+#[serde(deny_unknown_fields)]
+pub struct UserPatch {
+    #[serde(default, deserialize_with = "non_null")]
+    pub name: Option<String>,
+    #[serde(default, deserialize_with = "non_null")]
+    pub role: Option<Uuid>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub property: Option<Option<Uuid>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub email: Option<Option<String>>,
+    /// Replaced by its hash before the changes are written.
+    #[serde(default, deserialize_with = "non_null")]
+    #[diesel(column_name = password_hash)]
+    pub password: Option<String>,
+    #[serde(skip)]
+    pub updated_at: Option<chrono::NaiveDateTime>,
+}
+
+impl UserPatch {
+    /// Returns the payload resulting from applying this patch to `user`.
+    pub fn merge(&self, user: User) -> UserPayload {
+        UserPayload {
+            name: self.name.clone().unwrap_or(user.name),
+            role: self.role.unwrap_or(user.role),
+            property: self.property.unwrap_or(user.property),
+            email: self.email.clone().unwrap_or(user.email),
+            password: self.password.clone(),
+        }
+    }
+}
+
+/// Everything stored about a user, as handed out by `GET /users/{id}/export`.
+#[derive(Debug, Serialize)]
+pub struct UserExport {
+    pub profile: User,
+    pub memberships: Memberships,
+    pub reservations: Vec<Reservation>,
+    /// The user's share of others' reservations.
+    pub participations: Vec<Participant>,
+    pub items: Vec<Item>,
+    pub sessions: Vec<Session>,
+    pub api_keys: Vec<ApiKey>,
+    pub notifications: Vec<Notification>,
+    /// Found items the user reported or claimed.
+    pub found_items: Vec<FoundItem>,
+    /// Photos the user uploaded and those of their items.
+    pub images: Vec<Image>,
+    /// Loans the user borrowed and those of their items.
+    pub loans: Vec<Loan>,
+    pub audit: Vec<AuditEntry>,
+}
+
+/// The role and property a user belongs to.
+#[derive(Debug, Serialize)]
+pub struct Memberships {
+    pub role: Role,
+    pub property: Option<Property>,
 }
@@ -0,0 +1,107 @@
+use diesel::dsl::exists;
+use diesel::prelude::*;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::helpers::{self, ApiError, FieldError};
+
+/// Parent resource of a nested route such as `/properties/{id}/machines`.
+#[derive(Debug, Clone, Copy)]
+pub enum Parent {
+    Property(Uuid),
+    Machine(Uuid),
+    Resource(Uuid),
+    User(Uuid),
+    Item(Uuid),
+    Reservation(Uuid),
+    FoundItem(Uuid),
+}
+
+impl Parent {
+    pub fn id(&self) -> Uuid {
+        match *self {
+            Parent::Property(id)
+            | Parent::Machine(id)
+            | Parent::Resource(id)
+            | Parent::User(id)
+            | Parent::Item(id)
+            | Parent::Reservation(id)
+            | Parent::FoundItem(id) => id,
+        }
+    }
+
+    /// Fails with `404` if the parent does not exist, rather than listing nothing.
+    pub fn ensure_exists(&self, conn: &mut PgConnection) -> Result<(), ApiError> {
+        use crate::models::machine;
+        use crate::schema::{found_items, items, properties, reservations, resources, users};
+
+        let (found, name) = match *self {
+            Parent::Property(id) => (
+                diesel::select(exists(properties::table.find(id))).get_result::<bool>(conn)?,
+                "Property",
+            ),
+            Parent::Machine(id) => (
+                diesel::select(exists(
+                    resources::table
+                        .find(id)
+                        .filter(resources::kind.eq(machine::KIND)),
+                ))
+                .get_result::<bool>(conn)?,
+                "Machine",
+            ),
+            Parent::Resource(id) => (
+                diesel::select(exists(resources::table.find(id))).get_result::<bool>(conn)?,
+                "Resource",
+            ),
+            Parent::User(id) => (
+                diesel::select(exists(users::table.find(id))).get_result::<bool>(conn)?,
+                "User",
+            ),
+            Parent::Item(id) => (
+                diesel::select(exists(items::table.find(id))).get_result::<bool>(conn)?,
+                "Item",
+            ),
+            Parent::Reservation(id) => (
+                diesel::select(exists(reservations::table.find(id))).get_result::<bool>(conn)?,
+                "Reservation",
+            ),
+            Parent::FoundItem(id) => (
+                diesel::select(exists(found_items::table.find(id))).get_result::<bool>(conn)?,
+                "Found item",
+            ),
+        };
+
+        if found {
+            Ok(())
+        } else {
+            Err(ApiError::NotFound(format!("{} not found", name)))
+        }
+    }
+}
+
+/// Deserializes the body of a nested create with the foreign key `field` set to the
+/// parent from the path.
+///
+/// The field may be omitted from the body, but must not name a different parent.
+pub fn payload<T: DeserializeOwned>(
+    mut body: Value,
+    field: &str,
+    parent: &Parent,
+) -> Result<T, ApiError> {
+    if let Some(object) = body.as_object_mut() {
+        let given = object.get(field).filter(|given| !given.is_null());
+        if let Some(given) = given {
+            if given.as_str().and_then(|id| id.parse().ok()) != Some(parent.id()) {
+                return Err(ApiError::InvalidPayload(vec![FieldError {
+                    field: field.to_string(),
+                    code: "invalid".to_string(),
+                    message: format!("{} must match the id in the path", field),
+                }]));
+            }
+        }
+        object.insert(field.to_string(), Value::String(parent.id().to_string()));
+    }
+
+    helpers::from_value(body)
+}
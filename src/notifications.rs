@@ -0,0 +1,141 @@
+use actix_web::{get, post, web, HttpResponse};
+use diesel::prelude::*;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use super::DbPool;
+use crate::auth::Identity;
+use crate::helpers::{ApiError, PaginatedResponse, SuccessResponse};
+use crate::models::notification::{NewNotification, Notification};
+use crate::pagination::{paginate, Page, PageParams, Paginated};
+
+#[derive(Debug, Deserialize)]
+struct Filters {
+    read: Option<bool>,
+    kind: Option<String>,
+}
+
+const SORTABLE: [&str; 1] = ["created_at"];
+
+/// Lists the caller's own notifications.
+#[get("/notifications")]
+async fn index(
+    page: web::Query<PageParams>,
+    filters: web::Query<Filters>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require_session()?;
+
+    let page = page.resolve(&SORTABLE)?;
+    let notifications = web::block(move || {
+        let mut conn = pool.get()?;
+        find_all(identity.user.id, &filters, &page, &mut conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(PaginatedResponse {
+        status: 200,
+        message: "OK".to_string(),
+        data: notifications.data,
+        meta: notifications.meta,
+    }))
+}
+
+#[post("/notifications/{id}/read")]
+async fn read(
+    id: web::Path<Uuid>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require_session()?;
+
+    let notification = web::block(move || {
+        let mut conn = pool.get()?;
+        mark_read(id.into_inner(), identity.user.id, &mut conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(SuccessResponse {
+        status: 200,
+        message: "OK".to_string(),
+        data: notification,
+    }))
+}
+
+/// Leaves a message for `recipient` about `subject`. Call it in the transaction
+/// making the change the message is about.
+pub fn notify(
+    recipient: Uuid,
+    kind: &str,
+    subject: Option<Uuid>,
+    message: &str,
+    conn: &mut PgConnection,
+) -> Result<(), ApiError> {
+    use crate::schema::notifications;
+
+    diesel::insert_into(notifications::table)
+        .values(&NewNotification {
+            recipient,
+            kind,
+            subject,
+            message,
+            created_at: chrono::Local::now().naive_local(),
+        })
+        .execute(conn)?;
+
+    Ok(())
+}
+
+fn find_all(
+    user_id: Uuid,
+    filters: &Filters,
+    page: &Page,
+    conn: &mut PgConnection,
+) -> Result<Paginated<Notification>, ApiError> {
+    use crate::schema::notifications::dsl::*;
+
+    let filtered = || {
+        let mut query = notifications.filter(recipient.eq(user_id)).into_boxed();
+        match filters.read {
+            Some(true) => query = query.filter(read_at.is_not_null()),
+            Some(false) => query = query.filter(read_at.is_null()),
+            None => {}
+        }
+        if let Some(name) = &filters.kind {
+            query = query.filter(kind.eq(name));
+        }
+        query
+    };
+
+    let total = filtered().count().get_result(conn)?;
+    let rows = paginate!(filtered(), page, created_at, id, chrono::NaiveDateTime).load(conn)?;
+
+    Ok(page.finish(rows, total))
+}
+
+fn mark_read(
+    notification_id: Uuid,
+    user_id: Uuid,
+    conn: &mut PgConnection,
+) -> Result<Notification, ApiError> {
+    use crate::schema::notifications::dsl::*;
+
+    conn.transaction(|conn| {
+        let current = notifications
+            .find(notification_id)
+            .filter(recipient.eq(user_id))
+            .for_update()
+            .first::<Notification>(conn)
+            .optional()?
+            .ok_or_else(|| ApiError::NotFound("Notification not found".to_string()))?;
+        if current.read_at.is_some() {
+            return Ok(current);
+        }
+
+        let notification = diesel::update(notifications.find(notification_id))
+            .set(read_at.eq(chrono::Local::now().naive_local()))
+            .get_result::<Notification>(conn)?;
+        Ok(notification)
+    })
+}
@@ -0,0 +1,478 @@
+use std::sync::RwLock;
+use std::time::Duration;
+use std::{env, io};
+
+use actix_web::http::header;
+use actix_web::{get, web, HttpResponse};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use diesel::prelude::*;
+use jsonwebtoken::jwk::{Jwk, JwkSet};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use super::DbPool;
+use crate::audit;
+use crate::auth;
+use crate::db;
+use crate::helpers::{self, ApiError, SuccessResponse};
+use crate::includes::Resource;
+use crate::models::oidc::{CallbackParams, IdTokenClaims, OidcLogin};
+use crate::models::user::{NewUser, User};
+
+/// Minutes a user has to complete the login at the identity provider.
+const LOGIN_TTL_MINUTES: i64 = 10;
+/// Signature algorithms accepted for ID tokens. Symmetric ones are left out, they would
+/// turn the client secret into a signing key.
+const ALGORITHMS: [Algorithm; 6] = [
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::ES256,
+    Algorithm::ES384,
+];
+
+/// Settings of the identity provider, read from the environment.
+#[derive(Debug, Clone)]
+struct Config {
+    issuer: String,
+    client_id: String,
+    client_secret: Option<String>,
+    redirect_uri: String,
+    scopes: String,
+    /// Creates unknown users on their first login instead of rejecting them.
+    auto_provision: bool,
+    /// Name of the role given to created users.
+    default_role: String,
+}
+
+impl Config {
+    /// OIDC login is disabled unless `OIDC_ISSUER` is set.
+    fn from_env() -> Option<Self> {
+        let issuer = env::var("OIDC_ISSUER").ok()?;
+        let var = |key: &str, default: &str| env::var(key).unwrap_or_else(|_| default.to_string());
+
+        Some(Config {
+            issuer,
+            client_id: var("OIDC_CLIENT_ID", "beutler"),
+            client_secret: env::var("OIDC_CLIENT_SECRET").ok(),
+            redirect_uri: var(
+                "OIDC_REDIRECT_URI",
+                "http://localhost:8080/auth/oidc/callback",
+            ),
+            scopes: var("OIDC_SCOPES", "openid email profile"),
+            auto_provision: db::env_or("OIDC_AUTO_PROVISION", false),
+            default_role: var("OIDC_DEFAULT_ROLE", "tenant"),
+        })
+    }
+}
+
+/// Endpoints of the identity provider from its discovery document.
+#[derive(Debug, Clone, Deserialize)]
+struct Metadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+/// Client of the identity provider. Its metadata and signing keys are fetched on first use
+/// and cached for the lifetime of the server.
+pub struct Oidc {
+    config: Config,
+    http: reqwest::Client,
+    metadata: RwLock<Option<Metadata>>,
+    keys: RwLock<Option<JwkSet>>,
+}
+
+impl Oidc {
+    pub fn from_env() -> io::Result<Option<Self>> {
+        let Some(config) = Config::from_env() else {
+            return Ok(None);
+        };
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .map_err(io::Error::other)?;
+        log::info!("OIDC login enabled with issuer {}", config.issuer);
+
+        Ok(Some(Oidc {
+            config,
+            http,
+            metadata: RwLock::new(None),
+            keys: RwLock::new(None),
+        }))
+    }
+
+    async fn metadata(&self) -> Result<Metadata, ApiError> {
+        if let Some(metadata) = self.metadata.read().ok().and_then(|cached| cached.clone()) {
+            return Ok(metadata);
+        }
+
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            self.config.issuer.trim_end_matches('/')
+        );
+        let metadata: Metadata = fetch(self.http.get(url)).await?;
+        if let Ok(mut cached) = self.metadata.write() {
+            *cached = Some(metadata.clone());
+        }
+        Ok(metadata)
+    }
+
+    /// Finds the key an ID token was signed with. The key set is fetched again once when
+    /// the key is unknown, as the identity provider may have rotated its keys.
+    async fn key(&self, kid: Option<&str>) -> Result<DecodingKey, ApiError> {
+        let find = |keys: &JwkSet| -> Option<Jwk> {
+            match kid {
+                Some(kid) => keys.find(kid).cloned(),
+                None if keys.keys.len() == 1 => keys.keys.first().cloned(),
+                None => None,
+            }
+        };
+
+        let cached = self
+            .keys
+            .read()
+            .ok()
+            .and_then(|keys| keys.as_ref().and_then(find));
+        let jwk = match cached {
+            Some(jwk) => jwk,
+            None => {
+                let metadata = self.metadata().await?;
+                let keys: JwkSet = fetch(self.http.get(&metadata.jwks_uri)).await?;
+                let jwk = find(&keys);
+                if let Ok(mut cached) = self.keys.write() {
+                    *cached = Some(keys);
+                }
+                jwk.ok_or_else(|| {
+                    ApiError::Unauthorized("ID token is signed with an unknown key".to_string())
+                })?
+            }
+        };
+
+        DecodingKey::from_jwk(&jwk).map_err(rejected)
+    }
+
+    /// Redeems an authorization code and returns the validated claims of its ID token.
+    async fn exchange(&self, code: &str, login: &OidcLogin) -> Result<IdTokenClaims, ApiError> {
+        let metadata = self.metadata().await?;
+
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", self.config.redirect_uri.as_str()),
+            ("client_id", self.config.client_id.as_str()),
+            ("code_verifier", login.code_verifier.as_str()),
+        ];
+        if let Some(secret) = &self.config.client_secret {
+            form.push(("client_secret", secret.as_str()));
+        }
+
+        let response = self
+            .http
+            .post(&metadata.token_endpoint)
+            .form(&form)
+            .send()
+            .await
+            .map_err(unavailable)?;
+        if !response.status().is_success() {
+            log::warn!("Token exchange failed with status {}", response.status());
+            return Err(ApiError::Unauthorized(
+                "The identity provider rejected the authorization code".to_string(),
+            ));
+        }
+        let tokens: TokenResponse = response.json().await.map_err(unavailable)?;
+
+        self.validate(&tokens.id_token, &metadata.issuer, &login.nonce)
+            .await
+    }
+
+    async fn validate(
+        &self,
+        id_token: &str,
+        issuer: &str,
+        nonce: &str,
+    ) -> Result<IdTokenClaims, ApiError> {
+        let header = jsonwebtoken::decode_header(id_token).map_err(rejected)?;
+        if !ALGORITHMS.contains(&header.alg) {
+            return Err(ApiError::Unauthorized(format!(
+                "ID token is signed with unsupported algorithm {:?}",
+                header.alg
+            )));
+        }
+        let key = self.key(header.kid.as_deref()).await?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[issuer]);
+        validation.set_audience(&[&self.config.client_id]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+        let claims = jsonwebtoken::decode::<IdTokenClaims>(id_token, &key, &validation)
+            .map_err(rejected)?
+            .claims;
+
+        // Binds the token to the login started by this client, against replayed tokens
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err(ApiError::Unauthorized(
+                "ID token was not issued for this login".to_string(),
+            ));
+        }
+        Ok(claims)
+    }
+}
+
+/// Starts the authorization code flow by redirecting to the identity provider.
+#[get("/auth/oidc/authorize")]
+async fn authorize(
+    oidc: Option<web::Data<Oidc>>,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, ApiError> {
+    let oidc = configured(oidc)?;
+    let metadata = oidc.metadata().await?;
+
+    let login = OidcLogin {
+        state: auth::random_token(),
+        code_verifier: auth::random_token(),
+        nonce: auth::random_token(),
+        created_at: chrono::Local::now().naive_local(),
+    };
+    let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(login.code_verifier.as_bytes()));
+    let url = reqwest::Url::parse_with_params(
+        &metadata.authorization_endpoint,
+        &[
+            ("response_type", "code"),
+            ("client_id", oidc.config.client_id.as_str()),
+            ("redirect_uri", oidc.config.redirect_uri.as_str()),
+            ("scope", oidc.config.scopes.as_str()),
+            ("state", login.state.as_str()),
+            ("nonce", login.nonce.as_str()),
+            ("code_challenge", challenge.as_str()),
+            ("code_challenge_method", "S256"),
+        ],
+    )
+    .map_err(helpers::internal)?;
+
+    web::block(move || {
+        let mut conn = pool.get()?;
+        start(&login, &mut conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Found()
+        .insert_header((header::LOCATION, url.as_str()))
+        .finish())
+}
+
+/// Completes the login the identity provider redirected back from and starts a session.
+#[get("/auth/oidc/callback")]
+async fn callback(
+    params: web::Query<CallbackParams>,
+    oidc: Option<web::Data<Oidc>>,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, ApiError> {
+    let oidc = configured(oidc)?;
+    let params = params.into_inner();
+    if let Some(error) = params.error {
+        return Err(ApiError::Unauthorized(format!(
+            "Login failed at the identity provider: {}",
+            params.error_description.unwrap_or(error)
+        )));
+    }
+    let (Some(code), Some(state)) = (params.code, params.state) else {
+        return Err(ApiError::BadRequest(
+            "code and state are required".to_string(),
+        ));
+    };
+
+    let login = {
+        let pool = pool.clone();
+        web::block(move || {
+            let mut conn = pool.get()?;
+            finish(&state, &mut conn)
+        })
+        .await??
+    };
+    let claims = oidc.exchange(&code, &login).await?;
+
+    let config = oidc.config.clone();
+    let tokens = web::block(move || {
+        let mut conn = pool.get()?;
+        let user = link(&claims, &config, &mut conn)?;
+        auth::start_session(user.id, &mut conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(SuccessResponse {
+        status: 200,
+        message: "OK".to_string(),
+        data: tokens,
+    }))
+}
+
+fn configured(oidc: Option<web::Data<Oidc>>) -> Result<web::Data<Oidc>, ApiError> {
+    oidc.ok_or_else(|| ApiError::NotFound("OIDC login is not configured".to_string()))
+}
+
+/// Remembers a started login, forgetting those which were never completed.
+fn start(login: &OidcLogin, conn: &mut PgConnection) -> Result<(), ApiError> {
+    use crate::schema::oidc_logins::dsl::*;
+
+    let expired = login.created_at - chrono::Duration::minutes(LOGIN_TTL_MINUTES);
+    diesel::delete(oidc_logins.filter(created_at.lt(expired))).execute(conn)?;
+    diesel::insert_into(oidc_logins)
+        .values(login)
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// Takes a started login by its `state`, each can be completed only once.
+fn finish(login_state: &str, conn: &mut PgConnection) -> Result<OidcLogin, ApiError> {
+    use crate::schema::oidc_logins::dsl::*;
+
+    let expired = chrono::Local::now().naive_local() - chrono::Duration::minutes(LOGIN_TTL_MINUTES);
+    diesel::delete(
+        oidc_logins
+            .filter(state.eq(login_state))
+            .filter(created_at.ge(expired)),
+    )
+    .get_result::<OidcLogin>(conn)
+    .optional()?
+    .ok_or_else(|| {
+        ApiError::Unauthorized("Unknown or expired login, please start again".to_string())
+    })
+}
+
+/// Finds the user of an ID token by its subject, or by its email on the first login,
+/// and creates one if provisioning is enabled.
+fn link(
+    claims: &IdTokenClaims,
+    config: &Config,
+    conn: &mut PgConnection,
+) -> Result<User, ApiError> {
+    use crate::schema::{roles, users};
+
+    conn.transaction(|conn| {
+        let linked = users::table
+            .filter(users::oidc_subject.eq(&claims.sub))
+            .first::<User>(conn)
+            .optional()?;
+        if let Some(user) = linked {
+            return Ok(user);
+        }
+
+        let now = chrono::Local::now().naive_local();
+        // Anyone can enter any address at the identity provider until it is verified
+        let email = claims
+            .email
+            .as_deref()
+            .filter(|_| claims.email_verified)
+            .map(auth::normalize_email);
+
+        if let Some(email) = &email {
+            let existing = users::table
+                .filter(users::email.eq(email))
+                .for_update()
+                .first::<User>(conn)
+                .optional()?;
+            if let Some(user) = existing {
+                if user.oidc_subject.is_some() {
+                    return Err(ApiError::Conflict(format!(
+                        "{} is already linked to another login",
+                        email
+                    )));
+                }
+                let linked = diesel::update(users::table.find(user.id))
+                    .set((
+                        users::oidc_subject.eq(&claims.sub),
+                        users::updated_at.eq(now),
+                    ))
+                    .get_result::<User>(conn)?;
+                audit::updated(
+                    &actor(&linked),
+                    Resource::User,
+                    user.id,
+                    &user,
+                    &linked,
+                    conn,
+                )?;
+                let user = linked;
+                log::info!("Linked user {} to OIDC subject {}", user.id, claims.sub);
+                return Ok(user);
+            }
+        }
+
+        if !config.auto_provision {
+            return Err(ApiError::Forbidden(
+                "No account is linked to this login".to_string(),
+            ));
+        }
+
+        let role = roles::table
+            .filter(roles::name.eq(&config.default_role))
+            .select(roles::id)
+            .first::<Uuid>(conn)
+            .optional()?
+            .ok_or_else(|| {
+                helpers::internal(format!(
+                    "Default role {} does not exist",
+                    config.default_role
+                ))
+            })?;
+        let name = claims
+            .name
+            .as_deref()
+            .or(claims.preferred_username.as_deref())
+            .or(email.as_deref())
+            .unwrap_or(&claims.sub);
+        let user = diesel::insert_into(users::table)
+            .values(&NewUser {
+                name,
+                role,
+                property: None,
+                created_at: now,
+                updated_at: now,
+                email: email.clone(),
+                password_hash: None,
+                oidc_subject: Some(claims.sub.clone()),
+            })
+            .get_result::<User>(conn)?;
+        audit::created(&actor(&user), Resource::User, user.id, &user, conn)?;
+        log::info!("Created user {} for OIDC subject {}", user.id, claims.sub);
+        Ok(user)
+    })
+}
+
+/// Changes made on login are attributed to the user logging in.
+fn actor(user: &User) -> String {
+    format!("user:{}", user.id)
+}
+
+async fn fetch<T: DeserializeOwned>(request: reqwest::RequestBuilder) -> Result<T, ApiError> {
+    request
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(unavailable)?
+        .json()
+        .await
+        .map_err(unavailable)
+}
+
+fn unavailable(err: reqwest::Error) -> ApiError {
+    log::error!("Request to the identity provider failed: {}", err);
+    ApiError::ServiceUnavailable("The identity provider is not reachable".to_string())
+}
+
+fn rejected(err: jsonwebtoken::errors::Error) -> ApiError {
+    log::warn!("Rejected ID token: {}", err);
+    ApiError::Unauthorized("Invalid ID token".to_string())
+}
@@ -0,0 +1,316 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::helpers::{ApiError, FieldError};
+
+const DEFAULT_LIMIT: i64 = 50;
+const MAX_LIMIT: i64 = 200;
+
+/// Pagination and sorting query parameters shared by all index endpoints.
+///
+/// `sort` names a column, prefixed with `-` for descending order, e.g. `sort=-created_at`.
+#[derive(Debug, Deserialize)]
+pub struct PageParams {
+    pub limit: Option<i64>,
+    pub cursor: Option<String>,
+    pub sort: Option<String>,
+}
+
+impl PageParams {
+    /// Validates the parameters against the columns an endpoint allows sorting by.
+    ///
+    /// Without `sort`, results are ordered by `created_at`.
+    pub fn resolve(&self, sortable: &[&str]) -> Result<Page, ApiError> {
+        let limit = self.limit.unwrap_or(DEFAULT_LIMIT);
+        if !(1..=MAX_LIMIT).contains(&limit) {
+            return Err(invalid(
+                "limit",
+                "out_of_range",
+                format!("limit must be between 1 and {}", MAX_LIMIT),
+            ));
+        }
+
+        let sort = self.sort.as_deref().unwrap_or("created_at");
+        let (column, descending) = match sort.strip_prefix('-') {
+            Some(column) => (column, true),
+            None => (sort, false),
+        };
+        if !sortable.contains(&column) {
+            return Err(invalid(
+                "sort",
+                "invalid",
+                format!("sort must be one of {}", sortable.join(", ")),
+            ));
+        }
+
+        let cursor = self.cursor.as_deref().map(Cursor::decode).transpose()?;
+        if let Some(cursor) = &cursor {
+            if cursor.sort != sort {
+                return Err(invalid(
+                    "cursor",
+                    "invalid",
+                    "cursor was issued for a different sort order".to_string(),
+                ));
+            }
+        }
+
+        Ok(Page {
+            limit,
+            column: column.to_string(),
+            descending,
+            cursor,
+        })
+    }
+}
+
+/// Validated pagination of a single request.
+#[derive(Debug)]
+pub struct Page {
+    pub limit: i64,
+    pub column: String,
+    pub descending: bool,
+    pub cursor: Option<Cursor>,
+}
+
+impl Page {
+    fn sort(&self) -> String {
+        if self.descending {
+            format!("-{}", self.column)
+        } else {
+            self.column.clone()
+        }
+    }
+
+    /// Splits off the extra row fetched by [`paginate!`] and builds the cursor to the
+    /// next page from the last row returned.
+    pub fn finish<T: Serialize>(&self, mut rows: Vec<T>, total: i64) -> Paginated<T> {
+        let has_more = rows.len() as i64 > self.limit;
+        rows.truncate(self.limit as usize);
+
+        let next_cursor = rows.last().filter(|_| has_more).and_then(|last| {
+            let row = serde_json::to_value(last).ok()?;
+            let cursor = Cursor {
+                sort: self.sort(),
+                value: row.get(&self.column)?.clone(),
+                id: serde_json::from_value(row.get("id")?.clone()).ok()?,
+            };
+            Some(cursor.encode())
+        });
+
+        Paginated {
+            data: rows,
+            meta: PageMeta {
+                limit: self.limit,
+                total,
+                next_cursor,
+            },
+        }
+    }
+}
+
+/// Position after the last row of a page: the value of the sort column and the id.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Cursor {
+    sort: String,
+    value: serde_json::Value,
+    pub id: Uuid,
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).unwrap_or_default())
+    }
+
+    fn decode(cursor: &str) -> Result<Self, ApiError> {
+        URL_SAFE_NO_PAD
+            .decode(cursor)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .ok_or_else(|| invalid("cursor", "invalid", "cursor is malformed".to_string()))
+    }
+
+    /// Returns the value of the sort column as the type of the column.
+    pub fn value<T: DeserializeOwned>(&self) -> Result<T, ApiError> {
+        serde_json::from_value(self.value.clone())
+            .map_err(|_| invalid("cursor", "invalid", "cursor is malformed".to_string()))
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct PageMeta {
+    pub limit: i64,
+    pub total: i64,
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct Paginated<T> {
+    pub data: Vec<T>,
+    pub meta: PageMeta,
+}
+
+fn invalid(field: &str, code: &str, message: String) -> ApiError {
+    ApiError::InvalidParameters(vec![FieldError {
+        field: field.to_string(),
+        code: code.to_string(),
+        message,
+    }])
+}
+
+/// Applies keyset pagination on `(column, id)` to a boxed query, fetching one row more
+/// than the page size to find out whether there is a next page.
+///
+/// `$ty` is the Rust type of `column`, used to decode the cursor.
+macro_rules! paginate {
+    ($query:expr, $page:expr, $column:expr, $id:expr, $ty:ty) => {{
+        let page: &$crate::pagination::Page = $page;
+        let mut query = $query;
+        if let Some(cursor) = &page.cursor {
+            let value: $ty = cursor.value()?;
+            query = if page.descending {
+                query.filter(
+                    $column
+                        .lt(value.clone())
+                        .or($column.eq(value).and($id.lt(cursor.id))),
+                )
+            } else {
+                query.filter(
+                    $column
+                        .gt(value.clone())
+                        .or($column.eq(value).and($id.gt(cursor.id))),
+                )
+            };
+        }
+        query = if page.descending {
+            query.order(($column.desc(), $id.desc()))
+        } else {
+            query.order(($column.asc(), $id.asc()))
+        };
+        query.limit(page.limit + 1)
+    }};
+}
+
+pub(crate) use paginate;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize)]
+    struct Row {
+        id: Uuid,
+        name: String,
+    }
+
+    fn params(limit: Option<i64>, cursor: Option<String>, sort: Option<&str>) -> PageParams {
+        PageParams {
+            limit,
+            cursor,
+            sort: sort.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn cursor_round_trips() {
+        let id = Uuid::new_v4();
+        let cursor = Cursor {
+            sort: "-name".to_string(),
+            value: serde_json::json!("Towel"),
+            id,
+        };
+        let decoded = Cursor::decode(&cursor.encode()).unwrap();
+        assert_eq!(decoded.sort, "-name");
+        assert_eq!(decoded.id, id);
+        assert_eq!(decoded.value::<String>().unwrap(), "Towel");
+    }
+
+    #[test]
+    fn rejects_malformed_cursors() {
+        assert!(Cursor::decode("not a cursor").is_err());
+        assert!(Cursor::decode(&URL_SAFE_NO_PAD.encode(b"{}")).is_err());
+        let cursor = Cursor {
+            sort: "name".to_string(),
+            value: serde_json::json!("Towel"),
+            id: Uuid::new_v4(),
+        };
+        assert!(cursor.value::<i64>().is_err());
+    }
+
+    #[test]
+    fn resolves_defaults_and_descending_sort() {
+        let page = params(None, None, None).resolve(&["created_at"]).unwrap();
+        assert_eq!(page.limit, DEFAULT_LIMIT);
+        assert_eq!(page.column, "created_at");
+        assert!(!page.descending);
+
+        let page = params(Some(10), None, Some("-name"))
+            .resolve(&["created_at", "name"])
+            .unwrap();
+        assert_eq!(page.column, "name");
+        assert!(page.descending);
+    }
+
+    #[test]
+    fn rejects_invalid_parameters() {
+        assert!(params(Some(0), None, None)
+            .resolve(&["created_at"])
+            .is_err());
+        assert!(params(Some(MAX_LIMIT + 1), None, None)
+            .resolve(&["created_at"])
+            .is_err());
+        assert!(params(None, None, Some("email"))
+            .resolve(&["created_at"])
+            .is_err());
+    }
+
+    #[test]
+    fn next_cursor_continues_after_the_last_row() {
+        let page = params(Some(2), None, Some("name"))
+            .resolve(&["name"])
+            .unwrap();
+        let rows: Vec<Row> = ["a", "b", "c"]
+            .into_iter()
+            .map(|name| Row {
+                id: Uuid::new_v4(),
+                name: name.to_string(),
+            })
+            .collect();
+        let last_id = rows[1].id;
+
+        let paginated = page.finish(rows, 3);
+        assert_eq!(paginated.data.len(), 2);
+        assert_eq!(paginated.meta.total, 3);
+
+        let next = params(Some(2), paginated.meta.next_cursor, Some("name"))
+            .resolve(&["name"])
+            .unwrap();
+        let cursor = next.cursor.unwrap();
+        assert_eq!(cursor.id, last_id);
+        assert_eq!(cursor.value::<String>().unwrap(), "b");
+    }
+
+    #[test]
+    fn last_page_has_no_cursor() {
+        let page = params(Some(2), None, None)
+            .resolve(&["created_at"])
+            .unwrap();
+        let rows = vec![Row {
+            id: Uuid::new_v4(),
+            name: "a".to_string(),
+        }];
+        assert!(page.finish(rows, 1).meta.next_cursor.is_none());
+    }
+
+    #[test]
+    fn rejects_cursor_of_another_sort_order() {
+        let cursor = Cursor {
+            sort: "name".to_string(),
+            value: serde_json::json!("a"),
+            id: Uuid::new_v4(),
+        };
+        assert!(params(None, Some(cursor.encode()), Some("-name"))
+            .resolve(&["name"])
+            .is_err());
+    }
+}
@@ -0,0 +1,418 @@
+use super::DbPool;
+use actix_web::{delete, get, post, web, HttpResponse};
+use diesel::prelude::*;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use crate::audit;
+use crate::auth::Identity;
+use crate::helpers::{ApiError, Json, SuccessResponse};
+use crate::idempotency;
+use crate::includes::Resource;
+use crate::models::participant::{
+    NewParticipant, Participant, ParticipantPayload, APPROVED, REQUESTED,
+};
+use crate::models::reservation::Reservation;
+use crate::notifications;
+use crate::permissions;
+use crate::reservations;
+use crate::validation::Validate;
+
+/// The resource a reservation is for, as far as sharing it is concerned.
+struct Shared {
+    name: String,
+    property: Uuid,
+    capacity: i32,
+    unit: String,
+}
+
+#[get("/reservations/{id}/participants")]
+async fn index(
+    id: web::Path<Uuid>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::RESERVATIONS_READ)?;
+
+    let reservation_id = id.into_inner();
+    let joined = web::block(move || {
+        use crate::schema::participants::dsl::*;
+
+        let mut conn = pool.get()?;
+        let current = find_reservation(reservation_id, false, &mut conn)?;
+        identity
+            .require_property_of(|| permissions::resource_property(current.resource, &mut conn))?;
+        let rows = participants
+            .filter(reservation.eq(reservation_id))
+            .order((created_at, id))
+            .load::<Participant>(&mut conn)?;
+        Ok::<_, ApiError>(rows)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(SuccessResponse {
+        status: 200,
+        message: "OK".to_string(),
+        data: joined,
+    }))
+}
+
+/// Asks to join a shared reservation with a share of its capacity. The owner of the
+/// reservation has to approve.
+#[post("/reservations/{id}/participants")]
+async fn create(
+    id: web::Path<Uuid>,
+    payload: Json<ParticipantPayload>,
+    key: idempotency::Key,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require_owner(&permissions::RESERVATIONS_WRITE, identity.user.id)?;
+
+    payload.validate()?;
+
+    let reservation_id = id.into_inner();
+    let scope = format!("POST /reservations/{}/participants", reservation_id);
+    let created = web::block(move || {
+        let mut conn = pool.get()?;
+        idempotency::create_once(&key, &scope, &payload.0, &mut conn, |conn| {
+            join(reservation_id, &payload, &identity, conn)
+        })
+    })
+    .await??;
+
+    Ok(created.respond())
+}
+
+#[post("/reservations/{id}/participants/{user}/approve")]
+async fn approve(
+    path: web::Path<(Uuid, Uuid)>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    let (reservation_id, user_id) = path.into_inner();
+    let approved = web::block(move || {
+        let mut conn = pool.get()?;
+        admit(reservation_id, user_id, &identity, &mut conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(SuccessResponse {
+        status: 200,
+        message: "OK".to_string(),
+        data: approved,
+    }))
+}
+
+/// The owner removes a participant, or a participant leaves.
+#[delete("/reservations/{id}/participants/{user}")]
+async fn destroy(
+    path: web::Path<(Uuid, Uuid)>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    let (reservation_id, user_id) = path.into_inner();
+    let count = web::block(move || {
+        let mut conn = pool.get()?;
+        remove(reservation_id, user_id, &identity, &mut conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(SuccessResponse {
+        status: 200,
+        message: "Deleted".to_string(),
+        data: count,
+    }))
+}
+
+/// Capacity approved participants take, per reservation. Reservations nobody joined
+/// are missing.
+pub fn taken(
+    reservation_ids: &[Uuid],
+    conn: &mut PgConnection,
+) -> Result<HashMap<Uuid, i64>, ApiError> {
+    use crate::schema::participants::dsl::*;
+
+    let sums = participants
+        .filter(reservation.eq_any(reservation_ids))
+        .filter(status.eq(APPROVED))
+        .group_by(reservation)
+        .select((reservation, diesel::dsl::sum(amount)))
+        .load::<(Uuid, Option<i64>)>(conn)?;
+
+    Ok(sums
+        .into_iter()
+        .map(|(reservation_id, sum)| (reservation_id, sum.unwrap_or_default()))
+        .collect())
+}
+
+fn join(
+    reservation_id: Uuid,
+    payload: &ParticipantPayload,
+    identity: &Identity,
+    conn: &mut PgConnection,
+) -> Result<Participant, ApiError> {
+    use crate::schema::participants::dsl::*;
+
+    conn.transaction(|conn| {
+        let current = find_reservation(reservation_id, true, conn)?;
+        let shared = find_shared(&current, conn)?;
+        identity.require_property(Some(shared.property))?;
+        if !current.shared {
+            return Err(ApiError::Conflict("Reservation is not shared".to_string()));
+        }
+        if current.owner == identity.user.id {
+            return Err(ApiError::UnprocessableEntity(
+                "Own reservations cannot be joined".to_string(),
+            ));
+        }
+        if current.end_time <= chrono::Local::now().naive_local() {
+            return Err(ApiError::Conflict("Reservation has ended".to_string()));
+        }
+        if permissions::user_property(identity.user.id, conn)? != Some(shared.property) {
+            return Err(ApiError::UnprocessableEntity(
+                "Only residents of the property can join its reservations".to_string(),
+            ));
+        }
+
+        let already = participants
+            .filter(reservation.eq(reservation_id))
+            .filter(participant.eq(identity.user.id))
+            .count()
+            .get_result::<i64>(conn)?;
+        if already > 0 {
+            return Err(ApiError::Conflict(
+                "You already take part in the reservation".to_string(),
+            ));
+        }
+        check_capacity(&current, &shared, payload.amount, conn)?;
+
+        let now = chrono::Local::now().naive_local();
+        let res = diesel::insert_into(participants)
+            .values(&NewParticipant {
+                reservation: reservation_id,
+                participant: identity.user.id,
+                amount: payload.amount,
+                created_at: now,
+                updated_at: now,
+            })
+            .returning(participants::all_columns())
+            .get_result::<Participant>(conn)?;
+        audit::created(&identity.actor(), Resource::Participant, res.id, &res, conn)?;
+        notifications::notify(
+            current.owner,
+            "participant_requested",
+            Some(reservation_id),
+            &format!(
+                "{} would like to join your reservation of {} on {} with {} {}",
+                identity.user.name,
+                shared.name,
+                current.start_time.format("%Y-%m-%d %H:%M"),
+                res.amount,
+                shared.unit
+            ),
+            conn,
+        )?;
+        Ok(res)
+    })
+}
+
+fn admit(
+    reservation_id: Uuid,
+    user_id: Uuid,
+    identity: &Identity,
+    conn: &mut PgConnection,
+) -> Result<Participant, ApiError> {
+    use crate::schema::participants::dsl::*;
+
+    conn.transaction(|conn| {
+        let current = find_reservation(reservation_id, true, conn)?;
+        identity.require_owner(&permissions::RESERVATIONS_WRITE, current.owner)?;
+        let shared = find_shared(&current, conn)?;
+        identity.require_property(Some(shared.property))?;
+        let requested = find_participant(reservation_id, user_id, conn)?;
+        if requested.status != REQUESTED {
+            return Err(ApiError::Conflict(format!(
+                "Participant is {}",
+                requested.status
+            )));
+        }
+        check_capacity(&current, &shared, requested.amount, conn)?;
+
+        let approved = diesel::update(participants.find(requested.id))
+            .set((
+                status.eq(APPROVED),
+                updated_at.eq(chrono::Local::now().naive_local()),
+            ))
+            .get_result::<Participant>(conn)?;
+        audit::updated(
+            &identity.actor(),
+            Resource::Participant,
+            approved.id,
+            &requested,
+            &approved,
+            conn,
+        )?;
+        notifications::notify(
+            user_id,
+            "participant_approved",
+            Some(reservation_id),
+            &format!(
+                "You may join the reservation of {} on {} with {} {}",
+                shared.name,
+                current.start_time.format("%Y-%m-%d %H:%M"),
+                approved.amount,
+                shared.unit
+            ),
+            conn,
+        )?;
+        Ok(approved)
+    })
+}
+
+fn remove(
+    reservation_id: Uuid,
+    user_id: Uuid,
+    identity: &Identity,
+    conn: &mut PgConnection,
+) -> Result<usize, ApiError> {
+    use crate::schema::participants::dsl::*;
+
+    conn.transaction(|conn| {
+        let current = find_reservation(reservation_id, true, conn)?;
+        let leaving = identity
+            .require_owner(&permissions::RESERVATIONS_WRITE, user_id)
+            .is_ok();
+        if !leaving {
+            identity.require_owner(&permissions::RESERVATIONS_WRITE, current.owner)?;
+        }
+        let shared = find_shared(&current, conn)?;
+        identity.require_property(Some(shared.property))?;
+        let removed = find_participant(reservation_id, user_id, conn)?;
+
+        let count = diesel::delete(participants.find(removed.id)).execute(conn)?;
+        audit::deleted(
+            &identity.actor(),
+            Resource::Participant,
+            removed.id,
+            &removed,
+            conn,
+        )?;
+        let when = current.start_time.format("%Y-%m-%d %H:%M");
+        if identity.user.id == user_id {
+            notifications::notify(
+                current.owner,
+                "participant_left",
+                Some(reservation_id),
+                &format!(
+                    "{} left your reservation of {} on {}",
+                    identity.user.name, shared.name, when
+                ),
+                conn,
+            )?;
+        } else {
+            notifications::notify(
+                user_id,
+                "participant_removed",
+                Some(reservation_id),
+                &format!(
+                    "You were removed from the reservation of {} on {}",
+                    shared.name, when
+                ),
+                conn,
+            )?;
+        }
+        Ok(count)
+    })
+}
+
+/// Fails with `409` unless `requested` fits next to the owner, the approved participants
+/// and other reservations of the resource at the same time.
+fn check_capacity(
+    current: &Reservation,
+    shared: &Shared,
+    requested: i32,
+    conn: &mut PgConnection,
+) -> Result<(), ApiError> {
+    use crate::schema::resources;
+
+    // Serializes with bookings of the resource, which lock the same row
+    resources::table
+        .find(current.resource)
+        .select(resources::id)
+        .for_update()
+        .first::<Uuid>(conn)?;
+    let left = i64::from(shared.capacity)
+        - reservations::in_use(
+            current.resource,
+            current.start_time,
+            current.end_time,
+            None,
+            conn,
+        )?;
+    if i64::from(requested) > left {
+        return Err(ApiError::Conflict(format!(
+            "Only {} {} of {} are left",
+            left.max(0),
+            shared.unit,
+            shared.name
+        )));
+    }
+    Ok(())
+}
+
+/// Loads a reservation, locking it until the end of the transaction when `lock` is set
+/// so participants are admitted one at a time.
+fn find_reservation(
+    reservation_id: Uuid,
+    lock: bool,
+    conn: &mut PgConnection,
+) -> Result<Reservation, ApiError> {
+    use crate::schema::reservations::dsl::*;
+
+    let query = reservations.find(reservation_id);
+    let found = if lock {
+        query.for_update().first::<Reservation>(conn).optional()?
+    } else {
+        query.first::<Reservation>(conn).optional()?
+    };
+    found.ok_or_else(|| ApiError::NotFound("Reservation not found".to_string()))
+}
+
+fn find_shared(current: &Reservation, conn: &mut PgConnection) -> Result<Shared, ApiError> {
+    use crate::schema::{resource_kinds, resources};
+
+    let (name, property, capacity, unit) = resources::table
+        .inner_join(resource_kinds::table.on(resource_kinds::name.eq(resources::kind)))
+        .filter(resources::id.eq(current.resource))
+        .select((
+            resources::name,
+            resources::property,
+            resources::capacity,
+            resource_kinds::unit,
+        ))
+        .first::<(String, Uuid, i32, String)>(conn)?;
+
+    Ok(Shared {
+        name,
+        property,
+        capacity,
+        unit,
+    })
+}
+
+fn find_participant(
+    reservation_id: Uuid,
+    user_id: Uuid,
+    conn: &mut PgConnection,
+) -> Result<Participant, ApiError> {
+    use crate::schema::participants::dsl::*;
+
+    participants
+        .filter(reservation.eq(reservation_id))
+        .filter(participant.eq(user_id))
+        .for_update()
+        .first::<Participant>(conn)
+        .optional()?
+        .ok_or_else(|| ApiError::NotFound("Participant not found".to_string()))
+}
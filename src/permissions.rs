@@ -0,0 +1,221 @@
+use actix_web::{get, web, HttpResponse};
+use diesel::prelude::*;
+use uuid::Uuid;
+
+use super::DbPool;
+use crate::auth::{Credential, Identity};
+use crate::helpers::{ApiError, SuccessResponse};
+use crate::models::permission::Permission;
+
+pub const USERS_READ: &str = "users:read";
+pub const USERS_ADMIN: &str = "users:admin";
+pub const ROLES_READ: &str = "roles:read";
+pub const ROLES_WRITE: &str = "roles:write";
+pub const PROPERTIES_READ: &str = "properties:read";
+pub const MACHINES_READ: &str = "machines:read";
+pub const MACHINES_WRITE: &str = "machines:write";
+pub const RESOURCES_READ: &str = "resources:read";
+pub const RESOURCES_WRITE: &str = "resources:write";
+/// Kinds and their booking rules are shared by all properties.
+pub const RESOURCE_KINDS_WRITE: &str = "resource_kinds:write";
+pub const RESERVATIONS_READ: &str = "reservations:read";
+pub const ITEMS_READ: &str = "items:read";
+pub const FOUND_ITEMS_READ: &str = "found_items:read";
+pub const LOANS_READ: &str = "loans:read";
+pub const AUDIT_READ: &str = "audit:read";
+
+/// Permission granted either for every resource or only for those the caller owns.
+pub struct Scoped {
+    pub any: &'static str,
+    pub own: &'static str,
+}
+
+/// Users own themselves, there is no `users:write:any` besides [`USERS_ADMIN`].
+pub const USERS_WRITE: Scoped = Scoped {
+    any: USERS_ADMIN,
+    own: "users:write:own",
+};
+pub const PROPERTIES_WRITE: Scoped = Scoped {
+    any: "properties:write:any",
+    own: "properties:write:own",
+};
+pub const RESERVATIONS_WRITE: Scoped = Scoped {
+    any: "reservations:write:any",
+    own: "reservations:write:own",
+};
+pub const ITEMS_WRITE: Scoped = Scoped {
+    any: "items:write:any",
+    own: "items:write:own",
+};
+/// Own reports are those the caller reported.
+pub const FOUND_ITEMS_WRITE: Scoped = Scoped {
+    any: "found_items:write:any",
+    own: "found_items:write:own",
+};
+/// Own loans are those of the caller's items and those the caller borrows.
+pub const LOANS_WRITE: Scoped = Scoped {
+    any: "loans:write:any",
+    own: "loans:write:own",
+};
+/// Own keys are those of properties owned by the caller.
+pub const API_KEYS_WRITE: Scoped = Scoped {
+    any: "api_keys:write:any",
+    own: "api_keys:write:own",
+};
+
+/// Permissions which can be granted to API keys. The others are not bound to a property.
+pub const DELEGABLE: [&str; 19] = [
+    USERS_READ,
+    PROPERTIES_READ,
+    "properties:write:own",
+    MACHINES_READ,
+    MACHINES_WRITE,
+    RESOURCES_READ,
+    RESOURCES_WRITE,
+    RESERVATIONS_READ,
+    "reservations:write:own",
+    "reservations:write:any",
+    ITEMS_READ,
+    "items:write:own",
+    "items:write:any",
+    FOUND_ITEMS_READ,
+    "found_items:write:own",
+    "found_items:write:any",
+    LOANS_READ,
+    "loans:write:own",
+    "loans:write:any",
+];
+
+impl Identity {
+    pub fn can(&self, permission: &str) -> bool {
+        self.permissions.iter().any(|granted| granted == permission)
+    }
+
+    /// Fails with `403` unless the caller's role grants `permission`.
+    pub fn require(&self, permission: &str) -> Result<(), ApiError> {
+        if self.can(permission) {
+            Ok(())
+        } else {
+            Err(forbidden(permission))
+        }
+    }
+
+    /// Fails with `403` unless the caller may write any resource, or owns this one.
+    pub fn require_owner(&self, scoped: &Scoped, owner: Uuid) -> Result<(), ApiError> {
+        if self.can(scoped.any) || (self.can(scoped.own) && owner == self.user.id) {
+            Ok(())
+        } else if self.can(scoped.own) {
+            Err(ApiError::Forbidden(format!(
+                "Only the owner may do this without {}",
+                scoped.any
+            )))
+        } else {
+            Err(forbidden(scoped.own))
+        }
+    }
+
+    /// The property an API key is restricted to, `None` for users.
+    pub fn property_scope(&self) -> Option<Uuid> {
+        match self.credential {
+            Credential::ApiKey { property, .. } => Some(property),
+            Credential::Session(_) => None,
+        }
+    }
+
+    /// Fails with `403` when an API key reaches for a resource outside of its property.
+    pub fn require_property(&self, property: Option<Uuid>) -> Result<(), ApiError> {
+        match self.property_scope() {
+            Some(scope) if property != Some(scope) => Err(ApiError::Forbidden(format!(
+                "API key is restricted to property {}",
+                scope
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Like [`Identity::require_property`], but only looks the property up for API keys.
+    pub fn require_property_of(
+        &self,
+        lookup: impl FnOnce() -> Result<Option<Uuid>, ApiError>,
+    ) -> Result<(), ApiError> {
+        if self.property_scope().is_none() {
+            return Ok(());
+        }
+        self.require_property(lookup()?)
+    }
+
+    /// Narrows the property filter of an index to the property of an API key.
+    pub fn scope_filter(&self, property: Option<Uuid>) -> Result<Option<Uuid>, ApiError> {
+        if property.is_some() {
+            self.require_property(property)?;
+        }
+        Ok(property.or(self.property_scope()))
+    }
+}
+
+fn forbidden(permission: &str) -> ApiError {
+    ApiError::Forbidden(format!("Missing permission {}", permission))
+}
+
+/// Names of the permissions granted to `role`.
+pub fn granted(role: Uuid, conn: &mut PgConnection) -> Result<Vec<String>, ApiError> {
+    use crate::schema::role_permissions;
+
+    let names = role_permissions::table
+        .filter(role_permissions::role.eq(role))
+        .select(role_permissions::permission)
+        .order(role_permissions::permission)
+        .load::<String>(conn)?;
+
+    Ok(names)
+}
+
+/// Property of the resource, such as a machine, with the given id.
+pub fn resource_property(
+    resource: Uuid,
+    conn: &mut PgConnection,
+) -> Result<Option<Uuid>, ApiError> {
+    use crate::schema::resources;
+
+    let property = resources::table
+        .find(resource)
+        .select(resources::property)
+        .first::<Uuid>(conn)
+        .optional()?;
+
+    Ok(property)
+}
+
+/// Property the user with the given id lives in.
+pub fn user_property(user: Uuid, conn: &mut PgConnection) -> Result<Option<Uuid>, ApiError> {
+    use crate::schema::users;
+
+    let property = users::table
+        .find(user)
+        .select(users::property)
+        .first::<Option<Uuid>>(conn)
+        .optional()?;
+
+    Ok(property.flatten())
+}
+
+/// Lists every permission which can be granted to roles.
+#[get("/permissions")]
+async fn index(identity: Identity, pool: web::Data<DbPool>) -> Result<HttpResponse, ApiError> {
+    identity.require(ROLES_READ)?;
+
+    let permissions = web::block(move || {
+        use crate::schema::permissions::dsl::*;
+
+        let mut conn = pool.get()?;
+        let all = permissions.order(name).load::<Permission>(&mut conn)?;
+        Ok::<_, ApiError>(all)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(SuccessResponse {
+        status: 200,
+        message: "OK".to_string(),
+        data: permissions,
+    }))
+}
@@ -0,0 +1,378 @@
+use std::io::{Cursor, Write};
+
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::{get, post, web, HttpResponse};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use uuid::Uuid;
+
+use super::DbPool;
+use crate::audit;
+use crate::auth::Identity;
+use crate::helpers::{self, ApiError, SuccessResponse};
+use crate::images;
+use crate::includes::Resource;
+use crate::models::api_key::ApiKey;
+use crate::models::audit_entry::AuditEntry;
+use crate::models::found_item::FoundItem;
+use crate::models::image::Image;
+use crate::models::item::Item;
+use crate::models::loan::Loan;
+use crate::models::notification::Notification;
+use crate::models::participant::Participant;
+use crate::models::property::Property;
+use crate::models::reservation::Reservation;
+use crate::models::role::Role;
+use crate::models::session::Session;
+use crate::models::user::{Memberships, User, UserExport};
+use crate::permissions;
+use crate::storage::{Object, Storage};
+
+/// Name erased users are left with.
+const ERASED_NAME: &str = "Erased user";
+
+#[derive(Debug, Deserialize)]
+struct ExportParams {
+    #[serde(default)]
+    format: Format,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Format {
+    #[default]
+    Json,
+    /// One JSON file per part of the export, and the files of the images.
+    Zip,
+}
+
+/// Exports everything stored about a user, for themselves or an administrator.
+#[get("/users/{id}/export")]
+async fn export(
+    id: web::Path<Uuid>,
+    params: web::Query<ExportParams>,
+    pool: web::Data<DbPool>,
+    storage: web::Data<dyn Storage>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = id.into_inner();
+    identity.require_owner(&permissions::USERS_WRITE, user_id)?;
+
+    let bundle = web::block(move || {
+        let mut conn = pool.get()?;
+        collect(user_id, &mut conn)
+    })
+    .await??;
+
+    match params.format {
+        Format::Json => Ok(HttpResponse::Ok().json(SuccessResponse {
+            status: 200,
+            message: "OK".to_string(),
+            data: bundle,
+        })),
+        Format::Zip => {
+            let archive = web::block(move || archive(&bundle, storage.as_ref())).await??;
+            Ok(HttpResponse::Ok()
+                .content_type("application/zip")
+                .insert_header(ContentDisposition {
+                    disposition: DispositionType::Attachment,
+                    parameters: vec![DispositionParam::Filename(format!("user-{}.zip", user_id))],
+                })
+                .body(archive))
+        }
+    }
+}
+
+/// Anonymizes a user. Their reservations are kept for the machines' statistics and
+/// their found item reports for the property and their loans for the lenders, without
+/// their messages. Their items with their photos, their participations in others'
+/// reservations and their notifications are deleted and their sessions and API keys revoked.
+#[post("/users/{id}/erase")]
+async fn erase(
+    id: web::Path<Uuid>,
+    pool: web::Data<DbPool>,
+    storage: web::Data<dyn Storage>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::USERS_ADMIN)?;
+
+    let user = web::block(move || {
+        let mut conn = pool.get()?;
+        anonymize(id.into_inner(), &identity, storage.as_ref(), &mut conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(SuccessResponse {
+        status: 200,
+        message: "Erased".to_string(),
+        data: user,
+    }))
+}
+
+fn collect(user_id: Uuid, conn: &mut PgConnection) -> Result<UserExport, ApiError> {
+    use crate::schema::{
+        api_keys, audit_log, found_items, images, items, loans, notifications, participants,
+        properties, reservations, roles, sessions, users,
+    };
+
+    let profile = users::table
+        .find(user_id)
+        .first::<User>(conn)
+        .optional()?
+        .ok_or_else(|| ApiError::NotFound("User not found".to_string()))?;
+    let role = roles::table.find(profile.role).first::<Role>(conn)?;
+    let property = profile
+        .property
+        .map(|property| properties::table.find(property).first::<Property>(conn))
+        .transpose()?;
+    let reservations = reservations::table
+        .filter(reservations::owner.eq(user_id))
+        .order(reservations::start_time)
+        .load::<Reservation>(conn)?;
+    let participations = participants::table
+        .filter(participants::participant.eq(user_id))
+        .order(participants::created_at)
+        .load::<Participant>(conn)?;
+    let items = items::table
+        .filter(items::owner.eq(user_id))
+        .order(items::created_at)
+        .load::<Item>(conn)?;
+    let sessions = sessions::table
+        .filter(sessions::owner.eq(user_id))
+        .order(sessions::created_at)
+        .select(Session::as_select())
+        .load(conn)?;
+    let api_keys = api_keys::table
+        .filter(api_keys::owner.eq(user_id))
+        .order(api_keys::created_at)
+        .select(ApiKey::as_select())
+        .load(conn)?;
+    let notifications = notifications::table
+        .filter(notifications::recipient.eq(user_id))
+        .order(notifications::created_at)
+        .load::<Notification>(conn)?;
+    let found_items = found_items::table
+        .filter(
+            found_items::reporter
+                .eq(user_id)
+                .or(found_items::claimed_by.eq(user_id)),
+        )
+        .order(found_items::created_at)
+        .load::<FoundItem>(conn)?;
+    let loans = loans::table
+        .filter(
+            loans::borrower.eq(user_id).or(loans::item.eq_any(
+                items::table
+                    .filter(items::owner.eq(user_id))
+                    .select(items::id),
+            )),
+        )
+        .order(loans::created_at)
+        .load::<Loan>(conn)?;
+    let own_items = items::table
+        .filter(items::owner.eq(user_id))
+        .select(items::id.nullable());
+    let images = images::table
+        .filter(
+            images::uploader
+                .eq(user_id)
+                .or(images::item.eq_any(own_items)),
+        )
+        .order(images::created_at)
+        .load::<Image>(conn)?;
+
+    // Changes made to the user, and those made by the user or their API keys
+    let mut actors = vec![format!("user:{}", user_id)];
+    actors.extend(api_keys.iter().map(|key| format!("api_key:{}", key.id)));
+    let audit = audit_log::table
+        .filter(
+            audit_log::resource
+                .eq(Resource::User.name())
+                .and(audit_log::resource_id.eq(user_id))
+                .or(audit_log::actor.eq_any(&actors)),
+        )
+        .order(audit_log::created_at)
+        .load::<AuditEntry>(conn)?;
+
+    Ok(UserExport {
+        profile,
+        memberships: Memberships { role, property },
+        reservations,
+        participations,
+        items,
+        sessions,
+        api_keys,
+        notifications,
+        found_items,
+        images,
+        loans,
+        audit,
+    })
+}
+
+fn archive(bundle: &UserExport, storage: &dyn Storage) -> Result<Vec<u8>, ApiError> {
+    let parts = [
+        ("profile.json", to_json(&bundle.profile)?),
+        ("memberships.json", to_json(&bundle.memberships)?),
+        ("reservations.json", to_json(&bundle.reservations)?),
+        ("participations.json", to_json(&bundle.participations)?),
+        ("items.json", to_json(&bundle.items)?),
+        ("sessions.json", to_json(&bundle.sessions)?),
+        ("api_keys.json", to_json(&bundle.api_keys)?),
+        ("notifications.json", to_json(&bundle.notifications)?),
+        ("found_items.json", to_json(&bundle.found_items)?),
+        ("images.json", to_json(&bundle.images)?),
+        ("loans.json", to_json(&bundle.loans)?),
+        ("audit.json", to_json(&bundle.audit)?),
+    ];
+
+    let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, json) in parts {
+        zip.start_file(name, zip::write::FileOptions::default())
+            .map_err(helpers::internal)?;
+        zip.write_all(&json).map_err(helpers::internal)?;
+    }
+    for image in &bundle.images {
+        let Some(file) = image_file(image, storage)? else {
+            continue;
+        };
+        let extension = match image.content_type.as_str() {
+            "image/png" => "png",
+            _ => "jpg",
+        };
+        zip.start_file(
+            format!("images/{}.{}", image.id, extension),
+            zip::write::FileOptions::default(),
+        )
+        .map_err(helpers::internal)?;
+        zip.write_all(&file).map_err(helpers::internal)?;
+    }
+    let archive = zip.finish().map_err(helpers::internal)?;
+    Ok(archive.into_inner())
+}
+
+/// The stored file of an image, `None` if it has gone missing.
+fn image_file(image: &Image, storage: &dyn Storage) -> Result<Option<Vec<u8>>, ApiError> {
+    let object = match storage.get(&images::key(image.id, false)) {
+        Ok(object) => object,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(helpers::internal(err)),
+    };
+    match object {
+        Object::File(path) => std::fs::read(path).map(Some).map_err(helpers::internal),
+        Object::Bytes(bytes) => Ok(Some(bytes)),
+    }
+}
+
+fn to_json<T: Serialize>(part: &T) -> Result<Vec<u8>, ApiError> {
+    serde_json::to_vec_pretty(part).map_err(helpers::internal)
+}
+
+fn anonymize(
+    user_id: Uuid,
+    identity: &Identity,
+    storage: &dyn Storage,
+    conn: &mut PgConnection,
+) -> Result<User, ApiError> {
+    use crate::schema::{
+        api_keys, images, items, loans, notifications, participants, properties, sessions, users,
+    };
+
+    conn.transaction(|conn| {
+        let current = users::table
+            .find(user_id)
+            .for_update()
+            .first::<User>(conn)
+            .optional()?
+            .ok_or_else(|| ApiError::NotFound("User not found".to_string()))?;
+        if current.erased_at.is_some() {
+            return Err(ApiError::Conflict(
+                "User has already been erased".to_string(),
+            ));
+        }
+
+        let owned: i64 = properties::table
+            .filter(properties::owner.eq(user_id))
+            .count()
+            .get_result(conn)?;
+        if owned > 0 {
+            return Err(ApiError::Conflict(format!(
+                "User owns {} properties, transfer them before erasing the user",
+                owned
+            )));
+        }
+
+        let now = chrono::Local::now().naive_local();
+        let image_ids = images::table
+            .inner_join(items::table)
+            .filter(items::owner.eq(user_id))
+            .select(images::id)
+            .load::<Uuid>(conn)?;
+        let deleted = diesel::delete(items::table.filter(items::owner.eq(user_id)))
+            .get_results::<Item>(conn)?;
+        for item in &deleted {
+            audit::deleted(&identity.actor(), Resource::Item, item.id, item, conn)?;
+        }
+        let left =
+            diesel::delete(participants::table.filter(participants::participant.eq(user_id)))
+                .get_results::<Participant>(conn)?;
+        for participation in &left {
+            audit::deleted(
+                &identity.actor(),
+                Resource::Participant,
+                participation.id,
+                participation,
+                conn,
+            )?;
+        }
+        // Notes to owners are free text, the loans themselves stay with the owners' items.
+        let borrowed = diesel::update(
+            loans::table
+                .filter(loans::borrower.eq(user_id))
+                .filter(loans::message.is_not_null()),
+        )
+        .set((loans::message.eq(None::<String>), loans::updated_at.eq(now)))
+        .get_results::<Loan>(conn)?;
+        let mut no_message = Map::new();
+        no_message.insert("message".to_string(), Value::Null);
+        for loan in &borrowed {
+            audit::redact(Resource::Loan, loan.id, &no_message, conn)?;
+        }
+        diesel::update(
+            sessions::table
+                .filter(sessions::owner.eq(user_id))
+                .filter(sessions::revoked_at.is_null()),
+        )
+        .set((sessions::revoked_at.eq(now), sessions::updated_at.eq(now)))
+        .execute(conn)?;
+        diesel::update(
+            api_keys::table
+                .filter(api_keys::owner.eq(user_id))
+                .filter(api_keys::revoked_at.is_null()),
+        )
+        .set((api_keys::revoked_at.eq(now), api_keys::updated_at.eq(now)))
+        .execute(conn)?;
+        diesel::delete(notifications::table.filter(notifications::recipient.eq(user_id)))
+            .execute(conn)?;
+
+        let user = diesel::update(users::table.find(user_id))
+            .set((
+                users::name.eq(ERASED_NAME),
+                users::email.eq(None::<String>),
+                users::password_hash.eq(None::<String>),
+                users::oidc_subject.eq(None::<String>),
+                users::erased_at.eq(now),
+                users::updated_at.eq(now),
+            ))
+            .get_result::<User>(conn)?;
+
+        let mut redacted = Map::new();
+        redacted.insert("name".to_string(), Value::from(ERASED_NAME));
+        redacted.insert("email".to_string(), Value::Null);
+        audit::redact(Resource::User, user_id, &redacted, conn)?;
+        audit::erased(&identity.actor(), Resource::User, user_id, conn)?;
+        crate::images::remove_files(&image_ids, storage)?;
+        Ok(user)
+    })
+}
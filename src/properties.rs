@@ -1,96 +1,218 @@
 use super::DbPool;
-use actix_web::{delete, get, post, put, web, Error, HttpResponse};
+use actix_web::http::header::{ETag, IfMatch, IfNoneMatch};
+use actix_web::{delete, get, patch, post, put, web, HttpResponse};
 use diesel::prelude::*;
+use serde::Deserialize;
 use uuid::Uuid;
 
-use crate::helpers::{ErrorResponse, SuccessResponse};
-use crate::models::property::{NewProperty, Property, PropertyPayload};
+use crate::audit;
+use crate::auth::Identity;
+use crate::helpers::{self, ApiError, Json, PaginatedResponse, SuccessResponse};
+use crate::idempotency;
+use crate::includes::{IncludeParams, Includes, Resource};
+use crate::models::property::{NewProperty, Property, PropertyPatch, PropertyPayload};
+use crate::pagination::{paginate, Page, PageParams, Paginated};
+use crate::permissions;
+use crate::validation::Validate;
 
-type DbError = Box<dyn std::error::Error + Send + Sync>;
+#[derive(Debug, Deserialize)]
+struct Filters {
+    owner: Option<Uuid>,
+    city: Option<String>,
+    zip: Option<String>,
+    country: Option<String>,
+    /// Property an API key is restricted to.
+    #[serde(skip)]
+    scope: Option<Uuid>,
+}
+
+const SORTABLE: [&str; 5] = ["created_at", "updated_at", "name", "city", "zip"];
 
 #[get("/properties")]
-async fn index(pool: web::Data<DbPool>) -> Result<HttpResponse, Error> {
+async fn index(
+    page: web::Query<PageParams>,
+    filters: web::Query<Filters>,
+    include: web::Query<IncludeParams>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::PROPERTIES_READ)?;
+
+    let mut filters = filters.into_inner();
+    filters.scope = identity.property_scope();
+    let page = page.resolve(&SORTABLE)?;
+    let includes = Includes::parse(include.include.as_deref(), Resource::Property, &identity)?;
     let properties = web::block(move || {
         let mut conn = pool.get()?;
-        find_all(&mut conn)
+        let properties = find_all(&filters, &page, &mut conn)?;
+        includes.embed_page(Resource::Property, properties, &mut conn)
     })
-    .await?
-    .map_err(actix_web::error::ErrorInternalServerError)?;
+    .await??;
 
-    Ok(HttpResponse::Ok().json(SuccessResponse {
+    Ok(HttpResponse::Ok().json(PaginatedResponse {
         status: 200,
         message: "OK".to_string(),
-        data: properties,
+        data: properties.data,
+        meta: properties.meta,
     }))
 }
 
 #[post("/properties")]
 async fn create(
     pool: web::Data<DbPool>,
-    payload: web::Json<PropertyPayload>,
-) -> Result<HttpResponse, Error> {
-    let property = web::block(move || {
+    payload: Json<PropertyPayload>,
+    key: idempotency::Key,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require_owner(&permissions::PROPERTIES_WRITE, payload.owner)?;
+    // A new property is never the one an API key is restricted to
+    identity.require_property(None)?;
+
+    payload.validate()?;
+
+    let created = web::block(move || {
         let mut conn = pool.get()?;
-        add(&payload, &mut conn)
+        idempotency::create_once(&key, "POST /properties", &*payload, &mut conn, |conn| {
+            add(&payload, &identity, conn)
+        })
     })
-    .await?
-    .map_err(actix_web::error::ErrorInternalServerError)?;
+    .await??;
 
-    Ok(HttpResponse::Created().json(SuccessResponse {
-        status: 201,
-        message: "Created".to_string(),
-        data: property,
-    }))
+    Ok(created.respond())
 }
 
 #[get("/properties/{id}")]
-async fn show(id: web::Path<Uuid>, pool: web::Data<DbPool>) -> Result<HttpResponse, Error> {
+async fn show(
+    id: web::Path<Uuid>,
+    include: web::Query<IncludeParams>,
+    if_none_match: Option<web::Header<IfNoneMatch>>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::PROPERTIES_READ)?;
+    identity.require_property(Some(*id))?;
+
+    let includes = Includes::parse(include.include.as_deref(), Resource::Property, &identity)?;
+    let embedding = !includes.is_empty();
     let property = web::block(move || {
         let mut conn = pool.get()?;
-        find_by_id(id.into_inner(), &mut conn)
+        find_by_id(id.into_inner(), &mut conn)?
+            .map(|property| {
+                let data = includes.embed_one(Resource::Property, &property, &mut conn)?;
+                Ok::<_, ApiError>((property.updated_at, data))
+            })
+            .transpose()
     })
-    .await?
-    .map_err(actix_web::error::ErrorInternalServerError)?;
+    .await??;
 
-    if property.is_none() {
-        return Ok(HttpResponse::NotFound().json(ErrorResponse {
-            status: 404,
-            message: "Property not found".to_string(),
+    let Some((updated_at, property)) = property else {
+        return Err(ApiError::NotFound("Property not found".to_string()));
+    };
+
+    // Embedded resources change independently of this one, so only the plain
+    // representation is cacheable by its version.
+    if embedding {
+        return Ok(HttpResponse::Ok().json(SuccessResponse {
+            status: 200,
+            message: "OK".to_string(),
+            data: property,
         }));
     }
 
-    Ok(HttpResponse::Ok().json(SuccessResponse {
-        status: 200,
-        message: "OK".to_string(),
-        data: property,
-    }))
+    let etag = helpers::etag(&updated_at);
+    if helpers::not_modified(if_none_match.as_deref(), &etag) {
+        return Ok(HttpResponse::NotModified()
+            .insert_header(ETag(etag))
+            .finish());
+    }
+
+    Ok(HttpResponse::Ok()
+        .insert_header(ETag(etag))
+        .json(SuccessResponse {
+            status: 200,
+            message: "OK".to_string(),
+            data: property,
+        }))
 }
 
 #[put("/properties/{id}")]
 async fn update(
     id: web::Path<Uuid>,
-    payload: web::Json<PropertyPayload>,
+    payload: Json<PropertyPayload>,
+    if_match: Option<web::Header<IfMatch>>,
     pool: web::Data<DbPool>,
-) -> Result<HttpResponse, Error> {
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require_property(Some(*id))?;
+
+    payload.validate()?;
+
     let property = web::block(move || {
         let mut conn = pool.get()?;
-        update_by_id(id.into_inner(), &payload, &mut conn)
+        update_by_id(
+            id.into_inner(),
+            &payload,
+            if_match.as_deref(),
+            &identity,
+            &mut conn,
+        )
     })
-    .await?
-    .map_err(actix_web::error::ErrorInternalServerError)?;
+    .await??;
 
-    Ok(HttpResponse::Ok().json(SuccessResponse {
-        status: 200,
-        message: "OK".to_string(),
-        data: property,
-    }))
+    let etag = helpers::etag(&property.updated_at);
+    Ok(HttpResponse::Ok()
+        .insert_header(ETag(etag))
+        .json(SuccessResponse {
+            status: 200,
+            message: "OK".to_string(),
+            data: property,
+        }))
+}
+
+#[patch("/properties/{id}")]
+async fn partial_update(
+    id: web::Path<Uuid>,
+    changes: Json<PropertyPatch>,
+    if_match: Option<web::Header<IfMatch>>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require_property(Some(*id))?;
+
+    let property = web::block(move || {
+        let mut conn = pool.get()?;
+        patch_by_id(
+            id.into_inner(),
+            changes.0,
+            if_match.as_deref(),
+            &identity,
+            &mut conn,
+        )
+    })
+    .await??;
+
+    let etag = helpers::etag(&property.updated_at);
+    Ok(HttpResponse::Ok()
+        .insert_header(ETag(etag))
+        .json(SuccessResponse {
+            status: 200,
+            message: "OK".to_string(),
+            data: property,
+        }))
 }
 
 #[delete("/properties/{id}")]
-async fn destroy(id: web::Path<Uuid>, pool: web::Data<DbPool>) -> Result<HttpResponse, Error> {
+async fn destroy(
+    id: web::Path<Uuid>,
+    if_match: Option<web::Header<IfMatch>>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require_property(Some(*id))?;
+
     let result = web::block(move || {
         let mut conn = pool.get()?;
-        delete(id.into_inner(), &mut conn)
+        delete(id.into_inner(), if_match.as_deref(), &identity, &mut conn)
     })
     .await?
     .map(|property| {
@@ -99,13 +221,16 @@ async fn destroy(id: web::Path<Uuid>, pool: web::Data<DbPool>) -> Result<HttpRes
             message: "Deleted".to_string(),
             data: property,
         })
-    })
-    .map_err(actix_web::error::ErrorInternalServerError)?;
+    })?;
 
     Ok(result)
 }
 
-fn add(payload: &PropertyPayload, conn: &mut PgConnection) -> Result<Property, DbError> {
+fn add(
+    payload: &PropertyPayload,
+    identity: &Identity,
+    conn: &mut PgConnection,
+) -> Result<Property, ApiError> {
     use crate::schema::properties::dsl::*;
 
     let new_property = NewProperty {
@@ -114,27 +239,64 @@ fn add(payload: &PropertyPayload, conn: &mut PgConnection) -> Result<Property, D
         address2: payload.address2.as_deref(),
         city: payload.city.as_str(),
         zip: payload.zip.as_str(),
+        country: payload.country.as_str(),
         owner: payload.owner,
         created_at: chrono::Local::now().naive_local(),
         updated_at: chrono::Local::now().naive_local(),
     };
 
-    let res = diesel::insert_into(properties)
-        .values(&new_property)
-        .returning(properties::all_columns())
-        .get_result(conn)?;
-
-    Ok(res)
+    conn.transaction(|conn| {
+        let res = diesel::insert_into(properties)
+            .values(&new_property)
+            .returning(properties::all_columns())
+            .get_result::<Property>(conn)?;
+        audit::created(&identity.actor(), Resource::Property, res.id, &res, conn)?;
+        Ok(res)
+    })
 }
 
-fn find_all(conn: &mut PgConnection) -> Result<Vec<Property>, DbError> {
+fn find_all(
+    filters: &Filters,
+    page: &Page,
+    conn: &mut PgConnection,
+) -> Result<Paginated<Property>, ApiError> {
     use crate::schema::properties::dsl::*;
 
-    let items = properties.load::<Property>(conn)?;
-    Ok(items)
+    let filtered = || {
+        let mut query = properties.into_boxed();
+        if let Some(property_id) = filters.scope {
+            query = query.filter(id.eq(property_id));
+        }
+        if let Some(owner_id) = filters.owner {
+            query = query.filter(owner.eq(owner_id));
+        }
+        if let Some(property_city) = &filters.city {
+            query = query.filter(city.eq(property_city));
+        }
+        if let Some(property_zip) = &filters.zip {
+            query = query.filter(zip.eq(property_zip));
+        }
+        if let Some(property_country) = &filters.country {
+            query = query.filter(country.eq(property_country));
+        }
+        query
+    };
+
+    let total = filtered().count().get_result(conn)?;
+    let rows = match page.column.as_str() {
+        "updated_at" => {
+            paginate!(filtered(), page, updated_at, id, chrono::NaiveDateTime).load(conn)?
+        }
+        "name" => paginate!(filtered(), page, name, id, String).load(conn)?,
+        "city" => paginate!(filtered(), page, city, id, String).load(conn)?,
+        "zip" => paginate!(filtered(), page, zip, id, String).load(conn)?,
+        _ => paginate!(filtered(), page, created_at, id, chrono::NaiveDateTime).load(conn)?,
+    };
+
+    Ok(page.finish(rows, total))
 }
 
-fn find_by_id(property_id: Uuid, conn: &mut PgConnection) -> Result<Option<Property>, DbError> {
+fn find_by_id(property_id: Uuid, conn: &mut PgConnection) -> Result<Option<Property>, ApiError> {
     use crate::schema::properties::dsl::*;
 
     let property = properties
@@ -148,27 +310,108 @@ fn find_by_id(property_id: Uuid, conn: &mut PgConnection) -> Result<Option<Prope
 fn update_by_id(
     property_id: Uuid,
     payload: &PropertyPayload,
+    if_match: Option<&IfMatch>,
+    identity: &Identity,
     conn: &mut PgConnection,
-) -> Result<Property, DbError> {
+) -> Result<Property, ApiError> {
     use crate::schema::properties::dsl::*;
 
-    let property = diesel::update(properties.find(property_id))
-        .set((
-            name.eq(payload.name.to_string()),
-            address.eq(payload.address.to_string()),
-            address2.eq(payload.address2.as_deref()),
-            city.eq(payload.city.to_string()),
-            zip.eq(payload.zip.to_string()),
-            owner.eq(payload.owner),
-            updated_at.eq(chrono::Local::now().naive_local()),
-        ))
-        .get_result::<Property>(conn)?;
-    Ok(property)
+    conn.transaction(|conn| {
+        let current = find_for_update(property_id, conn)?;
+        identity.require_owner(&permissions::PROPERTIES_WRITE, current.owner)?;
+        identity.require_owner(&permissions::PROPERTIES_WRITE, payload.owner)?;
+        helpers::check_if_match(if_match, &current.updated_at)?;
+
+        let property = diesel::update(properties.find(property_id))
+            .set((
+                name.eq(payload.name.to_string()),
+                address.eq(payload.address.to_string()),
+                address2.eq(payload.address2.as_deref()),
+                city.eq(payload.city.to_string()),
+                zip.eq(payload.zip.to_string()),
+                country.eq(payload.country.to_string()),
+                owner.eq(payload.owner),
+                updated_at.eq(chrono::Local::now().naive_local()),
+            ))
+            .get_result::<Property>(conn)?;
+        audit::updated(
+            &identity.actor(),
+            Resource::Property,
+            property_id,
+            &current,
+            &property,
+            conn,
+        )?;
+        Ok(property)
+    })
+}
+
+fn patch_by_id(
+    property_id: Uuid,
+    mut changes: PropertyPatch,
+    if_match: Option<&IfMatch>,
+    identity: &Identity,
+    conn: &mut PgConnection,
+) -> Result<Property, ApiError> {
+    use crate::schema::properties::dsl::*;
+
+    conn.transaction(|conn| {
+        let current = find_for_update(property_id, conn)?;
+        identity.require_owner(&permissions::PROPERTIES_WRITE, current.owner)?;
+        helpers::check_if_match(if_match, &current.updated_at)?;
+        let merged = changes.merge(current.clone());
+        identity.require_owner(&permissions::PROPERTIES_WRITE, merged.owner)?;
+        merged.validate()?;
+
+        changes.updated_at = Some(chrono::Local::now().naive_local());
+        let property = diesel::update(properties.find(property_id))
+            .set(&changes)
+            .get_result::<Property>(conn)?;
+        audit::updated(
+            &identity.actor(),
+            Resource::Property,
+            property_id,
+            &current,
+            &property,
+            conn,
+        )?;
+        Ok(property)
+    })
 }
 
-fn delete(property_id: Uuid, conn: &mut PgConnection) -> Result<usize, DbError> {
+/// Loads a property and locks it until the end of the transaction.
+fn find_for_update(property_id: Uuid, conn: &mut PgConnection) -> Result<Property, ApiError> {
     use crate::schema::properties::dsl::*;
 
-    let count = diesel::delete(properties.find(property_id)).execute(conn)?;
-    Ok(count)
+    properties
+        .find(property_id)
+        .for_update()
+        .first::<Property>(conn)
+        .optional()?
+        .ok_or_else(|| ApiError::NotFound("Property not found".to_string()))
+}
+
+fn delete(
+    property_id: Uuid,
+    if_match: Option<&IfMatch>,
+    identity: &Identity,
+    conn: &mut PgConnection,
+) -> Result<usize, ApiError> {
+    use crate::schema::properties::dsl::*;
+
+    conn.transaction(|conn| {
+        let current = find_for_update(property_id, conn)?;
+        identity.require_owner(&permissions::PROPERTIES_WRITE, current.owner)?;
+        helpers::check_if_match(if_match, &current.updated_at)?;
+
+        let count = diesel::delete(properties.find(property_id)).execute(conn)?;
+        audit::deleted(
+            &identity.actor(),
+            Resource::Property,
+            property_id,
+            &current,
+            conn,
+        )?;
+        Ok(count)
+    })
 }
@@ -1,111 +1,384 @@
 use super::DbPool;
-use actix_web::{delete, get, post, put, web, Error, HttpResponse};
+use actix_web::http::header::{ETag, IfMatch, IfNoneMatch};
+use actix_web::{delete, get, patch, post, put, web, HttpResponse};
 use diesel::prelude::*;
-use serde::{Deserialize, Serialize};
+use serde::Deserialize;
+use std::collections::HashMap;
 use uuid::Uuid;
 
-use crate::helpers::{ErrorResponse, SuccessResponse};
-use crate::models::reservation::{NewReservation, Reservation, ReservationPayload};
+use crate::audit;
+use crate::auth::Identity;
+use crate::helpers::{self, ApiError, FieldError, Json, PaginatedResponse, SuccessResponse};
+use crate::idempotency;
+use crate::includes::{IncludeParams, Includes, Resource};
+use crate::models::reservation::{
+    NewReservation, Reservation, ReservationItemsPayload, ReservationPatch, ReservationPayload,
+};
+use crate::models::resource_kind::{self, ResourceKind};
+use crate::nested::{self, Parent};
+use crate::pagination::{paginate, Page, PageParams, Paginated};
+use crate::participants;
+use crate::permissions;
+use crate::validation::Validate;
 
-type DbError = Box<dyn std::error::Error + Send + Sync>;
-
-#[derive(Debug, Deserialize, Serialize)]
-struct QueryParams {
+#[derive(Debug, Deserialize)]
+struct Filters {
+    owner: Option<Uuid>,
+    #[serde(alias = "machine")]
+    resource: Option<Uuid>,
+    shared: Option<bool>,
+    /// Reservations the item was washed in.
+    item: Option<Uuid>,
     date: Option<String>,
+    /// Property an API key is restricted to.
+    #[serde(skip)]
+    property: Option<Uuid>,
 }
 
+const SORTABLE: [&str; 4] = ["created_at", "updated_at", "start_time", "end_time"];
+
 #[get("/reservations")]
 async fn index(
-    info: web::Query<QueryParams>,
+    page: web::Query<PageParams>,
+    filters: web::Query<Filters>,
+    include: web::Query<IncludeParams>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::RESERVATIONS_READ)?;
+
+    let mut filters = filters.into_inner();
+    filters.property = identity.property_scope();
+    list(None, &page, filters, &include, &identity, pool).await
+}
+
+#[get("/machines/{id}/reservations")]
+async fn machine_index(
+    id: web::Path<Uuid>,
+    page: web::Query<PageParams>,
+    filters: web::Query<Filters>,
+    include: web::Query<IncludeParams>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::RESERVATIONS_READ)?;
+
+    let parent = Parent::Machine(id.into_inner());
+    let mut filters = filters.into_inner();
+    filters.resource = Some(parent.id());
+    filters.property = identity.property_scope();
+    list(Some(parent), &page, filters, &include, &identity, pool).await
+}
+
+#[get("/resources/{id}/reservations")]
+async fn resource_index(
+    id: web::Path<Uuid>,
+    page: web::Query<PageParams>,
+    filters: web::Query<Filters>,
+    include: web::Query<IncludeParams>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::RESERVATIONS_READ)?;
+
+    let parent = Parent::Resource(id.into_inner());
+    let mut filters = filters.into_inner();
+    filters.resource = Some(parent.id());
+    filters.property = identity.property_scope();
+    list(Some(parent), &page, filters, &include, &identity, pool).await
+}
+
+#[get("/users/{id}/reservations")]
+async fn user_index(
+    id: web::Path<Uuid>,
+    page: web::Query<PageParams>,
+    filters: web::Query<Filters>,
+    include: web::Query<IncludeParams>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::RESERVATIONS_READ)?;
+
+    let parent = Parent::User(id.into_inner());
+    let mut filters = filters.into_inner();
+    filters.owner = Some(parent.id());
+    filters.property = identity.property_scope();
+    list(Some(parent), &page, filters, &include, &identity, pool).await
+}
+
+/// The reservations an item was washed in.
+#[get("/items/{id}/history")]
+async fn item_index(
+    id: web::Path<Uuid>,
+    page: web::Query<PageParams>,
+    filters: web::Query<Filters>,
+    include: web::Query<IncludeParams>,
     pool: web::Data<DbPool>,
-) -> Result<HttpResponse, Error> {
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::RESERVATIONS_READ)?;
+    identity.require(permissions::ITEMS_READ)?;
+
+    let parent = Parent::Item(id.into_inner());
+    let mut filters = filters.into_inner();
+    filters.item = Some(parent.id());
+    filters.property = identity.property_scope();
+    list(Some(parent), &page, filters, &include, &identity, pool).await
+}
+
+async fn list(
+    parent: Option<Parent>,
+    page: &PageParams,
+    filters: Filters,
+    include: &IncludeParams,
+    identity: &Identity,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, ApiError> {
+    let page = page.resolve(&SORTABLE)?;
+    let date: Option<chrono::NaiveDate> = match &filters.date {
+        None => None,
+        Some(date) => Some(date.parse().map_err(|_| {
+            ApiError::BadRequest(format!("Invalid date '{}', expected YYYY-MM-DD", date))
+        })?),
+    };
+    let includes = Includes::parse(include.include.as_deref(), Resource::Reservation, identity)?;
     let reservations = web::block(move || {
         let mut conn = pool.get()?;
-
-        if info.date.is_none() {
-            return find_all(&mut conn);
+        if let Some(parent) = parent {
+            parent.ensure_exists(&mut conn)?;
         }
-        let date: chrono::NaiveDate = info.date.is_some().to_string().parse().unwrap();
-
-        find_all_by_date(date, &mut conn)
+        let reservations = find_all(&filters, date, &page, &mut conn)?;
+        includes.embed_page(Resource::Reservation, reservations, &mut conn)
     })
-    .await?
-    .map_err(actix_web::error::ErrorInternalServerError)?;
+    .await??;
 
-    Ok(HttpResponse::Ok().json(SuccessResponse {
+    Ok(HttpResponse::Ok().json(PaginatedResponse {
         status: 200,
         message: "OK".to_string(),
-        data: reservations,
+        data: reservations.data,
+        meta: reservations.meta,
     }))
 }
 
 #[post("/reservations")]
 async fn create(
     pool: web::Data<DbPool>,
-    payload: web::Json<ReservationPayload>,
-) -> Result<HttpResponse, Error> {
-    let reservation = web::block(move || {
+    payload: Json<ReservationPayload>,
+    key: idempotency::Key,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    insert(
+        None,
+        identity,
+        "POST /reservations".to_string(),
+        payload.0,
+        key,
+        pool,
+    )
+    .await
+}
+
+#[post("/machines/{id}/reservations")]
+async fn machine_create(
+    id: web::Path<Uuid>,
+    pool: web::Data<DbPool>,
+    body: Json<serde_json::Value>,
+    key: idempotency::Key,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    let parent = Parent::Machine(id.into_inner());
+    let payload = nested::payload(body.0, "resource", &parent)?;
+    let scope = format!("POST /machines/{}/reservations", parent.id());
+    insert(Some(parent), identity, scope, payload, key, pool).await
+}
+
+#[post("/resources/{id}/reservations")]
+async fn resource_create(
+    id: web::Path<Uuid>,
+    pool: web::Data<DbPool>,
+    body: Json<serde_json::Value>,
+    key: idempotency::Key,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    let parent = Parent::Resource(id.into_inner());
+    let payload = nested::payload(body.0, "resource", &parent)?;
+    let scope = format!("POST /resources/{}/reservations", parent.id());
+    insert(Some(parent), identity, scope, payload, key, pool).await
+}
+
+#[post("/users/{id}/reservations")]
+async fn user_create(
+    id: web::Path<Uuid>,
+    pool: web::Data<DbPool>,
+    body: Json<serde_json::Value>,
+    key: idempotency::Key,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    let parent = Parent::User(id.into_inner());
+    let payload = nested::payload(body.0, "owner", &parent)?;
+    let scope = format!("POST /users/{}/reservations", parent.id());
+    insert(Some(parent), identity, scope, payload, key, pool).await
+}
+
+async fn insert(
+    parent: Option<Parent>,
+    identity: Identity,
+    scope: String,
+    payload: ReservationPayload,
+    key: idempotency::Key,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, ApiError> {
+    identity.require_owner(&permissions::RESERVATIONS_WRITE, payload.owner)?;
+
+    payload.validate()?;
+
+    let created = web::block(move || {
         let mut conn = pool.get()?;
-        add(&payload, &mut conn)
+        if let Some(parent) = parent {
+            parent.ensure_exists(&mut conn)?;
+        }
+        identity
+            .require_property_of(|| permissions::resource_property(payload.resource, &mut conn))?;
+        idempotency::create_once(&key, &scope, &payload, &mut conn, |conn| {
+            add(&payload, &identity, conn)
+        })
     })
-    .await?
-    .map_err(actix_web::error::ErrorInternalServerError)?;
+    .await??;
 
-    Ok(HttpResponse::Created().json(SuccessResponse {
-        status: 201,
-        message: "Created".to_string(),
-        data: reservation,
-    }))
+    Ok(created.respond())
 }
 
 #[get("/reservations/{id}")]
-async fn show(id: web::Path<Uuid>, pool: web::Data<DbPool>) -> Result<HttpResponse, Error> {
+async fn show(
+    id: web::Path<Uuid>,
+    include: web::Query<IncludeParams>,
+    if_none_match: Option<web::Header<IfNoneMatch>>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::RESERVATIONS_READ)?;
+
+    let includes = Includes::parse(include.include.as_deref(), Resource::Reservation, &identity)?;
+    let embedding = !includes.is_empty();
     let reservation = web::block(move || {
         let mut conn = pool.get()?;
-        find_by_id(id.into_inner(), &mut conn)
+        let reservation = find_by_id(id.into_inner(), &mut conn)?;
+        if let Some(reservation) = &reservation {
+            identity.require_property_of(|| {
+                permissions::resource_property(reservation.resource, &mut conn)
+            })?;
+        }
+        reservation
+            .map(|reservation| {
+                let data = includes.embed_one(Resource::Reservation, &reservation, &mut conn)?;
+                Ok::<_, ApiError>((reservation.updated_at, data))
+            })
+            .transpose()
     })
-    .await?
-    .map_err(actix_web::error::ErrorInternalServerError)?;
+    .await??;
+
+    let Some((updated_at, reservation)) = reservation else {
+        return Err(ApiError::NotFound("Reservation not found".to_string()));
+    };
 
-    if reservation.is_none() {
-        return Ok(HttpResponse::NotFound().json(ErrorResponse {
-            status: 404,
-            message: "Reservation not found".to_string(),
+    // Embedded resources change independently of this one, so only the plain
+    // representation is cacheable by its version.
+    if embedding {
+        return Ok(HttpResponse::Ok().json(SuccessResponse {
+            status: 200,
+            message: "OK".to_string(),
+            data: reservation,
         }));
     }
 
-    Ok(HttpResponse::Ok().json(SuccessResponse {
-        status: 200,
-        message: "OK".to_string(),
-        data: reservation,
-    }))
+    let etag = helpers::etag(&updated_at);
+    if helpers::not_modified(if_none_match.as_deref(), &etag) {
+        return Ok(HttpResponse::NotModified()
+            .insert_header(ETag(etag))
+            .finish());
+    }
+
+    Ok(HttpResponse::Ok()
+        .insert_header(ETag(etag))
+        .json(SuccessResponse {
+            status: 200,
+            message: "OK".to_string(),
+            data: reservation,
+        }))
 }
 
 #[put("/reservations/{id}")]
 async fn update(
     id: web::Path<Uuid>,
-    payload: web::Json<ReservationPayload>,
+    payload: Json<ReservationPayload>,
+    if_match: Option<web::Header<IfMatch>>,
     pool: web::Data<DbPool>,
-) -> Result<HttpResponse, Error> {
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    payload.validate()?;
+
     let reservation = web::block(move || {
         let mut conn = pool.get()?;
-        update_by_id(id.into_inner(), &payload, &mut conn)
+        update_by_id(
+            id.into_inner(),
+            &payload,
+            if_match.as_deref(),
+            &identity,
+            &mut conn,
+        )
     })
-    .await?
-    .map_err(actix_web::error::ErrorInternalServerError)?;
+    .await??;
 
-    Ok(HttpResponse::Ok().json(SuccessResponse {
-        status: 200,
-        message: "OK".to_string(),
-        data: reservation,
-    }))
+    let etag = helpers::etag(&reservation.updated_at);
+    Ok(HttpResponse::Ok()
+        .insert_header(ETag(etag))
+        .json(SuccessResponse {
+            status: 200,
+            message: "OK".to_string(),
+            data: reservation,
+        }))
+}
+
+#[patch("/reservations/{id}")]
+async fn partial_update(
+    id: web::Path<Uuid>,
+    changes: Json<ReservationPatch>,
+    if_match: Option<web::Header<IfMatch>>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    let reservation = web::block(move || {
+        let mut conn = pool.get()?;
+        patch_by_id(
+            id.into_inner(),
+            changes.0,
+            if_match.as_deref(),
+            &identity,
+            &mut conn,
+        )
+    })
+    .await??;
+
+    let etag = helpers::etag(&reservation.updated_at);
+    Ok(HttpResponse::Ok()
+        .insert_header(ETag(etag))
+        .json(SuccessResponse {
+            status: 200,
+            message: "OK".to_string(),
+            data: reservation,
+        }))
 }
 
 #[delete("/reservations/{id}")]
-async fn destroy(id: web::Path<Uuid>, pool: web::Data<DbPool>) -> Result<HttpResponse, Error> {
+async fn destroy(
+    id: web::Path<Uuid>,
+    if_match: Option<web::Header<IfMatch>>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
     let result = web::block(move || {
         let mut conn = pool.get()?;
-        delete(id.into_inner(), &mut conn)
+        delete(id.into_inner(), if_match.as_deref(), &identity, &mut conn)
     })
     .await?
     .map(|reservation| {
@@ -114,63 +387,132 @@ async fn destroy(id: web::Path<Uuid>, pool: web::Data<DbPool>) -> Result<HttpRes
             message: "Deleted".to_string(),
             data: reservation,
         })
-    })
-    .map_err(actix_web::error::ErrorInternalServerError)?;
+    })?;
 
     Ok(result)
 }
 
-fn add(payload: &ReservationPayload, conn: &mut PgConnection) -> Result<Reservation, DbError> {
+/// Replaces the items recorded as washed in a reservation.
+#[put("/reservations/{id}/items")]
+async fn update_items(
+    id: web::Path<Uuid>,
+    payload: Json<ReservationItemsPayload>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    let items = web::block(move || {
+        let mut conn = pool.get()?;
+        record_items(id.into_inner(), &payload.items, &identity, &mut conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(SuccessResponse {
+        status: 200,
+        message: "OK".to_string(),
+        data: items,
+    }))
+}
+
+fn add(
+    payload: &ReservationPayload,
+    identity: &Identity,
+    conn: &mut PgConnection,
+) -> Result<Reservation, ApiError> {
     use crate::schema::reservations::dsl::*;
 
     let new_reservation = NewReservation {
         owner: payload.owner,
-        machine: payload.machine,
+        resource: payload.resource,
         start_time: payload.start_time,
         end_time: payload.end_time,
         shared: payload.shared,
+        amount: payload.amount,
         created_at: chrono::Local::now().naive_local(),
         updated_at: chrono::Local::now().naive_local(),
     };
 
-    let res = diesel::insert_into(reservations)
-        .values(&new_reservation)
-        .returning(reservations::all_columns())
-        .get_result(conn)?;
-
-    Ok(res)
-}
-
-fn find_all(conn: &mut PgConnection) -> Result<Vec<Reservation>, DbError> {
-    use crate::schema::reservations::dsl::*;
-
-    let items = reservations.load::<Reservation>(conn)?;
-    Ok(items)
+    conn.transaction(|conn| {
+        check_booking(payload, None, conn)?;
+        let res = diesel::insert_into(reservations)
+            .values(&new_reservation)
+            .returning(reservations::all_columns())
+            .get_result::<Reservation>(conn)?;
+        audit::created(&identity.actor(), Resource::Reservation, res.id, &res, conn)?;
+        Ok(res)
+    })
 }
 
-fn find_all_by_date(
-    date: chrono::NaiveDate,
+fn find_all(
+    filters: &Filters,
+    date: Option<chrono::NaiveDate>,
+    page: &Page,
     conn: &mut PgConnection,
-) -> Result<Vec<Reservation>, DbError> {
+) -> Result<Paginated<Reservation>, ApiError> {
     use crate::schema::reservations::dsl::*;
+    use crate::schema::{reservation_items, resources};
 
-    // Filter by date
-    let items = reservations
-        .filter(start_time.between(
-            date.and_hms_opt(0, 0, 0).unwrap(),
-            date.and_hms_opt(23, 59, 59).unwrap(),
-        ))
-        .order(start_time.asc())
-        .limit(10)
-        .load::<Reservation>(conn)?;
+    let filtered = || {
+        let mut query = reservations.into_boxed();
+        if let Some(owner_id) = filters.owner {
+            query = query.filter(owner.eq(owner_id));
+        }
+        if let Some(resource_id) = filters.resource {
+            query = query.filter(resource.eq(resource_id));
+        }
+        if let Some(property_id) = filters.property {
+            query = query.filter(
+                resource.eq_any(
+                    resources::table
+                        .filter(resources::property.eq(property_id))
+                        .select(resources::id),
+                ),
+            );
+        }
+        if let Some(is_shared) = filters.shared {
+            query = query.filter(shared.eq(is_shared));
+        }
+        if let Some(item_id) = filters.item {
+            query = query.filter(
+                id.eq_any(
+                    reservation_items::table
+                        .filter(reservation_items::item.eq(item_id))
+                        .select(reservation_items::reservation),
+                ),
+            );
+        }
+        // Reservations starting on the given day
+        if let Some(date) = date {
+            let start = date.and_time(chrono::NaiveTime::MIN);
+            query = query.filter(
+                start_time
+                    .ge(start)
+                    .and(start_time.lt(start + chrono::Days::new(1))),
+            );
+        }
+        query
+    };
 
-    Ok(items)
+    let total = filtered().count().get_result(conn)?;
+    let rows = match page.column.as_str() {
+        "updated_at" => {
+            paginate!(filtered(), page, updated_at, id, chrono::NaiveDateTime).load(conn)?
+        }
+        "start_time" => {
+            paginate!(filtered(), page, start_time, id, chrono::NaiveDateTime).load(conn)?
+        }
+        "end_time" => {
+            paginate!(filtered(), page, end_time, id, chrono::NaiveDateTime).load(conn)?
+        }
+        _ => paginate!(filtered(), page, created_at, id, chrono::NaiveDateTime).load(conn)?,
+    };
+
+    Ok(page.finish(rows, total))
 }
 
 fn find_by_id(
     reservation_id: Uuid,
     conn: &mut PgConnection,
-) -> Result<Option<Reservation>, DbError> {
+) -> Result<Option<Reservation>, ApiError> {
     use crate::schema::reservations::dsl::*;
 
     let reservation = reservations
@@ -184,26 +526,637 @@ fn find_by_id(
 fn update_by_id(
     reservation_id: Uuid,
     payload: &ReservationPayload,
+    if_match: Option<&IfMatch>,
+    identity: &Identity,
+    conn: &mut PgConnection,
+) -> Result<Reservation, ApiError> {
+    use crate::schema::reservations::dsl::*;
+
+    conn.transaction(|conn| {
+        let current = find_for_update(reservation_id, conn)?;
+        identity.require_owner(&permissions::RESERVATIONS_WRITE, current.owner)?;
+        identity.require_owner(&permissions::RESERVATIONS_WRITE, payload.owner)?;
+        identity.require_property_of(|| permissions::resource_property(current.resource, conn))?;
+        identity.require_property_of(|| permissions::resource_property(payload.resource, conn))?;
+        helpers::check_if_match(if_match, &current.updated_at)?;
+        if rebooks(&current, payload) {
+            check_booking(payload, Some(reservation_id), conn)?;
+        }
+        if current.shared && !payload.shared {
+            check_unshared(reservation_id, conn)?;
+        }
+
+        let reservation = diesel::update(reservations.find(reservation_id))
+            .set((
+                owner.eq(payload.owner),
+                resource.eq(payload.resource),
+                start_time.eq(payload.start_time),
+                end_time.eq(payload.end_time),
+                shared.eq(payload.shared),
+                amount.eq(payload.amount),
+                updated_at.eq(chrono::Local::now().naive_local()),
+            ))
+            .get_result::<Reservation>(conn)?;
+        audit::updated(
+            &identity.actor(),
+            Resource::Reservation,
+            reservation_id,
+            &current,
+            &reservation,
+            conn,
+        )?;
+        Ok(reservation)
+    })
+}
+
+fn patch_by_id(
+    reservation_id: Uuid,
+    mut changes: ReservationPatch,
+    if_match: Option<&IfMatch>,
+    identity: &Identity,
+    conn: &mut PgConnection,
+) -> Result<Reservation, ApiError> {
+    use crate::schema::reservations::dsl::*;
+
+    conn.transaction(|conn| {
+        let current = find_for_update(reservation_id, conn)?;
+        identity.require_owner(&permissions::RESERVATIONS_WRITE, current.owner)?;
+        identity.require_property_of(|| permissions::resource_property(current.resource, conn))?;
+        helpers::check_if_match(if_match, &current.updated_at)?;
+        let merged = changes.merge(current.clone());
+        identity.require_owner(&permissions::RESERVATIONS_WRITE, merged.owner)?;
+        identity.require_property_of(|| permissions::resource_property(merged.resource, conn))?;
+        merged.validate()?;
+        if rebooks(&current, &merged) {
+            check_booking(&merged, Some(reservation_id), conn)?;
+        }
+        if current.shared && !merged.shared {
+            check_unshared(reservation_id, conn)?;
+        }
+
+        changes.updated_at = Some(chrono::Local::now().naive_local());
+        let reservation = diesel::update(reservations.find(reservation_id))
+            .set(&changes)
+            .get_result::<Reservation>(conn)?;
+        audit::updated(
+            &identity.actor(),
+            Resource::Reservation,
+            reservation_id,
+            &current,
+            &reservation,
+            conn,
+        )?;
+        Ok(reservation)
+    })
+}
+
+fn record_items(
+    reservation_id: Uuid,
+    item_ids: &[Uuid],
+    identity: &Identity,
+    conn: &mut PgConnection,
+) -> Result<Vec<Uuid>, ApiError> {
+    use crate::schema::{items, reservation_items};
+
+    conn.transaction(|conn| {
+        let current = find_for_update(reservation_id, conn)?;
+        identity.require_owner(&permissions::RESERVATIONS_WRITE, current.owner)?;
+        identity.require_property_of(|| permissions::resource_property(current.resource, conn))?;
+
+        let recorded = |conn: &mut PgConnection| {
+            reservation_items::table
+                .filter(reservation_items::reservation.eq(reservation_id))
+                .order(reservation_items::item)
+                .select(reservation_items::item)
+                .load::<Uuid>(conn)
+        };
+        let before = ReservationItemsPayload {
+            items: recorded(conn)?,
+        };
+
+        let owners = items::table
+            .filter(items::id.eq_any(item_ids))
+            .select((items::id, items::owner))
+            .load::<(Uuid, Uuid)>(conn)?;
+        let errors: Vec<FieldError> = item_ids
+            .iter()
+            .enumerate()
+            .filter(|(_, item_id)| !owners.iter().any(|(known, _)| known == *item_id))
+            .map(|(position, item_id)| FieldError {
+                field: format!("items[{}]", position),
+                code: "not_found".to_string(),
+                message: format!("Item {} not found", item_id),
+            })
+            .collect();
+        if !errors.is_empty() {
+            return Err(ApiError::InvalidPayload(errors));
+        }
+        for (_, item_owner) in &owners {
+            identity.require_owner(&permissions::ITEMS_WRITE, *item_owner)?;
+        }
+
+        diesel::delete(
+            reservation_items::table.filter(reservation_items::reservation.eq(reservation_id)),
+        )
+        .execute(conn)?;
+        let rows: Vec<_> = owners
+            .iter()
+            .map(|(item_id, _)| {
+                (
+                    reservation_items::reservation.eq(reservation_id),
+                    reservation_items::item.eq(item_id),
+                )
+            })
+            .collect();
+        diesel::insert_into(reservation_items::table)
+            .values(&rows)
+            .execute(conn)?;
+
+        let after = ReservationItemsPayload {
+            items: recorded(conn)?,
+        };
+        audit::updated(
+            &identity.actor(),
+            Resource::Reservation,
+            reservation_id,
+            &before,
+            &after,
+            conn,
+        )?;
+        Ok(after.items)
+    })
+}
+
+/// Whether `payload` changes who reserves how much of what when and how, so it has to be
+/// checked again.
+fn rebooks(current: &Reservation, payload: &ReservationPayload) -> bool {
+    (
+        current.resource,
+        current.owner,
+        current.start_time,
+        current.end_time,
+        current.shared,
+        current.amount,
+    ) != (
+        payload.resource,
+        payload.owner,
+        payload.start_time,
+        payload.end_time,
+        payload.shared,
+        payload.amount,
+    )
+}
+
+/// Fails with `409` while others take part in a reservation which stops being shared.
+fn check_unshared(reservation_id: Uuid, conn: &mut PgConnection) -> Result<(), ApiError> {
+    use crate::schema::participants;
+
+    let joined = participants::table
+        .filter(participants::reservation.eq(reservation_id))
+        .count()
+        .get_result::<i64>(conn)?;
+    if joined > 0 {
+        return Err(ApiError::Conflict(format!(
+            "{} participant(s) joined the reservation, remove them first",
+            joined
+        )));
+    }
+    Ok(())
+}
+
+/// Checks a reservation against the booking rules of the resource's kind and the other
+/// reservations of the resource, `except` the one being changed.
+///
+/// The resource stays locked until the end of the transaction, so concurrent bookings
+/// of it cannot both pass.
+fn check_booking(
+    booking: &ReservationPayload,
+    except: Option<Uuid>,
+    conn: &mut PgConnection,
+) -> Result<(), ApiError> {
+    use crate::schema::reservations::dsl::*;
+    use crate::schema::{resource_kinds, resources};
+
+    let Some((resource_name, kind_name, capacity)) = resources::table
+        .find(booking.resource)
+        .select((resources::name, resources::kind, resources::capacity))
+        .for_update()
+        .first::<(String, String, i32)>(conn)
+        .optional()?
+    else {
+        return Err(ApiError::InvalidPayload(vec![FieldError {
+            field: "resource".to_string(),
+            code: "not_found".to_string(),
+            message: format!("Resource {} not found", booking.resource),
+        }]));
+    };
+    let kind = resource_kinds::table
+        .filter(resource_kinds::name.eq(&kind_name))
+        .first::<ResourceKind>(conn)?;
+    let now = chrono::Local::now().naive_local();
+
+    let mut errors = Vec::new();
+    if let Some(minutes) = kind.max_duration_minutes {
+        if booking.end_time - booking.start_time > chrono::Duration::minutes(minutes.into()) {
+            errors.push(FieldError {
+                field: "end_time".to_string(),
+                code: "out_of_range".to_string(),
+                message: format!(
+                    "Reservations of kind {} may last at most {} minutes",
+                    kind.name, minutes
+                ),
+            });
+        }
+    }
+    if let Some(days) = kind.max_advance_days {
+        if booking.start_time > now + chrono::Duration::days(days.into()) {
+            errors.push(FieldError {
+                field: "start_time".to_string(),
+                code: "out_of_range".to_string(),
+                message: format!(
+                    "Reservations of kind {} may start at most {} days ahead",
+                    kind.name, days
+                ),
+            });
+        }
+    }
+    let joined = match except {
+        Some(reservation_id) => participants::taken(&[reservation_id], conn)?
+            .remove(&reservation_id)
+            .unwrap_or_default(),
+        None => 0,
+    };
+    if i64::from(booking.amount) + joined > capacity.into() {
+        errors.push(FieldError {
+            field: "amount".to_string(),
+            code: "out_of_range".to_string(),
+            message: format!(
+                "amount must be at most {} {} on {}",
+                i64::from(capacity) - joined,
+                kind.unit,
+                resource_name
+            ),
+        });
+    }
+    if !errors.is_empty() {
+        return Err(ApiError::InvalidPayload(errors));
+    }
+
+    let others = || {
+        let mut query = reservations.into_boxed();
+        if let Some(except) = except {
+            query = query.filter(id.ne(except));
+        }
+        query
+    };
+
+    let booked = usages(
+        &[booking.resource],
+        booking.start_time,
+        booking.end_time,
+        except,
+        conn,
+    )?
+    .remove(&booking.resource)
+    .unwrap_or_default();
+    let between = format!(
+        "between {} and {}",
+        booking.start_time.format("%Y-%m-%d %H:%M"),
+        booking.end_time.format("%Y-%m-%d %H:%M")
+    );
+    if !shares_with(
+        &booked,
+        booking.start_time,
+        booking.end_time,
+        is_exclusive(booking.shared, &kind.unit),
+    ) {
+        return Err(ApiError::Conflict(format!(
+            "{} is already reserved {}",
+            resource_name, between
+        )));
+    }
+    let left = i64::from(capacity) - peak(&booked, booking.start_time, booking.end_time) - joined;
+    if i64::from(booking.amount) > left {
+        return Err(ApiError::Conflict(format!(
+            "Only {} {} of {} are left {}",
+            left.max(0),
+            kind.unit,
+            resource_name,
+            between
+        )));
+    }
+
+    if let Some(limit) = kind.max_upcoming {
+        let upcoming = others()
+            .filter(owner.eq(booking.owner))
+            .filter(end_time.gt(now))
+            .filter(
+                resource.eq_any(
+                    resources::table
+                        .filter(resources::kind.eq(&kind.name))
+                        .select(resources::id),
+                ),
+            )
+            .count()
+            .get_result::<i64>(conn)?;
+        if booking.end_time > now && upcoming >= limit.into() {
+            return Err(ApiError::Conflict(format!(
+                "At most {} upcoming reservations of kind {} are allowed per user",
+                limit, kind.name
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// What a reservation takes of its resource while it lasts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Usage {
+    pub start_time: chrono::NaiveDateTime,
+    pub end_time: chrono::NaiveDateTime,
+    /// Capacity the owner and the approved participants take together.
+    pub amount: i64,
+    /// Whether nothing else may be booked next to it.
+    pub exclusive: bool,
+}
+
+/// The capacity in use during a span of time in which no reservation starts or ends.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Level {
+    pub start_time: chrono::NaiveDateTime,
+    pub end_time: chrono::NaiveDateTime,
+    pub amount: i64,
+    /// Whether an exclusive reservation takes the resource.
+    pub exclusive: bool,
+}
+
+/// Whether a reservation keeps its resource to itself. Only shared reservations of kinds
+/// counted in slots split the capacity with each other, anything else is shared through
+/// its participants alone.
+pub fn is_exclusive(shared: bool, unit: &str) -> bool {
+    !shared || unit != resource_kind::SLOTS
+}
+
+/// The usage of resources by the reservations overlapping `from` to `to`, leaving out
+/// `except`. Resources nobody reserved are missing.
+pub fn usages(
+    resource_ids: &[Uuid],
+    from: chrono::NaiveDateTime,
+    to: chrono::NaiveDateTime,
+    except: Option<Uuid>,
     conn: &mut PgConnection,
-) -> Result<Reservation, DbError> {
+) -> Result<HashMap<Uuid, Vec<Usage>>, ApiError> {
     use crate::schema::reservations::dsl::*;
+    use crate::schema::{resource_kinds, resources};
 
-    let reservation = diesel::update(reservations.find(reservation_id))
-        .set((
-            owner.eq(payload.owner),
-            machine.eq(payload.machine),
-            start_time.eq(payload.start_time),
-            end_time.eq(payload.end_time),
-            shared.eq(payload.shared),
-            updated_at.eq(chrono::Local::now().naive_local()),
+    let mut query = reservations
+        .inner_join(resources::table)
+        .inner_join(resource_kinds::table.on(resource_kinds::name.eq(resources::kind)))
+        .filter(resource.eq_any(resource_ids))
+        .filter(start_time.lt(to))
+        .filter(end_time.gt(from))
+        .into_boxed();
+    if let Some(except) = except {
+        query = query.filter(id.ne(except));
+    }
+    let overlapping = query
+        .select((
+            id,
+            resource,
+            start_time,
+            end_time,
+            shared,
+            amount,
+            resource_kinds::unit,
         ))
-        .get_result::<Reservation>(conn)?;
-    Ok(reservation)
+        .load::<(
+            Uuid,
+            Uuid,
+            chrono::NaiveDateTime,
+            chrono::NaiveDateTime,
+            bool,
+            i32,
+            String,
+        )>(conn)?;
+
+    let ids = overlapping.iter().map(|row| row.0).collect::<Vec<_>>();
+    let joined = participants::taken(&ids, conn)?;
+    let mut by_resource: HashMap<Uuid, Vec<Usage>> = HashMap::new();
+    for (reservation_id, resource_id, from, to, is_shared, owned, unit) in overlapping {
+        by_resource.entry(resource_id).or_default().push(Usage {
+            start_time: from,
+            end_time: to,
+            amount: i64::from(owned) + joined.get(&reservation_id).copied().unwrap_or_default(),
+            exclusive: is_exclusive(is_shared, &unit),
+        });
+    }
+    Ok(by_resource)
+}
+
+/// Splits `from` to `to` where any of `usages` starts or ends, with the capacity in use
+/// in between.
+pub fn levels(
+    usages: &[Usage],
+    from: chrono::NaiveDateTime,
+    to: chrono::NaiveDateTime,
+) -> Vec<Level> {
+    let mut bounds = vec![from, to];
+    for usage in usages {
+        bounds.extend(
+            [usage.start_time, usage.end_time]
+                .into_iter()
+                .filter(|bound| *bound > from && *bound < to),
+        );
+    }
+    bounds.sort_unstable();
+    bounds.dedup();
+
+    bounds
+        .windows(2)
+        .map(|span| {
+            let during = usages
+                .iter()
+                .filter(|usage| usage.start_time < span[1] && usage.end_time > span[0]);
+            Level {
+                start_time: span[0],
+                end_time: span[1],
+                amount: during.clone().map(|usage| usage.amount).sum(),
+                exclusive: during.clone().any(|usage| usage.exclusive),
+            }
+        })
+        .collect()
 }
 
-fn delete(reservation_id: Uuid, conn: &mut PgConnection) -> Result<usize, DbError> {
+/// The most capacity in use at any one time between `from` and `to`.
+pub fn peak(usages: &[Usage], from: chrono::NaiveDateTime, to: chrono::NaiveDateTime) -> i64 {
+    levels(usages, from, to)
+        .iter()
+        .map(|level| level.amount)
+        .max()
+        .unwrap_or_default()
+}
+
+/// Whether a reservation from `from` to `to` may overlap `usages`: only if neither it nor
+/// any of them is exclusive. Capacity is checked separately.
+pub fn shares_with(
+    usages: &[Usage],
+    from: chrono::NaiveDateTime,
+    to: chrono::NaiveDateTime,
+    exclusive: bool,
+) -> bool {
+    levels(usages, from, to)
+        .iter()
+        .all(|level| level.amount == 0 || (!exclusive && !level.exclusive))
+}
+
+/// Whether another reservation fits at all times between `from` and `to`, if only a
+/// shared one: nothing exclusive is reserved and not all of `capacity` is in use.
+pub fn has_room(
+    usages: &[Usage],
+    from: chrono::NaiveDateTime,
+    to: chrono::NaiveDateTime,
+    capacity: i64,
+) -> bool {
+    levels(usages, from, to)
+        .iter()
+        .all(|level| !level.exclusive && level.amount < capacity)
+}
+
+/// The most capacity of a resource the reservations overlapping `from` to `to` take at
+/// any one time, leaving out `except`.
+pub fn in_use(
+    resource_id: Uuid,
+    from: chrono::NaiveDateTime,
+    to: chrono::NaiveDateTime,
+    except: Option<Uuid>,
+    conn: &mut PgConnection,
+) -> Result<i64, ApiError> {
+    let booked = usages(&[resource_id], from, to, except, conn)?
+        .remove(&resource_id)
+        .unwrap_or_default();
+    Ok(peak(&booked, from, to))
+}
+
+/// Loads a reservation and locks it until the end of the transaction.
+fn find_for_update(reservation_id: Uuid, conn: &mut PgConnection) -> Result<Reservation, ApiError> {
+    use crate::schema::reservations::dsl::*;
+
+    reservations
+        .find(reservation_id)
+        .for_update()
+        .first::<Reservation>(conn)
+        .optional()?
+        .ok_or_else(|| ApiError::NotFound("Reservation not found".to_string()))
+}
+
+fn delete(
+    reservation_id: Uuid,
+    if_match: Option<&IfMatch>,
+    identity: &Identity,
+    conn: &mut PgConnection,
+) -> Result<usize, ApiError> {
     use crate::schema::reservations::dsl::*;
 
-    let count = diesel::delete(reservations.find(reservation_id)).execute(conn)?;
-    Ok(count)
+    conn.transaction(|conn| {
+        let current = find_for_update(reservation_id, conn)?;
+        identity.require_owner(&permissions::RESERVATIONS_WRITE, current.owner)?;
+        identity.require_property_of(|| permissions::resource_property(current.resource, conn))?;
+        helpers::check_if_match(if_match, &current.updated_at)?;
+
+        let count = diesel::delete(reservations.find(reservation_id)).execute(conn)?;
+        audit::deleted(
+            &identity.actor(),
+            Resource::Reservation,
+            reservation_id,
+            &current,
+            conn,
+        )?;
+        Ok(count)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(hour: u32) -> chrono::NaiveDateTime {
+        chrono::NaiveDate::from_ymd_opt(2026, 1, 1)
+            .unwrap()
+            .and_hms_opt(hour, 0, 0)
+            .unwrap()
+    }
+
+    fn usage(from: u32, to: u32, amount: i64, exclusive: bool) -> Usage {
+        Usage {
+            start_time: at(from),
+            end_time: at(to),
+            amount,
+            exclusive,
+        }
+    }
+
+    #[test]
+    fn room_is_left_until_capacity_or_an_exclusive_reservation() {
+        let booked = [usage(9, 10, 2, false), usage(10, 11, 4, false)];
+        assert!(has_room(&booked, at(9), at(10), 4));
+        assert!(!has_room(&booked, at(9), at(11), 4));
+        assert!(has_room(&booked, at(9), at(11), 5));
+        assert!(!has_room(&[usage(9, 10, 1, true)], at(8), at(12), 5));
+        assert!(has_room(&[usage(9, 10, 1, true)], at(10), at(12), 5));
+    }
+
+    #[test]
+    fn peak_counts_only_reservations_at_the_same_time() {
+        let booked = [usage(9, 10, 2, false), usage(10, 11, 3, false)];
+        assert_eq!(peak(&booked, at(9), at(11)), 3);
+
+        let booked = [usage(9, 11, 2, false), usage(10, 12, 3, false)];
+        assert_eq!(peak(&booked, at(9), at(12)), 5);
+        assert_eq!(peak(&booked, at(11), at(12)), 3);
+        assert_eq!(peak(&[], at(9), at(12)), 0);
+    }
+
+    #[test]
+    fn levels_split_the_window_where_reservations_start_or_end() {
+        let booked = [usage(8, 10, 1, false), usage(11, 12, 2, true)];
+        let found: Vec<(u32, u32, i64, bool)> = levels(&booked, at(9), at(13))
+            .iter()
+            .map(|level| {
+                (
+                    chrono::Timelike::hour(&level.start_time),
+                    chrono::Timelike::hour(&level.end_time),
+                    level.amount,
+                    level.exclusive,
+                )
+            })
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (9, 10, 1, false),
+                (10, 11, 0, false),
+                (11, 12, 2, true),
+                (12, 13, 0, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn only_shared_reservations_overlap() {
+        let shared = [usage(9, 10, 1, false)];
+        let exclusive = [usage(9, 10, 1, true)];
+
+        assert!(shares_with(&shared, at(9), at(10), false));
+        assert!(!shares_with(&shared, at(9), at(10), true));
+        assert!(!shares_with(&exclusive, at(9), at(10), false));
+        assert!(shares_with(&exclusive, at(10), at(11), true));
+    }
+
+    #[test]
+    fn reservations_of_load_sizes_are_exclusive() {
+        assert!(is_exclusive(false, resource_kind::SLOTS));
+        assert!(is_exclusive(true, resource_kind::KG));
+        assert!(!is_exclusive(true, resource_kind::SLOTS));
+    }
 }
@@ -0,0 +1,411 @@
+use super::DbPool;
+use actix_web::http::header::{ETag, IfMatch, IfNoneMatch};
+use actix_web::{delete, get, patch, post, put, web, HttpResponse};
+use diesel::prelude::*;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::audit;
+use crate::auth::Identity;
+use crate::helpers::{self, ApiError, Json, PaginatedResponse, SuccessResponse};
+use crate::idempotency;
+use crate::includes::Resource;
+use crate::models::machine;
+use crate::models::resource_kind::{
+    NewResourceKind, ResourceKind, ResourceKindPatch, ResourceKindPayload,
+};
+use crate::pagination::{paginate, Page, PageParams, Paginated};
+use crate::permissions;
+use crate::validation::Validate;
+
+#[derive(Debug, Deserialize)]
+struct Filters {
+    name: Option<String>,
+    unit: Option<String>,
+}
+
+const SORTABLE: [&str; 3] = ["created_at", "updated_at", "name"];
+
+#[get("/resource-kinds")]
+async fn index(
+    page: web::Query<PageParams>,
+    filters: web::Query<Filters>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::RESOURCES_READ)?;
+
+    let page = page.resolve(&SORTABLE)?;
+    let kinds = web::block(move || {
+        let mut conn = pool.get()?;
+        find_all(&filters, &page, &mut conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(PaginatedResponse {
+        status: 200,
+        message: "OK".to_string(),
+        data: kinds.data,
+        meta: kinds.meta,
+    }))
+}
+
+#[post("/resource-kinds")]
+async fn create(
+    pool: web::Data<DbPool>,
+    payload: Json<ResourceKindPayload>,
+    key: idempotency::Key,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::RESOURCE_KINDS_WRITE)?;
+
+    payload.validate()?;
+
+    let created = web::block(move || {
+        let mut conn = pool.get()?;
+        idempotency::create_once(&key, "POST /resource-kinds", &*payload, &mut conn, |conn| {
+            add(&payload, &identity, conn)
+        })
+    })
+    .await??;
+
+    Ok(created.respond())
+}
+
+/// Kinds are addressed by id or by name, e.g. `/resource-kinds/drying_room`.
+#[get("/resource-kinds/{kind}")]
+async fn show(
+    kind: web::Path<String>,
+    if_none_match: Option<web::Header<IfNoneMatch>>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::RESOURCES_READ)?;
+
+    let kind = web::block(move || {
+        let mut conn = pool.get()?;
+        let kind_id = find_id(&kind, &mut conn)?;
+        find_by_id(kind_id, &mut conn)
+    })
+    .await??;
+
+    let Some(kind) = kind else {
+        return Err(ApiError::NotFound("Resource kind not found".to_string()));
+    };
+
+    let etag = helpers::etag(&kind.updated_at);
+    if helpers::not_modified(if_none_match.as_deref(), &etag) {
+        return Ok(HttpResponse::NotModified()
+            .insert_header(ETag(etag))
+            .finish());
+    }
+
+    Ok(HttpResponse::Ok()
+        .insert_header(ETag(etag))
+        .json(SuccessResponse {
+            status: 200,
+            message: "OK".to_string(),
+            data: kind,
+        }))
+}
+
+/// Renaming a kind renames it on its resources, changed rules apply to new bookings.
+#[put("/resource-kinds/{kind}")]
+async fn update(
+    kind: web::Path<String>,
+    payload: Json<ResourceKindPayload>,
+    if_match: Option<web::Header<IfMatch>>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::RESOURCE_KINDS_WRITE)?;
+
+    payload.validate()?;
+
+    let kind = web::block(move || {
+        let mut conn = pool.get()?;
+        let kind_id = find_id(&kind, &mut conn)?;
+        update_by_id(kind_id, &payload, if_match.as_deref(), &identity, &mut conn)
+    })
+    .await??;
+
+    let etag = helpers::etag(&kind.updated_at);
+    Ok(HttpResponse::Ok()
+        .insert_header(ETag(etag))
+        .json(SuccessResponse {
+            status: 200,
+            message: "OK".to_string(),
+            data: kind,
+        }))
+}
+
+/// Renaming a kind renames it on its resources, changed rules apply to new bookings.
+#[patch("/resource-kinds/{kind}")]
+async fn partial_update(
+    kind: web::Path<String>,
+    changes: Json<ResourceKindPatch>,
+    if_match: Option<web::Header<IfMatch>>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::RESOURCE_KINDS_WRITE)?;
+
+    let kind = web::block(move || {
+        let mut conn = pool.get()?;
+        let kind_id = find_id(&kind, &mut conn)?;
+        patch_by_id(
+            kind_id,
+            changes.0,
+            if_match.as_deref(),
+            &identity,
+            &mut conn,
+        )
+    })
+    .await??;
+
+    let etag = helpers::etag(&kind.updated_at);
+    Ok(HttpResponse::Ok()
+        .insert_header(ETag(etag))
+        .json(SuccessResponse {
+            status: 200,
+            message: "OK".to_string(),
+            data: kind,
+        }))
+}
+
+#[delete("/resource-kinds/{kind}")]
+async fn destroy(
+    kind: web::Path<String>,
+    if_match: Option<web::Header<IfMatch>>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::RESOURCE_KINDS_WRITE)?;
+
+    let result = web::block(move || {
+        let mut conn = pool.get()?;
+        let kind_id = find_id(&kind, &mut conn)?;
+        delete(kind_id, if_match.as_deref(), &identity, &mut conn)
+    })
+    .await?
+    .map(|kind| {
+        HttpResponse::Ok().json(SuccessResponse {
+            status: 200,
+            message: "Deleted".to_string(),
+            data: kind,
+        })
+    })?;
+
+    Ok(result)
+}
+
+fn add(
+    payload: &ResourceKindPayload,
+    identity: &Identity,
+    conn: &mut PgConnection,
+) -> Result<ResourceKind, ApiError> {
+    use crate::schema::resource_kinds::dsl::*;
+
+    let new_kind = NewResourceKind {
+        name: payload.name.as_str(),
+        unit: payload.unit.as_str(),
+        max_duration_minutes: payload.max_duration_minutes,
+        max_advance_days: payload.max_advance_days,
+        max_upcoming: payload.max_upcoming,
+        created_at: chrono::Local::now().naive_local(),
+        updated_at: chrono::Local::now().naive_local(),
+    };
+
+    conn.transaction(|conn| {
+        let res = diesel::insert_into(resource_kinds)
+            .values(&new_kind)
+            .returning(resource_kinds::all_columns())
+            .get_result::<ResourceKind>(conn)?;
+        audit::created(&identity.actor(), Resource::Kind, res.id, &res, conn)?;
+        Ok(res)
+    })
+}
+
+fn find_all(
+    filters: &Filters,
+    page: &Page,
+    conn: &mut PgConnection,
+) -> Result<Paginated<ResourceKind>, ApiError> {
+    use crate::schema::resource_kinds::dsl::*;
+
+    let filtered = || {
+        let mut query = resource_kinds.into_boxed();
+        if let Some(kind_name) = &filters.name {
+            query = query.filter(name.eq(kind_name));
+        }
+        if let Some(kind_unit) = &filters.unit {
+            query = query.filter(unit.eq(kind_unit));
+        }
+        query
+    };
+
+    let total = filtered().count().get_result(conn)?;
+    let rows = match page.column.as_str() {
+        "updated_at" => {
+            paginate!(filtered(), page, updated_at, id, chrono::NaiveDateTime).load(conn)?
+        }
+        "name" => paginate!(filtered(), page, name, id, String).load(conn)?,
+        _ => paginate!(filtered(), page, created_at, id, chrono::NaiveDateTime).load(conn)?,
+    };
+
+    Ok(page.finish(rows, total))
+}
+
+fn find_by_id(kind_id: Uuid, conn: &mut PgConnection) -> Result<Option<ResourceKind>, ApiError> {
+    use crate::schema::resource_kinds::dsl::*;
+
+    let kind = resource_kinds
+        .filter(id.eq(kind_id))
+        .first::<ResourceKind>(conn)
+        .optional()?;
+
+    Ok(kind)
+}
+
+/// The id of a kind given by id or by name.
+fn find_id(kind: &str, conn: &mut PgConnection) -> Result<Uuid, ApiError> {
+    use crate::schema::resource_kinds::dsl::*;
+
+    if let Ok(kind_id) = kind.parse::<Uuid>() {
+        return Ok(kind_id);
+    }
+    resource_kinds
+        .filter(name.eq(kind))
+        .select(id)
+        .first::<Uuid>(conn)
+        .optional()?
+        .ok_or_else(|| ApiError::NotFound("Resource kind not found".to_string()))
+}
+
+fn update_by_id(
+    kind_id: Uuid,
+    payload: &ResourceKindPayload,
+    if_match: Option<&IfMatch>,
+    identity: &Identity,
+    conn: &mut PgConnection,
+) -> Result<ResourceKind, ApiError> {
+    use crate::schema::resource_kinds::dsl::*;
+
+    conn.transaction(|conn| {
+        let current = find_for_update(kind_id, conn)?;
+        helpers::check_if_match(if_match, &current.updated_at)?;
+        check_rename(&current, &payload.name)?;
+
+        let kind = diesel::update(resource_kinds.find(kind_id))
+            .set((
+                name.eq(&payload.name),
+                unit.eq(&payload.unit),
+                max_duration_minutes.eq(payload.max_duration_minutes),
+                max_advance_days.eq(payload.max_advance_days),
+                max_upcoming.eq(payload.max_upcoming),
+                updated_at.eq(chrono::Local::now().naive_local()),
+            ))
+            .get_result::<ResourceKind>(conn)?;
+        audit::updated(
+            &identity.actor(),
+            Resource::Kind,
+            kind_id,
+            &current,
+            &kind,
+            conn,
+        )?;
+        Ok(kind)
+    })
+}
+
+fn patch_by_id(
+    kind_id: Uuid,
+    mut changes: ResourceKindPatch,
+    if_match: Option<&IfMatch>,
+    identity: &Identity,
+    conn: &mut PgConnection,
+) -> Result<ResourceKind, ApiError> {
+    use crate::schema::resource_kinds::dsl::*;
+
+    conn.transaction(|conn| {
+        let current = find_for_update(kind_id, conn)?;
+        helpers::check_if_match(if_match, &current.updated_at)?;
+        if let Some(new_name) = &changes.name {
+            check_rename(&current, new_name)?;
+        }
+        changes.merge(current.clone()).validate()?;
+
+        changes.updated_at = Some(chrono::Local::now().naive_local());
+        let kind = diesel::update(resource_kinds.find(kind_id))
+            .set(&changes)
+            .get_result::<ResourceKind>(conn)?;
+        audit::updated(
+            &identity.actor(),
+            Resource::Kind,
+            kind_id,
+            &current,
+            &kind,
+            conn,
+        )?;
+        Ok(kind)
+    })
+}
+
+/// Fails with `409` when renaming the built-in machine kind.
+fn check_rename(current: &ResourceKind, new_name: &str) -> Result<(), ApiError> {
+    if current.name == machine::KIND && new_name != current.name {
+        return Err(ApiError::Conflict(format!(
+            "Kind {} is built in and cannot be renamed",
+            current.name
+        )));
+    }
+    Ok(())
+}
+
+/// Loads a resource kind and locks it until the end of the transaction.
+fn find_for_update(kind_id: Uuid, conn: &mut PgConnection) -> Result<ResourceKind, ApiError> {
+    use crate::schema::resource_kinds::dsl::*;
+
+    resource_kinds
+        .find(kind_id)
+        .for_update()
+        .first::<ResourceKind>(conn)
+        .optional()?
+        .ok_or_else(|| ApiError::NotFound("Resource kind not found".to_string()))
+}
+
+fn delete(
+    kind_id: Uuid,
+    if_match: Option<&IfMatch>,
+    identity: &Identity,
+    conn: &mut PgConnection,
+) -> Result<usize, ApiError> {
+    use crate::schema::resource_kinds::dsl::*;
+    use crate::schema::resources;
+
+    conn.transaction(|conn| {
+        let current = find_for_update(kind_id, conn)?;
+        helpers::check_if_match(if_match, &current.updated_at)?;
+        if current.name == machine::KIND {
+            return Err(ApiError::Conflict(format!(
+                "Kind {} is built in and cannot be deleted",
+                current.name
+            )));
+        }
+
+        let used = resources::table
+            .filter(resources::kind.eq(&current.name))
+            .count()
+            .get_result::<i64>(conn)?;
+        if used > 0 {
+            return Err(ApiError::Conflict(format!(
+                "Kind {} is still used by {} resource(s), change their kind first",
+                current.name, used
+            )));
+        }
+
+        let count = diesel::delete(resource_kinds.find(kind_id)).execute(conn)?;
+        audit::deleted(&identity.actor(), Resource::Kind, kind_id, &current, conn)?;
+        Ok(count)
+    })
+}
@@ -0,0 +1,774 @@
+use super::DbPool;
+use actix_web::http::header::{ETag, IfMatch, IfNoneMatch};
+use actix_web::{delete, get, patch, post, put, web, HttpResponse};
+use diesel::dsl::exists;
+use diesel::prelude::*;
+use serde::Deserialize;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use crate::audit;
+use crate::auth::Identity;
+use crate::helpers::{self, ApiError, FieldError, Json, PaginatedResponse, SuccessResponse};
+use crate::idempotency;
+use crate::images::{self, Subject};
+use crate::includes::{IncludeParams, Includes, Resource};
+use crate::models::bookable::{
+    Availability, Bookable, BookablePatch, BookablePayload, FreeSlot, NewBookable, OpenSlot, Slot,
+};
+use crate::models::machine;
+use crate::nested::{self, Parent};
+use crate::pagination::{paginate, Page, PageParams, Paginated};
+use crate::permissions;
+use crate::storage::Storage;
+use crate::validation::Validate;
+
+/// Longest window availability is reported or filtered for.
+const MAX_WINDOW_DAYS: i64 = 31;
+
+#[derive(Debug, Deserialize)]
+struct Filters {
+    property: Option<Uuid>,
+    kind: Option<String>,
+    status: Option<String>,
+    /// Resources with room for another reservation throughout `available_from` to
+    /// `available_to`.
+    available_from: Option<chrono::NaiveDateTime>,
+    available_to: Option<chrono::NaiveDateTime>,
+}
+
+/// Time range of an availability query, the next day from now by default.
+#[derive(Debug, Deserialize)]
+struct Window {
+    from: Option<chrono::NaiveDateTime>,
+    to: Option<chrono::NaiveDateTime>,
+}
+
+const SORTABLE: [&str; 5] = ["created_at", "updated_at", "name", "kind", "status"];
+
+#[get("/resources")]
+async fn index(
+    page: web::Query<PageParams>,
+    filters: web::Query<Filters>,
+    include: web::Query<IncludeParams>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::RESOURCES_READ)?;
+
+    let mut filters = filters.into_inner();
+    filters.property = identity.scope_filter(filters.property)?;
+    list(None, &page, filters, &include, &identity, pool).await
+}
+
+#[get("/properties/{id}/resources")]
+async fn property_index(
+    id: web::Path<Uuid>,
+    page: web::Query<PageParams>,
+    filters: web::Query<Filters>,
+    include: web::Query<IncludeParams>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::RESOURCES_READ)?;
+
+    let parent = Parent::Property(id.into_inner());
+    identity.require_property(Some(parent.id()))?;
+    let mut filters = filters.into_inner();
+    filters.property = Some(parent.id());
+    list(Some(parent), &page, filters, &include, &identity, pool).await
+}
+
+async fn list(
+    parent: Option<Parent>,
+    page: &PageParams,
+    filters: Filters,
+    include: &IncludeParams,
+    identity: &Identity,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, ApiError> {
+    let page = page.resolve(&SORTABLE)?;
+    let window = match (filters.available_from, filters.available_to) {
+        (None, None) => None,
+        (Some(from), Some(to)) => Some(check_window(from, to, "available_to")?),
+        _ => {
+            return Err(ApiError::InvalidParameters(vec![FieldError {
+                field: "available_to".to_string(),
+                code: "required".to_string(),
+                message: "available_from and available_to must be given together".to_string(),
+            }]))
+        }
+    };
+    let includes = Includes::parse(include.include.as_deref(), Resource::Bookable, identity)?;
+    let resources = web::block(move || {
+        let mut conn = pool.get()?;
+        if let Some(parent) = parent {
+            parent.ensure_exists(&mut conn)?;
+        }
+        let resources = find_all(&filters, window, &page, &mut conn)?;
+        includes.embed_page(Resource::Bookable, resources, &mut conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(PaginatedResponse {
+        status: 200,
+        message: "OK".to_string(),
+        data: resources.data,
+        meta: resources.meta,
+    }))
+}
+
+#[post("/resources")]
+async fn create(
+    pool: web::Data<DbPool>,
+    payload: Json<BookablePayload>,
+    key: idempotency::Key,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::RESOURCES_WRITE)?;
+    identity.require_property(Some(payload.property))?;
+
+    insert(
+        None,
+        identity,
+        "POST /resources".to_string(),
+        payload.0,
+        key,
+        pool,
+    )
+    .await
+}
+
+#[post("/properties/{id}/resources")]
+async fn property_create(
+    id: web::Path<Uuid>,
+    pool: web::Data<DbPool>,
+    body: Json<serde_json::Value>,
+    key: idempotency::Key,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::RESOURCES_WRITE)?;
+
+    let parent = Parent::Property(id.into_inner());
+    identity.require_property(Some(parent.id()))?;
+    let payload = nested::payload(body.0, "property", &parent)?;
+    let scope = format!("POST /properties/{}/resources", parent.id());
+    insert(Some(parent), identity, scope, payload, key, pool).await
+}
+
+async fn insert(
+    parent: Option<Parent>,
+    identity: Identity,
+    scope: String,
+    payload: BookablePayload,
+    key: idempotency::Key,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, ApiError> {
+    payload.validate()?;
+
+    let created = web::block(move || {
+        let mut conn = pool.get()?;
+        if let Some(parent) = parent {
+            parent.ensure_exists(&mut conn)?;
+        }
+        check_kind(&payload, &mut conn)?;
+        idempotency::create_once(&key, &scope, &payload, &mut conn, |conn| {
+            add(&payload, &identity, conn)
+        })
+    })
+    .await??;
+
+    Ok(created.respond())
+}
+
+#[get("/resources/{id}")]
+async fn show(
+    id: web::Path<Uuid>,
+    include: web::Query<IncludeParams>,
+    if_none_match: Option<web::Header<IfNoneMatch>>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::RESOURCES_READ)?;
+
+    let includes = Includes::parse(include.include.as_deref(), Resource::Bookable, &identity)?;
+    let embedding = !includes.is_empty();
+    let resource = web::block(move || {
+        let mut conn = pool.get()?;
+        let resource = find_by_id(id.into_inner(), &mut conn)?;
+        if let Some(resource) = &resource {
+            identity.require_property(Some(resource.property))?;
+        }
+        resource
+            .map(|resource| {
+                let data = includes.embed_one(Resource::Bookable, &resource, &mut conn)?;
+                Ok::<_, ApiError>((resource.updated_at, data))
+            })
+            .transpose()
+    })
+    .await??;
+
+    let Some((updated_at, resource)) = resource else {
+        return Err(ApiError::NotFound("Resource not found".to_string()));
+    };
+
+    // Embedded resources change independently of this one, so only the plain
+    // representation is cacheable by its version.
+    if embedding {
+        return Ok(HttpResponse::Ok().json(SuccessResponse {
+            status: 200,
+            message: "OK".to_string(),
+            data: resource,
+        }));
+    }
+
+    let etag = helpers::etag(&updated_at);
+    if helpers::not_modified(if_none_match.as_deref(), &etag) {
+        return Ok(HttpResponse::NotModified()
+            .insert_header(ETag(etag))
+            .finish());
+    }
+
+    Ok(HttpResponse::Ok()
+        .insert_header(ETag(etag))
+        .json(SuccessResponse {
+            status: 200,
+            message: "OK".to_string(),
+            data: resource,
+        }))
+}
+
+/// The reserved and free time of a resource, e.g. to pick a slot to book, and the
+/// shared reservations which can still be joined.
+#[get("/resources/{id}/availability")]
+async fn availability(
+    id: web::Path<Uuid>,
+    window: web::Query<Window>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    schedule(id.into_inner(), None, &window, pool, identity).await
+}
+
+/// The schedule of a machine, as for any other resource.
+#[get("/machines/{id}/availability")]
+async fn machine_availability(
+    id: web::Path<Uuid>,
+    window: web::Query<Window>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    schedule(
+        id.into_inner(),
+        Some(machine::KIND),
+        &window,
+        pool,
+        identity,
+    )
+    .await
+}
+
+/// Reports the availability of a resource within `window`, which has to be of
+/// `only_kind` when given.
+async fn schedule(
+    resource_id: Uuid,
+    only_kind: Option<&'static str>,
+    window: &Window,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::RESOURCES_READ)?;
+    identity.require(permissions::RESERVATIONS_READ)?;
+
+    let from = window
+        .from
+        .unwrap_or_else(|| chrono::Local::now().naive_local());
+    let to = window.to.unwrap_or(from + chrono::Duration::days(1));
+    let (from, to) = check_window(from, to, "to")?;
+
+    let report = web::block(move || {
+        let mut conn = pool.get()?;
+        let not_found = || match only_kind {
+            Some(_) => ApiError::NotFound("Machine not found".to_string()),
+            None => ApiError::NotFound("Resource not found".to_string()),
+        };
+        let resource = find_by_id(resource_id, &mut conn)?.ok_or_else(not_found)?;
+        if only_kind.is_some_and(|k| k != resource.kind) {
+            return Err(not_found());
+        }
+        identity.require_property(Some(resource.property))?;
+        find_availability(&resource, from, to, &mut conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(SuccessResponse {
+        status: 200,
+        message: "OK".to_string(),
+        data: report,
+    }))
+}
+
+#[put("/resources/{id}")]
+async fn update(
+    id: web::Path<Uuid>,
+    payload: Json<BookablePayload>,
+    if_match: Option<web::Header<IfMatch>>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::RESOURCES_WRITE)?;
+
+    payload.validate()?;
+
+    let resource = web::block(move || {
+        let mut conn = pool.get()?;
+        update_by_id(
+            id.into_inner(),
+            &payload,
+            if_match.as_deref(),
+            &identity,
+            &mut conn,
+        )
+    })
+    .await??;
+
+    let etag = helpers::etag(&resource.updated_at);
+    Ok(HttpResponse::Ok()
+        .insert_header(ETag(etag))
+        .json(SuccessResponse {
+            status: 200,
+            message: "OK".to_string(),
+            data: resource,
+        }))
+}
+
+#[patch("/resources/{id}")]
+async fn partial_update(
+    id: web::Path<Uuid>,
+    changes: Json<BookablePatch>,
+    if_match: Option<web::Header<IfMatch>>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::RESOURCES_WRITE)?;
+
+    let resource = web::block(move || {
+        let mut conn = pool.get()?;
+        patch_by_id(
+            id.into_inner(),
+            changes.0,
+            if_match.as_deref(),
+            &identity,
+            &mut conn,
+        )
+    })
+    .await??;
+
+    let etag = helpers::etag(&resource.updated_at);
+    Ok(HttpResponse::Ok()
+        .insert_header(ETag(etag))
+        .json(SuccessResponse {
+            status: 200,
+            message: "OK".to_string(),
+            data: resource,
+        }))
+}
+
+#[delete("/resources/{id}")]
+async fn destroy(
+    id: web::Path<Uuid>,
+    if_match: Option<web::Header<IfMatch>>,
+    pool: web::Data<DbPool>,
+    storage: web::Data<dyn Storage>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::RESOURCES_WRITE)?;
+
+    let result = web::block(move || {
+        let mut conn = pool.get()?;
+        delete(
+            id.into_inner(),
+            if_match.as_deref(),
+            &identity,
+            storage.as_ref(),
+            &mut conn,
+        )
+    })
+    .await?
+    .map(|resource| {
+        HttpResponse::Ok().json(SuccessResponse {
+            status: 200,
+            message: "Deleted".to_string(),
+            data: resource,
+        })
+    })?;
+
+    Ok(result)
+}
+
+/// Fails with `400` unless `to` is after `from` and the window is not too long.
+fn check_window(
+    from: chrono::NaiveDateTime,
+    to: chrono::NaiveDateTime,
+    field: &str,
+) -> Result<(chrono::NaiveDateTime, chrono::NaiveDateTime), ApiError> {
+    let error = if to <= from {
+        Some(("out_of_order", format!("{} must be after from", field)))
+    } else if to - from > chrono::Duration::days(MAX_WINDOW_DAYS) {
+        Some((
+            "out_of_range",
+            format!(
+                "{} must be at most {} days after from",
+                field, MAX_WINDOW_DAYS
+            ),
+        ))
+    } else {
+        None
+    };
+
+    match error {
+        Some((code, message)) => Err(ApiError::InvalidParameters(vec![FieldError {
+            field: field.to_string(),
+            code: code.to_string(),
+            message,
+        }])),
+        None => Ok((from, to)),
+    }
+}
+
+/// Fails with `422` unless the kind of the resource is known.
+fn check_kind(payload: &BookablePayload, conn: &mut PgConnection) -> Result<(), ApiError> {
+    use crate::schema::resource_kinds;
+
+    let known = diesel::select(exists(
+        resource_kinds::table.filter(resource_kinds::name.eq(&payload.kind)),
+    ))
+    .get_result::<bool>(conn)?;
+
+    if known {
+        Ok(())
+    } else {
+        Err(ApiError::InvalidPayload(vec![FieldError {
+            field: "kind".to_string(),
+            code: "not_found".to_string(),
+            message: format!("{} is not a known kind of resource", payload.kind),
+        }]))
+    }
+}
+
+fn add(
+    payload: &BookablePayload,
+    identity: &Identity,
+    conn: &mut PgConnection,
+) -> Result<Bookable, ApiError> {
+    use crate::schema::resources::dsl::*;
+
+    let new_resource = NewBookable {
+        name: payload.name.as_str(),
+        property: payload.property,
+        status: payload.status.as_str(),
+        eta: payload.eta,
+        kind: payload.kind.as_str(),
+        capacity: payload.capacity,
+        created_at: chrono::Local::now().naive_local(),
+        updated_at: chrono::Local::now().naive_local(),
+    };
+
+    conn.transaction(|conn| {
+        let res = diesel::insert_into(resources)
+            .values(&new_resource)
+            .returning(resources::all_columns())
+            .get_result::<Bookable>(conn)?;
+        audit::created(&identity.actor(), Resource::Bookable, res.id, &res, conn)?;
+        Ok(res)
+    })
+}
+
+fn find_all(
+    filters: &Filters,
+    window: Option<(chrono::NaiveDateTime, chrono::NaiveDateTime)>,
+    page: &Page,
+    conn: &mut PgConnection,
+) -> Result<Paginated<Bookable>, ApiError> {
+    use crate::schema::reservations;
+    use crate::schema::resources::dsl::*;
+
+    // Resources without room for another reservation at some point of the window
+    let full = window
+        .map(|(from, to)| {
+            let reserved = reservations::table
+                .filter(reservations::start_time.lt(to))
+                .filter(reservations::end_time.gt(from))
+                .select(reservations::resource)
+                .distinct()
+                .load::<Uuid>(conn)?;
+            let capacities: HashMap<Uuid, i32> = resources
+                .filter(id.eq_any(&reserved))
+                .select((id, capacity))
+                .load::<(Uuid, i32)>(conn)?
+                .into_iter()
+                .collect();
+            let booked = crate::reservations::usages(&reserved, from, to, None, conn)?;
+            Ok::<_, ApiError>(
+                booked
+                    .into_iter()
+                    .filter(|(resource_id, usages)| {
+                        let left = capacities.get(resource_id).copied().unwrap_or_default();
+                        !crate::reservations::has_room(usages, from, to, left.into())
+                    })
+                    .map(|(resource_id, _)| resource_id)
+                    .collect::<Vec<_>>(),
+            )
+        })
+        .transpose()?;
+
+    let filtered = || {
+        let mut query = resources.into_boxed();
+        if let Some(property_id) = filters.property {
+            query = query.filter(property.eq(property_id));
+        }
+        if let Some(resource_kind) = &filters.kind {
+            query = query.filter(kind.eq(resource_kind));
+        }
+        if let Some(resource_status) = &filters.status {
+            query = query.filter(status.eq(resource_status));
+        }
+        if let Some(full) = &full {
+            query = query.filter(id.ne_all(full));
+        }
+        query
+    };
+
+    let total = filtered().count().get_result(conn)?;
+    let rows = match page.column.as_str() {
+        "updated_at" => {
+            paginate!(filtered(), page, updated_at, id, chrono::NaiveDateTime).load(conn)?
+        }
+        "name" => paginate!(filtered(), page, name, id, String).load(conn)?,
+        "kind" => paginate!(filtered(), page, kind, id, String).load(conn)?,
+        "status" => paginate!(filtered(), page, status, id, String).load(conn)?,
+        _ => paginate!(filtered(), page, created_at, id, chrono::NaiveDateTime).load(conn)?,
+    };
+
+    Ok(page.finish(rows, total))
+}
+
+fn find_by_id(resource_id: Uuid, conn: &mut PgConnection) -> Result<Option<Bookable>, ApiError> {
+    use crate::schema::resources::dsl::*;
+
+    let resource = resources
+        .filter(id.eq(resource_id))
+        .first::<Bookable>(conn)
+        .optional()?;
+
+    Ok(resource)
+}
+
+fn find_availability(
+    bookable: &Bookable,
+    from: chrono::NaiveDateTime,
+    to: chrono::NaiveDateTime,
+    conn: &mut PgConnection,
+) -> Result<Availability, ApiError> {
+    use crate::schema::reservations::dsl::*;
+    use crate::schema::resource_kinds;
+
+    let reserved = reservations
+        .filter(resource.eq(bookable.id))
+        .filter(start_time.lt(to))
+        .filter(end_time.gt(from))
+        .order(start_time)
+        .select((id, start_time, end_time, shared))
+        .load::<(Uuid, chrono::NaiveDateTime, chrono::NaiveDateTime, bool)>(conn)?;
+
+    let booked = crate::reservations::usages(&[bookable.id], from, to, None, conn)?
+        .remove(&bookable.id)
+        .unwrap_or_default();
+    let free = free_slots(
+        &crate::reservations::levels(&booked, from, to),
+        bookable.capacity.into(),
+    );
+
+    let capacity_unit = resource_kinds::table
+        .filter(resource_kinds::name.eq(&bookable.kind))
+        .select(resource_kinds::unit)
+        .first::<String>(conn)?;
+    let now = chrono::Local::now().naive_local();
+    let joinable = reserved
+        .iter()
+        .filter(|(_, _, reserved_to, is_shared)| *is_shared && *reserved_to > now)
+        .map(|(reservation_id, ..)| *reservation_id)
+        .collect::<Vec<_>>();
+    let open = reserved
+        .iter()
+        .filter(|(reservation_id, ..)| joinable.contains(reservation_id))
+        .map(|(reservation_id, reserved_from, reserved_to, _)| {
+            let remaining = i64::from(bookable.capacity)
+                - crate::reservations::in_use(
+                    bookable.id,
+                    *reserved_from,
+                    *reserved_to,
+                    None,
+                    conn,
+                )?;
+            Ok::<_, ApiError>((remaining > 0).then_some(OpenSlot {
+                reservation: *reservation_id,
+                start_time: *reserved_from,
+                end_time: *reserved_to,
+                remaining,
+            }))
+        })
+        .filter_map(Result::transpose)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Availability {
+        resource: bookable.id,
+        from,
+        to,
+        unit: capacity_unit,
+        reserved: reserved
+            .into_iter()
+            .map(|(_, reserved_from, reserved_to, _)| Slot {
+                start_time: reserved_from,
+                end_time: reserved_to,
+            })
+            .collect(),
+        free,
+        open,
+    })
+}
+
+/// The levels with capacity left, joining neighbours with as much left.
+fn free_slots(levels: &[crate::reservations::Level], capacity: i64) -> Vec<FreeSlot> {
+    let mut free: Vec<FreeSlot> = Vec::new();
+    for level in levels {
+        let remaining = capacity - level.amount;
+        if level.exclusive || remaining <= 0 {
+            continue;
+        }
+        match free.last_mut() {
+            Some(last) if last.end_time == level.start_time && last.remaining == remaining => {
+                last.end_time = level.end_time;
+            }
+            _ => free.push(FreeSlot {
+                start_time: level.start_time,
+                end_time: level.end_time,
+                remaining,
+            }),
+        }
+    }
+    free
+}
+
+fn update_by_id(
+    resource_id: Uuid,
+    payload: &BookablePayload,
+    if_match: Option<&IfMatch>,
+    identity: &Identity,
+    conn: &mut PgConnection,
+) -> Result<Bookable, ApiError> {
+    use crate::schema::resources::dsl::*;
+
+    conn.transaction(|conn| {
+        let current = find_for_update(resource_id, conn)?;
+        identity.require_property(Some(current.property))?;
+        identity.require_property(Some(payload.property))?;
+        helpers::check_if_match(if_match, &current.updated_at)?;
+        check_kind(payload, conn)?;
+
+        let resource = diesel::update(resources.find(resource_id))
+            .set((
+                name.eq(payload.name.to_string()),
+                property.eq(payload.property),
+                kind.eq(payload.kind.to_string()),
+                capacity.eq(payload.capacity),
+                status.eq(payload.status.to_string()),
+                eta.eq(payload.eta),
+                updated_at.eq(chrono::Local::now().naive_local()),
+            ))
+            .get_result::<Bookable>(conn)?;
+        audit::updated(
+            &identity.actor(),
+            Resource::Bookable,
+            resource_id,
+            &current,
+            &resource,
+            conn,
+        )?;
+        Ok(resource)
+    })
+}
+
+fn patch_by_id(
+    resource_id: Uuid,
+    mut changes: BookablePatch,
+    if_match: Option<&IfMatch>,
+    identity: &Identity,
+    conn: &mut PgConnection,
+) -> Result<Bookable, ApiError> {
+    use crate::schema::resources::dsl::*;
+
+    conn.transaction(|conn| {
+        let current = find_for_update(resource_id, conn)?;
+        identity.require_property(Some(current.property))?;
+        helpers::check_if_match(if_match, &current.updated_at)?;
+        let merged = changes.merge(current.clone());
+        identity.require_property(Some(merged.property))?;
+        merged.validate()?;
+        check_kind(&merged, conn)?;
+
+        changes.updated_at = Some(chrono::Local::now().naive_local());
+        let resource = diesel::update(resources.find(resource_id))
+            .set(&changes)
+            .get_result::<Bookable>(conn)?;
+        audit::updated(
+            &identity.actor(),
+            Resource::Bookable,
+            resource_id,
+            &current,
+            &resource,
+            conn,
+        )?;
+        Ok(resource)
+    })
+}
+
+/// Loads a resource and locks it until the end of the transaction.
+fn find_for_update(resource_id: Uuid, conn: &mut PgConnection) -> Result<Bookable, ApiError> {
+    use crate::schema::resources::dsl::*;
+
+    resources
+        .find(resource_id)
+        .for_update()
+        .first::<Bookable>(conn)
+        .optional()?
+        .ok_or_else(|| ApiError::NotFound("Resource not found".to_string()))
+}
+
+fn delete(
+    resource_id: Uuid,
+    if_match: Option<&IfMatch>,
+    identity: &Identity,
+    storage: &dyn Storage,
+    conn: &mut PgConnection,
+) -> Result<usize, ApiError> {
+    use crate::schema::resources::dsl::*;
+
+    conn.transaction(|conn| {
+        let current = find_for_update(resource_id, conn)?;
+        identity.require_property(Some(current.property))?;
+        helpers::check_if_match(if_match, &current.updated_at)?;
+
+        let image_ids = images::ids_of(Subject::Machine(resource_id), conn)?;
+        let count = diesel::delete(resources.find(resource_id)).execute(conn)?;
+        audit::deleted(
+            &identity.actor(),
+            Resource::Bookable,
+            resource_id,
+            &current,
+            conn,
+        )?;
+        images::remove_files(&image_ids, storage)?;
+        Ok(count)
+    })
+}
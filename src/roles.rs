@@ -1,96 +1,187 @@
 use super::DbPool;
-use actix_web::{delete, get, post, put, web, Error, HttpResponse};
+use actix_web::http::header::{ETag, IfMatch, IfNoneMatch};
+use actix_web::{delete, get, patch, post, put, web, HttpResponse};
 use diesel::prelude::*;
+use serde::Deserialize;
 use uuid::Uuid;
 
-use crate::helpers::{ErrorResponse, SuccessResponse};
-use crate::models::role::{NewRole, Role, RolePayload};
+use crate::audit;
+use crate::auth::Identity;
+use crate::helpers::{self, ApiError, FieldError, Json, PaginatedResponse, SuccessResponse};
+use crate::idempotency;
+use crate::includes::Resource;
+use crate::models::permission::RolePermissionsPayload;
+use crate::models::role::{NewRole, Role, RolePatch, RolePayload};
+use crate::pagination::{paginate, Page, PageParams, Paginated};
+use crate::permissions;
+use crate::validation::Validate;
 
-type DbError = Box<dyn std::error::Error + Send + Sync>;
+#[derive(Debug, Deserialize)]
+struct Filters {
+    name: Option<String>,
+}
+
+const SORTABLE: [&str; 3] = ["created_at", "updated_at", "name"];
 
 #[get("/roles")]
-async fn index(pool: web::Data<DbPool>) -> Result<HttpResponse, Error> {
+async fn index(
+    page: web::Query<PageParams>,
+    filters: web::Query<Filters>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::ROLES_READ)?;
+
+    let page = page.resolve(&SORTABLE)?;
     let roles = web::block(move || {
         let mut conn = pool.get()?;
-        find_all(&mut conn)
+        find_all(&filters, &page, &mut conn)
     })
-    .await?
-    .map_err(actix_web::error::ErrorInternalServerError)?;
+    .await??;
 
-    Ok(HttpResponse::Ok().json(SuccessResponse {
+    Ok(HttpResponse::Ok().json(PaginatedResponse {
         status: 200,
         message: "OK".to_string(),
-        data: roles,
+        data: roles.data,
+        meta: roles.meta,
     }))
 }
 
 #[post("/roles")]
 async fn create(
     pool: web::Data<DbPool>,
-    payload: web::Json<RolePayload>,
-) -> Result<HttpResponse, Error> {
-    let role = web::block(move || {
+    payload: Json<RolePayload>,
+    key: idempotency::Key,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::ROLES_WRITE)?;
+
+    payload.validate()?;
+
+    let created = web::block(move || {
         let mut conn = pool.get()?;
-        add(&payload, &mut conn)
+        idempotency::create_once(&key, "POST /roles", &*payload, &mut conn, |conn| {
+            add(&payload, &identity, conn)
+        })
     })
-    .await?
-    .map_err(actix_web::error::ErrorInternalServerError)?;
+    .await??;
 
-    Ok(HttpResponse::Created().json(SuccessResponse {
-        status: 201,
-        message: "Created".to_string(),
-        data: role,
-    }))
+    Ok(created.respond())
 }
 
 #[get("/roles/{id}")]
-async fn show(id: web::Path<Uuid>, pool: web::Data<DbPool>) -> Result<HttpResponse, Error> {
+async fn show(
+    id: web::Path<Uuid>,
+    if_none_match: Option<web::Header<IfNoneMatch>>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::ROLES_READ)?;
+
     let role = web::block(move || {
         let mut conn = pool.get()?;
         find_by_id(id.into_inner(), &mut conn)
     })
-    .await?
-    .map_err(actix_web::error::ErrorInternalServerError)?;
+    .await??;
+
+    let Some(role) = role else {
+        return Err(ApiError::NotFound("Role not found".to_string()));
+    };
 
-    if role.is_none() {
-        return Ok(HttpResponse::NotFound().json(ErrorResponse {
-            status: 404,
-            message: "Role not found".to_string(),
-        }));
+    let etag = helpers::etag(&role.updated_at);
+    if helpers::not_modified(if_none_match.as_deref(), &etag) {
+        return Ok(HttpResponse::NotModified()
+            .insert_header(ETag(etag))
+            .finish());
     }
 
-    Ok(HttpResponse::Ok().json(SuccessResponse {
-        status: 200,
-        message: "OK".to_string(),
-        data: role,
-    }))
+    Ok(HttpResponse::Ok()
+        .insert_header(ETag(etag))
+        .json(SuccessResponse {
+            status: 200,
+            message: "OK".to_string(),
+            data: role,
+        }))
 }
 
 #[put("/roles/{id}")]
 async fn update(
     id: web::Path<Uuid>,
-    payload: web::Json<RolePayload>,
+    payload: Json<RolePayload>,
+    if_match: Option<web::Header<IfMatch>>,
     pool: web::Data<DbPool>,
-) -> Result<HttpResponse, Error> {
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::ROLES_WRITE)?;
+
+    payload.validate()?;
+
     let role = web::block(move || {
         let mut conn = pool.get()?;
-        update_by_id(id.into_inner(), &payload, &mut conn)
+        update_by_id(
+            id.into_inner(),
+            &payload,
+            if_match.as_deref(),
+            &identity,
+            &mut conn,
+        )
     })
-    .await?
-    .map_err(actix_web::error::ErrorInternalServerError)?;
+    .await??;
 
-    Ok(HttpResponse::Ok().json(SuccessResponse {
-        status: 200,
-        message: "OK".to_string(),
-        data: role,
-    }))
+    let etag = helpers::etag(&role.updated_at);
+    Ok(HttpResponse::Ok()
+        .insert_header(ETag(etag))
+        .json(SuccessResponse {
+            status: 200,
+            message: "OK".to_string(),
+            data: role,
+        }))
+}
+
+#[patch("/roles/{id}")]
+async fn partial_update(
+    id: web::Path<Uuid>,
+    changes: Json<RolePatch>,
+    if_match: Option<web::Header<IfMatch>>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::ROLES_WRITE)?;
+
+    let role = web::block(move || {
+        let mut conn = pool.get()?;
+        patch_by_id(
+            id.into_inner(),
+            changes.0,
+            if_match.as_deref(),
+            &identity,
+            &mut conn,
+        )
+    })
+    .await??;
+
+    let etag = helpers::etag(&role.updated_at);
+    Ok(HttpResponse::Ok()
+        .insert_header(ETag(etag))
+        .json(SuccessResponse {
+            status: 200,
+            message: "OK".to_string(),
+            data: role,
+        }))
 }
 
 #[delete("/roles/{id}")]
-async fn destroy(id: web::Path<Uuid>, pool: web::Data<DbPool>) -> Result<HttpResponse, Error> {
+async fn destroy(
+    id: web::Path<Uuid>,
+    if_match: Option<web::Header<IfMatch>>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::ROLES_WRITE)?;
+
     let result = web::block(move || {
         let mut conn = pool.get()?;
-        delete(id.into_inner(), &mut conn)
+        delete(id.into_inner(), if_match.as_deref(), &identity, &mut conn)
     })
     .await?
     .map(|role| {
@@ -99,13 +190,64 @@ async fn destroy(id: web::Path<Uuid>, pool: web::Data<DbPool>) -> Result<HttpRes
             message: "Deleted".to_string(),
             data: role,
         })
-    })
-    .map_err(actix_web::error::ErrorInternalServerError)?;
+    })?;
 
     Ok(result)
 }
 
-fn add(payload: &RolePayload, conn: &mut PgConnection) -> Result<Role, DbError> {
+#[get("/roles/{id}/permissions")]
+async fn show_permissions(
+    id: web::Path<Uuid>,
+    identity: Identity,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::ROLES_READ)?;
+
+    let role_id = id.into_inner();
+    let granted = web::block(move || {
+        let mut conn = pool.get()?;
+        if find_by_id(role_id, &mut conn)?.is_none() {
+            return Err(ApiError::NotFound("Role not found".to_string()));
+        }
+        permissions::granted(role_id, &mut conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(SuccessResponse {
+        status: 200,
+        message: "OK".to_string(),
+        data: granted,
+    }))
+}
+
+/// Replaces the permissions of a role with the given set.
+#[put("/roles/{id}/permissions")]
+async fn update_permissions(
+    id: web::Path<Uuid>,
+    payload: Json<RolePermissionsPayload>,
+    identity: Identity,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::ROLES_WRITE)?;
+
+    let granted = web::block(move || {
+        let mut conn = pool.get()?;
+        grant(id.into_inner(), &payload.permissions, &identity, &mut conn)
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok().json(SuccessResponse {
+        status: 200,
+        message: "OK".to_string(),
+        data: granted,
+    }))
+}
+
+fn add(
+    payload: &RolePayload,
+    identity: &Identity,
+    conn: &mut PgConnection,
+) -> Result<Role, ApiError> {
     use crate::schema::roles::dsl::*;
 
     let new_role = NewRole {
@@ -114,22 +256,44 @@ fn add(payload: &RolePayload, conn: &mut PgConnection) -> Result<Role, DbError>
         updated_at: chrono::Local::now().naive_local(),
     };
 
-    let res = diesel::insert_into(roles)
-        .values(&new_role)
-        .returning(roles::all_columns())
-        .get_result(conn)?;
-
-    Ok(res)
+    conn.transaction(|conn| {
+        let res = diesel::insert_into(roles)
+            .values(&new_role)
+            .returning(roles::all_columns())
+            .get_result::<Role>(conn)?;
+        audit::created(&identity.actor(), Resource::Role, res.id, &res, conn)?;
+        Ok(res)
+    })
 }
 
-fn find_all(conn: &mut PgConnection) -> Result<Vec<Role>, DbError> {
+fn find_all(
+    filters: &Filters,
+    page: &Page,
+    conn: &mut PgConnection,
+) -> Result<Paginated<Role>, ApiError> {
     use crate::schema::roles::dsl::*;
 
-    let items = roles.load::<Role>(conn)?;
-    Ok(items)
+    let filtered = || {
+        let mut query = roles.into_boxed();
+        if let Some(role_name) = &filters.name {
+            query = query.filter(name.eq(role_name));
+        }
+        query
+    };
+
+    let total = filtered().count().get_result(conn)?;
+    let rows = match page.column.as_str() {
+        "updated_at" => {
+            paginate!(filtered(), page, updated_at, id, chrono::NaiveDateTime).load(conn)?
+        }
+        "name" => paginate!(filtered(), page, name, id, String).load(conn)?,
+        _ => paginate!(filtered(), page, created_at, id, chrono::NaiveDateTime).load(conn)?,
+    };
+
+    Ok(page.finish(rows, total))
 }
 
-fn find_by_id(role_id: Uuid, conn: &mut PgConnection) -> Result<Option<Role>, DbError> {
+fn find_by_id(role_id: Uuid, conn: &mut PgConnection) -> Result<Option<Role>, ApiError> {
     use crate::schema::roles::dsl::*;
 
     let role = roles
@@ -143,22 +307,164 @@ fn find_by_id(role_id: Uuid, conn: &mut PgConnection) -> Result<Option<Role>, Db
 fn update_by_id(
     role_id: Uuid,
     payload: &RolePayload,
+    if_match: Option<&IfMatch>,
+    identity: &Identity,
     conn: &mut PgConnection,
-) -> Result<Role, DbError> {
+) -> Result<Role, ApiError> {
     use crate::schema::roles::dsl::*;
 
-    let role = diesel::update(roles.find(role_id))
-        .set((
-            name.eq(payload.name.to_string()),
-            updated_at.eq(chrono::Local::now().naive_local()),
-        ))
-        .get_result::<Role>(conn)?;
-    Ok(role)
+    conn.transaction(|conn| {
+        let current = find_for_update(role_id, conn)?;
+        helpers::check_if_match(if_match, &current.updated_at)?;
+
+        let role = diesel::update(roles.find(role_id))
+            .set((
+                name.eq(payload.name.to_string()),
+                updated_at.eq(chrono::Local::now().naive_local()),
+            ))
+            .get_result::<Role>(conn)?;
+        audit::updated(
+            &identity.actor(),
+            Resource::Role,
+            role_id,
+            &current,
+            &role,
+            conn,
+        )?;
+        Ok(role)
+    })
 }
 
-fn delete(role_id: Uuid, conn: &mut PgConnection) -> Result<usize, DbError> {
+fn patch_by_id(
+    role_id: Uuid,
+    mut changes: RolePatch,
+    if_match: Option<&IfMatch>,
+    identity: &Identity,
+    conn: &mut PgConnection,
+) -> Result<Role, ApiError> {
+    use crate::schema::roles::dsl::*;
+
+    conn.transaction(|conn| {
+        let current = find_for_update(role_id, conn)?;
+        helpers::check_if_match(if_match, &current.updated_at)?;
+        changes.merge(current.clone()).validate()?;
+
+        changes.updated_at = Some(chrono::Local::now().naive_local());
+        let role = diesel::update(roles.find(role_id))
+            .set(&changes)
+            .get_result::<Role>(conn)?;
+        audit::updated(
+            &identity.actor(),
+            Resource::Role,
+            role_id,
+            &current,
+            &role,
+            conn,
+        )?;
+        Ok(role)
+    })
+}
+
+/// Loads a role and locks it until the end of the transaction.
+fn find_for_update(role_id: Uuid, conn: &mut PgConnection) -> Result<Role, ApiError> {
     use crate::schema::roles::dsl::*;
 
-    let count = diesel::delete(roles.find(role_id)).execute(conn)?;
-    Ok(count)
+    roles
+        .find(role_id)
+        .for_update()
+        .first::<Role>(conn)
+        .optional()?
+        .ok_or_else(|| ApiError::NotFound("Role not found".to_string()))
+}
+
+fn delete(
+    role_id: Uuid,
+    if_match: Option<&IfMatch>,
+    identity: &Identity,
+    conn: &mut PgConnection,
+) -> Result<usize, ApiError> {
+    use crate::schema::roles::dsl::*;
+    use crate::schema::users;
+
+    conn.transaction(|conn| {
+        let current = find_for_update(role_id, conn)?;
+        helpers::check_if_match(if_match, &current.updated_at)?;
+
+        let assigned = users::table
+            .filter(users::role.eq(role_id))
+            .count()
+            .get_result::<i64>(conn)?;
+        if assigned > 0 {
+            return Err(ApiError::Conflict(format!(
+                "Role {} is still assigned to {} user(s), reassign them first",
+                current.name, assigned
+            )));
+        }
+
+        let count = diesel::delete(roles.find(role_id)).execute(conn)?;
+        audit::deleted(&identity.actor(), Resource::Role, role_id, &current, conn)?;
+        Ok(count)
+    })
+}
+
+fn grant(
+    role_id: Uuid,
+    names: &[String],
+    identity: &Identity,
+    conn: &mut PgConnection,
+) -> Result<Vec<String>, ApiError> {
+    use crate::schema::{permissions, role_permissions};
+
+    conn.transaction(|conn| {
+        find_for_update(role_id, conn)?;
+        let before = RolePermissionsPayload {
+            permissions: crate::permissions::granted(role_id, conn)?,
+        };
+
+        let known = permissions::table
+            .filter(permissions::name.eq_any(names))
+            .select(permissions::name)
+            .load::<String>(conn)?;
+        let errors: Vec<FieldError> = names
+            .iter()
+            .enumerate()
+            .filter(|(_, name)| !known.contains(name))
+            .map(|(position, name)| FieldError {
+                field: format!("permissions[{}]", position),
+                code: "invalid".to_string(),
+                message: format!("{} is not a known permission", name),
+            })
+            .collect();
+        if !errors.is_empty() {
+            return Err(ApiError::InvalidPayload(errors));
+        }
+
+        diesel::delete(role_permissions::table.filter(role_permissions::role.eq(role_id)))
+            .execute(conn)?;
+        let rows: Vec<_> = known
+            .iter()
+            .map(|name| {
+                (
+                    role_permissions::role.eq(role_id),
+                    role_permissions::permission.eq(name),
+                )
+            })
+            .collect();
+        diesel::insert_into(role_permissions::table)
+            .values(&rows)
+            .execute(conn)?;
+
+        let after = RolePermissionsPayload {
+            permissions: crate::permissions::granted(role_id, conn)?,
+        };
+        audit::updated(
+            &identity.actor(),
+            Resource::Role,
+            role_id,
+            &before,
+            &after,
+            conn,
+        )?;
+        Ok(after.permissions)
+    })
 }
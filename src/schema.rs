@@ -1,29 +1,154 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    api_keys (id) {
+        id -> Uuid,
+        name -> Varchar,
+        property -> Uuid,
+        owner -> Uuid,
+        prefix -> Varchar,
+        key_hash -> Varchar,
+        permissions -> Array<Text>,
+        expires_at -> Nullable<Timestamp>,
+        revoked_at -> Nullable<Timestamp>,
+        last_used_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    audit_log (id) {
+        id -> Uuid,
+        actor -> Varchar,
+        action -> Varchar,
+        resource -> Varchar,
+        resource_id -> Uuid,
+        before -> Nullable<Jsonb>,
+        after -> Nullable<Jsonb>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    found_items (id) {
+        id -> Uuid,
+        property -> Uuid,
+        reporter -> Uuid,
+        machine -> Nullable<Uuid>,
+        description -> Varchar,
+        colors -> Array<Text>,
+        size -> Nullable<Varchar>,
+        found_at -> Timestamp,
+        item -> Nullable<Uuid>,
+        claimed_by -> Nullable<Uuid>,
+        claimed_at -> Nullable<Timestamp>,
+        archived_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    idempotency_keys (scope, key) {
+        scope -> Varchar,
+        key -> Varchar,
+        request_hash -> Varchar,
+        response -> Json,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    images (id) {
+        id -> Uuid,
+        item -> Nullable<Uuid>,
+        machine -> Nullable<Uuid>,
+        uploader -> Uuid,
+        content_type -> Varchar,
+        size -> Int4,
+        width -> Int4,
+        height -> Int4,
+        created_at -> Timestamp,
+        found_item -> Nullable<Uuid>,
+    }
+}
+
 diesel::table! {
     items (id) {
         id -> Uuid,
         name -> Varchar,
         size -> Varchar,
-        colors -> Varchar,
+        colors -> Array<Text>,
         owner -> Uuid,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        fabric -> Nullable<Varchar>,
+        max_wash_temperature -> Nullable<Int2>,
+        tumble_dry -> Nullable<Bool>,
+        delicate -> Bool,
+        weight -> Nullable<Int4>,
+        tag -> Varchar,
+        holder -> Nullable<Uuid>,
     }
 }
 
 diesel::table! {
-    machines (id) {
+    loans (id) {
         id -> Uuid,
-        name -> Varchar,
-        property -> Uuid,
+        item -> Uuid,
+        borrower -> Uuid,
+        status -> Varchar,
+        message -> Nullable<Varchar>,
+        due_at -> Timestamp,
+        approved_at -> Nullable<Timestamp>,
+        returned_at -> Nullable<Timestamp>,
+        reminded_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    notifications (id) {
+        id -> Uuid,
+        recipient -> Uuid,
+        kind -> Varchar,
+        subject -> Nullable<Uuid>,
+        message -> Varchar,
+        read_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    oidc_logins (state) {
+        state -> Varchar,
+        code_verifier -> Varchar,
+        nonce -> Varchar,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    participants (id) {
+        id -> Uuid,
+        reservation -> Uuid,
+        participant -> Uuid,
+        amount -> Int4,
         status -> Varchar,
-        eta -> Timestamp,
         created_at -> Timestamp,
         updated_at -> Timestamp,
     }
 }
 
+diesel::table! {
+    permissions (name) {
+        name -> Varchar,
+        description -> Varchar,
+    }
+}
+
 diesel::table! {
     properties (id) {
         id -> Uuid,
@@ -35,19 +160,65 @@ diesel::table! {
         owner -> Uuid,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        #[max_length = 2]
+        country -> Varchar,
+    }
+}
+
+diesel::table! {
+    reservation_items (reservation, item) {
+        reservation -> Uuid,
+        item -> Uuid,
+        created_at -> Timestamp,
     }
 }
 
 diesel::table! {
     reservations (id) {
         id -> Uuid,
-        machine -> Uuid,
+        resource -> Uuid,
         owner -> Uuid,
         start_time -> Timestamp,
         end_time -> Timestamp,
         shared -> Bool,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        amount -> Int4,
+    }
+}
+
+diesel::table! {
+    resource_kinds (id) {
+        id -> Uuid,
+        name -> Varchar,
+        unit -> Varchar,
+        max_duration_minutes -> Nullable<Int4>,
+        max_advance_days -> Nullable<Int4>,
+        max_upcoming -> Nullable<Int4>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    resources (id) {
+        id -> Uuid,
+        name -> Varchar,
+        property -> Uuid,
+        status -> Varchar,
+        eta -> Timestamp,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        kind -> Varchar,
+        capacity -> Int4,
+    }
+}
+
+diesel::table! {
+    role_permissions (role, permission) {
+        role -> Uuid,
+        permission -> Varchar,
+        created_at -> Timestamp,
     }
 }
 
@@ -60,6 +231,20 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    sessions (id) {
+        id -> Uuid,
+        owner -> Uuid,
+        token_hash -> Varchar,
+        refresh_hash -> Varchar,
+        expires_at -> Timestamp,
+        refresh_expires_at -> Timestamp,
+        revoked_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     users (id) {
         id -> Uuid,
@@ -68,20 +253,58 @@ diesel::table! {
         property -> Nullable<Uuid>,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        #[max_length = 255]
+        email -> Nullable<Varchar>,
+        password_hash -> Nullable<Varchar>,
+        oidc_subject -> Nullable<Varchar>,
+        erased_at -> Nullable<Timestamp>,
     }
 }
 
+diesel::joinable!(api_keys -> properties (property));
+diesel::joinable!(api_keys -> users (owner));
+diesel::joinable!(found_items -> items (item));
+diesel::joinable!(found_items -> resources (machine));
+diesel::joinable!(found_items -> properties (property));
+diesel::joinable!(images -> found_items (found_item));
+diesel::joinable!(images -> items (item));
+diesel::joinable!(images -> resources (machine));
+diesel::joinable!(images -> users (uploader));
 diesel::joinable!(items -> users (owner));
-diesel::joinable!(machines -> properties (property));
-diesel::joinable!(reservations -> machines (machine));
+diesel::joinable!(loans -> items (item));
+diesel::joinable!(loans -> users (borrower));
+diesel::joinable!(notifications -> users (recipient));
+diesel::joinable!(participants -> reservations (reservation));
+diesel::joinable!(participants -> users (participant));
+diesel::joinable!(reservation_items -> items (item));
+diesel::joinable!(reservation_items -> reservations (reservation));
+diesel::joinable!(reservations -> resources (resource));
 diesel::joinable!(reservations -> users (owner));
+diesel::joinable!(resources -> properties (property));
+diesel::joinable!(role_permissions -> permissions (permission));
+diesel::joinable!(role_permissions -> roles (role));
+diesel::joinable!(sessions -> users (owner));
 diesel::joinable!(users -> roles (role));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    api_keys,
+    audit_log,
+    found_items,
+    idempotency_keys,
+    images,
     items,
-    machines,
+    loans,
+    notifications,
+    oidc_logins,
+    participants,
+    permissions,
     properties,
+    reservation_items,
     reservations,
+    resource_kinds,
+    resources,
+    role_permissions,
     roles,
+    sessions,
     users,
 );
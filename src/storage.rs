@@ -0,0 +1,308 @@
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+/// Directory uploads are stored in, unless `STORAGE_PATH` is set.
+const DEFAULT_PATH: &str = "uploads";
+const DEFAULT_REGION: &str = "us-east-1";
+
+/// A stored file, either on the local disk or downloaded into memory.
+pub enum Object {
+    File(PathBuf),
+    Bytes(Vec<u8>),
+}
+
+/// Where uploaded files are kept. Keys are relative paths such as `images/{id}`.
+///
+/// Implementations block, call them from `web::block`.
+pub trait Storage: Send + Sync {
+    fn put(&self, key: &str, content_type: &str, bytes: &[u8]) -> io::Result<()>;
+    fn get(&self, key: &str) -> io::Result<Object>;
+    /// Succeeds if there is nothing stored under the key.
+    fn delete(&self, key: &str) -> io::Result<()>;
+}
+
+/// The backend selected by `STORAGE_BACKEND`, `local` unless set to `s3`.
+pub fn from_env() -> io::Result<Arc<dyn Storage>> {
+    let backend = std::env::var("STORAGE_BACKEND").unwrap_or_else(|_| "local".to_string());
+    match backend.as_str() {
+        "local" => {
+            let root = std::env::var("STORAGE_PATH").unwrap_or_else(|_| DEFAULT_PATH.to_string());
+            log::info!("Storing uploads in {}", root);
+            Ok(Arc::new(Local::new(root)?))
+        }
+        "s3" => {
+            let s3 = S3::from_env()?;
+            log::info!("Storing uploads in bucket {} at {}", s3.bucket, s3.endpoint);
+            Ok(Arc::new(s3))
+        }
+        other => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Unknown STORAGE_BACKEND {}", other),
+        )),
+    }
+}
+
+/// Files in a directory of the local file system.
+pub struct Local {
+    root: PathBuf,
+}
+
+impl Local {
+    pub fn new(root: impl Into<PathBuf>) -> io::Result<Self> {
+        let root = root.into();
+        std::fs::create_dir_all(&root)?;
+        Ok(Local { root })
+    }
+
+    fn path(&self, key: &str) -> io::Result<PathBuf> {
+        let safe = key
+            .split('/')
+            .all(|part| !part.is_empty() && part != "." && part != "..");
+        if !safe {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid storage key {}", key),
+            ));
+        }
+        Ok(self.root.join(key))
+    }
+}
+
+impl Storage for Local {
+    fn put(&self, key: &str, _content_type: &str, bytes: &[u8]) -> io::Result<()> {
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // Written aside and renamed, so readers never see a partial file.
+        let partial = path.with_extension("partial");
+        std::fs::write(&partial, bytes)?;
+        std::fs::rename(partial, path)
+    }
+
+    fn get(&self, key: &str) -> io::Result<Object> {
+        let path = self.path(key)?;
+        if path.is_file() {
+            Ok(Object::File(path))
+        } else {
+            Err(io::Error::new(io::ErrorKind::NotFound, key.to_string()))
+        }
+    }
+
+    fn delete(&self, key: &str) -> io::Result<()> {
+        match std::fs::remove_file(self.path(key)?) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+}
+
+/// A bucket of an S3-compatible object store such as MinIO, addressed path-style and
+/// authenticated with AWS Signature Version 4.
+pub struct S3 {
+    endpoint: String,
+    bucket: String,
+    region: String,
+    access_key: String,
+    secret_key: String,
+    /// Created on first use, as the blocking client must not be created on the runtime.
+    http: OnceLock<reqwest::blocking::Client>,
+}
+
+impl S3 {
+    pub fn from_env() -> io::Result<Self> {
+        let var = |name: &str| {
+            std::env::var(name).map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} must be set for STORAGE_BACKEND=s3", name),
+                )
+            })
+        };
+
+        Ok(S3 {
+            endpoint: var("S3_ENDPOINT")?.trim_end_matches('/').to_string(),
+            bucket: var("S3_BUCKET")?,
+            region: std::env::var("S3_REGION").unwrap_or_else(|_| DEFAULT_REGION.to_string()),
+            access_key: var("S3_ACCESS_KEY")?,
+            secret_key: var("S3_SECRET_KEY")?,
+            http: OnceLock::new(),
+        })
+    }
+
+    fn http(&self) -> io::Result<&reqwest::blocking::Client> {
+        if let Some(http) = self.http.get() {
+            return Ok(http);
+        }
+        let http = reqwest::blocking::Client::builder()
+            .timeout(Duration::from_secs(30))
+            .build()
+            .map_err(io::Error::other)?;
+        Ok(self.http.get_or_init(|| http))
+    }
+
+    /// Sends a request signed with AWS Signature Version 4.
+    fn send(
+        &self,
+        method: reqwest::Method,
+        key: &str,
+        content_type: Option<&str>,
+        body: Vec<u8>,
+    ) -> io::Result<reqwest::blocking::Response> {
+        let path = format!("/{}/{}", self.bucket, key);
+        let url = reqwest::Url::parse(&format!("{}{}", self.endpoint, path))
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        let host = match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (Some(host), None) => host.to_string(),
+            (None, _) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "S3_ENDPOINT has no host",
+                ))
+            }
+        };
+
+        let now = chrono::Utc::now();
+        let date = now.format("%Y%m%d").to_string();
+        let timestamp = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let payload_hash = hex::encode(Sha256::digest(&body));
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+
+        let canonical_request = format!(
+            "{}\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            method,
+            url.path(),
+            host,
+            payload_hash,
+            timestamp,
+            SIGNED_HEADERS,
+            payload_hash
+        );
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            timestamp,
+            scope,
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+        let signing_key = signing_key(&self.secret_key, &date, &self.region, "s3");
+        let signature = hex::encode(hmac(&signing_key, string_to_sign.as_bytes()));
+
+        let mut request = self
+            .http()?
+            .request(method, url)
+            .header("x-amz-content-sha256", payload_hash)
+            .header("x-amz-date", timestamp)
+            .header(
+                "Authorization",
+                format!(
+                    "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+                    self.access_key, scope, SIGNED_HEADERS, signature
+                ),
+            );
+        if let Some(content_type) = content_type {
+            request = request.header("Content-Type", content_type);
+        }
+        request.body(body).send().map_err(io::Error::other)
+    }
+}
+
+const SIGNED_HEADERS: &str = "host;x-amz-content-sha256;x-amz-date";
+
+/// The key requests of a day are signed with, derived from the secret key.
+fn signing_key(secret_key: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    [date, region, service, "aws4_request"]
+        .iter()
+        .fold(format!("AWS4{}", secret_key).into_bytes(), |key, part| {
+            hmac(&key, part.as_bytes())
+        })
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Fails unless the object store answered with a success status.
+fn check(response: reqwest::blocking::Response) -> io::Result<reqwest::blocking::Response> {
+    let status = response.status();
+    if status.is_success() {
+        Ok(response)
+    } else if status == reqwest::StatusCode::NOT_FOUND {
+        Err(io::Error::new(io::ErrorKind::NotFound, status.to_string()))
+    } else {
+        let body = response.text().unwrap_or_default();
+        Err(io::Error::other(format!("{}: {}", status, body)))
+    }
+}
+
+impl Storage for S3 {
+    fn put(&self, key: &str, content_type: &str, bytes: &[u8]) -> io::Result<()> {
+        check(self.send(
+            reqwest::Method::PUT,
+            key,
+            Some(content_type),
+            bytes.to_vec(),
+        )?)?;
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> io::Result<Object> {
+        let response = check(self.send(reqwest::Method::GET, key, None, Vec::new())?)?;
+        let bytes = response.bytes().map_err(io::Error::other)?;
+        Ok(Object::Bytes(bytes.to_vec()))
+    }
+
+    fn delete(&self, key: &str) -> io::Result<()> {
+        match check(self.send(reqwest::Method::DELETE, key, None, Vec::new())?) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result.map(|_| ()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derives_the_signing_key() {
+        // The example from the AWS Signature Version 4 documentation.
+        let key = signing_key(
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            "20120215",
+            "us-east-1",
+            "iam",
+        );
+        assert_eq!(
+            hex::encode(key),
+            "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d"
+        );
+    }
+
+    /// Needs the bucket configured by the `S3_*` variables, such as the `minio` service of
+    /// `docker-compose.yml`: `cargo test -- --ignored`.
+    #[test]
+    #[ignore]
+    fn s3_stores_reads_and_deletes() {
+        let s3 = S3::from_env().expect("S3_* variables are set");
+        let key = format!("tests/{}", uuid::Uuid::new_v4());
+
+        s3.put(&key, "text/plain", b"hello").unwrap();
+        match s3.get(&key).unwrap() {
+            Object::Bytes(bytes) => assert_eq!(bytes, b"hello"),
+            Object::File(_) => panic!("S3 objects are downloaded"),
+        }
+
+        s3.delete(&key).unwrap();
+        let err = s3.get(&key).err().expect("the object is gone");
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        // Deleting what is not there succeeds.
+        s3.delete(&key).unwrap();
+    }
+}
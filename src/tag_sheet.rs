@@ -0,0 +1,354 @@
+use std::fmt::Write;
+
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::{get, web, HttpResponse};
+use diesel::prelude::*;
+use qrcode::{Color, EcLevel, QrCode};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use super::DbPool;
+use crate::auth::Identity;
+use crate::helpers::{self, ApiError};
+use crate::permissions;
+
+/// Prefix of the URL encoded in labels, unless `ITEM_TAG_URL` is set. The tag is appended.
+const DEFAULT_TAG_URL: &str = "https://app.iperka.com/tags/";
+
+// A4 pages of 3 × 8 labels, all lengths in millimetres.
+const PAGE_WIDTH: f64 = 210.0;
+const PAGE_HEIGHT: f64 = 297.0;
+const COLUMNS: usize = 3;
+const ROWS: usize = 8;
+const LABEL_WIDTH: f64 = 70.0;
+const LABEL_HEIGHT: f64 = 35.0;
+const MARGIN_TOP: f64 = (PAGE_HEIGHT - ROWS as f64 * LABEL_HEIGHT) / 2.0;
+const QR_SIZE: f64 = 27.0;
+const PADDING: f64 = 4.0;
+/// Longest item name printed in full.
+const MAX_NAME_CHARS: usize = 20;
+
+#[derive(Debug, Deserialize)]
+struct SheetParams {
+    #[serde(default)]
+    format: Format,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Format {
+    #[default]
+    Svg,
+    Pdf,
+}
+
+/// One label, with the QR code's dark modules as rectangles in module units.
+struct Label {
+    name: String,
+    tag: String,
+    modules: usize,
+    dark: Vec<(usize, usize, usize)>,
+}
+
+impl Label {
+    fn new(name: &str, tag: &str, url: &str) -> Result<Label, ApiError> {
+        let code =
+            QrCode::with_error_correction_level(url, EcLevel::M).map_err(helpers::internal)?;
+        let modules = code.width();
+        let colors = code.to_colors();
+
+        // Runs of dark modules in a row are drawn as one rectangle.
+        let mut dark = Vec::new();
+        for (y, row) in colors.chunks(modules).enumerate() {
+            let mut x = 0;
+            while x < modules {
+                if row[x] == Color::Dark {
+                    let start = x;
+                    while x < modules && row[x] == Color::Dark {
+                        x += 1;
+                    }
+                    dark.push((start, y, x - start));
+                } else {
+                    x += 1;
+                }
+            }
+        }
+
+        let name = if name.chars().count() > MAX_NAME_CHARS {
+            let short: String = name.chars().take(MAX_NAME_CHARS - 1).collect();
+            format!("{}...", short.trim_end())
+        } else {
+            name.to_string()
+        };
+
+        Ok(Label {
+            name,
+            // Grouped for reading, lookups ignore the dash.
+            tag: format!("{}-{}", &tag[..tag.len() / 2], &tag[tag.len() / 2..]),
+            modules,
+            dark,
+        })
+    }
+}
+
+/// Top left corner of the `index`th label on its page.
+fn position(index: usize) -> (f64, f64) {
+    let slot = index % (COLUMNS * ROWS);
+    let x =
+        (PAGE_WIDTH - COLUMNS as f64 * LABEL_WIDTH) / 2.0 + (slot % COLUMNS) as f64 * LABEL_WIDTH;
+    let y = MARGIN_TOP + (slot / COLUMNS) as f64 * LABEL_HEIGHT;
+    (x, y)
+}
+
+/// Renders printable QR labels for all items of a user. Scanning a label opens
+/// `ITEM_TAG_URL` followed by the item's tag, which `GET /items/by-tag/{code}` resolves.
+#[get("/users/{id}/item-tags")]
+async fn show(
+    id: web::Path<Uuid>,
+    params: web::Query<SheetParams>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = id.into_inner();
+    identity.require(permissions::ITEMS_READ)?;
+    identity.require_owner(&permissions::ITEMS_WRITE, user_id)?;
+
+    let items = web::block(move || {
+        let mut conn = pool.get()?;
+        identity.require_property_of(|| permissions::user_property(user_id, &mut conn))?;
+        find_items(user_id, &mut conn)
+    })
+    .await??;
+    if items.is_empty() {
+        return Err(ApiError::UnprocessableEntity(
+            "User has no items to print labels for".to_string(),
+        ));
+    }
+
+    let base = std::env::var("ITEM_TAG_URL").unwrap_or_else(|_| DEFAULT_TAG_URL.to_string());
+    let labels = items
+        .iter()
+        .map(|(name, tag)| Label::new(name, tag, &format!("{}{}", base, tag)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let (content_type, extension, body) = match params.format {
+        Format::Svg => ("image/svg+xml", "svg", svg(&labels).into_bytes()),
+        Format::Pdf => ("application/pdf", "pdf", pdf(&labels)),
+    };
+    Ok(HttpResponse::Ok()
+        .content_type(content_type)
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Inline,
+            parameters: vec![DispositionParam::Filename(format!(
+                "item-tags-{}.{}",
+                user_id, extension
+            ))],
+        })
+        .body(body))
+}
+
+/// Names and tags of the user's items, by name.
+fn find_items(user_id: Uuid, conn: &mut PgConnection) -> Result<Vec<(String, String)>, ApiError> {
+    use crate::schema::items::dsl::*;
+
+    let found = items
+        .filter(owner.eq(user_id))
+        .order((name, created_at))
+        .select((name, tag))
+        .load::<(String, String)>(conn)?;
+
+    Ok(found)
+}
+
+/// All pages stacked in one drawing.
+fn svg(labels: &[Label]) -> String {
+    let pages = labels.len().div_ceil(COLUMNS * ROWS);
+    let height = pages as f64 * PAGE_HEIGHT;
+    let mut out = format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}mm" height="{h}mm" viewBox="0 0 {w} {h}">"#,
+        w = PAGE_WIDTH,
+        h = height
+    );
+    out.push_str(r##"<rect width="100%" height="100%" fill="#fff"/>"##);
+
+    for (index, label) in labels.iter().enumerate() {
+        let (x, y) = position(index);
+        let y = y + (index / (COLUMNS * ROWS)) as f64 * PAGE_HEIGHT;
+        let module = QR_SIZE / label.modules as f64;
+        let (qr_x, qr_y) = (x + PADDING, y + (LABEL_HEIGHT - QR_SIZE) / 2.0);
+
+        let _ = write!(
+            out,
+            r##"<rect x="{}" y="{}" width="{}" height="{}" fill="none" stroke="#bbb" stroke-width="0.2" stroke-dasharray="1 1"/>"##,
+            x, y, LABEL_WIDTH, LABEL_HEIGHT
+        );
+        out.push_str(r##"<path fill="#000" d=""##);
+        for (column, row, run) in &label.dark {
+            let _ = write!(
+                out,
+                "M{:.3} {:.3}h{:.3}v{:.3}h-{:.3}z",
+                qr_x + *column as f64 * module,
+                qr_y + *row as f64 * module,
+                *run as f64 * module,
+                module,
+                *run as f64 * module
+            );
+        }
+        out.push_str(r#""/>"#);
+
+        let text_x = qr_x + QR_SIZE + PADDING;
+        let _ = write!(
+            out,
+            r#"<text x="{}" y="{}" font-family="Helvetica, Arial, sans-serif" font-size="3.5">{}</text>"#,
+            text_x,
+            y + LABEL_HEIGHT / 2.0 - 2.0,
+            escape_xml(&label.name)
+        );
+        let _ = write!(
+            out,
+            r#"<text x="{}" y="{}" font-family="Courier, monospace" font-size="4.5" font-weight="bold">{}</text>"#,
+            text_x,
+            y + LABEL_HEIGHT / 2.0 + 5.0,
+            label.tag
+        );
+    }
+
+    out.push_str("</svg>");
+    out
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// One A4 page per 24 labels, written without a PDF library as it only takes rectangles
+/// and text in the standard fonts.
+fn pdf(labels: &[Label]) -> Vec<u8> {
+    let pt = |mm: f64| mm * 72.0 / 25.4;
+    // PDF measures from the bottom of the page.
+    let flip = |mm: f64| pt(PAGE_HEIGHT - mm);
+
+    let pages: Vec<String> = labels
+        .chunks(COLUMNS * ROWS)
+        .map(|page| {
+            let mut content = String::new();
+            for (index, label) in page.iter().enumerate() {
+                let (x, y) = position(index);
+                let module = QR_SIZE / label.modules as f64;
+                let (qr_x, qr_y) = (x + PADDING, y + (LABEL_HEIGHT - QR_SIZE) / 2.0);
+
+                let _ = writeln!(
+                    content,
+                    "0.73 G 0.57 w [3 3] 0 d {:.2} {:.2} {:.2} {:.2} re S",
+                    pt(x),
+                    flip(y + LABEL_HEIGHT),
+                    pt(LABEL_WIDTH),
+                    pt(LABEL_HEIGHT)
+                );
+                content.push_str("0 g\n");
+                for (column, row, run) in &label.dark {
+                    let _ = writeln!(
+                        content,
+                        "{:.3} {:.3} {:.3} {:.3} re",
+                        pt(qr_x + *column as f64 * module),
+                        flip(qr_y + (*row + 1) as f64 * module),
+                        pt(*run as f64 * module),
+                        pt(module)
+                    );
+                }
+                content.push_str("f\n");
+
+                let text_x = pt(qr_x + QR_SIZE + PADDING);
+                let _ = writeln!(
+                    content,
+                    "BT /F1 10 Tf {:.2} {:.2} Td ({}) Tj ET",
+                    text_x,
+                    flip(y + LABEL_HEIGHT / 2.0 - 2.0),
+                    escape_pdf(&label.name)
+                );
+                let _ = writeln!(
+                    content,
+                    "BT /F2 12.75 Tf {:.2} {:.2} Td ({}) Tj ET",
+                    text_x,
+                    flip(y + LABEL_HEIGHT / 2.0 + 5.0),
+                    escape_pdf(&label.tag)
+                );
+            }
+            content
+        })
+        .collect();
+
+    // Objects 1 to 4 are fixed, then every page is followed by its content.
+    let mut objects = vec![
+        "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            (0..pages.len())
+                .map(|page| format!("{} 0 R", 5 + 2 * page))
+                .collect::<Vec<_>>()
+                .join(" "),
+            pages.len()
+        ),
+        "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>"
+            .to_string(),
+        "<< /Type /Font /Subtype /Type1 /BaseFont /Courier-Bold /Encoding /WinAnsiEncoding >>"
+            .to_string(),
+    ];
+    for (page, content) in pages.iter().enumerate() {
+        objects.push(format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {:.2} {:.2}] \
+             /Resources << /Font << /F1 3 0 R /F2 4 0 R >> >> /Contents {} 0 R >>",
+            pt(PAGE_WIDTH),
+            pt(PAGE_HEIGHT),
+            6 + 2 * page
+        ));
+        objects.push(format!(
+            "<< /Length {} >>\nstream\n{}endstream",
+            content.len(),
+            content
+        ));
+    }
+
+    let mut out = b"%PDF-1.4\n".to_vec();
+    let mut offsets = Vec::with_capacity(objects.len());
+    for (index, object) in objects.iter().enumerate() {
+        offsets.push(out.len());
+        out.extend_from_slice(format!("{} 0 obj\n{}\nendobj\n", index + 1, object).as_bytes());
+    }
+    let xref = out.len();
+    let mut trailer = format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1);
+    for offset in offsets {
+        let _ = writeln!(trailer, "{:010} 00000 n ", offset);
+    }
+    let _ = write!(
+        trailer,
+        "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+        objects.len() + 1,
+        xref
+    );
+    out.extend_from_slice(trailer.as_bytes());
+    out
+}
+
+/// Escapes a PDF string literal. Characters beyond Latin-1 have no glyph in the
+/// standard fonts and become `?`.
+fn escape_pdf(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' | '(' | ')' => {
+                out.push('\\');
+                out.push(c);
+            }
+            ' '..='~' => out.push(c),
+            '\u{a0}'..='\u{ff}' => {
+                let _ = write!(out, "\\{:03o}", c as u32);
+            }
+            _ => out.push('?'),
+        }
+    }
+    out
+}
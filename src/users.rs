@@ -1,96 +1,282 @@
 use super::DbPool;
-use actix_web::{delete, get, post, put, web, Error, HttpResponse};
+use actix_web::http::header::{ETag, IfMatch, IfNoneMatch};
+use actix_web::{delete, get, patch, post, put, web, HttpResponse};
 use diesel::prelude::*;
+use serde::Deserialize;
 use uuid::Uuid;
 
-use crate::helpers::{ErrorResponse, SuccessResponse};
-use crate::models::user::{NewUser, User, UserPayload};
+use crate::audit;
+use crate::auth::{self, Identity};
+use crate::helpers::{self, ApiError, Json, PaginatedResponse, SuccessResponse};
+use crate::idempotency;
+use crate::includes::{IncludeParams, Includes, Resource};
+use crate::models::user::{NewUser, User, UserPatch, UserPayload};
+use crate::nested::{self, Parent};
+use crate::pagination::{paginate, Page, PageParams, Paginated};
+use crate::permissions;
+use crate::validation::Validate;
 
-type DbError = Box<dyn std::error::Error + Send + Sync>;
+#[derive(Debug, Deserialize)]
+struct Filters {
+    role: Option<Uuid>,
+    property: Option<Uuid>,
+}
+
+const SORTABLE: [&str; 3] = ["created_at", "updated_at", "name"];
 
 #[get("/users")]
-async fn index(pool: web::Data<DbPool>) -> Result<HttpResponse, Error> {
+async fn index(
+    page: web::Query<PageParams>,
+    filters: web::Query<Filters>,
+    include: web::Query<IncludeParams>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::USERS_READ)?;
+
+    let mut filters = filters.into_inner();
+    filters.property = identity.scope_filter(filters.property)?;
+    list(None, &page, filters, &include, &identity, pool).await
+}
+
+#[get("/properties/{id}/users")]
+async fn property_index(
+    id: web::Path<Uuid>,
+    page: web::Query<PageParams>,
+    filters: web::Query<Filters>,
+    include: web::Query<IncludeParams>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::USERS_READ)?;
+
+    let parent = Parent::Property(id.into_inner());
+    identity.require_property(Some(parent.id()))?;
+    let mut filters = filters.into_inner();
+    filters.property = Some(parent.id());
+    list(Some(parent), &page, filters, &include, &identity, pool).await
+}
+
+async fn list(
+    parent: Option<Parent>,
+    page: &PageParams,
+    filters: Filters,
+    include: &IncludeParams,
+    identity: &Identity,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, ApiError> {
+    let page = page.resolve(&SORTABLE)?;
+    let includes = Includes::parse(include.include.as_deref(), Resource::User, identity)?;
     let users = web::block(move || {
         let mut conn = pool.get()?;
-        find_all(&mut conn)
+        if let Some(parent) = parent {
+            parent.ensure_exists(&mut conn)?;
+        }
+        let users = find_all(&filters, &page, &mut conn)?;
+        includes.embed_page(Resource::User, users, &mut conn)
     })
-    .await?
-    .map_err(actix_web::error::ErrorInternalServerError)?;
+    .await??;
 
-    Ok(HttpResponse::Ok().json(SuccessResponse {
+    Ok(HttpResponse::Ok().json(PaginatedResponse {
         status: 200,
         message: "OK".to_string(),
-        data: users,
+        data: users.data,
+        meta: users.meta,
     }))
 }
 
 #[post("/users")]
 async fn create(
     pool: web::Data<DbPool>,
-    payload: web::Json<UserPayload>,
-) -> Result<HttpResponse, Error> {
-    let user = web::block(move || {
+    payload: Json<UserPayload>,
+    key: idempotency::Key,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::USERS_ADMIN)?;
+
+    insert(
+        None,
+        identity,
+        "POST /users".to_string(),
+        payload.0,
+        key,
+        pool,
+    )
+    .await
+}
+
+#[post("/properties/{id}/users")]
+async fn property_create(
+    id: web::Path<Uuid>,
+    pool: web::Data<DbPool>,
+    body: Json<serde_json::Value>,
+    key: idempotency::Key,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::USERS_ADMIN)?;
+
+    let parent = Parent::Property(id.into_inner());
+    let payload = nested::payload(body.0, "property", &parent)?;
+    let scope = format!("POST /properties/{}/users", parent.id());
+    insert(Some(parent), identity, scope, payload, key, pool).await
+}
+
+async fn insert(
+    parent: Option<Parent>,
+    identity: Identity,
+    scope: String,
+    payload: UserPayload,
+    key: idempotency::Key,
+    pool: web::Data<DbPool>,
+) -> Result<HttpResponse, ApiError> {
+    payload.validate()?;
+
+    let created = web::block(move || {
         let mut conn = pool.get()?;
-        add(&payload, &mut conn)
+        if let Some(parent) = parent {
+            parent.ensure_exists(&mut conn)?;
+        }
+        // The password is not serialized with the payload, but retries must repeat it too.
+        let request = (&payload, payload.password.as_deref());
+        idempotency::create_once(&key, &scope, &request, &mut conn, |conn| {
+            add(&payload, &identity, conn)
+        })
     })
-    .await?
-    .map_err(actix_web::error::ErrorInternalServerError)?;
+    .await??;
 
-    Ok(HttpResponse::Created().json(SuccessResponse {
-        status: 201,
-        message: "Created".to_string(),
-        data: user,
-    }))
+    Ok(created.respond())
 }
 
 #[get("/users/{id}")]
-async fn show(id: web::Path<Uuid>, pool: web::Data<DbPool>) -> Result<HttpResponse, Error> {
+async fn show(
+    id: web::Path<Uuid>,
+    include: web::Query<IncludeParams>,
+    if_none_match: Option<web::Header<IfNoneMatch>>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::USERS_READ)?;
+
+    let includes = Includes::parse(include.include.as_deref(), Resource::User, &identity)?;
+    let embedding = !includes.is_empty();
     let user = web::block(move || {
         let mut conn = pool.get()?;
-        find_by_id(id.into_inner(), &mut conn)
+        let user = find_by_id(id.into_inner(), &mut conn)?;
+        if let Some(user) = &user {
+            identity.require_property(user.property)?;
+        }
+        user.map(|user| {
+            let data = includes.embed_one(Resource::User, &user, &mut conn)?;
+            Ok::<_, ApiError>((user.updated_at, data))
+        })
+        .transpose()
     })
-    .await?
-    .map_err(actix_web::error::ErrorInternalServerError)?;
+    .await??;
 
-    if user.is_none() {
-        return Ok(HttpResponse::NotFound().json(ErrorResponse {
-            status: 404,
-            message: "User not found".to_string(),
+    let Some((updated_at, user)) = user else {
+        return Err(ApiError::NotFound("User not found".to_string()));
+    };
+
+    // Embedded resources change independently of this one, so only the plain
+    // representation is cacheable by its version.
+    if embedding {
+        return Ok(HttpResponse::Ok().json(SuccessResponse {
+            status: 200,
+            message: "OK".to_string(),
+            data: user,
         }));
     }
 
-    Ok(HttpResponse::Ok().json(SuccessResponse {
-        status: 200,
-        message: "OK".to_string(),
-        data: user,
-    }))
+    let etag = helpers::etag(&updated_at);
+    if helpers::not_modified(if_none_match.as_deref(), &etag) {
+        return Ok(HttpResponse::NotModified()
+            .insert_header(ETag(etag))
+            .finish());
+    }
+
+    Ok(HttpResponse::Ok()
+        .insert_header(ETag(etag))
+        .json(SuccessResponse {
+            status: 200,
+            message: "OK".to_string(),
+            data: user,
+        }))
 }
 
 #[put("/users/{id}")]
 async fn update(
     id: web::Path<Uuid>,
-    payload: web::Json<UserPayload>,
+    payload: Json<UserPayload>,
+    if_match: Option<web::Header<IfMatch>>,
     pool: web::Data<DbPool>,
-) -> Result<HttpResponse, Error> {
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    payload.validate()?;
+
     let user = web::block(move || {
         let mut conn = pool.get()?;
-        update_by_id(id.into_inner(), &payload, &mut conn)
+        update_by_id(
+            id.into_inner(),
+            &payload,
+            if_match.as_deref(),
+            &identity,
+            &mut conn,
+        )
     })
-    .await?
-    .map_err(actix_web::error::ErrorInternalServerError)?;
+    .await??;
 
-    Ok(HttpResponse::Ok().json(SuccessResponse {
-        status: 200,
-        message: "OK".to_string(),
-        data: user,
-    }))
+    let etag = helpers::etag(&user.updated_at);
+    Ok(HttpResponse::Ok()
+        .insert_header(ETag(etag))
+        .json(SuccessResponse {
+            status: 200,
+            message: "OK".to_string(),
+            data: user,
+        }))
+}
+
+#[patch("/users/{id}")]
+async fn partial_update(
+    id: web::Path<Uuid>,
+    changes: Json<UserPatch>,
+    if_match: Option<web::Header<IfMatch>>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    let user = web::block(move || {
+        let mut conn = pool.get()?;
+        patch_by_id(
+            id.into_inner(),
+            changes.0,
+            if_match.as_deref(),
+            &identity,
+            &mut conn,
+        )
+    })
+    .await??;
+
+    let etag = helpers::etag(&user.updated_at);
+    Ok(HttpResponse::Ok()
+        .insert_header(ETag(etag))
+        .json(SuccessResponse {
+            status: 200,
+            message: "OK".to_string(),
+            data: user,
+        }))
 }
 
 #[delete("/users/{id}")]
-async fn destroy(id: web::Path<Uuid>, pool: web::Data<DbPool>) -> Result<HttpResponse, Error> {
+async fn destroy(
+    id: web::Path<Uuid>,
+    if_match: Option<web::Header<IfMatch>>,
+    pool: web::Data<DbPool>,
+    identity: Identity,
+) -> Result<HttpResponse, ApiError> {
+    identity.require(permissions::USERS_ADMIN)?;
+
     let result = web::block(move || {
         let mut conn = pool.get()?;
-        delete(id.into_inner(), &mut conn)
+        delete(id.into_inner(), if_match.as_deref(), &identity, &mut conn)
     })
     .await?
     .map(|user| {
@@ -99,13 +285,16 @@ async fn destroy(id: web::Path<Uuid>, pool: web::Data<DbPool>) -> Result<HttpRes
             message: "Deleted".to_string(),
             data: user,
         })
-    })
-    .map_err(actix_web::error::ErrorInternalServerError)?;
+    })?;
 
     Ok(result)
 }
 
-fn add(payload: &UserPayload, conn: &mut PgConnection) -> Result<User, DbError> {
+fn add(
+    payload: &UserPayload,
+    identity: &Identity,
+    conn: &mut PgConnection,
+) -> Result<User, ApiError> {
     use crate::schema::users::dsl::*;
 
     let new_user = NewUser {
@@ -114,24 +303,56 @@ fn add(payload: &UserPayload, conn: &mut PgConnection) -> Result<User, DbError>
         property: payload.property,
         created_at: chrono::Local::now().naive_local(),
         updated_at: chrono::Local::now().naive_local(),
+        email: payload.email.as_deref().map(auth::normalize_email),
+        password_hash: payload
+            .password
+            .as_deref()
+            .map(auth::hash_password)
+            .transpose()?,
+        oidc_subject: None,
     };
 
-    let res = diesel::insert_into(users)
-        .values(&new_user)
-        .returning(users::all_columns())
-        .get_result(conn)?;
-
-    Ok(res)
+    conn.transaction(|conn| {
+        let res = diesel::insert_into(users)
+            .values(&new_user)
+            .returning(users::all_columns())
+            .get_result::<User>(conn)?;
+        audit::created(&identity.actor(), Resource::User, res.id, &res, conn)?;
+        Ok(res)
+    })
 }
 
-fn find_all(conn: &mut PgConnection) -> Result<Vec<User>, DbError> {
+fn find_all(
+    filters: &Filters,
+    page: &Page,
+    conn: &mut PgConnection,
+) -> Result<Paginated<User>, ApiError> {
     use crate::schema::users::dsl::*;
 
-    let items = users.load::<User>(conn)?;
-    Ok(items)
+    let filtered = || {
+        let mut query = users.into_boxed();
+        if let Some(role_id) = filters.role {
+            query = query.filter(role.eq(role_id));
+        }
+        if let Some(property_id) = filters.property {
+            query = query.filter(property.eq(property_id));
+        }
+        query
+    };
+
+    let total = filtered().count().get_result(conn)?;
+    let rows = match page.column.as_str() {
+        "updated_at" => {
+            paginate!(filtered(), page, updated_at, id, chrono::NaiveDateTime).load(conn)?
+        }
+        "name" => paginate!(filtered(), page, name, id, String).load(conn)?,
+        _ => paginate!(filtered(), page, created_at, id, chrono::NaiveDateTime).load(conn)?,
+    };
+
+    Ok(page.finish(rows, total))
 }
 
-fn find_by_id(user_id: Uuid, conn: &mut PgConnection) -> Result<Option<User>, DbError> {
+fn find_by_id(user_id: Uuid, conn: &mut PgConnection) -> Result<Option<User>, ApiError> {
     use crate::schema::users::dsl::*;
 
     let user = users
@@ -145,24 +366,128 @@ fn find_by_id(user_id: Uuid, conn: &mut PgConnection) -> Result<Option<User>, Db
 fn update_by_id(
     user_id: Uuid,
     payload: &UserPayload,
+    if_match: Option<&IfMatch>,
+    identity: &Identity,
     conn: &mut PgConnection,
-) -> Result<User, DbError> {
+) -> Result<User, ApiError> {
     use crate::schema::users::dsl::*;
 
-    let user = diesel::update(users.find(user_id))
-        .set((
-            name.eq(payload.name.to_string()),
-            role.eq(payload.role),
-            property.eq(payload.property),
-            updated_at.eq(chrono::Local::now().naive_local()),
-        ))
-        .get_result::<User>(conn)?;
-    Ok(user)
+    conn.transaction(|conn| {
+        let current = find_for_update(user_id, conn)?;
+        authorize_update(identity, &current, payload)?;
+        helpers::check_if_match(if_match, &current.updated_at)?;
+
+        let new_hash = payload
+            .password
+            .as_deref()
+            .map(auth::hash_password)
+            .transpose()?;
+        let user = diesel::update(users.find(user_id))
+            .set((
+                name.eq(payload.name.to_string()),
+                role.eq(payload.role),
+                property.eq(payload.property),
+                email.eq(payload.email.as_deref().map(auth::normalize_email)),
+                new_hash.map(|hash| password_hash.eq(hash)),
+                updated_at.eq(chrono::Local::now().naive_local()),
+            ))
+            .get_result::<User>(conn)?;
+        audit::updated(
+            &identity.actor(),
+            Resource::User,
+            user_id,
+            &current,
+            &user,
+            conn,
+        )?;
+        Ok(user)
+    })
+}
+
+fn patch_by_id(
+    user_id: Uuid,
+    mut changes: UserPatch,
+    if_match: Option<&IfMatch>,
+    identity: &Identity,
+    conn: &mut PgConnection,
+) -> Result<User, ApiError> {
+    use crate::schema::users::dsl::*;
+
+    conn.transaction(|conn| {
+        let current = find_for_update(user_id, conn)?;
+        helpers::check_if_match(if_match, &current.updated_at)?;
+        let merged = changes.merge(current.clone());
+        authorize_update(identity, &current, &merged)?;
+        merged.validate()?;
+
+        if let Some(new_email) = &mut changes.email {
+            *new_email = new_email.as_deref().map(auth::normalize_email);
+        }
+        if let Some(password) = &changes.password {
+            changes.password = Some(auth::hash_password(password)?);
+        }
+        changes.updated_at = Some(chrono::Local::now().naive_local());
+        let user = diesel::update(users.find(user_id))
+            .set(&changes)
+            .get_result::<User>(conn)?;
+        audit::updated(
+            &identity.actor(),
+            Resource::User,
+            user_id,
+            &current,
+            &user,
+            conn,
+        )?;
+        Ok(user)
+    })
+}
+
+/// Users may update themselves, but only administrators may move anyone to another
+/// role or property.
+fn authorize_update(
+    identity: &Identity,
+    current: &User,
+    payload: &UserPayload,
+) -> Result<(), ApiError> {
+    identity.require_owner(&permissions::USERS_WRITE, current.id)?;
+
+    if current.erased_at.is_some() {
+        return Err(ApiError::Conflict(
+            "Erased users cannot be changed".to_string(),
+        ));
+    }
+    if payload.role != current.role || payload.property != current.property {
+        identity.require(permissions::USERS_ADMIN)?;
+    }
+    Ok(())
+}
+
+/// Loads a user and locks it until the end of the transaction.
+fn find_for_update(user_id: Uuid, conn: &mut PgConnection) -> Result<User, ApiError> {
+    use crate::schema::users::dsl::*;
+
+    users
+        .find(user_id)
+        .for_update()
+        .first::<User>(conn)
+        .optional()?
+        .ok_or_else(|| ApiError::NotFound("User not found".to_string()))
 }
 
-fn delete(user_id: Uuid, conn: &mut PgConnection) -> Result<usize, DbError> {
+fn delete(
+    user_id: Uuid,
+    if_match: Option<&IfMatch>,
+    identity: &Identity,
+    conn: &mut PgConnection,
+) -> Result<usize, ApiError> {
     use crate::schema::users::dsl::*;
 
-    let count = diesel::delete(users.find(user_id)).execute(conn)?;
-    Ok(count)
+    conn.transaction(|conn| {
+        let current = find_for_update(user_id, conn)?;
+        helpers::check_if_match(if_match, &current.updated_at)?;
+
+        let count = diesel::delete(users.find(user_id)).execute(conn)?;
+        audit::deleted(&identity.actor(), Resource::User, user_id, &current, conn)?;
+        Ok(count)
+    })
 }
@@ -0,0 +1,303 @@
+use crate::helpers::{ApiError, FieldError};
+
+/// Payloads declare their rules in [`Validate::rules`], e.g.
+///
+/// ```ignore
+/// v.field("name", self.name.as_str()).required().max_length(100);
+/// ```
+///
+/// Only the first failing rule of a field is reported.
+pub trait Validate {
+    fn rules(&self, v: &mut Validator);
+
+    /// Runs all rules and fails with a `422` listing every invalid field.
+    fn validate(&self) -> Result<(), ApiError> {
+        let mut validator = Validator::default();
+        self.rules(&mut validator);
+
+        if validator.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ApiError::InvalidPayload(validator.errors))
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Validator {
+    errors: Vec<FieldError>,
+}
+
+impl Validator {
+    pub fn field<'a, T: ?Sized>(&'a mut self, name: &'a str, value: &'a T) -> Field<'a, T> {
+        Field {
+            validator: self,
+            name,
+            value,
+            failed: false,
+        }
+    }
+}
+
+pub struct Field<'a, T: ?Sized> {
+    validator: &'a mut Validator,
+    name: &'a str,
+    value: &'a T,
+    failed: bool,
+}
+
+impl<'a, T: ?Sized> Field<'a, T> {
+    fn check(mut self, valid: impl FnOnce(&T) -> bool, code: &str, message: String) -> Self {
+        if !self.failed && !valid(self.value) {
+            self.failed = true;
+            self.validator.errors.push(FieldError {
+                field: self.name.to_string(),
+                code: code.to_string(),
+                message,
+            });
+        }
+        self
+    }
+}
+
+impl<'a> Field<'a, str> {
+    /// The value must contain something other than whitespace.
+    pub fn required(self) -> Self {
+        let message = format!("{} must not be empty", self.name);
+        self.check(|value| !value.trim().is_empty(), "required", message)
+    }
+
+    pub fn min_length(self, min: usize) -> Self {
+        let message = format!("{} must be at least {} characters long", self.name, min);
+        self.check(|value| value.chars().count() >= min, "too_short", message)
+    }
+
+    pub fn max_length(self, max: usize) -> Self {
+        let message = format!("{} must be at most {} characters long", self.name, max);
+        self.check(|value| value.chars().count() <= max, "too_long", message)
+    }
+
+    /// The value must be one of `allowed`, e.g. a name from a fixed palette.
+    pub fn one_of(self, allowed: &[&str]) -> Self {
+        let message = format!("{} must be one of {}", self.name, allowed.join(", "));
+        self.check(|value| allowed.contains(&value), "invalid_choice", message)
+    }
+
+    /// The value must look like an email address, `local@domain.tld`.
+    pub fn email(self) -> Self {
+        let message = format!("{} must be a valid email address", self.name);
+        self.check(is_email, "invalid_format", message)
+    }
+
+    /// The value must be an ISO 3166-1 alpha-2 country code like `CH`.
+    pub fn country_code(self) -> Self {
+        let message = format!("{} must be a two letter country code", self.name);
+        self.check(
+            |value| value.len() == 2 && value.chars().all(|c| c.is_ascii_uppercase()),
+            "invalid_country",
+            message,
+        )
+    }
+
+    /// The value must be a postal code in the format used by `country`.
+    ///
+    /// Countries without a known format only require a non-empty value.
+    pub fn postal_code(self, country: &str) -> Self {
+        let message = format!("{} is not a valid postal code for {}", self.name, country);
+        self.check(
+            |value| is_postal_code(country, value),
+            "invalid_format",
+            message,
+        )
+    }
+}
+
+impl<'a> Field<'a, chrono::NaiveDateTime> {
+    pub fn after(self, other_name: &str, other: &chrono::NaiveDateTime) -> Self {
+        let message = format!("{} must be after {}", self.name, other_name);
+        self.check(|value| value > other, "out_of_order", message)
+    }
+}
+
+impl<'a, T: PartialOrd + std::fmt::Display> Field<'a, T> {
+    pub fn one_of(self, allowed: &[T]) -> Self {
+        let names: Vec<String> = allowed.iter().map(T::to_string).collect();
+        let message = format!("{} must be one of {}", self.name, names.join(", "));
+        self.check(|value| allowed.contains(value), "invalid_choice", message)
+    }
+
+    pub fn between(self, min: T, max: T) -> Self {
+        let message = format!("{} must be between {} and {}", self.name, min, max);
+        self.check(
+            |value| *value >= min && *value <= max,
+            "out_of_range",
+            message,
+        )
+    }
+}
+
+fn is_email(value: &str) -> bool {
+    match value.trim().split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && !domain.contains('@')
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.')
+                && !value.trim().contains(char::is_whitespace)
+        }
+        None => false,
+    }
+}
+
+fn is_postal_code(country: &str, zip: &str) -> bool {
+    let digits = |n: usize| zip.len() == n && zip.chars().all(|c| c.is_ascii_digit());
+
+    match country {
+        "CH" | "AT" | "LI" | "BE" | "DK" | "NO" => digits(4),
+        "DE" | "FR" | "IT" | "ES" | "FI" => digits(5),
+        "NL" => {
+            let compact = zip.replacen(' ', "", 1);
+            compact.is_ascii()
+                && compact.len() == 6
+                && compact[..4].chars().all(|c| c.is_ascii_digit())
+                && !compact.starts_with('0')
+                && compact[4..].chars().all(|c| c.is_ascii_uppercase())
+        }
+        "US" => match zip.split_once('-') {
+            Some((zip5, plus4)) => {
+                zip5.len() == 5
+                    && plus4.len() == 4
+                    && zip5
+                        .chars()
+                        .chain(plus4.chars())
+                        .all(|c| c.is_ascii_digit())
+            }
+            None => digits(5),
+        },
+        _ => !zip.trim().is_empty(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn codes(rules: impl FnOnce(&mut Validator)) -> Vec<(String, String)> {
+        let mut v = Validator::default();
+        rules(&mut v);
+        v.errors
+            .into_iter()
+            .map(|error| (error.field, error.code))
+            .collect()
+    }
+
+    fn pair(field: &str, code: &str) -> (String, String) {
+        (field.to_string(), code.to_string())
+    }
+
+    #[test]
+    fn reports_only_the_first_failing_rule_of_a_field() {
+        let errors = codes(|v| {
+            v.field("name", "  ").required().min_length(3);
+        });
+        assert_eq!(errors, vec![pair("name", "required")]);
+    }
+
+    #[test]
+    fn reports_every_invalid_field() {
+        let errors = codes(|v| {
+            v.field("name", "").required();
+            v.field("notes", "abcdef").max_length(5);
+            v.field("capacity", &0).between(1, 10);
+            v.field("unit", "l").one_of(&["slots", "kg"]);
+        });
+        assert_eq!(
+            errors,
+            vec![
+                pair("name", "required"),
+                pair("notes", "too_long"),
+                pair("capacity", "out_of_range"),
+                pair("unit", "invalid_choice"),
+            ]
+        );
+    }
+
+    #[test]
+    fn counts_length_in_characters() {
+        assert!(codes(|v| {
+            v.field("name", "äöü").min_length(3).max_length(3);
+        })
+        .is_empty());
+    }
+
+    #[test]
+    fn accepts_valid_values() {
+        let start = chrono::NaiveDate::from_ymd_opt(2026, 1, 1)
+            .unwrap()
+            .and_hms_opt(10, 0, 0)
+            .unwrap();
+        assert!(codes(|v| {
+            v.field("capacity", &10).between(1, 10);
+            v.field("weight", &1).one_of(&[1, 2]);
+            v.field("email", "jane@example.com").email();
+            v.field("country", "CH").country_code();
+            v.field("end_time", &(start + chrono::Duration::hours(1)))
+                .after("start_time", &start);
+        })
+        .is_empty());
+    }
+
+    #[test]
+    fn rejects_end_not_after_start() {
+        let start = chrono::NaiveDate::from_ymd_opt(2026, 1, 1)
+            .unwrap()
+            .and_hms_opt(10, 0, 0)
+            .unwrap();
+        let errors = codes(|v| {
+            v.field("end_time", &start).after("start_time", &start);
+        });
+        assert_eq!(errors, vec![pair("end_time", "out_of_order")]);
+    }
+
+    #[test]
+    fn checks_email_addresses() {
+        for valid in ["a@b.ch", " jane.doe@mail.example.com "] {
+            assert!(is_email(valid), "{valid}");
+        }
+        for invalid in [
+            "", "jane", "@b.ch", "a@b", "a@.ch", "a@b.", "a@b@c.ch", "a b@c.ch",
+        ] {
+            assert!(!is_email(invalid), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn checks_country_codes() {
+        let errors = codes(|v| {
+            v.field("a", "ch").country_code();
+            v.field("b", "CHE").country_code();
+        });
+        assert_eq!(
+            errors,
+            vec![pair("a", "invalid_country"), pair("b", "invalid_country")]
+        );
+    }
+
+    #[test]
+    fn checks_postal_codes_by_country() {
+        assert!(is_postal_code("CH", "8004"));
+        assert!(!is_postal_code("CH", "80045"));
+        assert!(is_postal_code("DE", "10115"));
+        assert!(!is_postal_code("DE", "1011a"));
+        assert!(is_postal_code("NL", "1234 AB"));
+        assert!(is_postal_code("NL", "1234AB"));
+        assert!(!is_postal_code("NL", "0234 AB"));
+        assert!(!is_postal_code("NL", "1234 ab"));
+        assert!(is_postal_code("US", "12345"));
+        assert!(is_postal_code("US", "12345-6789"));
+        assert!(!is_postal_code("US", "12345-678"));
+        assert!(is_postal_code("SE", "114 55"));
+        assert!(!is_postal_code("SE", " "));
+    }
+}